@@ -1,6 +1,13 @@
 use std::ptr::NonNull;
 
-use crate::{context::Context, types::*, Backend};
+use crate::{
+    command_buffer::BufferCopyRegion,
+    context::Context,
+    queue::{Job, Queue},
+    types::*,
+    Backend,
+};
+use bytemuck::Pod;
 use thiserror::Error;
 
 pub struct BufferCreateInfo {
@@ -13,18 +20,41 @@ pub struct BufferCreateInfo {
     pub buffer_usage: BufferUsage,
     /// Describes what memory operations are supported by this buffer.
     pub memory_usage: MemoryUsage,
+    /// Describes how the buffer is shared between queues. Use [`SharingMode::Concurrent`] for
+    /// buffers that are frequently used on multiple queues.
+    pub sharing: SharingMode,
     /// The backend *should* use the provided debug name for easy identification.
     pub debug_name: Option<String>,
 }
 
+/// A region of memory owned by an existing buffer that a new buffer can be placed in.
+pub struct BufferMemoryRegion<'a, B: Backend> {
+    /// The buffer whose memory is aliased.
+    pub buffer: &'a Buffer<B>,
+    /// Offset in bytes from the start of `buffer`.
+    pub offset: u64,
+}
+
 #[derive(Debug, Error)]
 pub enum BufferCreateError {
+    #[error("the memory of the aliased buffer is not compatible with the new buffer")]
+    AliasIncompatible,
+    #[error("the offset into the aliased buffer must be a multiple of {0}")]
+    AliasMisaligned(u64),
+    #[error("the new buffer does not fit within the aliased buffer")]
+    AliasOutOfBounds,
+    #[error("uniform buffers can be at most {0} bytes large")]
+    UniformBufferTooLarge(u64),
+    #[error("storage buffers can be at most {0} bytes large")]
+    StorageBufferTooLarge(u64),
     #[error("an error has occured: {0}")]
     Other(String),
 }
 
 #[derive(Debug, Error)]
 pub enum BufferViewError {
+    #[error("the buffer is not mappable")]
+    NotMapable,
     #[error("an error has occured: {0}")]
     Other(String),
 }
@@ -35,6 +65,10 @@ pub struct Buffer<B: Backend> {
     size: u64,
     memory_usage: MemoryUsage,
     array_elements: usize,
+    /// Kept so that the buffer can be [`recreated`](Buffer::recreate).
+    buffer_usage: BufferUsage,
+    sharing: SharingMode,
+    debug_name: Option<String>,
     pub(crate) id: B::Buffer,
 }
 
@@ -59,6 +93,11 @@ impl<B: Backend> Buffer<B> {
     /// - `ctx` - The [`Context`] to create the buffer with.
     /// - `create_info` - Describes the buffer to create.
     ///
+    /// # Errors
+    /// - Returns [`BufferCreateError::UniformBufferTooLarge`] or
+    /// [`BufferCreateError::StorageBufferTooLarge`] if `create_info.size` exceeds the largest
+    /// range the device can bind for the requested usage.
+    ///
     /// # Panics
     /// - If `create_info.size` is `0`.
     /// - If `create_info.array_elements` is `0`.
@@ -72,6 +111,9 @@ impl<B: Backend> Buffer<B> {
         let size = create_info.size;
         let memory_usage = create_info.memory_usage;
         let array_elements = create_info.array_elements;
+        let buffer_usage = create_info.buffer_usage;
+        let sharing = create_info.sharing;
+        let debug_name = create_info.debug_name.clone();
         let id = unsafe { ctx.0.create_buffer(create_info)? };
         Ok(Self {
             ctx,
@@ -79,6 +121,68 @@ impl<B: Backend> Buffer<B> {
             size,
             memory_usage,
             array_elements,
+            buffer_usage,
+            sharing,
+            debug_name,
+        })
+    }
+
+    /// Creates a new buffer placed in the memory of an existing buffer instead of allocating its
+    /// own. This is useful for transient buffers whose lifetimes don't overlap, since they can
+    /// share the same memory.
+    ///
+    /// Every use of the new buffer is synchronized with every use of the buffers it shares memory
+    /// with. The contents of the memory are undefined when it is first used through a different
+    /// buffer.
+    ///
+    /// # Arguments
+    /// - `ctx` - The [`Context`] to create the buffer with.
+    /// - `create_info` - Describes the buffer to create.
+    /// - `region` - The memory to place the buffer in. The source buffer is kept alive for as long
+    /// as the new buffer is.
+    ///
+    /// # Errors
+    /// - Returns an error if the new buffer does not fit in the region, if `region.offset` does
+    /// not meet the alignment requirements of the new buffer, or if the memory of the source
+    /// buffer can't be used with the new buffer.
+    ///
+    /// # Panics
+    /// - If `create_info.size` is `0`.
+    /// - If `create_info.array_elements` is `0`.
+    /// - If `create_info.memory_usage` does not match the memory usage of `region.buffer`.
+    pub fn new_aliased(
+        ctx: Context<B>,
+        create_info: BufferCreateInfo,
+        region: BufferMemoryRegion<B>,
+    ) -> Result<Self, BufferCreateError> {
+        assert_ne!(create_info.size, 0, "buffer size cannot be zero");
+        assert_ne!(
+            create_info.array_elements, 0,
+            "buffer array elements cannot be zero"
+        );
+        assert_eq!(
+            create_info.memory_usage, region.buffer.memory_usage,
+            "aliased buffers must have the same memory usage as the buffer they alias"
+        );
+        let size = create_info.size;
+        let memory_usage = create_info.memory_usage;
+        let array_elements = create_info.array_elements;
+        let buffer_usage = create_info.buffer_usage;
+        let sharing = create_info.sharing;
+        let debug_name = create_info.debug_name.clone();
+        let id = unsafe {
+            ctx.0
+                .create_aliased_buffer(create_info, &region.buffer.id, region.offset)?
+        };
+        Ok(Self {
+            ctx,
+            id,
+            size,
+            memory_usage,
+            array_elements,
+            buffer_usage,
+            sharing,
+            debug_name,
         })
     }
 
@@ -98,16 +202,46 @@ impl<B: Backend> Buffer<B> {
     ///
     /// # Panics
     /// - If `data.is_empty()`.
+    #[inline(always)]
     pub fn new_staging(
         ctx: Context<B>,
         debug_name: Option<String>,
         data: &[u8],
+    ) -> Result<Buffer<B>, BufferCreateError> {
+        Self::new_staging_with_hint(ctx, debug_name, data, StagingMemoryHint::Bandwidth)
+    }
+
+    /// Creates a new staging buffer like [`new_staging`](Buffer::new_staging), but lets you
+    /// choose what the memory of the buffer is optimized for.
+    ///
+    /// [`Bandwidth`](StagingMemoryHint::Bandwidth) creates the buffer with
+    /// [`CpuToGpu`](MemoryUsage::CpuToGpu) memory, and
+    /// [`Availability`](StagingMemoryHint::Availability) creates the buffer with
+    /// [`CpuOnly`](MemoryUsage::CpuOnly) memory.
+    ///
+    /// # Arguments
+    /// - `ctx` - The [`Context`] to create the buffer with.
+    /// - `debug_name` - The backend *should* use the provided debug name for easy identification.
+    /// - `data` - The data to upload to the buffer.
+    /// - `hint` - What the memory of the buffer should be optimized for.
+    ///
+    /// # Panics
+    /// - If `data.is_empty()`.
+    pub fn new_staging_with_hint(
+        ctx: Context<B>,
+        debug_name: Option<String>,
+        data: &[u8],
+        hint: StagingMemoryHint,
     ) -> Result<Buffer<B>, BufferCreateError> {
         let create_info = BufferCreateInfo {
             size: data.len() as u64,
             array_elements: 1,
             buffer_usage: BufferUsage::TRANSFER_SRC,
-            memory_usage: MemoryUsage::CpuToGpu,
+            memory_usage: match hint {
+                StagingMemoryHint::Bandwidth => MemoryUsage::CpuToGpu,
+                StagingMemoryHint::Availability => MemoryUsage::CpuOnly,
+            },
+            sharing: SharingMode::Exclusive,
             debug_name,
         };
         let mut buffer = Buffer::new(ctx, create_info)?;
@@ -117,6 +251,78 @@ impl<B: Backend> Buffer<B> {
         Ok(buffer)
     }
 
+    /// Replaces the buffer with a new buffer of a different size, keeping every other property
+    /// of the buffer. This is useful for dynamic buffers that outgrow their size. The old buffer
+    /// is destroyed once all work using it has completed.
+    ///
+    /// # Arguments
+    /// - `size` - The size in bytes of each array element of the new buffer.
+    /// - `copy_on` - If provided, the contents of every array element of the old buffer are
+    /// copied into the new buffer on this queue, up to the smaller of the two sizes. Any
+    /// remaining bytes of the new buffer are undefined.
+    ///
+    /// # Errors
+    /// - See [`Buffer::new`]. The buffer is left unchanged on error.
+    ///
+    /// # Panics
+    /// - If `size` is `0`.
+    /// - If `copy_on` is provided but the buffer was not created with both the `TRANSFER_SRC` and
+    /// `TRANSFER_DST` usage flags, or if the queue does not
+    /// [`support transfer commands`](Queue::supports_transfer).
+    ///
+    /// # Valid Usage
+    /// - The new buffer is unrelated to the old one, so descriptor sets and prebuilt commands
+    /// referring to the old buffer *must* be updated or re-recorded to use the new one. A buffer
+    /// placed in the memory of another buffer with [`new_aliased`](Buffer::new_aliased) gets its
+    /// own memory when recreated.
+    pub fn recreate(
+        &mut self,
+        size: u64,
+        copy_on: Option<&Queue<B>>,
+    ) -> Result<Option<Job<B>>, BufferCreateError> {
+        assert_ne!(size, 0, "buffer size cannot be zero");
+        if copy_on.is_some() {
+            assert!(
+                self.buffer_usage
+                    .contains(BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST),
+                "buffers recreated with a copy must have `TRANSFER_SRC` and `TRANSFER_DST` usage"
+            );
+        }
+
+        let mut new = Buffer::new(
+            self.ctx.clone(),
+            BufferCreateInfo {
+                size,
+                array_elements: self.array_elements,
+                buffer_usage: self.buffer_usage,
+                memory_usage: self.memory_usage,
+                sharing: self.sharing,
+                debug_name: self.debug_name.clone(),
+            },
+        )?;
+
+        let job = copy_on.map(|queue| {
+            let len = self.size.min(size);
+            let regions = (0..self.array_elements)
+                .map(|array_element| BufferCopyRegion {
+                    src_array_element: array_element,
+                    src_offset: 0,
+                    dst_array_element: array_element,
+                    dst_offset: 0,
+                    len,
+                })
+                .collect();
+            queue.submit(Some("buffer_recreate"), |cb| {
+                cb.copy_buffer_regions(self, &new, regions);
+            })
+        });
+
+        // The old buffer is dropped with `new`, which defers its destruction until the copy and
+        // all other work using it has completed
+        std::mem::swap(self, &mut new);
+        Ok(job)
+    }
+
     #[inline(always)]
     pub fn internal(&self) -> &B::Buffer {
         &self.id
@@ -127,6 +333,11 @@ impl<B: Backend> Buffer<B> {
         self.size
     }
 
+    #[inline(always)]
+    pub fn array_elements(&self) -> usize {
+        self.array_elements
+    }
+
     /// Provides a view into the buffer for read only operations.
     ///
     /// # Arguments
@@ -136,18 +347,16 @@ impl<B: Backend> Buffer<B> {
     /// The backend *must* guarantee that the buffer is not being read or written to by any
     /// in-flight commands by the time the user has access to the buffer map.
     ///
+    /// # Errors
+    /// - Returns [`BufferViewError::NotMapable`] if the buffer was not created with `memory_usage`
+    /// equal to [`MemoryUsage::CpuToGpu`], [`MemoryUsage::GpuToCpu`], or
+    /// [`MemoryUsage::CpuOnly`].
+    ///
     /// # Panics
-    /// - If the buffer is not mappable. That is, the buffer was created with `memory_usage` equal
-    /// to [`MemoryUsage::CpuToGpu`] or [`MemoryUsage::GpuToCpu`].
     /// - If `idx` is not a valid array element of the buffer.
     #[inline(always)]
     pub fn read(&mut self, idx: usize) -> Result<BufferReadView<B>, BufferViewError> {
         assert!(idx < self.array_elements, "`idx` is out of bounds");
-        assert!(
-            self.memory_usage == MemoryUsage::CpuToGpu
-                || self.memory_usage == MemoryUsage::GpuToCpu,
-            "buffer is not mappable"
-        );
 
         let (map, len) = unsafe {
             let res = self.ctx.0.map_memory(&mut self.id, idx)?;
@@ -163,12 +372,13 @@ impl<B: Backend> Buffer<B> {
 
     /// Provides a view into the buffer for read and write operations.
     ///
-    /// See [`read`](Buffer::read) for synchronization requirements and panics.
+    /// See [`read`](Buffer::read) for synchronization requirements, errors, and panics.
     ///
     /// # Arguments
     /// - `idx` - The array element of the buffer to view.
     #[inline(always)]
     pub fn write(&mut self, idx: usize) -> Result<BufferWriteView<B>, BufferViewError> {
+        assert!(idx < self.array_elements, "`idx` is out of bounds");
         let (map, len) = unsafe {
             let res = self.ctx.0.map_memory(&mut self.id, idx)?;
             self.ctx.0.invalidate_range(&mut self.id, idx);
@@ -182,6 +392,57 @@ impl<B: Backend> Buffer<B> {
             len,
         })
     }
+
+    /// Makes host writes to an array element of the buffer visible to the device.
+    ///
+    /// [`BufferWriteView`] flushes automatically when dropped, so this is only needed when you
+    /// want to control exactly when the flush happens (for example, once after several partial
+    /// writes).
+    ///
+    /// # Arguments
+    /// - `idx` - The array element of the buffer to flush.
+    ///
+    /// # Panics
+    /// - If the buffer is not mappable.
+    /// - If `idx` is not a valid array element of the buffer.
+    #[inline(always)]
+    pub fn flush(&mut self, idx: usize) {
+        assert!(idx < self.array_elements, "`idx` is out of bounds");
+        assert!(
+            self.memory_usage == MemoryUsage::CpuToGpu
+                || self.memory_usage == MemoryUsage::GpuToCpu
+                || self.memory_usage == MemoryUsage::CpuOnly,
+            "buffer is not mappable"
+        );
+        unsafe {
+            self.ctx.0.flush_range(&mut self.id, idx);
+        }
+    }
+
+    /// Makes device writes to an array element of the buffer visible to the host.
+    ///
+    /// [`read`](Buffer::read) and [`write`](Buffer::write) invalidate automatically, so this is
+    /// only needed when you want to control exactly when the invalidation happens.
+    ///
+    /// # Arguments
+    /// - `idx` - The array element of the buffer to invalidate.
+    ///
+    /// # Panics
+    /// - If the buffer is not mappable.
+    /// - If `idx` is not a valid array element of the buffer.
+    #[inline(always)]
+    pub fn invalidate(&mut self, idx: usize) {
+        assert!(idx < self.array_elements, "`idx` is out of bounds");
+        assert!(
+            self.memory_usage == MemoryUsage::CpuToGpu
+                || self.memory_usage == MemoryUsage::GpuToCpu
+                || self.memory_usage == MemoryUsage::CpuOnly,
+            "buffer is not mappable"
+        );
+        unsafe {
+            self.ctx.0.invalidate_range(&mut self.id, idx);
+        }
+    }
 }
 
 impl<B: Backend> Drop for Buffer<B> {
@@ -197,6 +458,16 @@ impl<'a, B: Backend> BufferReadView<'a, B> {
     pub fn as_slice(&self) -> &[u8] {
         unsafe { std::slice::from_raw_parts(self.map.as_ptr(), self.len as usize) }
     }
+
+    /// Views the mapped memory as a slice of `T`.
+    ///
+    /// # Panics
+    /// - If the length of the view is not a multiple of `size_of::<T>()`.
+    /// - If the mapped memory is not aligned for `T`.
+    #[inline(always)]
+    pub fn as_slice_of<T: Pod>(&self) -> &[T] {
+        cast_view(self.as_slice())
+    }
 }
 
 impl<'a, B: Backend> BufferWriteView<'a, B> {
@@ -209,6 +480,48 @@ impl<'a, B: Backend> BufferWriteView<'a, B> {
     pub fn as_slice_mut(&mut self) -> &mut [u8] {
         unsafe { std::slice::from_raw_parts_mut(self.map.as_ptr(), self.len as usize) }
     }
+
+    /// Views the mapped memory as a slice of `T`.
+    ///
+    /// # Panics
+    /// - If the length of the view is not a multiple of `size_of::<T>()`.
+    /// - If the mapped memory is not aligned for `T`.
+    #[inline(always)]
+    pub fn as_slice_of<T: Pod>(&self) -> &[T] {
+        cast_view(self.as_slice())
+    }
+
+    /// Views the mapped memory as a mutable slice of `T`.
+    ///
+    /// # Panics
+    /// - If the length of the view is not a multiple of `size_of::<T>()`.
+    /// - If the mapped memory is not aligned for `T`.
+    #[inline(always)]
+    pub fn as_slice_of_mut<T: Pod>(&mut self) -> &mut [T] {
+        check_view::<T>(self.as_slice());
+        bytemuck::cast_slice_mut(self.as_slice_mut())
+    }
+}
+
+/// Checks that a mapped view can be reinterpreted as a slice of `T`.
+#[inline(always)]
+fn check_view<T: Pod>(bytes: &[u8]) {
+    assert_eq!(
+        bytes.len() % std::mem::size_of::<T>(),
+        0,
+        "view length is not a multiple of the size of the type"
+    );
+    assert_eq!(
+        bytes.as_ptr() as usize % std::mem::align_of::<T>(),
+        0,
+        "view is not aligned for the type"
+    );
+}
+
+#[inline(always)]
+fn cast_view<T: Pod>(bytes: &[u8]) -> &[T] {
+    check_view::<T>(bytes);
+    bytemuck::cast_slice(bytes)
 }
 
 impl<'a, B: Backend> Drop for BufferWriteView<'a, B> {
@@ -1,12 +1,22 @@
+use std::ops::Range;
+
 use crate::{
     buffer::Buffer,
     compute_pass::ComputePass,
     compute_pipeline::ComputePipeline,
-    descriptor_set::DescriptorSet,
-    graphics_pipeline::GraphicsPipeline,
-    render_pass::{RenderPass, RenderPassDescriptor, VertexBind},
+    context::Context,
+    descriptor_set::{DescriptorSet, DescriptorSetLayout},
+    graphics_pipeline::{GraphicsPipeline, PushConstantRange},
+    query::{QueryPool, TimestampWriteError},
+    render_pass::{
+        ColorAttachment, ColorAttachmentSource, RenderPass, RenderPassDescriptor, VertexBind,
+    },
+    surface::SurfaceImage,
     texture::Texture,
-    types::{IndexType, QueueType, ShaderStage},
+    types::{
+        CullMode, FrontFace, IndexType, LoadOp, QueueType, ShaderStage, StoreOp, TextureAspect,
+        TextureFormat, ValidationSeverity,
+    },
     Backend,
 };
 
@@ -27,6 +37,21 @@ pub struct CopyBufferToBuffer<'a, B: Backend> {
     pub len: u64,
 }
 
+/// A single region of a [`CommandBuffer::copy_buffer_regions`] copy.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BufferCopyRegion {
+    /// The source array element to read from.
+    pub src_array_element: usize,
+    /// The offset within the array element of the source buffer to read from.
+    pub src_offset: u64,
+    /// The destination array element to write to.
+    pub dst_array_element: usize,
+    /// The offset within the array element of the destination buffer to write to.
+    pub dst_offset: u64,
+    /// The number of bytes to copy.
+    pub len: u64,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BufferTextureCopy {
     /// Offset from the start of the buffer array element to begin read/write.
@@ -47,6 +72,14 @@ pub struct BufferTextureCopy {
     pub texture_mip_level: usize,
     /// The array element of the texture to read/write.
     pub texture_array_element: usize,
+    /// The aspect of the texture to read/write. Copies can only access one aspect at a time, so
+    /// this is used to pick between the depth and stencil aspects of depth stencil textures. If
+    /// `None`, the [`default`](TextureFormat::default_aspect) aspect of the texture's format is
+    /// used.
+    ///
+    /// Depth and stencil aspects are tightly packed in buffer memory, so copies of the stencil
+    /// aspect use one byte per texel regardless of the texture's format.
+    pub texture_aspect: Option<TextureAspect>,
 }
 
 pub enum Command<'a, B: Backend> {
@@ -57,6 +90,12 @@ pub enum Command<'a, B: Backend> {
     BindGraphicsPipeline(GraphicsPipeline<B>),
     BindComputePipeline(ComputePipeline<B>),
     Dispatch(u32, u32, u32),
+    DispatchIndirect {
+        buffer: &'a Buffer<B>,
+        array_element: usize,
+        offset: u64,
+    },
+    Raw(B::RawCommand),
     BindDescriptorSets {
         sets: Vec<&'a DescriptorSet<B>>,
         first: usize,
@@ -92,7 +131,20 @@ pub enum Command<'a, B: Backend> {
         draw_count: usize,
         stride: u64,
     },
+    SetDepthTestEnable(bool),
+    SetDepthWriteEnable(bool),
+    SetCullMode(CullMode),
+    SetFrontFace(FrontFace),
+    PushConstants {
+        stage: ShaderStage,
+        data: Vec<u8>,
+    },
     CopyBufferToBuffer(CopyBufferToBuffer<'a, B>),
+    CopyBufferRegions {
+        src: &'a Buffer<B>,
+        dst: &'a Buffer<B>,
+        regions: Vec<BufferCopyRegion>,
+    },
     CopyBufferToTexture {
         buffer: &'a Buffer<B>,
         texture: &'a Texture<B>,
@@ -103,11 +155,33 @@ pub enum Command<'a, B: Backend> {
         texture: &'a Texture<B>,
         copy: BufferTextureCopy,
     },
+    CopyBufferToTextureBatch(Vec<(&'a Texture<B>, &'a Buffer<B>, BufferTextureCopy)>),
+    GenerateMipmaps {
+        texture: &'a Texture<B>,
+        array_elements: Range<usize>,
+    },
+    BlitToSurfaceImage {
+        texture: &'a Texture<B>,
+        array_element: usize,
+        mip_level: usize,
+        image: &'a SurfaceImage<B>,
+    },
+    WriteTimestamp {
+        pool: &'a QueryPool<B>,
+        index: u32,
+    },
 }
 
 /// A command buffer is used to record commands which are the submitted to a queue.
 pub struct CommandBuffer<'a, B: Backend> {
     pub(crate) queue_ty: QueueType,
+    /// Cached result of [`Queue::supports_transfer`](crate::queue::Queue::supports_transfer).
+    pub(crate) supports_transfer: bool,
+    /// Cached result of [`Queue::supports_timestamps`](crate::queue::Queue::supports_timestamps).
+    pub(crate) supports_timestamps: bool,
+    /// Cached result of the context's extended
+    /// [`dynamic state support`](crate::context::Context::supports_extended_dynamic_state).
+    pub(crate) supports_extended_dynamic_state: bool,
     pub(crate) commands: Vec<Command<'a, B>>,
 }
 
@@ -121,6 +195,7 @@ impl<'a, B: Backend> CommandBuffer<'a, B> {
     /// # Panics
     /// - If the queue type this command buffer was created with does not support graphics
     /// commands.
+    /// - If a color attachment uses a view format that its texture was not created with.
     ///
     pub fn render_pass(
         &mut self,
@@ -133,10 +208,32 @@ impl<'a, B: Backend> CommandBuffer<'a, B> {
             "queue `{:?}` does not support render passes",
             self.queue_ty
         );
+        for attachment in &descriptor.color_attachments {
+            if let ColorAttachmentSource::Texture {
+                texture,
+                view_format: Some(view_format),
+                ..
+            } = &attachment.source
+            {
+                assert!(
+                    *view_format == texture.format()
+                        || texture.view_formats().contains(view_format),
+                    "texture wasn't created with the view format `{:?}`",
+                    view_format
+                );
+            }
+        }
 
+        let read_only_depth = descriptor
+            .depth_stencil_attachment
+            .as_ref()
+            .is_some_and(|attachment| attachment.read_only_depth);
         self.commands.push(Command::BeginRenderPass(descriptor));
         let mut render_pass = RenderPass {
             bound_pipeline: false,
+            push_constants: None,
+            read_only_depth,
+            supports_extended_dynamic_state: self.supports_extended_dynamic_state,
             commands: Vec::default(),
         };
         pass(&mut render_pass);
@@ -144,6 +241,44 @@ impl<'a, B: Backend> CommandBuffer<'a, B> {
         self.commands.push(Command::EndRenderPass);
     }
 
+    /// Records a render pass that draws a single triangle covering the whole of `target` with a
+    /// pipeline created by
+    /// [`create_fullscreen_pipeline`](crate::context::Context::create_fullscreen_pipeline). Every
+    /// pixel of the target is written, so its previous contents are not loaded.
+    ///
+    /// # Arguments
+    /// - `pipeline` - The fullscreen pipeline to draw with.
+    /// - `target` - The color attachment to draw to.
+    /// - `sets` - The descriptor sets to bind, starting at set `0`. May be empty.
+    ///
+    /// # Panics
+    /// - If the queue type this command buffer was created with does not support graphics
+    /// commands.
+    pub fn fullscreen_pass(
+        &mut self,
+        pipeline: GraphicsPipeline<B>,
+        target: ColorAttachmentSource<'a, B>,
+        sets: &[&'a DescriptorSet<B>],
+    ) {
+        self.render_pass(
+            RenderPassDescriptor {
+                color_attachments: vec![ColorAttachment {
+                    source: target,
+                    load_op: LoadOp::DontCare,
+                    store_op: StoreOp::Store,
+                }],
+                depth_stencil_attachment: None,
+            },
+            |pass| {
+                pass.bind_pipeline(pipeline);
+                if !sets.is_empty() {
+                    pass.bind_sets(0, sets.to_vec());
+                }
+                pass.draw(3, 1, 0, 0);
+            },
+        );
+    }
+
     /// Begins a compute pass scope.
     ///
     /// # Arguments
@@ -176,10 +311,14 @@ impl<'a, B: Backend> CommandBuffer<'a, B> {
     /// # Panics
     /// - If the queue type this command buffer was created with does not support transfer
     /// commands.
+    ///
+    /// # Valid Usage
+    /// - If `copy.src` and `copy.dst` are the same buffer, the source and destination regions
+    /// *must not* overlap. This is checked in debug builds.
     #[inline(always)]
     pub fn copy_buffer_to_buffer(&mut self, copy: CopyBufferToBuffer<'a, B>) {
         assert!(
-            self.queue_ty == QueueType::Main || self.queue_ty == QueueType::Transfer,
+            self.supports_transfer,
             "queue `{:?}` does not support transfer commands",
             self.queue_ty
         );
@@ -187,8 +326,49 @@ impl<'a, B: Backend> CommandBuffer<'a, B> {
         self.commands.push(Command::CopyBufferToBuffer(copy));
     }
 
+    /// Copies many regions of data from one buffer into another as a single command. This is
+    /// preferred over multiple calls to [`copy_buffer_to_buffer`](CommandBuffer::copy_buffer_to_buffer)
+    /// when performing many small scattered copies.
+    ///
+    /// # Arguments
+    /// - `src` - The source buffer to read from.
+    /// - `dst` - The destination buffer to write to.
+    /// - `regions` - The regions to copy.
+    ///
+    /// # Panics
+    /// - If the queue type this command buffer was created with does not support transfer
+    /// commands.
+    /// - If `regions` is empty.
+    ///
+    /// # Valid Usage
+    /// - If `src` and `dst` are the same buffer, the source and destination regions *must not*
+    /// overlap. This is checked in debug builds.
+    #[inline(always)]
+    pub fn copy_buffer_regions(
+        &mut self,
+        src: &'a Buffer<B>,
+        dst: &'a Buffer<B>,
+        regions: Vec<BufferCopyRegion>,
+    ) {
+        assert!(
+            self.supports_transfer,
+            "queue `{:?}` does not support transfer commands",
+            self.queue_ty
+        );
+        assert!(!regions.is_empty(), "at least one copy region is required");
+
+        self.commands
+            .push(Command::CopyBufferRegions { src, dst, regions });
+    }
+
     /// Copies data from a buffer into a texture.
     ///
+    /// Texels are copied verbatim, so the buffer *must* already hold them in the channel order of
+    /// the texture's format. For example, RGBA data uploaded to a
+    /// [`Bgra8Unorm`](crate::types::TextureFormat::Bgra8Unorm) texture has its red and blue
+    /// channels swapped unless it is first converted with
+    /// [`TextureFormat::reorder_channels`](crate::types::TextureFormat::reorder_channels).
+    ///
     /// # Arguments
     /// - `texture` - The destination texture to write to.
     /// - `buffer` - The source buffer to copy from.
@@ -197,6 +377,7 @@ impl<'a, B: Backend> CommandBuffer<'a, B> {
     /// # Panics
     /// - If the queue type this command buffer was created with does not support transfer
     /// commands.
+    /// - If `copy.texture_aspect` is an aspect the format of `texture` doesn't have.
     #[inline(always)]
     pub fn copy_buffer_to_texture(
         &mut self,
@@ -205,10 +386,11 @@ impl<'a, B: Backend> CommandBuffer<'a, B> {
         copy: BufferTextureCopy,
     ) {
         assert!(
-            self.queue_ty == QueueType::Main || self.queue_ty == QueueType::Transfer,
+            self.supports_transfer,
             "queue `{:?}` does not support transfer commands",
             self.queue_ty
         );
+        assert_copy_aspect(texture.format(), &copy);
 
         self.commands.push(Command::CopyBufferToTexture {
             buffer,
@@ -217,8 +399,138 @@ impl<'a, B: Backend> CommandBuffer<'a, B> {
         });
     }
 
+    /// Copies data from buffers into textures as a single batch. Every destination texture is
+    /// transitioned once before the batch instead of once per copy, which makes this much faster
+    /// than many [`copy_buffer_to_texture`](CommandBuffer::copy_buffer_to_texture) calls when
+    /// streaming lots of regions (e.g. the tiles of a large texture).
+    ///
+    /// # Arguments
+    /// - `copies` - The destination texture, source buffer, and description of each copy.
+    ///
+    /// # Panics
+    /// - If the queue type this command buffer was created with does not support transfer
+    /// commands.
+    /// - If `copies.is_empty()`.
+    /// - If the aspect of a copy is one the format of its texture doesn't have.
+    ///
+    /// # Valid Usage
+    /// Since the copies are not synchronized with each other, copies writing to the same aspect of
+    /// a mip level and array element of a texture *must not* overlap. This is checked in debug
+    /// builds.
+    #[inline(always)]
+    pub fn copy_buffer_to_texture_batch(
+        &mut self,
+        copies: Vec<(&'a Texture<B>, &'a Buffer<B>, BufferTextureCopy)>,
+    ) {
+        assert!(
+            self.supports_transfer,
+            "queue `{:?}` does not support transfer commands",
+            self.queue_ty
+        );
+        assert!(!copies.is_empty(), "at least one copy is required");
+        for (texture, _, copy) in &copies {
+            assert_copy_aspect(texture.format(), copy);
+        }
+
+        self.commands
+            .push(Command::CopyBufferToTextureBatch(copies));
+    }
+
+    /// Copies a tightly packed region of texels from the start of a buffer into a texture. The row
+    /// length and image height of the buffer data are computed from `extent`, so the buffer must
+    /// hold exactly `extent.0 * extent.1 * extent.2` texels one row after another.
+    ///
+    /// # Arguments
+    /// - `texture` - The destination texture to write to.
+    /// - `buffer` - The source buffer to copy from. Data is read from the start of its first
+    /// array element.
+    /// - `mip_level` - The mip level of the texture to write to.
+    /// - `array_element` - The array element of the texture to write to.
+    /// - `offset` - The offset in texels of the region within the mip level.
+    /// - `extent` - The size in texels of the region.
+    ///
+    /// # Panics
+    /// - If the queue type this command buffer was created with does not support transfer
+    /// commands.
+    /// - If the format of `texture` has a stencil aspect.
+    /// - If `mip_level` or `array_element` are out of bounds for the texture.
+    /// - If the region is empty or out of bounds for the mip level.
+    /// - If the first array element of `buffer` is too small to hold the region.
+    pub fn upload_texture_region(
+        &mut self,
+        texture: &'a Texture<B>,
+        buffer: &'a Buffer<B>,
+        mip_level: usize,
+        array_element: usize,
+        offset: (u32, u32, u32),
+        extent: (u32, u32, u32),
+    ) {
+        assert!(
+            !texture.format().is_stencil(),
+            "textures with a stencil aspect can't be uploaded to as a whole"
+        );
+        assert!(
+            mip_level < texture.mip_count(),
+            "mip level is out of bounds"
+        );
+        assert!(
+            array_element < texture.array_elements(),
+            "array element is out of bounds"
+        );
+        assert!(
+            extent.0 != 0 && extent.1 != 0 && extent.2 != 0,
+            "region cannot be empty"
+        );
+
+        let dims = texture.dims();
+        let mip_dims = (
+            (dims.0 >> mip_level).max(1),
+            (dims.1 >> mip_level).max(1),
+            (dims.2 >> mip_level).max(1),
+        );
+        assert!(
+            offset.0 + extent.0 <= mip_dims.0
+                && offset.1 + extent.1 <= mip_dims.1
+                && offset.2 + extent.2 <= mip_dims.2,
+            "region is out of bounds for mip level {}",
+            mip_level
+        );
+
+        let format = texture.format();
+        let len = format.bytes_per_aspect_texel(format.default_aspect()) as u64
+            * extent.0 as u64
+            * extent.1 as u64
+            * extent.2 as u64;
+        assert!(
+            len <= buffer.size(),
+            "buffer is too small to hold the region ({} bytes required, but the buffer has {})",
+            len,
+            buffer.size()
+        );
+
+        self.copy_buffer_to_texture(
+            texture,
+            buffer,
+            BufferTextureCopy {
+                buffer_offset: 0,
+                buffer_row_length: extent.0,
+                buffer_image_height: extent.1,
+                buffer_array_element: 0,
+                texture_offset: offset,
+                texture_extent: extent,
+                texture_mip_level: mip_level,
+                texture_array_element: array_element,
+                texture_aspect: None,
+            },
+        );
+    }
+
     /// Copies data from a texture into a buffer.
     ///
+    /// Texels are copied verbatim in the channel order of the texture's format. See
+    /// [`TextureFormat::reorder_channels`](crate::types::TextureFormat::reorder_channels) to
+    /// convert them.
+    ///
     /// # Arguments
     /// - `buffer` - The destination buffer to write to.
     /// - `texture` The source texture to read from.
@@ -227,6 +539,7 @@ impl<'a, B: Backend> CommandBuffer<'a, B> {
     /// # Panics
     /// - If the queue type this command buffer was created with does not support transfer
     /// commands.
+    /// - If `copy.texture_aspect` is an aspect the format of `texture` doesn't have.
     #[inline(always)]
     pub fn copy_texture_to_buffer(
         &mut self,
@@ -235,10 +548,11 @@ impl<'a, B: Backend> CommandBuffer<'a, B> {
         copy: BufferTextureCopy,
     ) {
         assert!(
-            self.queue_ty == QueueType::Main || self.queue_ty == QueueType::Transfer,
+            self.supports_transfer,
             "queue `{:?}` does not support transfer commands",
             self.queue_ty
         );
+        assert_copy_aspect(texture.format(), &copy);
 
         self.commands.push(Command::CopyTextureToBuffer {
             buffer,
@@ -246,4 +560,539 @@ impl<'a, B: Backend> CommandBuffer<'a, B> {
             copy,
         });
     }
+
+    /// Generates every mip level of a texture array element from its first mip level by
+    /// successively downsampling each level into the next with a linear filter.
+    ///
+    /// # Arguments
+    /// - `texture` - The texture to generate mip levels for. It must have been created with
+    /// [`TRANSFER_SRC`](crate::types::TextureUsage) and [`TRANSFER_DST`](crate::types::TextureUsage)
+    /// usage and with a format that supports mipmap generation (see
+    /// [`Context::supports_mipmap_generation`](crate::context::Context::supports_mipmap_generation)).
+    /// - `array_element` - The array element of the texture to generate mip levels for.
+    ///
+    /// # Panics
+    /// - If the queue type this command buffer was created with does not support graphics
+    /// commands.
+    /// - If `array_element` is not a valid array element of `texture`.
+    #[inline(always)]
+    pub fn generate_mipmaps(&mut self, texture: &'a Texture<B>, array_element: usize) {
+        self.generate_mipmaps_range(texture, array_element..(array_element + 1));
+    }
+
+    /// Generates every mip level of a range of texture array elements from their first mip
+    /// level. Each array element (e.g. each face of a cubemap) is downsampled independently.
+    ///
+    /// # Arguments
+    /// - `texture` - The texture to generate mip levels for. Has the same requirements as for
+    /// [`CommandBuffer::generate_mipmaps`].
+    /// - `array_elements` - The array elements of the texture to generate mip levels for. Use
+    /// `0..texture.array_elements()` for every array element.
+    ///
+    /// # Panics
+    /// - If the queue type this command buffer was created with does not support graphics
+    /// commands.
+    /// - If `array_elements` is empty or out of bounds for `texture`.
+    pub fn generate_mipmaps_range(
+        &mut self,
+        texture: &'a Texture<B>,
+        array_elements: Range<usize>,
+    ) {
+        assert_eq!(
+            self.queue_ty,
+            QueueType::Main,
+            "queue `{:?}` does not support mipmap generation",
+            self.queue_ty
+        );
+        assert!(!array_elements.is_empty(), "`array_elements` is empty");
+        assert!(
+            array_elements.end <= texture.array_elements(),
+            "`array_elements` is out of bounds"
+        );
+
+        self.commands.push(Command::GenerateMipmaps {
+            texture,
+            array_elements,
+        });
+    }
+
+    /// Blits a texture onto the whole of a surface image, scaling it if the sizes differ. This
+    /// lets you render to an offscreen texture and then present it without a render pass.
+    ///
+    /// The surface image is left ready to be [`presented`](crate::queue::Queue::present).
+    ///
+    /// # Arguments
+    /// - `texture` - The texture to read from. It must have been created with
+    /// [`TRANSFER_SRC`](crate::types::TextureUsage) usage.
+    /// - `array_element` - The array element of `texture` to read from.
+    /// - `mip_level` - The mip level of `texture` to read from.
+    /// - `image` - The surface image to write to.
+    ///
+    /// # Panics
+    /// - If the queue type this command buffer was created with does not support graphics
+    /// commands.
+    /// - If `array_element` or `mip_level` is out of bounds for `texture`.
+    pub fn blit_to_surface_image(
+        &mut self,
+        texture: &'a Texture<B>,
+        array_element: usize,
+        mip_level: usize,
+        image: &'a SurfaceImage<B>,
+    ) {
+        assert_eq!(
+            self.queue_ty,
+            QueueType::Main,
+            "queue `{:?}` does not support blitting to surface images",
+            self.queue_ty
+        );
+        assert!(
+            array_element < texture.array_elements(),
+            "`array_element` is out of bounds"
+        );
+        assert!(
+            mip_level < texture.mip_count(),
+            "`mip_level` is out of bounds"
+        );
+
+        self.commands.push(Command::BlitToSurfaceImage {
+            texture,
+            array_element,
+            mip_level,
+            image,
+        });
+    }
+
+    /// Writes a timestamp to a query once all previously recorded commands have completed.
+    ///
+    /// # Arguments
+    /// - `pool` - The query pool to write to.
+    /// - `index` - The index of the query within `pool` to write to.
+    ///
+    /// # Errors
+    /// - Returns [`TimestampWriteError::Unsupported`] if the queue type this command buffer was
+    /// created with does not [`support timestamps`](crate::queue::Queue::supports_timestamps).
+    ///
+    /// # Panics
+    /// - If `index` is not a valid query of `pool`.
+    ///
+    /// # Valid Usage
+    /// Each query *must* only be written once between [`resets`](QueryPool::reset) of the pool.
+    #[inline(always)]
+    pub fn write_timestamp(
+        &mut self,
+        pool: &'a QueryPool<B>,
+        index: u32,
+    ) -> Result<(), TimestampWriteError> {
+        assert!(index < pool.count(), "`index` is out of bounds");
+        if !self.supports_timestamps {
+            return Err(TimestampWriteError::Unsupported(self.queue_ty));
+        }
+        self.commands.push(Command::WriteTimestamp { pool, index });
+        Ok(())
+    }
+
+    /// Records a backend specific command. The command is recorded in place among the other
+    /// commands and is submitted along with them.
+    ///
+    /// # Arguments
+    /// - `command` - The command to record.
+    ///
+    /// # Safety
+    /// Resource tracking does not see raw commands. Any resources used by `command` are not
+    /// synchronized with other commands or queues, and it is up to the user to ensure they are
+    /// valid and in the expected state. The command *must* leave the command buffer in a state
+    /// the backend expects (e.g. it must not end the render pass it is recorded in).
+    #[cfg(feature = "raw")]
+    #[inline(always)]
+    pub unsafe fn raw(&mut self, command: B::RawCommand) {
+        self.commands.push(Command::Raw(command));
+    }
+
+    /// Checks that every command was recorded within the pass it belongs to (e.g. no dispatches
+    /// within render passes). Passes can only record their own kind of commands, so a failure
+    /// here indicates a bug in how commands are recorded rather than a user error.
+    ///
+    /// # Panics
+    /// - If a command is found outside of the pass it belongs to.
+    pub(crate) fn validate_scopes(&self) {
+        #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+        enum Scope {
+            None,
+            RenderPass,
+            ComputePass,
+        }
+
+        let mut scope = Scope::None;
+        for (i, command) in self.commands.iter().enumerate() {
+            let (expected, name) = match command {
+                Command::BeginRenderPass(_) => (Scope::None, "BeginRenderPass"),
+                Command::EndRenderPass => (Scope::RenderPass, "EndRenderPass"),
+                Command::BeginComputePass => (Scope::None, "BeginComputePass"),
+                Command::EndComputePass => (Scope::ComputePass, "EndComputePass"),
+                Command::BindComputePipeline(_) => (Scope::ComputePass, "BindComputePipeline"),
+                Command::Dispatch(_, _, _) => (Scope::ComputePass, "Dispatch"),
+                Command::DispatchIndirect { .. } => (Scope::ComputePass, "DispatchIndirect"),
+                Command::BindGraphicsPipeline(_) => (Scope::RenderPass, "BindGraphicsPipeline"),
+                Command::BindVertexBuffers { .. } => (Scope::RenderPass, "BindVertexBuffers"),
+                Command::BindIndexBuffer { .. } => (Scope::RenderPass, "BindIndexBuffer"),
+                Command::Draw { .. } => (Scope::RenderPass, "Draw"),
+                Command::DrawIndexed { .. } => (Scope::RenderPass, "DrawIndexed"),
+                Command::DrawIndexedIndirect { .. } => (Scope::RenderPass, "DrawIndexedIndirect"),
+                Command::SetDepthTestEnable(_) => (Scope::RenderPass, "SetDepthTestEnable"),
+                Command::SetDepthWriteEnable(_) => (Scope::RenderPass, "SetDepthWriteEnable"),
+                Command::SetCullMode(_) => (Scope::RenderPass, "SetCullMode"),
+                Command::SetFrontFace(_) => (Scope::RenderPass, "SetFrontFace"),
+                Command::PushConstants { .. } => (Scope::RenderPass, "PushConstants"),
+                Command::CopyBufferToBuffer(_) => (Scope::None, "CopyBufferToBuffer"),
+                Command::CopyBufferRegions { .. } => (Scope::None, "CopyBufferRegions"),
+                Command::CopyBufferToTexture { .. } => (Scope::None, "CopyBufferToTexture"),
+                Command::CopyTextureToBuffer { .. } => (Scope::None, "CopyTextureToBuffer"),
+                Command::CopyBufferToTextureBatch(_) => (Scope::None, "CopyBufferToTextureBatch"),
+                Command::GenerateMipmaps { .. } => (Scope::None, "GenerateMipmaps"),
+                Command::BlitToSurfaceImage { .. } => (Scope::None, "BlitToSurfaceImage"),
+                // Valid in any scope
+                Command::BindDescriptorSets { .. } | Command::WriteTimestamp { .. } => continue,
+                Command::Raw(_) => continue,
+            };
+
+            assert_eq!(
+                scope, expected,
+                "command {} (`{}`) was recorded in the wrong pass scope",
+                i, name
+            );
+
+            scope = match command {
+                Command::BeginRenderPass(_) => Scope::RenderPass,
+                Command::BeginComputePass => Scope::ComputePass,
+                Command::EndRenderPass | Command::EndComputePass => Scope::None,
+                _ => scope,
+            };
+        }
+        assert_eq!(scope, Scope::None, "a pass scope was never ended");
+    }
+
+    /// Checks that the descriptor sets bound when dispatching or drawing are compatible with the
+    /// layouts of the bound pipeline. Sets stay bound when a different pipeline is bound, so this
+    /// catches sets bound for one pipeline that are reused by another. Push constant ranges are
+    /// part of pipeline layout compatibility, so sets bound while a pipeline with different push
+    /// constants was bound are caught as well. Set slots that were never bound within the pass
+    /// are not checked.
+    ///
+    /// # Panics
+    /// - If a bound set has a layout that is incompatible with the layout of its slot in the
+    /// bound pipeline.
+    /// - If a bound set was bound while a pipeline with different push constants was bound.
+    pub(crate) fn validate_set_layouts(&self) {
+        let mut bound: Vec<Option<(&DescriptorSet<B>, Option<PushConstantRange>)>> = Vec::default();
+        let mut layouts: &[DescriptorSetLayout<B>] = &[];
+        let mut push_constants = None;
+        for (i, command) in self.commands.iter().enumerate() {
+            match command {
+                Command::BeginRenderPass(_) | Command::BeginComputePass => {
+                    bound.clear();
+                    layouts = &[];
+                    push_constants = None;
+                }
+                Command::BindGraphicsPipeline(pipeline) => {
+                    layouts = pipeline.layouts();
+                    push_constants = pipeline.push_constants();
+                }
+                Command::BindComputePipeline(pipeline) => {
+                    layouts = pipeline.layouts();
+                    push_constants = None;
+                }
+                Command::BindDescriptorSets { sets, first, .. } => {
+                    if bound.len() < first + sets.len() {
+                        bound.resize(first + sets.len(), None);
+                    }
+                    for (j, set) in sets.iter().enumerate() {
+                        bound[first + j] = Some((*set, push_constants));
+                    }
+                }
+                Command::Dispatch(_, _, _)
+                | Command::DispatchIndirect { .. }
+                | Command::Draw { .. }
+                | Command::DrawIndexed { .. }
+                | Command::DrawIndexedIndirect { .. } => {
+                    for (slot, (set, layout)) in bound.iter().zip(layouts).enumerate() {
+                        if let Some((set, bound_push_constants)) = set {
+                            assert!(
+                                set.layout().compatible_with(layout),
+                                "command {} uses a descriptor set bound to slot {} whose layout \
+                                is incompatible with the bound pipeline",
+                                i,
+                                slot
+                            );
+                            assert_eq!(
+                                *bound_push_constants, push_constants,
+                                "command {} uses a descriptor set bound to slot {} while a \
+                                pipeline with different push constants was bound",
+                                i, slot
+                            );
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Checks that copies within a single buffer don't read from and write to overlapping
+    /// regions.
+    ///
+    /// # Panics
+    /// - If a source region of a copy within a single buffer overlaps any of its destination
+    /// regions.
+    pub(crate) fn validate_buffer_copies(&self) {
+        for (i, command) in self.commands.iter().enumerate() {
+            let (src, dst, src_ranges, dst_ranges): (_, _, Vec<_>, Vec<_>) = match command {
+                Command::CopyBufferToBuffer(copy) => (
+                    copy.src,
+                    copy.dst,
+                    vec![element_range(
+                        copy.src,
+                        copy.src_array_element,
+                        copy.src_offset,
+                        copy.len,
+                    )],
+                    vec![element_range(
+                        copy.dst,
+                        copy.dst_array_element,
+                        copy.dst_offset,
+                        copy.len,
+                    )],
+                ),
+                Command::CopyBufferRegions { src, dst, regions } => (
+                    *src,
+                    *dst,
+                    regions
+                        .iter()
+                        .map(|region| {
+                            element_range(
+                                src,
+                                region.src_array_element,
+                                region.src_offset,
+                                region.len,
+                            )
+                        })
+                        .collect(),
+                    regions
+                        .iter()
+                        .map(|region| {
+                            element_range(
+                                dst,
+                                region.dst_array_element,
+                                region.dst_offset,
+                                region.len,
+                            )
+                        })
+                        .collect(),
+                ),
+                _ => continue,
+            };
+
+            assert!(
+                !std::ptr::eq(src, dst) || !copy_regions_overlap(&src_ranges, &dst_ranges),
+                "command {} copies between overlapping regions of the same buffer",
+                i
+            );
+        }
+    }
+
+    /// Checks that the copies of a batch don't write to overlapping regions of the same texture.
+    ///
+    /// # Panics
+    /// - If two copies of a batch write to overlapping regions of the same aspect of a texture
+    /// subresource.
+    pub(crate) fn validate_texture_copies(&self) {
+        for (i, command) in self.commands.iter().enumerate() {
+            let copies = match command {
+                Command::CopyBufferToTextureBatch(copies) => copies,
+                _ => continue,
+            };
+
+            // Group the copies by subresource and aspect, ordered by their horizontal offset, so
+            // each copy only needs to be checked against the copies that start before it ends
+            let mut order: Vec<_> = (0..copies.len()).collect();
+            order.sort_unstable_by_key(|&j| {
+                let (texture, _, copy) = &copies[j];
+                (
+                    *texture as *const Texture<B>,
+                    copy.texture_mip_level,
+                    copy.texture_array_element,
+                    resolved_aspect(texture, copy),
+                    copy.texture_offset.0,
+                )
+            });
+
+            for (k, &a) in order.iter().enumerate() {
+                let (texture, _, copy) = &copies[a];
+                for &b in &order[(k + 1)..] {
+                    let (other_texture, _, other) = &copies[b];
+                    if !std::ptr::eq(*texture, *other_texture)
+                        || copy.texture_mip_level != other.texture_mip_level
+                        || copy.texture_array_element != other.texture_array_element
+                        || resolved_aspect(texture, copy) != resolved_aspect(other_texture, other)
+                        || other.texture_offset.0 >= copy.texture_offset.0 + copy.texture_extent.0
+                    {
+                        break;
+                    }
+
+                    assert!(
+                        !copies_overlap(copy, other),
+                        "command {} writes to overlapping regions of the same texture in copies \
+                        {} and {}",
+                        i,
+                        a.min(b),
+                        a.max(b)
+                    );
+                }
+            }
+        }
+    }
+
+    /// Checks that bound vertex buffers are laid out according to the strides of the most
+    /// recently bound graphics pipeline. Vertex buffers bound before any pipeline are not checked.
+    ///
+    /// Array elements whose size is not a multiple of the stride of their binding are reported as
+    /// a [`Warning`](ValidationSeverity::Warning) through `ctx`, since buffers holding several
+    /// vertex streams at different offsets are valid.
+    ///
+    /// # Panics
+    /// - If a bound array element can't hold a single element past the bind offset.
+    pub(crate) fn validate_vertex_strides(&self, ctx: &Context<B>) {
+        let mut pipeline = None;
+        for (i, command) in self.commands.iter().enumerate() {
+            match command {
+                Command::BindGraphicsPipeline(bound) => pipeline = Some(bound),
+                Command::EndRenderPass => pipeline = None,
+                Command::BindVertexBuffers { first, binds } => {
+                    let pipeline = match pipeline {
+                        Some(pipeline) => pipeline,
+                        None => continue,
+                    };
+
+                    for (j, bind) in binds.iter().enumerate() {
+                        let binding = (first + j) as u32;
+                        let stride = match pipeline
+                            .0
+                            .vertex_bindings
+                            .iter()
+                            .find(|vertex_binding| vertex_binding.binding == binding)
+                        {
+                            Some(vertex_binding) if vertex_binding.stride != 0 => {
+                                vertex_binding.stride as u64
+                            }
+                            _ => continue,
+                        };
+
+                        let size = bind.buffer.size();
+                        assert!(
+                            bind.offset + stride <= size,
+                            "command {} binds a vertex buffer with an element size of {} bytes \
+                            at offset {} to binding {}, which has a stride of {} bytes",
+                            i,
+                            size,
+                            bind.offset,
+                            binding,
+                            stride
+                        );
+                        if !size.is_multiple_of(stride) {
+                            let message = format!(
+                                "command {} binds a vertex buffer with an element size of {} \
+                                bytes to binding {}, which is not a multiple of its stride of {} \
+                                bytes",
+                                i, size, binding, stride
+                            );
+                            unsafe {
+                                ctx.0.report_validation_message(
+                                    ValidationSeverity::Warning,
+                                    &message,
+                                )
+                            };
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Checks that a buffer texture copy only accesses an aspect the texture has.
+#[inline(always)]
+fn assert_copy_aspect(format: TextureFormat, copy: &BufferTextureCopy) {
+    if let Some(aspect) = copy.texture_aspect {
+        assert!(
+            format.has_aspect(aspect),
+            "textures of format `{:?}` have no `{:?}` aspect to copy",
+            format,
+            aspect
+        );
+    }
+}
+
+/// The aspect of `texture` a buffer texture copy accesses.
+#[inline(always)]
+fn resolved_aspect<B: Backend>(texture: &Texture<B>, copy: &BufferTextureCopy) -> TextureAspect {
+    copy.texture_aspect
+        .unwrap_or_else(|| texture.format().default_aspect())
+}
+
+/// Checks if two copies into the same texture subresource write to overlapping texels.
+#[inline(always)]
+fn copies_overlap(a: &BufferTextureCopy, b: &BufferTextureCopy) -> bool {
+    let axis = |a_offset: u32, a_extent: u32, b_offset: u32, b_extent: u32| {
+        a_offset < b_offset + b_extent && b_offset < a_offset + a_extent
+    };
+    axis(
+        a.texture_offset.0,
+        a.texture_extent.0,
+        b.texture_offset.0,
+        b.texture_extent.0,
+    ) && axis(
+        a.texture_offset.1,
+        a.texture_extent.1,
+        b.texture_offset.1,
+        b.texture_extent.1,
+    ) && axis(
+        a.texture_offset.2,
+        a.texture_extent.2,
+        b.texture_offset.2,
+        b.texture_extent.2,
+    )
+}
+
+/// The byte range of a region of a buffer array element. Array elements never share memory, so
+/// each one is treated as starting `size` bytes after the previous.
+#[inline(always)]
+fn element_range<B: Backend>(
+    buffer: &Buffer<B>,
+    array_element: usize,
+    offset: u64,
+    len: u64,
+) -> Range<u64> {
+    let start = array_element as u64 * buffer.size() + offset;
+    start..(start + len)
+}
+
+/// Checks if any of the `src` byte ranges overlap any of the `dst` byte ranges of a copy within a
+/// single buffer.
+fn copy_regions_overlap(src: &[Range<u64>], dst: &[Range<u64>]) -> bool {
+    // Sort destinations by their start, tracking the furthest end seen so far, so each source
+    // only needs to check the destinations that start before it ends
+    let mut dst = dst.to_vec();
+    dst.sort_unstable_by_key(|range| range.start);
+    let max_ends: Vec<_> = dst
+        .iter()
+        .scan(0, |max_end, range| {
+            *max_end = range.end.max(*max_end);
+            Some(*max_end)
+        })
+        .collect();
+
+    src.iter().any(|range| {
+        let before = dst.partition_point(|dst| dst.start < range.end);
+        before != 0 && max_ends[before - 1] > range.start
+    })
 }
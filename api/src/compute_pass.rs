@@ -1,6 +1,6 @@
 use crate::{
-    command_buffer::Command, compute_pipeline::ComputePipeline, descriptor_set::DescriptorSet,
-    types::ShaderStage, Backend,
+    buffer::Buffer, command_buffer::Command, compute_pipeline::ComputePipeline,
+    descriptor_set::DescriptorSet, types::ShaderStage, Backend,
 };
 
 pub struct ComputePass<'a, B: Backend> {
@@ -56,4 +56,42 @@ impl<'a, B: Backend> ComputePass<'a, B> {
         assert!(self.bound_pipeline, "no bound compute pipeline");
         self.commands.push(Command::Dispatch(x, y, z));
     }
+
+    /// Dispatches local workgroups with the workgroup counts contained within an indirect buffer.
+    ///
+    /// # Arguments
+    /// - `buffer` - The indirect buffer to read the workgroup counts from.
+    /// - `array_element` - The array element of the indirect buffer to read from.
+    /// - `offset` - The offset in bytes within the array element to read from.
+    ///
+    /// # Panics
+    /// - If there is no bound compute pipeline.
+    /// - If `offset` is not a multiple of 4.
+    ///
+    /// # Valid Usage
+    /// - `buffer` *must* have been created with the `INDIRECT_BUFFER` usage flag.
+    /// - The array element of `buffer` *must* contain a `DispatchIndirect` command at `offset`.
+    #[inline]
+    pub fn dispatch_indirect(&mut self, buffer: &'a Buffer<B>, array_element: usize, offset: u64) {
+        assert!(self.bound_pipeline, "no bound compute pipeline");
+        assert_eq!(offset % 4, 0, "offset must be a multiple of 4");
+        self.commands.push(Command::DispatchIndirect {
+            buffer,
+            array_element,
+            offset,
+        });
+    }
+
+    /// Records a backend specific command within the compute pass.
+    ///
+    /// # Arguments
+    /// - `command` - The command to record.
+    ///
+    /// # Safety
+    /// See [`CommandBuffer::raw`](crate::command_buffer::CommandBuffer::raw).
+    #[cfg(feature = "raw")]
+    #[inline(always)]
+    pub unsafe fn raw(&mut self, command: B::RawCommand) {
+        self.commands.push(Command::Raw(command));
+    }
 }
@@ -1,10 +1,43 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use crate::{queue::Queue, types::QueueType, Backend};
+use crate::{
+    buffer::{Buffer, BufferCreateError, BufferCreateInfo, BufferMemoryRegion},
+    command_buffer::CopyBufferToBuffer,
+    compute_pipeline::ComputePipeline,
+    descriptor_set::{DescriptorSet, DescriptorSetLayout},
+    graphics_pipeline::{
+        ColorBlendAttachment, ColorBlendState, GraphicsPipeline, GraphicsPipelineCreateError,
+        GraphicsPipelineCreateInfo, MultisampleState, RasterizationState, ShaderStages,
+        VertexInputState,
+    },
+    queue::{Job, Queue},
+    render_pass::{ColorAttachmentSource, RenderPassDescriptor},
+    shader::{Shader, ShaderCreateInfo},
+    texture::{SparseBindError, SparseTextureBind},
+    types::{
+        BufferUsage, ColorComponents, CullMode, MemoryUsage, QueueType, SharingMode, TextureFormat,
+        TextureUsage, ValidationMessage,
+    },
+    Backend,
+};
 
 /// The context is the entry point for Pal. It is used to create all other Pal objects.
 ///
 /// The context also provides you with a selection of four [`Queues`](Queue).
+///
+/// # Sharing
+///
+/// Cloning a context is cheap and shares the backend, including its device, memory allocator, and
+/// queues. Independent subsystems (e.g. a UI renderer and a scene renderer) should each hold a
+/// clone of the same context instead of creating their own backend. Subsystems that only receive
+/// the backend can create a context for it with [`from_shared`](Context::from_shared).
+///
+/// # Drop Order
+///
+/// Every object created from a context holds its own clone of it, so the backend is only dropped
+/// once the last context *and* the last object created from it are gone. Dropping your context
+/// before your resources is therefore always valid; the resources are destroyed through the
+/// backend when they are dropped, and the backend is destroyed after them.
 pub struct Context<B: Backend>(pub(crate) Arc<B>);
 
 impl<B: Backend> Context<B> {
@@ -19,6 +52,32 @@ impl<B: Backend> Context<B> {
         Self(Arc::new(backend))
     }
 
+    /// Creates a context that shares an existing backend. Every context sharing a backend sees
+    /// the same queues and resources, so work submitted through one is synchronized with work
+    /// submitted through the others.
+    ///
+    /// # Arguments
+    /// - `backend` - The backend to share, usually obtained from
+    /// [`shared_backend`](Context::shared_backend).
+    #[inline(always)]
+    pub fn from_shared(backend: Arc<B>) -> Self {
+        Self(backend)
+    }
+
+    /// Gets a shared handle to the backend of the context. The backend is kept alive for as long
+    /// as the handle exists.
+    #[inline(always)]
+    pub fn shared_backend(&self) -> Arc<B> {
+        self.0.clone()
+    }
+
+    /// Gets the backend the context was created with. Useful for backend specific functionality
+    /// not exposed by the context.
+    #[inline(always)]
+    pub fn backend(&self) -> &B {
+        &self.0
+    }
+
     /// Gets a reference to the primary queue.
     ///
     /// # Supported Commands
@@ -61,6 +120,347 @@ impl<B: Backend> Context<B> {
     pub fn present(&self) -> Queue<B> {
         Queue::new(self.clone(), QueueType::Present)
     }
+
+    /// Checks, without blocking, if all work submitted to every queue has completed.
+    #[inline(always)]
+    pub fn all_work_complete(&self) -> bool {
+        unsafe { self.0.all_work_complete() }
+    }
+
+    /// Waits for all work submitted to every queue, up to the time of the call, to complete.
+    /// Returns `true` if all work completed before the timeout was reached.
+    ///
+    /// Unlike waiting for the device to go idle, work submitted by other threads after this call
+    /// is not waited on.
+    ///
+    /// # Arguments
+    /// - `timeout` - The time to wait, or `None` if there should be no timeout.
+    #[inline(always)]
+    pub fn wait_all_work(&self, timeout: Option<Duration>) -> bool {
+        unsafe { self.0.wait_all_work(timeout) }
+    }
+
+    /// The number of nanoseconds it takes for a timestamp query value to be incremented by one.
+    #[inline(always)]
+    pub fn timestamp_period(&self) -> f32 {
+        unsafe { self.0.timestamp_period() }
+    }
+
+    /// Creates a new buffer placed in the memory of an existing buffer. See
+    /// [`Buffer::new_aliased`] for details.
+    ///
+    /// # Arguments
+    /// - `create_info` - Describes the buffer to create.
+    /// - `region` - The memory to place the buffer in.
+    #[inline(always)]
+    pub fn create_aliased_buffer(
+        &self,
+        create_info: BufferCreateInfo,
+        region: BufferMemoryRegion<B>,
+    ) -> Result<Buffer<B>, BufferCreateError> {
+        Buffer::new_aliased(self.clone(), create_info, region)
+    }
+
+    /// Dispatches a compute pipeline on the [`main`](Context::main) queue, copies an array element
+    /// of the buffer it writes to back to the CPU, and blocks until the results are available.
+    ///
+    /// This composes a compute pass, a buffer copy, and a readback into a single call, which is
+    /// mostly useful for tests and tools.
+    ///
+    /// # Arguments
+    /// - `pipeline` - The compute pipeline to dispatch.
+    /// - `sets` - The descriptor sets to bind, starting at set `0`. May be empty.
+    /// - `groups` - The number of local workgroups to dispatch in the X, Y, and Z dimensions.
+    /// - `output` - The buffer written to by the pipeline. *Must* have been created with
+    /// [`TRANSFER_SRC`](BufferUsage::TRANSFER_SRC) usage.
+    /// - `output_array_element` - The array element of `output` to read back.
+    ///
+    /// # Errors
+    /// - Returns an error if the buffer used for the readback could not be created.
+    ///
+    /// # Panics
+    /// - If `output_array_element` is out of bounds for `output`.
+    pub fn compute_readback(
+        &self,
+        pipeline: &ComputePipeline<B>,
+        sets: &[&DescriptorSet<B>],
+        groups: (u32, u32, u32),
+        output: &Buffer<B>,
+        output_array_element: usize,
+    ) -> Result<Vec<u8>, BufferCreateError> {
+        assert!(
+            output_array_element < output.array_elements(),
+            "`output_array_element` is out of bounds"
+        );
+
+        let mut readback = Buffer::new(
+            self.clone(),
+            BufferCreateInfo {
+                size: output.size(),
+                array_elements: 1,
+                buffer_usage: BufferUsage::TRANSFER_DST,
+                memory_usage: MemoryUsage::GpuToCpu,
+                sharing: SharingMode::Exclusive,
+                debug_name: Some(String::from("compute_readback")),
+            },
+        )?;
+
+        let job = self.main().submit(Some("compute_readback"), |commands| {
+            commands.compute_pass(|pass| {
+                pass.bind_pipeline(pipeline.clone());
+                if !sets.is_empty() {
+                    pass.bind_sets(0, sets.to_vec());
+                }
+                pass.dispatch(groups.0, groups.1, groups.2);
+            });
+            commands.copy_buffer_to_buffer(CopyBufferToBuffer {
+                src: output,
+                src_array_element: output_array_element,
+                src_offset: 0,
+                dst: &readback,
+                dst_array_element: 0,
+                dst_offset: 0,
+                len: output.size(),
+            });
+        });
+        job.wait_on(None);
+
+        let view = readback.read(0).unwrap();
+        Ok(view.as_slice()[..output.size() as usize].to_vec())
+    }
+
+    /// Creates a graphics pipeline for fullscreen passes, such as post-processing. The pipeline
+    /// uses a vertex shader provided by the backend that draws a single triangle covering the
+    /// screen without any vertex inputs. The fragment shader receives texture coordinates at
+    /// location `0`, with `(0, 0)` at the top left, and writes to a single color attachment.
+    ///
+    /// Use the pipeline with
+    /// [`CommandBuffer::fullscreen_pass`](crate::command_buffer::CommandBuffer::fullscreen_pass),
+    /// or bind it in a render pass and draw `3` vertices.
+    ///
+    /// # Arguments
+    /// - `fragment` - The fragment shader to run for every pixel.
+    /// - `layouts` - The layouts of the descriptor sets used by `fragment`.
+    ///
+    /// # Errors
+    /// - Returns an error if the vertex shader or the pipeline could not be created.
+    pub fn create_fullscreen_pipeline(
+        &self,
+        fragment: Shader<B>,
+        layouts: Vec<DescriptorSetLayout<B>>,
+    ) -> Result<GraphicsPipeline<B>, GraphicsPipelineCreateError> {
+        let vertex = Shader::new(
+            self.clone(),
+            ShaderCreateInfo {
+                code: unsafe { self.0.fullscreen_vertex_shader() },
+                debug_name: Some(String::from("fullscreen_vertex")),
+            },
+        )
+        .map_err(|err| GraphicsPipelineCreateError::Other(err.to_string()))?;
+
+        GraphicsPipeline::new(
+            self.clone(),
+            GraphicsPipelineCreateInfo {
+                stages: ShaderStages {
+                    vertex,
+                    fragment: Some(fragment),
+                },
+                layouts,
+                vertex_input: VertexInputState::default(),
+                rasterization: RasterizationState {
+                    cull_mode: CullMode::None,
+                    ..Default::default()
+                },
+                multisample: MultisampleState::default(),
+                depth_stencil: None,
+                color_blend: Some(ColorBlendState {
+                    attachments: vec![ColorBlendAttachment {
+                        write_mask: ColorComponents::all(),
+                        ..Default::default()
+                    }],
+                }),
+                attachment_formats: None,
+                push_constants: None,
+                debug_name: Some(String::from("fullscreen_pipeline")),
+            },
+        )
+    }
+
+    /// Runs a fragment shader for every pixel of `target` on the [`main`](Context::main) queue.
+    ///
+    /// This creates a [`fullscreen pipeline`](Context::create_fullscreen_pipeline) on every call.
+    /// When the same pass runs repeatedly (e.g. every frame), create the pipeline once and record
+    /// it with
+    /// [`CommandBuffer::fullscreen_pass`](crate::command_buffer::CommandBuffer::fullscreen_pass)
+    /// instead.
+    ///
+    /// # Arguments
+    /// - `fragment` - The fragment shader to run for every pixel.
+    /// - `target` - The color attachment to draw to.
+    /// - `sets` - The descriptor sets used by `fragment`, bound starting at set `0`. May be empty.
+    ///
+    /// # Errors
+    /// - Returns an error if the pipeline could not be created.
+    pub fn fullscreen_pass(
+        &self,
+        fragment: Shader<B>,
+        target: ColorAttachmentSource<B>,
+        sets: &[&DescriptorSet<B>],
+    ) -> Result<Job<B>, GraphicsPipelineCreateError> {
+        let layouts = sets.iter().map(|set| set.layout().clone()).collect();
+        let pipeline = self.create_fullscreen_pipeline(fragment, layouts)?;
+        Ok(self.main().submit(Some("fullscreen_pass"), |commands| {
+            commands.fullscreen_pass(pipeline, target, sets);
+        }))
+    }
+
+    /// Informs the backend of how many frames the application keeps in flight. Resources that
+    /// are destroyed are held for at least this many presented frames in addition to waiting for
+    /// the GPU to stop using them. The default is `0`, in which case resources are destroyed as
+    /// soon as the GPU has finished using them.
+    ///
+    /// # Arguments
+    /// - `frames_in_flight` - The number of frames in flight.
+    #[inline(always)]
+    pub fn set_frames_in_flight(&self, frames_in_flight: usize) {
+        unsafe { self.0.set_frames_in_flight(frames_in_flight) }
+    }
+
+    /// Destroys resources that were dropped and are no longer in use by the GPU.
+    ///
+    /// Backends *should* collect garbage during submissions by default, in which case calling
+    /// this is optional. Backends that allow deferring garbage collection out of submissions to
+    /// reduce their latency only reclaim memory when this is called, so it *must* then be called
+    /// periodically (e.g. once per frame).
+    #[inline(always)]
+    pub fn collect_garbage(&self) {
+        unsafe { self.0.collect_garbage() }
+    }
+
+    /// Backends *may* create a variant of each graphics pipeline for every kind of render pass
+    /// it is used in, and keep the variants until the pipeline is destroyed. This frees the
+    /// variants that have not been used within the last `frames` presented frames, which bounds
+    /// the memory used by pipelines in applications that render into many transient render
+    /// passes. Freed variants are recreated if they are needed again.
+    ///
+    /// Variants used by [`prebuilt commands`](crate::queue::Queue::record) are never freed, since
+    /// the commands can be resubmitted at any time. Variants that are freed are destroyed once
+    /// the GPU has finished using them.
+    ///
+    /// # Arguments
+    /// - `frames` - The number of most recent frames whose variants are kept.
+    #[inline(always)]
+    pub fn trim_pipeline_variants(&self, frames: usize) {
+        unsafe { self.0.trim_pipeline_variants(frames) }
+    }
+
+    /// Removes and returns the messages reported by the debugging layers of the backend since the
+    /// last call, oldest first. Backends *should* only collect messages when debugging is
+    /// enabled, and *may* only keep a limited number of the most recent messages.
+    ///
+    /// This is mostly useful in tests to assert that a workload produced no errors.
+    #[inline(always)]
+    pub fn drain_validation_messages(&self) -> Vec<ValidationMessage> {
+        unsafe { self.0.drain_validation_messages() }
+    }
+
+    /// Checks if textures of the given format can be created with the given usage. Support for
+    /// some usages, such as [`STORAGE`](TextureUsage::STORAGE), varies between devices.
+    ///
+    /// # Arguments
+    /// - `format` - The texture format to check.
+    /// - `usage` - The usage to check for.
+    #[inline(always)]
+    pub fn supports_texture_usage(&self, format: TextureFormat, usage: TextureUsage) -> bool {
+        unsafe { self.0.supports_texture_usage(format, usage) }
+    }
+
+    /// Checks if textures of the given format can have their mip levels generated on the GPU
+    /// (see [`CommandBuffer::generate_mipmaps`](crate::command_buffer::CommandBuffer::generate_mipmaps)).
+    ///
+    /// # Arguments
+    /// - `format` - The texture format to check.
+    #[inline(always)]
+    pub fn supports_mipmap_generation(&self, format: TextureFormat) -> bool {
+        unsafe { self.0.supports_mipmap_generation(format) }
+    }
+
+    /// Checks if depth and culling state can be changed dynamically within render passes (e.g.
+    /// with [`RenderPass::set_cull_mode`](crate::render_pass::RenderPass::set_cull_mode)).
+    #[inline(always)]
+    pub fn supports_extended_dynamic_state(&self) -> bool {
+        unsafe { self.0.supports_extended_dynamic_state() }
+    }
+
+    /// Checks if the device supports [`sparse textures`](crate::texture::Texture::new_sparse).
+    #[inline(always)]
+    pub fn supports_sparse_textures(&self) -> bool {
+        unsafe { self.0.supports_sparse_textures() }
+    }
+
+    /// Makes pages of sparse textures resident or releases their memory.
+    ///
+    /// # Arguments
+    /// - `binds` - The pages to bind or unbind. Binding a page that is already resident, or
+    /// unbinding one that isn't, does nothing.
+    ///
+    /// # Synchronization
+    /// Binding is performed on the [`main`](Context::main) queue after all work previously
+    /// submitted to it, and after any work on other queues that uses the textures. Work
+    /// submitted after this call is synchronized by the backend. The contents of newly resident
+    /// pages are undefined until written to.
+    ///
+    /// # Errors
+    /// - Returns an error if memory could not be allocated for a page or the binding failed. No
+    /// page is bound or unbound in that case.
+    ///
+    /// # Panics
+    /// - If any texture wasn't created with [`Texture::new_sparse`](crate::texture::Texture::new_sparse).
+    /// - If any array element, mip level, or page is out of bounds, or a mip level is part of the
+    /// mip tail.
+    pub fn bind_sparse(&self, binds: &[SparseTextureBind<B>]) -> Result<Job<B>, SparseBindError> {
+        for bind in binds {
+            let sparse = bind
+                .texture
+                .sparse_properties()
+                .expect("texture is not sparse");
+            assert!(
+                bind.array_element < bind.texture.array_elements(),
+                "array element is out of bounds"
+            );
+            assert!(
+                bind.mip_level < sparse.mip_tail_first_level,
+                "mip level is out of bounds or part of the mip tail"
+            );
+            let (width, height, depth) = bind.texture.dims();
+            let mip_dim = |dim: u32, page_dim: u32| {
+                let dim = (dim >> bind.mip_level).max(1);
+                dim.div_ceil(page_dim)
+            };
+            assert!(
+                bind.page.0 < mip_dim(width, sparse.page_dims.0)
+                    && bind.page.1 < mip_dim(height, sparse.page_dims.1)
+                    && bind.page.2 < mip_dim(depth, sparse.page_dims.2),
+                "page is out of bounds"
+            );
+        }
+
+        let id = unsafe { self.0.bind_sparse(binds)? };
+        Ok(Job::new(self.clone(), id))
+    }
+
+    /// Creates the backend objects needed to begin a render pass with the given descriptor ahead
+    /// of time. Backends *should* otherwise create these objects the first time a render pass
+    /// with a new combination of attachments is used, which can cause a hitch.
+    ///
+    /// Only the formats, load/store operations, and attachments of the descriptor are considered.
+    ///
+    /// # Arguments
+    /// - `descriptor` - Describes the render pass to prepare for.
+    #[inline(always)]
+    pub fn prewarm_framebuffer(&self, descriptor: &RenderPassDescriptor<B>) {
+        unsafe { self.0.prewarm_framebuffer(descriptor) }
+    }
 }
 
 impl<B: Backend> Clone for Context<B> {
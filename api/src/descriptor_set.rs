@@ -12,6 +12,10 @@ use crate::{
 pub struct DescriptorSetCreateInfo<B: Backend> {
     /// The layout to create the set with.
     pub layout: DescriptorSetLayout<B>,
+    /// The number of descriptors in the variable count binding of the layout. Must be `Some` if,
+    /// and only if, the layout was created with
+    /// [`variable_count`](DescriptorSetLayoutCreateInfo::variable_count).
+    pub variable_count: Option<usize>,
     /// The backend *should* use the provided debug name for easy identification.
     pub debug_name: Option<String>,
 }
@@ -20,6 +24,13 @@ pub struct DescriptorSetCreateInfo<B: Backend> {
 pub struct DescriptorSetLayoutCreateInfo {
     /// The bindings of this set.
     pub bindings: Vec<DescriptorBinding>,
+    /// If `true`, the binding with the highest binding number has a variable number of
+    /// descriptors, chosen when each set is created. Its `count` is the maximum number of
+    /// descriptors. Descriptors of the variable count binding need not all be bound, which makes
+    /// it suitable for bindless texture arrays. Not every device supports variable counts, in
+    /// which case creating the layout fails with
+    /// [`VariableCountUnsupported`](DescriptorSetLayoutCreateError::VariableCountUnsupported).
+    pub variable_count: bool,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -46,6 +57,8 @@ pub enum DescriptorType {
 
 #[derive(Debug, Error)]
 pub enum DescriptorSetLayoutCreateError {
+    #[error("variable descriptor counts are not supported")]
+    VariableCountUnsupported,
     #[error("an error has occured: {0}")]
     Other(String),
 }
@@ -61,6 +74,8 @@ pub struct DescriptorSetLayout<B: Backend>(Arc<DescriptorSetLayoutInner<B>>);
 pub struct DescriptorSet<B: Backend> {
     ctx: Context<B>,
     layout: DescriptorSetLayout<B>,
+    /// The number of descriptors in the variable count binding, if the layout has one.
+    variable_count: Option<usize>,
     pub(crate) id: B::DescriptorSet,
 }
 
@@ -73,23 +88,41 @@ pub struct DescriptorSetUpdate<'a, B: Backend> {
     pub value: DescriptorValue<'a, B>,
 }
 
+/// Describes the portion of a buffer bound to a descriptor.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum BufferRange {
+    /// Binds exactly one array element.
+    #[default]
+    Element,
+    /// Binds `len` bytes beginning `offset` bytes after the start of the array element. The range
+    /// may extend past the end of the array element into the elements that follow it.
+    Bytes { offset: u64, len: u64 },
+    /// Binds everything from the start of the array element to the end of the buffer. Useful
+    /// for shaders with runtime-sized arrays.
+    Remaining,
+}
+
 pub enum DescriptorValue<'a, B: Backend> {
     UniformBuffer {
         /// The uniform buffer to bind.
         buffer: &'a Buffer<B>,
         /// The array element of the uniform buffer to bind.
         array_element: usize,
+        /// The portion of the buffer to bind, relative to the start of `array_element`.
+        range: BufferRange,
     },
     StorageBuffer {
         /// The storage buffer to bind.
         buffer: &'a Buffer<B>,
         /// The array element of the storage buffer to bind.
         array_element: usize,
+        /// The portion of the buffer to bind, relative to the start of `array_element`.
+        range: BufferRange,
     },
     Texture {
         /// The texture to bind.
         texture: &'a Texture<B>,
-        /// The array element of the texture to bind.
+        /// The array element of the texture to bind. Only this layer is visible to the shader.
         array_element: usize,
         /// How the texture should be sampled.
         sampler: Sampler,
@@ -102,6 +135,11 @@ pub enum DescriptorValue<'a, B: Backend> {
 
 pub(crate) struct DescriptorSetLayoutInner<B: Backend> {
     ctx: Context<B>,
+    /// The create info of the layout with bindings sorted by binding number. Used to check
+    /// compatibility.
+    create_info: DescriptorSetLayoutCreateInfo,
+    /// The maximum number of descriptors in the variable count binding, if there is one.
+    max_variable_count: Option<usize>,
     pub(crate) id: B::DescriptorSetLayout,
 }
 
@@ -111,14 +149,38 @@ impl<B: Backend> DescriptorSet<B> {
     /// # Arguments
     /// - `ctx` - The [`Context`] to create the buffer with.
     /// - `create_info` - Describes the descriptor set to create.
+    ///
+    /// # Panics
+    /// - If `create_info.variable_count` is `Some` but the layout has no variable count binding,
+    /// or if it is `None` but the layout has one.
+    /// - If `create_info.variable_count` is greater than the `count` of the variable count
+    /// binding.
     #[inline(always)]
     pub fn new(
         ctx: Context<B>,
         create_info: DescriptorSetCreateInfo<B>,
     ) -> Result<Self, DescriptorSetCreateError> {
+        match (
+            create_info.layout.0.max_variable_count,
+            create_info.variable_count,
+        ) {
+            (Some(max), Some(count)) => assert!(
+                count <= max,
+                "`variable_count` is greater than the count of the variable count binding"
+            ),
+            (None, None) => {}
+            (Some(_), None) => panic!("the layout requires a `variable_count`"),
+            (None, Some(_)) => panic!("the layout has no variable count binding"),
+        }
         let layout = create_info.layout.clone();
+        let variable_count = create_info.variable_count;
         let id = unsafe { ctx.0.create_descriptor_set(create_info)? };
-        Ok(Self { ctx, layout, id })
+        Ok(Self {
+            ctx,
+            layout,
+            variable_count,
+            id,
+        })
     }
 
     #[inline(always)]
@@ -137,18 +199,107 @@ impl<B: Backend> DescriptorSet<B> {
     /// - `updates` - The updates to perform on the set.
     ///
     /// # Panics
-    /// TODO
+    /// - If an update refers to a binding that does not exist in the layout of the set.
+    /// - If an update refers to an `array_element` that is out of bounds for its binding. The
+    /// variable count binding has as many array elements as the set was created with.
+    /// - If a texture update refers to an `array_element` that is out of bounds for the texture.
+    /// - If a texture update refers to a mip range that is out of bounds for the texture.
+    /// - If a buffer update binds a range that is empty, out of bounds for the buffer, larger than
+    /// the maximum uniform or storage buffer range of the backend, or whose offset does not meet
+    /// the uniform or storage buffer offset alignment of the backend.
     ///
     /// # Synchronization
     /// The backend *must* ensure that the descriptor set is not being accessed by any queue at the
     /// time of the update.
     pub fn update(&mut self, updates: &[DescriptorSetUpdate<B>]) {
+        for update in updates {
+            let count = self.binding_count(update.binding);
+            assert!(
+                update.array_element < count,
+                "array element {} is out of bounds for binding {}, which has {} descriptors",
+                update.array_element,
+                update.binding,
+                count
+            );
+
+            if let DescriptorValue::Texture {
+                texture,
+                array_element,
+                base_mip,
+                mip_count,
+                ..
+            } = &update.value
+            {
+                assert!(
+                    *array_element < texture.array_elements(),
+                    "texture array element is out of bounds"
+                );
+                assert!(
+                    *base_mip + *mip_count <= texture.mip_count(),
+                    "texture mip range is out of bounds"
+                );
+            }
+        }
+
         unsafe {
             self.ctx
                 .0
                 .update_descriptor_sets(&mut self.id, &self.layout.0.id, updates);
         }
     }
+
+    /// Gets the number of descriptors in a binding of the set.
+    ///
+    /// # Panics
+    /// - If `binding` does not exist in the layout of the set.
+    fn binding_count(&self, binding: u32) -> usize {
+        let bindings = &self.layout.0.create_info.bindings;
+        let idx = bindings
+            .iter()
+            .position(|b| b.binding == binding)
+            .unwrap_or_else(|| {
+                panic!(
+                    "binding {} does not exist in the layout of the set",
+                    binding
+                )
+            });
+
+        // Bindings are sorted, so the variable count binding is the last one
+        match self.variable_count {
+            Some(count) if idx == bindings.len() - 1 => count,
+            _ => bindings[idx].count,
+        }
+    }
+
+    /// Updates a contiguous range of array elements of a single binding. The backend *should*
+    /// write the whole range at once, which is cheaper than updating each element individually
+    /// for large arrays (e.g. texture arrays).
+    ///
+    /// # Arguments
+    /// - `binding` - The binding to update within the set.
+    /// - `first_array_element` - The array element of the first value.
+    /// - `values` - The values to update the binding with. Each value is written to the array
+    /// element following the previous one.
+    ///
+    /// # Panics
+    /// - See [`DescriptorSet::update`].
+    pub fn update_array<'a>(
+        &mut self,
+        binding: u32,
+        first_array_element: usize,
+        values: impl IntoIterator<Item = DescriptorValue<'a, B>>,
+    ) {
+        let updates: Vec<_> = values
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| DescriptorSetUpdate {
+                binding,
+                array_element: first_array_element + i,
+                value,
+            })
+            .collect();
+        self.update(&updates);
+    }
 }
 
 impl<B: Backend> Drop for DescriptorSet<B> {
@@ -161,19 +312,55 @@ impl<B: Backend> Drop for DescriptorSet<B> {
 }
 
 impl<B: Backend> DescriptorSetLayout<B> {
-    #[inline(always)]
+    /// Creates a new descriptor set layout.
+    ///
+    /// # Arguments
+    /// - `ctx` - The [`Context`] to create the layout with.
+    /// - `create_info` - Describes the layout to create.
+    ///
+    /// # Panics
+    /// - If `create_info.variable_count` is `true` but there are no bindings.
     pub fn new(
         ctx: Context<B>,
         create_info: DescriptorSetLayoutCreateInfo,
     ) -> Result<Self, DescriptorSetLayoutCreateError> {
+        let max_variable_count = if create_info.variable_count {
+            let binding = create_info
+                .bindings
+                .iter()
+                .max_by_key(|binding| binding.binding)
+                .expect("a variable count layout must have at least one binding");
+            Some(binding.count)
+        } else {
+            None
+        };
+        let mut sorted = create_info.clone();
+        sorted
+            .bindings
+            .sort_unstable_by_key(|binding| binding.binding);
         let id = unsafe { ctx.0.create_descriptor_set_layout(create_info)? };
-        Ok(Self(Arc::new(DescriptorSetLayoutInner { ctx, id })))
+        Ok(Self(Arc::new(DescriptorSetLayoutInner {
+            ctx,
+            create_info: sorted,
+            max_variable_count,
+            id,
+        })))
     }
 
     #[inline(always)]
     pub fn internal(&self) -> &B::DescriptorSetLayout {
         &self.0.id
     }
+
+    /// Checks if sets created with this layout can be used where `other` is expected. Two layouts
+    /// are compatible if they were created with the same bindings, regardless of binding order.
+    ///
+    /// # Arguments
+    /// - `other` - The layout to compare against.
+    #[inline]
+    pub fn compatible_with(&self, other: &DescriptorSetLayout<B>) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0.create_info == other.0.create_info
+    }
 }
 
 impl<B: Backend> Clone for DescriptorSetLayout<B> {
@@ -56,6 +56,21 @@ pub struct RasterizationState {
     pub cull_mode: CullMode,
     /// Which direction represents the front face of a primitive.
     pub front_face: FrontFace,
+    /// If `true`, primitives are discarded right before rasterization, so only vertex processing
+    /// is performed and no fragments are produced. Useful for vertex-only passes.
+    pub rasterizer_discard: bool,
+}
+
+/// Describes multisampling behavior for a graphics pipeline.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct MultisampleState {
+    /// If provided, enables sample shading with the given minimum fraction of samples that must
+    /// be shaded uniquely. Must be within `0.0..=1.0`.
+    ///
+    /// Requires the backend to support sample rate shading.
+    pub sample_shading: Option<f32>,
+    /// Should the alpha component of the first color output be used to generate a coverage mask.
+    pub alpha_to_coverage: bool,
 }
 
 /// Describes depth testing rules for a graphics pipeline.
@@ -95,6 +110,25 @@ pub struct ColorBlendAttachment {
     pub dst_alpha_blend_factor: BlendFactor,
 }
 
+/// Formats of the attachments of the render passes a graphics pipeline is used in.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct AttachmentFormats {
+    /// The format of each color attachment, in order.
+    pub color: Vec<TextureFormat>,
+    /// The format of the depth stencil attachment, if there is one.
+    pub depth_stencil: Option<TextureFormat>,
+}
+
+/// Push constants available to the shaders of a pipeline.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PushConstantRange {
+    /// The shader stages that can read the push constants.
+    pub stage: ShaderStage,
+    /// The size of the push constants in bytes. Must be a non-zero multiple of 4, and no larger
+    /// than the device supports.
+    pub size: u32,
+}
+
 /// Blending for color attachments.
 #[derive(Default, Clone)]
 pub struct ColorBlendState {
@@ -108,8 +142,17 @@ pub struct GraphicsPipelineCreateInfo<B: Backend> {
     pub layouts: Vec<DescriptorSetLayout<B>>,
     pub vertex_input: VertexInputState,
     pub rasterization: RasterizationState,
+    pub multisample: MultisampleState,
+    /// When the pipeline is used in a render pass without a depth stencil attachment, depth
+    /// testing and writing are disabled regardless of this state.
     pub depth_stencil: Option<DepthStencilState>,
     pub color_blend: Option<ColorBlendState>,
+    /// If provided, the backend *should* create the pipeline for render passes with these
+    /// attachments up front instead of when the pipeline is first bound in such a render pass.
+    /// The pipeline can still be used in render passes with other attachments.
+    pub attachment_formats: Option<AttachmentFormats>,
+    /// Push constants available to the pipeline, if any.
+    pub push_constants: Option<PushConstantRange>,
     /// The backend *should* use the provided debug name for easy identification.
     pub debug_name: Option<String>,
 }
@@ -119,6 +162,10 @@ pub struct GraphicsPipeline<B: Backend>(pub(crate) Arc<GraphicsPipelineInner<B>>
 pub(crate) struct GraphicsPipelineInner<B: Backend> {
     ctx: Context<B>,
     pub(crate) layouts: Vec<DescriptorSetLayout<B>>,
+    pub(crate) vertex_bindings: Vec<VertexInputBinding>,
+    /// Does the pipeline write to depth. Used to validate read-only depth attachments.
+    pub(crate) depth_write: bool,
+    pub(crate) push_constants: Option<PushConstantRange>,
     pub(crate) id: B::GraphicsPipeline,
 }
 
@@ -128,6 +175,10 @@ pub enum GraphicsPipelineCreateError {
     NoAttributesOrBindings,
     #[error("no depth/stencil or color attachments provided")]
     NoAttachments,
+    #[error("sample shading was requested but is not supported")]
+    SampleShadingUnsupported,
+    #[error("push constants of {0} bytes exceed the maximum size supported by the device")]
+    PushConstantsTooLarge(u32),
     #[error("an error occured: {0}")]
     Other(String),
 }
@@ -138,13 +189,41 @@ impl<B: Backend> GraphicsPipeline<B> {
     /// # Arguments
     /// - `ctx` - The [`Context`] to create the buffer with.
     /// - `create_info` - Describes the graphics pipeline to create.
+    ///
+    /// # Panics
+    /// - If `create_info.multisample.sample_shading` is not within `0.0..=1.0`.
+    /// - If the size of `create_info.push_constants` is `0` or not a multiple of 4.
     pub fn new(
         ctx: Context<B>,
         create_info: GraphicsPipelineCreateInfo<B>,
     ) -> Result<Self, GraphicsPipelineCreateError> {
+        if let Some(min_sample_shading) = create_info.multisample.sample_shading {
+            assert!(
+                (0.0..=1.0).contains(&min_sample_shading),
+                "minimum sample shading must be within 0 and 1"
+            );
+        }
+        if let Some(push_constants) = create_info.push_constants {
+            assert!(
+                push_constants.size != 0 && push_constants.size % 4 == 0,
+                "push constant size must be a non-zero multiple of 4"
+            );
+        }
         let layouts = create_info.layouts.clone();
+        let push_constants = create_info.push_constants;
+        let vertex_bindings = create_info.vertex_input.bindings.clone();
+        let depth_write = create_info
+            .depth_stencil
+            .is_some_and(|depth_stencil| depth_stencil.depth_write);
         let id = unsafe { ctx.0.create_graphics_pipeline(create_info)? };
-        Ok(Self(Arc::new(GraphicsPipelineInner { ctx, id, layouts })))
+        Ok(Self(Arc::new(GraphicsPipelineInner {
+            ctx,
+            id,
+            layouts,
+            vertex_bindings,
+            depth_write,
+            push_constants,
+        })))
     }
 
     #[inline(always)]
@@ -156,6 +235,11 @@ impl<B: Backend> GraphicsPipeline<B> {
     pub fn layouts(&self) -> &[DescriptorSetLayout<B>] {
         &self.0.layouts
     }
+
+    #[inline(always)]
+    pub fn push_constants(&self) -> Option<PushConstantRange> {
+        self.0.push_constants
+    }
 }
 
 impl<B: Backend> Clone for GraphicsPipeline<B> {
@@ -191,6 +275,7 @@ impl Default for RasterizationState {
             polygon_mode: PolygonMode::Fill,
             cull_mode: CullMode::Back,
             front_face: FrontFace::CounterClockwise,
+            rasterizer_discard: false,
         }
     }
 }
@@ -11,7 +11,9 @@ pub mod compute_pipeline;
 pub mod context;
 pub mod descriptor_set;
 pub mod graphics_pipeline;
+pub mod query;
 pub mod queue;
+pub mod render_graph;
 pub mod render_pass;
 pub mod shader;
 pub mod surface;
@@ -28,16 +30,27 @@ use descriptor_set::{
     DescriptorSetLayoutCreateInfo, DescriptorSetUpdate,
 };
 use graphics_pipeline::{GraphicsPipelineCreateError, GraphicsPipelineCreateInfo};
+use query::{QueryPoolCreateError, QueryPoolCreateInfo};
 use queue::SurfacePresentFailure;
 use raw_window_handle::HasRawWindowHandle;
+use render_pass::RenderPassDescriptor;
 use shader::{ShaderCreateError, ShaderCreateInfo};
 use surface::{
-    SurfaceConfiguration, SurfaceCreateError, SurfaceCreateInfo, SurfaceImageAcquireError,
-    SurfacePresentSuccess, SurfaceUpdateError,
+    SurfaceCaptureError, SurfaceConfiguration, SurfaceCreateError, SurfaceCreateInfo,
+    SurfaceImageAcquireError, SurfacePresentSuccess, SurfaceQueryError, SurfaceUpdateError,
+};
+use texture::{
+    SparseBindError, SparseProperties, SparseTextureBind, TextureCreateError, TextureCreateInfo,
+    TextureSubresourceLayout, TextureViewError,
+};
+use types::{
+    ColorSpace, JobStatus, PresentMode, QueueType, TextureFormat, TextureUsage, ValidationMessage,
+    ValidationSeverity,
 };
-use texture::{TextureCreateError, TextureCreateInfo};
-use types::{JobStatus, QueueType};
 
+/// Every object created through a backend is destroyed through its matching `destroy_*` method
+/// before the backend itself is dropped. See [`Context`](context::Context) for details.
+///
 /// TODO:
 /// - Describe [normative terminology](https://www.ietf.org/rfc/rfc2119.txt).
 /// - Explain backend requirements.
@@ -52,8 +65,15 @@ pub trait Backend: Sized + 'static {
     type ComputePipeline;
     type DescriptorSetLayout;
     type DescriptorSet;
-    type Job;
+    type QueryPool;
+    type Job: Clone;
+    type PrebuiltCommands;
     type DrawIndexedIndirect: Copy + Clone;
+    type DispatchIndirect: Copy + Clone;
+    /// A backend specific command. Recorded with `CommandBuffer::raw` when the `raw` feature is
+    /// enabled. Backends always declare it, so enabling the feature doesn't require enabling it
+    /// in the backend as well.
+    type RawCommand;
 
     unsafe fn create_surface<'a, W: HasRawWindowHandle>(
         &self,
@@ -68,35 +88,96 @@ pub trait Backend: Sized + 'static {
     unsafe fn acquire_image(
         &self,
         id: &mut Self::Surface,
+        timeout: Option<Duration>,
     ) -> Result<Self::SurfaceImage, SurfaceImageAcquireError>;
     unsafe fn destroy_surface_image(&self, id: &mut Self::SurfaceImage);
+    unsafe fn discard_surface_image(
+        &self,
+        surface: &mut Self::Surface,
+        image: &mut Self::SurfaceImage,
+    );
+    unsafe fn capture_surface_image(
+        &self,
+        queue: QueueType,
+        surface: &Self::Surface,
+        image: &Self::SurfaceImage,
+    ) -> Result<Vec<u8>, SurfaceCaptureError>;
+    unsafe fn surface_present_mode(&self, surface: &Self::Surface) -> PresentMode;
+    unsafe fn surface_color_space(&self, surface: &Self::Surface) -> ColorSpace;
+    unsafe fn surface_current_extent(
+        &self,
+        surface: &Self::Surface,
+    ) -> Result<(u32, u32), SurfaceQueryError>;
 
     unsafe fn submit_commands<'a>(
+        &self,
+        queue: QueueType,
+        debug_name: Option<&str>,
+        independent: bool,
+        commands: Vec<Command<'a, Self>>,
+    ) -> Self::Job;
+    unsafe fn record_commands<'a>(
         &self,
         queue: QueueType,
         debug_name: Option<&str>,
         commands: Vec<Command<'a, Self>>,
+    ) -> Self::PrebuiltCommands;
+    unsafe fn resubmit_commands(
+        &self,
+        queue: QueueType,
+        prebuilt: &Self::PrebuiltCommands,
     ) -> Self::Job;
     unsafe fn present_image(
         &self,
         surface: &Self::Surface,
         image: &mut Self::SurfaceImage,
-    ) -> Result<SurfacePresentSuccess, SurfacePresentFailure>;
+        track: bool,
+    ) -> Result<(SurfacePresentSuccess, Option<Self::Job>), SurfacePresentFailure>;
     unsafe fn wait_on(&self, job: &Self::Job, timeout: Option<Duration>) -> JobStatus;
     unsafe fn poll_status(&self, job: &Self::Job) -> JobStatus;
+    unsafe fn reclaim_commands(&self, job: &Self::Job);
+    unsafe fn on_job_complete(&self, job: &Self::Job, callback: Box<dyn FnOnce() + Send>);
+    unsafe fn all_work_complete(&self) -> bool;
+    unsafe fn present_queue_supports_transfer(&self) -> bool;
+    unsafe fn wait_all_work(&self, timeout: Option<Duration>) -> bool;
 
     unsafe fn create_buffer(
         &self,
         create_info: BufferCreateInfo,
     ) -> Result<Self::Buffer, BufferCreateError>;
+    unsafe fn create_aliased_buffer(
+        &self,
+        create_info: BufferCreateInfo,
+        source: &Self::Buffer,
+        offset: u64,
+    ) -> Result<Self::Buffer, BufferCreateError>;
     unsafe fn create_texture(
         &self,
         create_info: TextureCreateInfo,
     ) -> Result<Self::Texture, TextureCreateError>;
+    unsafe fn create_sparse_texture(
+        &self,
+        create_info: TextureCreateInfo,
+    ) -> Result<Self::Texture, TextureCreateError>;
+    unsafe fn texture_sparse_properties(&self, texture: &Self::Texture)
+        -> Option<SparseProperties>;
+    unsafe fn supports_sparse_textures(&self) -> bool;
+    unsafe fn bind_sparse<'a>(
+        &self,
+        binds: &[SparseTextureBind<'a, Self>],
+    ) -> Result<Self::Job, SparseBindError>;
+    unsafe fn supports_texture_usage(&self, format: TextureFormat, usage: TextureUsage) -> bool;
+    unsafe fn supports_mipmap_generation(&self, format: TextureFormat) -> bool;
+    unsafe fn supports_extended_dynamic_state(&self) -> bool;
+    unsafe fn prewarm_framebuffer(&self, descriptor: &RenderPassDescriptor<Self>);
     unsafe fn create_shader(
         &self,
         create_info: ShaderCreateInfo,
     ) -> Result<Self::Shader, ShaderCreateError>;
+    /// Code for a vertex shader that covers the screen with a single triangle of three vertices
+    /// using no vertex inputs. Texture coordinates are written to location `0`, with `(0, 0)` at
+    /// the top left of the screen.
+    unsafe fn fullscreen_vertex_shader(&self) -> &'static [u8];
     unsafe fn create_graphics_pipeline(
         &self,
         create_info: GraphicsPipelineCreateInfo<Self>,
@@ -113,6 +194,20 @@ pub trait Backend: Sized + 'static {
         &self,
         create_info: DescriptorSetLayoutCreateInfo,
     ) -> Result<Self::DescriptorSetLayout, DescriptorSetLayoutCreateError>;
+    unsafe fn create_query_pool(
+        &self,
+        create_info: QueryPoolCreateInfo,
+    ) -> Result<Self::QueryPool, QueryPoolCreateError>;
+    unsafe fn reset_query_pool(&self, id: &mut Self::QueryPool);
+    unsafe fn query_results(&self, id: &Self::QueryPool, results: &mut [u64]) -> bool;
+    unsafe fn available_query_results(&self, id: &Self::QueryPool, results: &mut [Option<u64>]);
+    unsafe fn timestamp_period(&self) -> f32;
+    unsafe fn timestamp_valid_bits(&self, queue: QueueType) -> u32;
+    unsafe fn drain_validation_messages(&self) -> Vec<ValidationMessage>;
+    unsafe fn report_validation_message(&self, severity: ValidationSeverity, message: &str);
+    unsafe fn set_frames_in_flight(&self, frames_in_flight: usize);
+    unsafe fn trim_pipeline_variants(&self, frames: usize);
+    unsafe fn collect_garbage(&self);
     unsafe fn destroy_buffer(&self, id: &mut Self::Buffer);
     unsafe fn destroy_texture(&self, id: &mut Self::Texture);
     unsafe fn destroy_shader(&self, id: &mut Self::Shader);
@@ -120,6 +215,8 @@ pub trait Backend: Sized + 'static {
     unsafe fn destroy_compute_pipeline(&self, id: &mut Self::ComputePipeline);
     unsafe fn destroy_descriptor_set(&self, id: &mut Self::DescriptorSet);
     unsafe fn destroy_descriptor_set_layout(&self, id: &mut Self::DescriptorSetLayout);
+    unsafe fn destroy_query_pool(&self, id: &mut Self::QueryPool);
+    unsafe fn destroy_prebuilt_commands(&self, id: &mut Self::PrebuiltCommands);
 
     unsafe fn map_memory(
         &self,
@@ -127,6 +224,13 @@ pub trait Backend: Sized + 'static {
         idx: usize,
     ) -> Result<(NonNull<u8>, u64), BufferViewError>;
     unsafe fn unmap_memory(&self, id: &mut Self::Buffer);
+    unsafe fn map_texture(
+        &self,
+        id: &mut Self::Texture,
+        array_element: usize,
+        mip_level: usize,
+    ) -> Result<(NonNull<u8>, TextureSubresourceLayout), TextureViewError>;
+    unsafe fn flush_texture(&self, id: &mut Self::Texture, array_element: usize, mip_level: usize);
     unsafe fn flush_range(&self, id: &mut Self::Buffer, idx: usize);
     unsafe fn invalidate_range(&self, id: &mut Self::Buffer, idx: usize);
 
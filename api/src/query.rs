@@ -0,0 +1,407 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use crate::{
+    command_buffer::CommandBuffer,
+    context::Context,
+    queue::{Job, Queue},
+    types::{JobStatus, QueueType},
+    Backend,
+};
+
+pub struct QueryPoolCreateInfo {
+    /// The number of timestamp queries in the pool.
+    pub count: u32,
+    /// The backend *should* use the provided debug name for easy identification.
+    pub debug_name: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum QueryPoolCreateError {
+    #[error("timestamp queries are not supported")]
+    Unsupported,
+    #[error("an error has occured: {0}")]
+    Other(String),
+}
+
+#[derive(Debug, Error)]
+pub enum TimestampWriteError {
+    #[error("queue `{0:?}` does not support timestamps")]
+    Unsupported(QueueType),
+    #[error("an error has occured: {0}")]
+    Other(String),
+}
+
+/// A pool of timestamp queries. Timestamps are written using
+/// [`CommandBuffer::write_timestamp`](crate::command_buffer::CommandBuffer::write_timestamp).
+///
+/// Query results are only valid once the commands that wrote them have completed. See
+/// [`QueryRing`] for a way to read results without stalling.
+pub struct QueryPool<B: Backend> {
+    ctx: Context<B>,
+    count: u32,
+    pub(crate) id: B::QueryPool,
+}
+
+/// A ring of [`QueryPool`]s used to read query results without stalling.
+///
+/// Each frame, a pool is taken from the ring with [`next_pool`](QueryRing::next_pool), written to
+/// by the frame's commands, and handed back with [`submit`](QueryRing::submit) along with the
+/// [`Job`] of the submission that wrote the queries. Results are read back in submission order
+/// with [`poll`](QueryRing::poll) or [`wait`](QueryRing::wait) once the associated job has
+/// completed, after which the pool is recycled.
+pub struct QueryRing<B: Backend> {
+    ctx: Context<B>,
+    queries_per_pool: u32,
+    in_flight: VecDeque<(QueryPool<B>, Job<B>)>,
+    free: Vec<QueryPool<B>>,
+}
+
+/// Measures the GPU time of named scopes within a frame.
+///
+/// Each frame is started with [`begin_frame`](FrameProfiler::begin_frame) and ended with
+/// [`end_frame`](FrameProfiler::end_frame). In between, work is submitted with
+/// [`scope`](FrameProfiler::scope), which surrounds the submitted commands with timestamps.
+/// Once a frame's work has completed, usually a frame or two later, its timings can be read with
+/// [`poll`](FrameProfiler::poll).
+pub struct FrameProfiler<B: Backend> {
+    ctx: Context<B>,
+    max_scopes: u32,
+    current: Option<ProfiledFrame<B>>,
+    in_flight: VecDeque<ProfiledFrame<B>>,
+    free: Vec<QueryPool<B>>,
+}
+
+struct ProfiledFrame<B: Backend> {
+    pool: QueryPool<B>,
+    /// Name of each scope. Scope `i` uses queries `2 * i` and `2 * i + 1`.
+    names: Vec<String>,
+    jobs: Vec<Job<B>>,
+}
+
+impl<B: Backend> QueryPool<B> {
+    /// Creates a new timestamp query pool.
+    ///
+    /// # Arguments
+    /// - `ctx` - The [`Context`] to create the pool with.
+    /// - `create_info` - Describes the pool to create.
+    ///
+    /// # Errors
+    /// - Returns [`QueryPoolCreateError::Unsupported`] if the backend does not support timestamp
+    /// queries.
+    ///
+    /// # Panics
+    /// - If `create_info.count` is `0`.
+    pub fn new(
+        ctx: Context<B>,
+        create_info: QueryPoolCreateInfo,
+    ) -> Result<Self, QueryPoolCreateError> {
+        assert_ne!(create_info.count, 0, "query pool count cannot be zero");
+        let count = create_info.count;
+        let id = unsafe { ctx.0.create_query_pool(create_info)? };
+        Ok(Self { ctx, count, id })
+    }
+
+    #[inline(always)]
+    pub fn internal(&self) -> &B::QueryPool {
+        &self.id
+    }
+
+    /// The number of queries in the pool.
+    #[inline(always)]
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Marks every query in the pool as unavailable so that it can be written to again. The
+    /// backend performs the reset before the pool is next written to.
+    ///
+    /// # Synchronization
+    /// The user *must* ensure that no in-flight commands write to the pool.
+    #[inline(always)]
+    pub fn reset(&mut self) {
+        unsafe {
+            self.ctx.0.reset_query_pool(&mut self.id);
+        }
+    }
+
+    /// Reads the results of every query in the pool, in backend timestamp ticks. Returns `None` if
+    /// any of the results are not yet available.
+    ///
+    /// Multiply tick differences by [`Context::timestamp_period`] to get nanoseconds.
+    #[inline(always)]
+    pub fn results(&self) -> Option<Vec<u64>> {
+        self.first_results(self.count)
+    }
+
+    /// Reads the results of the first `count` queries in the pool, in backend timestamp ticks.
+    /// Returns `None` if any of those results are not yet available. This is useful when only part
+    /// of the pool was written to.
+    ///
+    /// # Panics
+    /// - If `count` is `0` or greater than the number of queries in the pool.
+    #[inline(always)]
+    pub fn first_results(&self, count: u32) -> Option<Vec<u64>> {
+        assert!(
+            count != 0 && count <= self.count,
+            "`count` is out of bounds"
+        );
+        let mut results = vec![0; count as usize];
+        if unsafe { self.ctx.0.query_results(&self.id, &mut results) } {
+            Some(results)
+        } else {
+            None
+        }
+    }
+
+    /// Reads the result of every query in the pool, in backend timestamp ticks. Unlike
+    /// [`results`](QueryPool::results), results are read individually, so queries whose results
+    /// are not available (e.g. because they were never written to) are reported as `None`.
+    #[inline(always)]
+    pub fn available_results(&self) -> Vec<Option<u64>> {
+        let mut results = vec![None; self.count as usize];
+        unsafe { self.ctx.0.available_query_results(&self.id, &mut results) };
+        results
+    }
+}
+
+impl<B: Backend> Drop for QueryPool<B> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        unsafe {
+            self.ctx.0.destroy_query_pool(&mut self.id);
+        }
+    }
+}
+
+impl<B: Backend> QueryRing<B> {
+    /// Creates a new, empty query ring. Pools are created as needed.
+    ///
+    /// # Arguments
+    /// - `ctx` - The [`Context`] to create pools with.
+    /// - `queries_per_pool` - The number of queries in each pool of the ring.
+    ///
+    /// # Panics
+    /// - If `queries_per_pool` is `0`.
+    pub fn new(ctx: Context<B>, queries_per_pool: u32) -> Self {
+        assert_ne!(queries_per_pool, 0, "query pool count cannot be zero");
+        Self {
+            ctx,
+            queries_per_pool,
+            in_flight: VecDeque::default(),
+            free: Vec::default(),
+        }
+    }
+
+    /// Takes a pool that is ready to be written to. Recycled pools are preferred, and a new pool is
+    /// only created when every pool is in flight.
+    ///
+    /// # Errors
+    /// - See [`QueryPool::new`].
+    pub fn next_pool(&mut self) -> Result<QueryPool<B>, QueryPoolCreateError> {
+        match self.free.pop() {
+            Some(pool) => Ok(pool),
+            None => QueryPool::new(
+                self.ctx.clone(),
+                QueryPoolCreateInfo {
+                    count: self.queries_per_pool,
+                    debug_name: Some(String::from("query_ring_pool")),
+                },
+            ),
+        }
+    }
+
+    /// Hands a pool back to the ring along with the job of the submission that wrote to it.
+    ///
+    /// # Arguments
+    /// - `pool` - The pool taken with [`next_pool`](QueryRing::next_pool).
+    /// - `job` - The job that writes to the queries of `pool`. Results are only read once this job
+    /// is complete.
+    #[inline]
+    pub fn submit(&mut self, pool: QueryPool<B>, job: Job<B>) {
+        self.in_flight.push_back((pool, job));
+    }
+
+    /// Reads the results of the oldest submitted pool if its job has completed, without blocking.
+    /// Returns `None` if there are no submitted pools or if the oldest one is still in flight.
+    ///
+    /// Queries of the pool that the job did not write to are reported as `None`.
+    pub fn poll(&mut self) -> Option<Vec<Option<u64>>> {
+        match self.in_flight.front() {
+            Some((_, job)) if job.poll_status() == JobStatus::Complete => self.recycle_front(),
+            _ => None,
+        }
+    }
+
+    /// Waits for the job of the oldest submitted pool to complete and then reads its results.
+    /// Returns `None` if there are no submitted pools or if the timeout was reached.
+    ///
+    /// Queries of the pool that the job did not write to are reported as `None`.
+    ///
+    /// # Arguments
+    /// - `timeout` - The time to wait, or `None` if there should be no timeout.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Option<Vec<Option<u64>>> {
+        match self.in_flight.front() {
+            Some((_, job)) if job.wait_on(timeout) == JobStatus::Complete => self.recycle_front(),
+            _ => None,
+        }
+    }
+
+    /// The number of pools whose results have not been read yet.
+    #[inline]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    fn recycle_front(&mut self) -> Option<Vec<Option<u64>>> {
+        let (mut pool, _) = self.in_flight.pop_front()?;
+        let results = pool.available_results();
+        pool.reset();
+        self.free.push(pool);
+        Some(results)
+    }
+}
+
+impl<B: Backend> FrameProfiler<B> {
+    /// Creates a new frame profiler. Query pools are created as needed.
+    ///
+    /// # Arguments
+    /// - `ctx` - The [`Context`] to create query pools with.
+    /// - `max_scopes` - The maximum number of scopes that can be opened per frame.
+    ///
+    /// # Panics
+    /// - If `max_scopes` is `0`.
+    pub fn new(ctx: Context<B>, max_scopes: u32) -> Self {
+        assert_ne!(max_scopes, 0, "max scopes cannot be zero");
+        Self {
+            ctx,
+            max_scopes,
+            current: None,
+            in_flight: VecDeque::default(),
+            free: Vec::default(),
+        }
+    }
+
+    /// Begins profiling a new frame.
+    ///
+    /// # Errors
+    /// - See [`QueryPool::new`].
+    ///
+    /// # Panics
+    /// - If the previous frame was not ended with [`end_frame`](FrameProfiler::end_frame).
+    pub fn begin_frame(&mut self) -> Result<(), QueryPoolCreateError> {
+        assert!(self.current.is_none(), "the previous frame was not ended");
+        let pool = match self.free.pop() {
+            Some(pool) => pool,
+            None => QueryPool::new(
+                self.ctx.clone(),
+                QueryPoolCreateInfo {
+                    count: self.max_scopes * 2,
+                    debug_name: Some(String::from("frame_profiler_pool")),
+                },
+            )?,
+        };
+        self.current = Some(ProfiledFrame {
+            pool,
+            names: Vec::default(),
+            jobs: Vec::default(),
+        });
+        Ok(())
+    }
+
+    /// Submits commands to a queue, measuring the time it takes the GPU to execute them.
+    ///
+    /// # Arguments
+    /// - `queue` - The queue to submit to.
+    /// - `name` - The name of the scope. Scopes with the same name in a frame have their timings
+    /// summed. The name is also used as the debug name of the submission.
+    /// - `commands` - A function that records the commands.
+    ///
+    /// # Panics
+    /// - If no frame was begun with [`begin_frame`](FrameProfiler::begin_frame).
+    /// - If more than `max_scopes` scopes are opened in the frame.
+    /// - If `queue` does not [`support timestamps`](Queue::supports_timestamps).
+    pub fn scope<'a>(
+        &'a mut self,
+        queue: &Queue<B>,
+        name: &str,
+        commands: impl FnOnce(&mut CommandBuffer<'a, B>),
+    ) -> Job<B> {
+        assert!(
+            queue.supports_timestamps(),
+            "queue `{:?}` does not support timestamps",
+            queue.ty()
+        );
+        let max_scopes = self.max_scopes;
+        let frame = self.current.as_mut().expect("no frame has been begun");
+        assert!(
+            (frame.names.len() as u32) < max_scopes,
+            "too many scopes opened in a single frame"
+        );
+        let start = frame.names.len() as u32 * 2;
+        frame.names.push(String::from(name));
+
+        let pool = &frame.pool;
+        let job = queue.submit(Some(name), |cb| {
+            // Support was checked above
+            cb.write_timestamp(pool, start).unwrap();
+            commands(cb);
+            cb.write_timestamp(pool, start + 1).unwrap();
+        });
+        frame.jobs.push(job.clone());
+        job
+    }
+
+    /// Ends the frame begun with [`begin_frame`](FrameProfiler::begin_frame).
+    ///
+    /// # Panics
+    /// - If no frame was begun.
+    pub fn end_frame(&mut self) {
+        let frame = self.current.take().expect("no frame has been begun");
+        self.in_flight.push_back(frame);
+    }
+
+    /// Reads the timings of the oldest ended frame if all of its work has completed, without
+    /// blocking. Returns a map of scope names to GPU time in milliseconds, or `None` if there are
+    /// no ended frames or if the oldest one is still in flight. A frame whose results are not yet
+    /// available stays queued, so it is read by a later call.
+    pub fn poll(&mut self) -> Option<HashMap<String, f64>> {
+        let frame = self.in_flight.front()?;
+        let complete = frame
+            .jobs
+            .iter()
+            .all(|job| job.poll_status() == JobStatus::Complete);
+        if !complete {
+            return None;
+        }
+
+        // Only the queries of opened scopes were written to
+        let results = if frame.names.is_empty() {
+            Vec::default()
+        } else {
+            frame.pool.first_results(frame.names.len() as u32 * 2)?
+        };
+
+        let mut frame = self.in_flight.pop_front().unwrap();
+        frame.pool.reset();
+        self.free.push(frame.pool);
+
+        let period = self.ctx.timestamp_period() as f64;
+        let mut timings = HashMap::default();
+        for (i, name) in frame.names.into_iter().enumerate() {
+            let ticks = results[2 * i + 1].saturating_sub(results[2 * i]);
+            *timings.entry(name).or_insert(0.0) += ticks as f64 * period / 1_000_000.0;
+        }
+        Some(timings)
+    }
+
+    /// The number of ended frames whose timings have not been read yet.
+    #[inline]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+}
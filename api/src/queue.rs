@@ -1,8 +1,12 @@
-use std::time::Duration;
+use std::{
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    command_buffer::CommandBuffer,
+    command_buffer::{Command, CommandBuffer},
     context::Context,
+    render_pass::ColorAttachmentSource,
     surface::{Surface, SurfaceImage, SurfacePresentError, SurfacePresentSuccess},
     types::{JobStatus, QueueType},
     Backend,
@@ -32,6 +36,24 @@ pub struct Job<B: Backend> {
     id: B::Job,
 }
 
+/// Commands that have been recorded once and can be submitted any number of times with
+/// [`resubmit`](Queue::resubmit). Created with [`record`](Queue::record).
+///
+/// Unlike commands passed to [`submit`](Queue::submit), which are recorded for a single use,
+/// prebuilt commands let the backend skip recording and resource tracking when the same work is
+/// submitted every frame. Only the state of the resources when the commands begin is checked on
+/// each submission.
+///
+/// The resources used by the commands are borrowed for as long as the prebuilt commands exist, so
+/// they can't be modified or destroyed in the meantime. This is what keeps the recorded commands
+/// valid; to change them, drop the prebuilt commands and record new ones.
+pub struct PrebuiltCommands<'a, B: Backend> {
+    ctx: Context<B>,
+    queue_ty: QueueType,
+    id: B::PrebuiltCommands,
+    _resources: PhantomData<&'a ()>,
+}
+
 pub enum SurfacePresentFailure {
     BadImage,
     NoRender,
@@ -49,8 +71,39 @@ impl<B: Backend> Queue<B> {
         self.ty
     }
 
+    /// Checks if the queue supports transfer commands (copies).
+    ///
+    /// The [`main`](Context::main) and [`transfer`](Context::transfer) queues always support
+    /// transfer commands. The [`present`](Context::present) queue supports them only if the
+    /// backend reports that it does, which allows work like copying a final image on the same
+    /// queue that presents it.
+    #[inline(always)]
+    pub fn supports_transfer(&self) -> bool {
+        match self.ty {
+            QueueType::Main | QueueType::Transfer => true,
+            QueueType::Compute => false,
+            QueueType::Present => unsafe { self.ctx.0.present_queue_supports_transfer() },
+        }
+    }
+
+    /// The number of meaningful bits in timestamps written on this queue. Bits above these are
+    /// always zero. If `0`, the queue does not support timestamps.
+    #[inline(always)]
+    pub fn timestamp_valid_bits(&self) -> u32 {
+        unsafe { self.ctx.0.timestamp_valid_bits(self.ty) }
+    }
+
+    /// Checks if [`timestamps`](CommandBuffer::write_timestamp) can be written on this queue.
+    #[inline(always)]
+    pub fn supports_timestamps(&self) -> bool {
+        self.timestamp_valid_bits() != 0
+    }
+
     /// Records the commands to a command buffer, and then submits them to the queue.
     ///
+    /// If no commands are recorded, nothing is submitted. The returned job completes as soon as
+    /// all previously submitted work on the queue completes.
+    ///
     /// # Arguments
     /// - `debug_name` - The backend *should* use the provided debug name for easy identification.
     /// - `commands` - A function that records the commands.
@@ -59,13 +112,137 @@ impl<B: Backend> Queue<B> {
         &self,
         debug_name: Option<&str>,
         commands: impl FnOnce(&mut CommandBuffer<'a, B>),
+    ) -> Job<B> {
+        self.submit_inner(debug_name, false, commands)
+    }
+
+    /// Records the commands to a command buffer, and then submits them to the queue without
+    /// waiting on previously submitted work, so that independent work on the same queue may
+    /// overlap.
+    ///
+    /// Resources used by the commands are still synchronized with previous work, so the only
+    /// difference from [`submit`](Queue::submit) is that commands which don't share resources
+    /// with previous work may run alongside it. The returned job still completes only once all
+    /// previously submitted work on the queue completes.
+    ///
+    /// # Arguments
+    /// - `debug_name` - The backend *should* use the provided debug name for easy identification.
+    /// - `commands` - A function that records the commands.
+    #[inline(always)]
+    pub fn submit_independent<'a>(
+        &self,
+        debug_name: Option<&str>,
+        commands: impl FnOnce(&mut CommandBuffer<'a, B>),
+    ) -> Job<B> {
+        self.submit_inner(debug_name, true, commands)
+    }
+
+    #[inline(always)]
+    fn submit_inner<'a>(
+        &self,
+        debug_name: Option<&str>,
+        independent: bool,
+        commands: impl FnOnce(&mut CommandBuffer<'a, B>),
     ) -> Job<B> {
         let mut cb = CommandBuffer {
             queue_ty: self.ty,
+            supports_transfer: self.supports_transfer(),
+            supports_timestamps: self.supports_timestamps(),
+            supports_extended_dynamic_state: self.ctx.supports_extended_dynamic_state(),
             commands: Vec::default(),
         };
         commands(&mut cb);
-        let id = unsafe { self.ctx.0.submit_commands(self.ty, debug_name, cb.commands) };
+        if cfg!(debug_assertions) {
+            cb.validate_scopes();
+            cb.validate_vertex_strides(&self.ctx);
+            cb.validate_set_layouts();
+            cb.validate_buffer_copies();
+            cb.validate_texture_copies();
+        }
+        let id = unsafe {
+            self.ctx
+                .0
+                .submit_commands(self.ty, debug_name, independent, cb.commands)
+        };
+
+        Job {
+            id,
+            ctx: self.ctx.clone(),
+        }
+    }
+
+    /// Records commands once so they can be submitted many times with
+    /// [`resubmit`](Queue::resubmit). Nothing is submitted by this call.
+    ///
+    /// # Arguments
+    /// - `debug_name` - The backend *should* use the provided debug name for easy identification.
+    /// - `commands` - A function that records the commands.
+    ///
+    /// # Panics
+    /// - If the commands use a [`SurfaceImage`]. Surface images are acquired anew every frame, so
+    /// they can't be baked into prebuilt commands.
+    pub fn record<'a>(
+        &self,
+        debug_name: Option<&str>,
+        commands: impl FnOnce(&mut CommandBuffer<'a, B>),
+    ) -> PrebuiltCommands<'a, B> {
+        let mut cb = CommandBuffer {
+            queue_ty: self.ty,
+            supports_transfer: self.supports_transfer(),
+            supports_timestamps: self.supports_timestamps(),
+            supports_extended_dynamic_state: self.ctx.supports_extended_dynamic_state(),
+            commands: Vec::default(),
+        };
+        commands(&mut cb);
+        if cfg!(debug_assertions) {
+            cb.validate_scopes();
+            cb.validate_vertex_strides(&self.ctx);
+            cb.validate_set_layouts();
+            cb.validate_buffer_copies();
+            cb.validate_texture_copies();
+        }
+
+        for command in &cb.commands {
+            let uses_surface_image = match command {
+                Command::BeginRenderPass(descriptor) => {
+                    descriptor.color_attachments.iter().any(|attachment| {
+                        matches!(attachment.source, ColorAttachmentSource::SurfaceImage(_))
+                    })
+                }
+                Command::BlitToSurfaceImage { .. } => true,
+                _ => false,
+            };
+            assert!(
+                !uses_surface_image,
+                "surface images can't be used in prebuilt commands"
+            );
+        }
+
+        let id = unsafe { self.ctx.0.record_commands(self.ty, debug_name, cb.commands) };
+
+        PrebuiltCommands {
+            ctx: self.ctx.clone(),
+            queue_ty: self.ty,
+            id,
+            _resources: PhantomData,
+        }
+    }
+
+    /// Submits commands previously recorded with [`record`](Queue::record). The commands are
+    /// synchronized with other work exactly as if they were passed to [`submit`](Queue::submit).
+    ///
+    /// # Arguments
+    /// - `prebuilt` - The commands to submit.
+    ///
+    /// # Panics
+    /// - If `prebuilt` was recorded for a different queue type than `self`.
+    #[inline(always)]
+    pub fn resubmit(&self, prebuilt: &PrebuiltCommands<B>) -> Job<B> {
+        assert_eq!(
+            prebuilt.queue_ty, self.ty,
+            "prebuilt commands must be submitted to the queue they were recorded for"
+        );
+        let id = unsafe { self.ctx.0.resubmit_commands(self.ty, &prebuilt.id) };
 
         Job {
             id,
@@ -78,11 +255,39 @@ impl<B: Backend> Queue<B> {
     pub fn present(
         &self,
         surface: &Surface<B>,
-        mut image: SurfaceImage<B>,
+        image: SurfaceImage<B>,
     ) -> Result<SurfacePresentSuccess, SurfacePresentError<B>> {
+        self.present_inner(surface, image, false)
+            .map(|(success, _)| success)
+    }
+
+    /// Presents a rendered [`SurfaceImage`] to a [`Surface`] like [`present`](Queue::present),
+    /// and returns a [`Job`] which completes once the queue has processed the presentation.
+    ///
+    /// The job can be waited on for frame pacing or to measure present latency. The backend
+    /// *should* complete the job no earlier than when the image has been handed off to the
+    /// presentation engine. Backends that can't observe when the image is actually displayed
+    /// complete the job once the commands that rendered the image and all work submitted to the
+    /// queue before the presentation are complete.
+    #[inline(always)]
+    pub fn present_tracked(
+        &self,
+        surface: &Surface<B>,
+        image: SurfaceImage<B>,
+    ) -> Result<(SurfacePresentSuccess, Job<B>), SurfacePresentError<B>> {
+        self.present_inner(surface, image, true)
+            .map(|(success, job)| (success, job.unwrap()))
+    }
+
+    fn present_inner(
+        &self,
+        surface: &Surface<B>,
+        mut image: SurfaceImage<B>,
+        track: bool,
+    ) -> Result<(SurfacePresentSuccess, Option<Job<B>>), SurfacePresentError<B>> {
         unsafe {
-            match self.ctx.0.present_image(&surface.id, &mut image.id) {
-                Ok(success) => Ok(success),
+            match self.ctx.0.present_image(&surface.id, &mut image.id, track) {
+                Ok((success, job)) => Ok((success, job.map(|id| Job::new(self.ctx.clone(), id)))),
                 Err(err) => match err {
                     SurfacePresentFailure::BadImage => Err(SurfacePresentError::BadImage(image)),
                     SurfacePresentFailure::NoRender => Err(SurfacePresentError::NoRender(image)),
@@ -93,7 +298,39 @@ impl<B: Backend> Queue<B> {
     }
 }
 
+impl<'a, B: Backend> PrebuiltCommands<'a, B> {
+    /// Returns the type of queue the commands were recorded for.
+    #[inline(always)]
+    pub fn queue_ty(&self) -> QueueType {
+        self.queue_ty
+    }
+}
+
+impl<'a, B: Backend> Drop for PrebuiltCommands<'a, B> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        unsafe {
+            self.ctx.0.destroy_prebuilt_commands(&mut self.id);
+        }
+    }
+}
+
+impl<B: Backend> Clone for Job<B> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        Self {
+            ctx: self.ctx.clone(),
+            id: self.id.clone(),
+        }
+    }
+}
+
 impl<B: Backend> Job<B> {
+    #[inline(always)]
+    pub(crate) fn new(ctx: Context<B>, id: B::Job) -> Self {
+        Self { ctx, id }
+    }
+
     /// Wait's for the job to complete with the given timeout. If `None` is provided, then this
     /// call *must* block as long as possible for the job is finished. Returns the status of the
     /// job by the time the timeout is reached.
@@ -101,13 +338,60 @@ impl<B: Backend> Job<B> {
     /// # Arguments
     /// - `timeout` - The time to wait, or `None` if there should be no timeout.
     #[inline(always)]
-    fn wait_on(&self, timeout: Option<Duration>) -> JobStatus {
+    pub fn wait_on(&self, timeout: Option<Duration>) -> JobStatus {
         unsafe { self.ctx.0.wait_on(&self.id, timeout) }
     }
 
+    /// Wait's for the job to complete with the given timeout like [`wait_on`](Job::wait_on). If
+    /// the job completes, the backend *should* release the memory used to record the commands of
+    /// the job and every job before it on the same queue right away, instead of when the memory
+    /// is next needed.
+    ///
+    /// This is useful to bound memory usage when large command buffers are submitted
+    /// infrequently.
+    ///
+    /// # Arguments
+    /// - `timeout` - The time to wait, or `None` if there should be no timeout.
+    #[inline]
+    pub fn wait_and_reclaim(&self, timeout: Option<Duration>) -> JobStatus {
+        let status = self.wait_on(timeout);
+        if status == JobStatus::Complete {
+            unsafe { self.ctx.0.reclaim_commands(&self.id) };
+        }
+        status
+    }
+
+    /// Wait's for the job to complete until the given deadline. If the deadline has already
+    /// passed, the status of the job is polled without blocking. Returns the status of the job by
+    /// the time the deadline is reached.
+    ///
+    /// # Arguments
+    /// - `deadline` - The point in time to stop waiting at.
+    #[inline]
+    pub fn wait_until(&self, deadline: Instant) -> JobStatus {
+        match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => self.wait_on(Some(remaining)),
+            _ => self.poll_status(),
+        }
+    }
+
+    /// Registers a function to be called once the job has completed. If the job has already
+    /// completed, the function is called immediately.
+    ///
+    /// Otherwise, the backend *should* call the function the next time it checks the status of
+    /// submitted work, which happens at least once per submission. The function is called
+    /// exactly once, and is called when the context is destroyed if it hasn't been already.
+    ///
+    /// # Arguments
+    /// - `callback` - The function to call once the job has completed.
+    #[inline(always)]
+    pub fn on_complete(&self, callback: Box<dyn FnOnce() + Send>) {
+        unsafe { self.ctx.0.on_job_complete(&self.id, callback) }
+    }
+
     /// Polls the current status of the job without blocking.
     #[inline(always)]
-    fn poll_status(&self) -> JobStatus {
+    pub fn poll_status(&self) -> JobStatus {
         unsafe { self.ctx.0.poll_status(&self.id) }
     }
 }
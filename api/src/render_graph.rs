@@ -0,0 +1,325 @@
+//! A render graph describes a frame as a set of passes along with the resources each pass reads
+//! and writes. When [`executed`](RenderGraph::execute), the graph orders the passes so that every
+//! pass runs after the passes it depends on, creates transient resources, and batches passes on
+//! the same queue into as few submissions as possible.
+//!
+//! Barriers and cross-queue synchronization are not encoded by the graph. Because the passes are
+//! submitted in dependency order, the backend detects and inserts them the same way it does for
+//! any other submission.
+use thiserror::Error;
+
+use crate::{
+    buffer::Buffer,
+    command_buffer::CommandBuffer,
+    context::Context,
+    queue::{Job, Queue},
+    surface::SurfaceImage,
+    texture::{Texture, TextureCreateError, TextureCreateInfo},
+    types::QueueType,
+    Backend,
+};
+
+/// Identifies a resource registered with a [`RenderGraph`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ResourceId(usize);
+
+pub struct PassDescriptor {
+    /// The queue to submit the pass to.
+    pub queue: QueueType,
+    /// Resources the pass reads from.
+    pub reads: Vec<ResourceId>,
+    /// Resources the pass writes to. A resource that is both read and written only needs to be
+    /// listed here.
+    pub writes: Vec<ResourceId>,
+    /// The backend *should* use the provided debug name for easy identification.
+    pub debug_name: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum RenderGraphError {
+    #[error("unable to create transient texture: {0}")]
+    TransientTexture(#[from] TextureCreateError),
+    #[error("an error has occured: {0}")]
+    Other(String),
+}
+
+/// Function that records the commands of a pass.
+type RecordFn<'a, B> =
+    Box<dyn for<'r> FnOnce(&mut CommandBuffer<'r, B>, &'r PassResources<'a, B>) + 'a>;
+
+pub struct RenderGraph<'a, B: Backend> {
+    ctx: Context<B>,
+    resources: Vec<GraphResource<'a, B>>,
+    /// Tracks which resources have been written to by a registered pass.
+    written: Vec<bool>,
+    passes: Vec<Pass<'a, B>>,
+}
+
+/// Provides access to the resources of a [`RenderGraph`] while recording a pass.
+pub struct PassResources<'a, B: Backend> {
+    resources: Vec<ResolvedResource<'a, B>>,
+}
+
+enum GraphResource<'a, B: Backend> {
+    Buffer(&'a Buffer<B>),
+    Texture(&'a Texture<B>),
+    SurfaceImage(&'a SurfaceImage<B>),
+    TransientTexture(TextureCreateInfo),
+}
+
+enum ResolvedResource<'a, B: Backend> {
+    Buffer(&'a Buffer<B>),
+    Texture(&'a Texture<B>),
+    SurfaceImage(&'a SurfaceImage<B>),
+    TransientTexture(Texture<B>),
+}
+
+struct Pass<'a, B: Backend> {
+    descriptor: PassDescriptor,
+    record: RecordFn<'a, B>,
+}
+
+impl<'a, B: Backend> RenderGraph<'a, B> {
+    /// Creates a new, empty render graph.
+    ///
+    /// # Arguments
+    /// - `ctx` - The [`Context`] to create transient resources and submit passes with.
+    pub fn new(ctx: Context<B>) -> Self {
+        Self {
+            ctx,
+            resources: Vec::default(),
+            written: Vec::default(),
+            passes: Vec::default(),
+        }
+    }
+
+    /// Registers an existing buffer with the graph.
+    #[inline]
+    pub fn import_buffer(&mut self, buffer: &'a Buffer<B>) -> ResourceId {
+        self.add_resource(GraphResource::Buffer(buffer))
+    }
+
+    /// Registers an existing texture with the graph.
+    #[inline]
+    pub fn import_texture(&mut self, texture: &'a Texture<B>) -> ResourceId {
+        self.add_resource(GraphResource::Texture(texture))
+    }
+
+    /// Registers a surface image with the graph.
+    #[inline]
+    pub fn import_surface_image(&mut self, image: &'a SurfaceImage<B>) -> ResourceId {
+        self.add_resource(GraphResource::SurfaceImage(image))
+    }
+
+    /// Registers a texture that only exists while the graph is executed. The texture is created
+    /// when the graph is executed and is destroyed once every pass using it has completed.
+    ///
+    /// # Arguments
+    /// - `create_info` - Describes the texture to create.
+    #[inline]
+    pub fn create_texture(&mut self, create_info: TextureCreateInfo) -> ResourceId {
+        self.add_resource(GraphResource::TransientTexture(create_info))
+    }
+
+    /// Registers a pass with the graph.
+    ///
+    /// Passes are ordered using the resource accesses in their descriptors. A pass runs after the
+    /// last pass registered before it that writes to a resource it accesses, and a pass writing to
+    /// a resource runs after every pass registered before it that reads from that resource.
+    /// Passes which do not depend on each other may be reordered.
+    ///
+    /// # Arguments
+    /// - `descriptor` - Describes the queue and resource accesses of the pass.
+    /// - `record` - A function that records the commands of the pass. Resources registered with
+    /// the graph are accessed through the provided [`PassResources`].
+    ///
+    /// # Panics
+    /// - If a resource in `descriptor` was not registered with this graph.
+    /// - If the pass reads from a transient resource that no previously registered pass writes
+    /// to.
+    pub fn add_pass(
+        &mut self,
+        descriptor: PassDescriptor,
+        record: impl for<'r> FnOnce(&mut CommandBuffer<'r, B>, &'r PassResources<'a, B>) + 'a,
+    ) {
+        for id in descriptor.reads.iter().chain(descriptor.writes.iter()) {
+            assert!(id.0 < self.resources.len(), "unknown resource `{:?}`", id);
+        }
+        for id in &descriptor.reads {
+            if let GraphResource::TransientTexture(_) = &self.resources[id.0] {
+                assert!(
+                    self.written[id.0],
+                    "transient resource `{:?}` is read before it is written",
+                    id
+                );
+            }
+        }
+        for id in &descriptor.writes {
+            self.written[id.0] = true;
+        }
+
+        self.passes.push(Pass {
+            descriptor,
+            record: Box::new(record),
+        });
+    }
+
+    /// Orders the passes of the graph, creates transient resources, and submits the passes.
+    ///
+    /// Consecutive passes on the same queue are recorded into a single submission. The returned
+    /// jobs are in submission order.
+    ///
+    /// # Errors
+    /// - Returns [`RenderGraphError::TransientTexture`] if a transient texture could not be
+    /// created. No passes are submitted in this case.
+    pub fn execute(self) -> Result<Vec<Job<B>>, RenderGraphError> {
+        let order = self.order();
+
+        // Create transient resources
+        let mut resources = Vec::with_capacity(self.resources.len());
+        for resource in self.resources {
+            resources.push(match resource {
+                GraphResource::Buffer(buffer) => ResolvedResource::Buffer(buffer),
+                GraphResource::Texture(texture) => ResolvedResource::Texture(texture),
+                GraphResource::SurfaceImage(image) => ResolvedResource::SurfaceImage(image),
+                GraphResource::TransientTexture(create_info) => {
+                    ResolvedResource::TransientTexture(Texture::new(self.ctx.clone(), create_info)?)
+                }
+            });
+        }
+        let resources = PassResources { resources };
+
+        // Group consecutive passes on the same queue into batches
+        let mut passes: Vec<_> = self.passes.into_iter().map(Some).collect();
+        let mut batches: Vec<(QueueType, Vec<Pass<'a, B>>)> = Vec::default();
+        for idx in order {
+            let pass = passes[idx].take().unwrap();
+            match batches.last_mut() {
+                Some((queue, batch)) if *queue == pass.descriptor.queue => batch.push(pass),
+                _ => batches.push((pass.descriptor.queue, vec![pass])),
+            }
+        }
+
+        // Submit batches
+        let mut jobs = Vec::with_capacity(batches.len());
+        for (queue, batch) in batches {
+            let queue = Queue::new(self.ctx.clone(), queue);
+            let debug_name = batch[0].descriptor.debug_name.clone();
+            jobs.push(queue.submit(debug_name.as_deref(), |commands| {
+                for pass in batch {
+                    (pass.record)(commands, &resources);
+                }
+            }));
+        }
+
+        Ok(jobs)
+    }
+
+    #[inline]
+    fn add_resource(&mut self, resource: GraphResource<'a, B>) -> ResourceId {
+        self.resources.push(resource);
+        self.written.push(false);
+        ResourceId(self.resources.len() - 1)
+    }
+
+    /// Topologically sorts the passes. When multiple passes are ready, passes on the same queue as
+    /// the previously ordered pass are preferred to reduce the number of submissions.
+    fn order(&self) -> Vec<usize> {
+        // Determine dependencies between passes
+        let mut dependents = vec![Vec::default(); self.passes.len()];
+        let mut dependency_count = vec![0_usize; self.passes.len()];
+        let mut last_writer: Vec<Option<usize>> = vec![None; self.resources.len()];
+        let mut readers: Vec<Vec<usize>> = vec![Vec::default(); self.resources.len()];
+        for (i, pass) in self.passes.iter().enumerate() {
+            let mut dependencies = Vec::default();
+            for id in &pass.descriptor.reads {
+                dependencies.extend(last_writer[id.0]);
+            }
+            for id in &pass.descriptor.writes {
+                dependencies.extend(last_writer[id.0]);
+                dependencies.extend(readers[id.0].iter().copied());
+            }
+            dependencies.sort_unstable();
+            dependencies.dedup();
+            dependencies.retain(|dependency| *dependency != i);
+
+            for dependency in dependencies {
+                dependents[dependency].push(i);
+                dependency_count[i] += 1;
+            }
+
+            for id in &pass.descriptor.reads {
+                readers[id.0].push(i);
+            }
+            for id in &pass.descriptor.writes {
+                last_writer[id.0] = Some(i);
+                readers[id.0].clear();
+            }
+        }
+
+        // Kahn's algorithm. Dependencies always point to earlier passes, so there are no cycles.
+        let mut ready: Vec<usize> = (0..self.passes.len())
+            .filter(|i| dependency_count[*i] == 0)
+            .collect();
+        let mut order = Vec::with_capacity(self.passes.len());
+        let mut last_queue = None;
+        while !ready.is_empty() {
+            let pos = ready
+                .iter()
+                .position(|i| Some(self.passes[*i].descriptor.queue) == last_queue)
+                .unwrap_or(0);
+            let pass = ready.remove(pos);
+            last_queue = Some(self.passes[pass].descriptor.queue);
+            order.push(pass);
+
+            for dependent in &dependents[pass] {
+                dependency_count[*dependent] -= 1;
+                if dependency_count[*dependent] == 0 {
+                    let idx = ready.partition_point(|i| i < dependent);
+                    ready.insert(idx, *dependent);
+                }
+            }
+        }
+
+        order
+    }
+}
+
+impl<'a, B: Backend> PassResources<'a, B> {
+    /// Gets a buffer registered with the graph.
+    ///
+    /// # Panics
+    /// - If `id` does not refer to a buffer.
+    #[inline]
+    pub fn buffer(&self, id: ResourceId) -> &Buffer<B> {
+        match &self.resources[id.0] {
+            ResolvedResource::Buffer(buffer) => buffer,
+            _ => panic!("resource `{:?}` is not a buffer", id),
+        }
+    }
+
+    /// Gets a texture registered with the graph. This includes transient textures.
+    ///
+    /// # Panics
+    /// - If `id` does not refer to a texture.
+    #[inline]
+    pub fn texture(&self, id: ResourceId) -> &Texture<B> {
+        match &self.resources[id.0] {
+            ResolvedResource::Texture(texture) => texture,
+            ResolvedResource::TransientTexture(texture) => texture,
+            _ => panic!("resource `{:?}` is not a texture", id),
+        }
+    }
+
+    /// Gets a surface image registered with the graph.
+    ///
+    /// # Panics
+    /// - If `id` does not refer to a surface image.
+    #[inline]
+    pub fn surface_image(&self, id: ResourceId) -> &SurfaceImage<B> {
+        match &self.resources[id.0] {
+            ResolvedResource::SurfaceImage(image) => image,
+            _ => panic!("resource `{:?}` is not a surface image", id),
+        }
+    }
+}
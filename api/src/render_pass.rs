@@ -2,12 +2,13 @@ use crate::{
     buffer::Buffer,
     command_buffer::Command,
     descriptor_set::DescriptorSet,
-    graphics_pipeline::GraphicsPipeline,
+    graphics_pipeline::{GraphicsPipeline, PushConstantRange},
     surface::SurfaceImage,
     texture::Texture,
-    types::{IndexType, LoadOp, ShaderStage, StoreOp},
+    types::{CullMode, FrontFace, IndexType, LoadOp, ShaderStage, StoreOp, TextureFormat},
     Backend,
 };
+use bytemuck::Pod;
 
 /// Describes a render pass.
 pub struct RenderPassDescriptor<'a, B: Backend> {
@@ -34,6 +35,10 @@ pub enum ColorAttachmentSource<'a, B: Backend> {
         texture: &'a Texture<B>,
         array_element: usize,
         mip_level: usize,
+        /// The format to view the texture as, which *must* be the texture's format or one of its
+        /// [`view formats`](crate::texture::TextureCreateInfo::view_formats). If `None`, the
+        /// texture's format is used.
+        view_format: Option<TextureFormat>,
     },
 }
 
@@ -49,10 +54,23 @@ pub struct DepthStencilAttachment<'a, B: Backend> {
     pub load_op: LoadOp,
     /// How the depth stencil attachment should be stored.
     pub store_op: StoreOp,
+    /// If `true`, the depth aspect of the attachment is only read from during the pass while the
+    /// stencil aspect (if any) remains writable. This allows the texture to be depth tested
+    /// against and sampled in the same pass (e.g. for soft particles). Pipelines bound in the pass
+    /// must not write to depth.
+    pub read_only_depth: bool,
 }
 
 pub struct RenderPass<'a, B: Backend> {
     pub(crate) bound_pipeline: bool,
+    /// Push constants of the most recently bound pipeline.
+    pub(crate) push_constants: Option<PushConstantRange>,
+    /// Does the pass have a [`read-only`](DepthStencilAttachment::read_only_depth) depth
+    /// attachment.
+    pub(crate) read_only_depth: bool,
+    /// Cached result of the context's extended
+    /// [`dynamic state support`](crate::context::Context::supports_extended_dynamic_state).
+    pub(crate) supports_extended_dynamic_state: bool,
     pub(crate) commands: Vec<Command<'a, B>>,
 }
 
@@ -67,9 +85,17 @@ impl<'a, B: Backend> RenderPass<'a, B> {
     ///
     /// # Arguments
     /// - `pipeline` - The graphics pipeline to bind.
+    ///
+    /// # Panics
+    /// - If the pass has a read-only depth attachment and `pipeline` writes to depth.
     #[inline]
     pub fn bind_pipeline(&mut self, pipeline: GraphicsPipeline<B>) {
+        assert!(
+            !(self.read_only_depth && pipeline.0.depth_write),
+            "pipelines bound in a pass with a read-only depth attachment must not write to depth"
+        );
         self.bound_pipeline = true;
+        self.push_constants = pipeline.0.push_constants;
         self.commands.push(Command::BindGraphicsPipeline(pipeline));
     }
 
@@ -108,6 +134,10 @@ impl<'a, B: Backend> RenderPass<'a, B> {
     /// # Valid Usage
     /// The user *must* ensure that the bound buffers do not go out of bounds of the pipeline they
     /// are used in. Backends *should* perform validity checking of the set bounds.
+    ///
+    /// The size of each bound array element *must* be a multiple of the stride of the pipeline
+    /// binding it is bound to, and hold at least one element past `offset`. In debug builds, this
+    /// is checked against the most recently bound pipeline when the commands are submitted.
     #[inline]
     pub fn bind_vertex_buffers(&mut self, first: usize, binds: Vec<VertexBind<'a, B>>) {
         self.commands
@@ -137,6 +167,126 @@ impl<'a, B: Backend> RenderPass<'a, B> {
         });
     }
 
+    /// Enables or disables depth testing for subsequent draws, overriding the value of the bound
+    /// pipeline. Binding a new pipeline resets the value to the pipeline's.
+    /// Has no effect in render passes without a depth stencil attachment.
+    ///
+    /// # Arguments
+    /// - `enable` - Should depth testing be enabled.
+    ///
+    /// # Panics
+    /// - If the backend does not
+    /// [`support`](crate::context::Context::supports_extended_dynamic_state) dynamically changing
+    /// depth and culling state.
+    #[inline]
+    pub fn set_depth_test_enable(&mut self, enable: bool) {
+        self.assert_extended_dynamic_state();
+        self.commands.push(Command::SetDepthTestEnable(enable));
+    }
+
+    /// Enables or disables depth writes for subsequent draws, overriding the value of the bound
+    /// pipeline. Binding a new pipeline resets the value to the pipeline's.
+    /// Has no effect in render passes without a depth stencil attachment.
+    ///
+    /// # Arguments
+    /// - `enable` - Should depth writes be enabled.
+    ///
+    /// # Panics
+    /// - If the backend does not
+    /// [`support`](crate::context::Context::supports_extended_dynamic_state) dynamically changing
+    /// depth and culling state.
+    /// - If `enable` is `true` and the pass has a read-only depth attachment.
+    #[inline]
+    pub fn set_depth_write_enable(&mut self, enable: bool) {
+        self.assert_extended_dynamic_state();
+        assert!(
+            !(self.read_only_depth && enable),
+            "depth writes can't be enabled in a pass with a read-only depth attachment"
+        );
+        self.commands.push(Command::SetDepthWriteEnable(enable));
+    }
+
+    /// Sets the culling rule for subsequent draws, overriding the value of the bound pipeline.
+    /// Binding a new pipeline resets the value to the pipeline's.
+    ///
+    /// # Arguments
+    /// - `cull_mode` - The new culling rule.
+    ///
+    /// # Panics
+    /// - If the backend does not
+    /// [`support`](crate::context::Context::supports_extended_dynamic_state) dynamically changing
+    /// depth and culling state.
+    #[inline]
+    pub fn set_cull_mode(&mut self, cull_mode: CullMode) {
+        self.assert_extended_dynamic_state();
+        self.commands.push(Command::SetCullMode(cull_mode));
+    }
+
+    /// Sets which direction represents the front face of a primitive for subsequent draws,
+    /// overriding the value of the bound pipeline. Binding a new pipeline resets the value to the
+    /// pipeline's.
+    ///
+    /// # Arguments
+    /// - `front_face` - The new front face direction.
+    ///
+    /// # Panics
+    /// - If the backend does not
+    /// [`support`](crate::context::Context::supports_extended_dynamic_state) dynamically changing
+    /// depth and culling state.
+    #[inline]
+    pub fn set_front_face(&mut self, front_face: FrontFace) {
+        self.assert_extended_dynamic_state();
+        self.commands.push(Command::SetFrontFace(front_face));
+    }
+
+    /// Updates the push constants of the bound pipeline for subsequent draws.
+    ///
+    /// # Arguments
+    /// - `stage` - The shader stages the push constants are for. Must match the stages of the
+    /// bound pipeline's push constants.
+    /// - `data` - The new push constant values.
+    ///
+    /// # Panics
+    /// - If no pipeline has been bound.
+    /// - If the bound pipeline has no push constants.
+    /// - If `stage` does not match the stages of the bound pipeline's push constants.
+    /// - If the length of `data` does not match the size of the bound pipeline's push constants.
+    #[inline]
+    pub fn push_constants(&mut self, stage: ShaderStage, data: &[u8]) {
+        assert!(self.bound_pipeline, "no bound graphics pipeline");
+        let range = self
+            .push_constants
+            .expect("the bound graphics pipeline has no push constants");
+        assert_eq!(
+            stage, range.stage,
+            "push constant stage does not match the bound pipeline"
+        );
+        assert_eq!(
+            data.len(),
+            range.size as usize,
+            "push constant size does not match the bound pipeline"
+        );
+        self.commands.push(Command::PushConstants {
+            stage,
+            data: data.to_vec(),
+        });
+    }
+
+    /// Updates the push constants of the bound pipeline for subsequent draws using a typed value.
+    ///
+    /// # Arguments
+    /// - `stage` - The shader stages the push constants are for.
+    /// - `value` - The new push constant values. The layout of `T` must match the push constant
+    /// block declared in the shaders.
+    ///
+    /// # Panics
+    /// - See [`push_constants`](RenderPass::push_constants). In particular, if `size_of::<T>()`
+    /// does not match the size of the bound pipeline's push constants.
+    #[inline]
+    pub fn push_constants_typed<T: Pod>(&mut self, stage: ShaderStage, value: &T) {
+        self.push_constants(stage, bytemuck::bytes_of(value));
+    }
+
     /// Draws an unindexed sequence of triangles.
     ///
     /// # Arguments
@@ -230,4 +380,25 @@ impl<'a, B: Backend> RenderPass<'a, B> {
             stride,
         });
     }
+
+    /// Records a backend specific command within the render pass.
+    ///
+    /// # Arguments
+    /// - `command` - The command to record.
+    ///
+    /// # Safety
+    /// See [`CommandBuffer::raw`](crate::command_buffer::CommandBuffer::raw).
+    #[cfg(feature = "raw")]
+    #[inline(always)]
+    pub unsafe fn raw(&mut self, command: B::RawCommand) {
+        self.commands.push(Command::Raw(command));
+    }
+
+    #[inline(always)]
+    fn assert_extended_dynamic_state(&self) {
+        assert!(
+            self.supports_extended_dynamic_state,
+            "the backend does not support dynamically changing depth and culling state"
+        );
+    }
 }
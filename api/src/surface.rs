@@ -1,11 +1,12 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, time::Duration};
 
 use raw_window_handle::HasRawWindowHandle;
 use thiserror::Error;
 
 use crate::{
     context::Context,
-    types::{PresentMode, TextureFormat},
+    queue::Queue,
+    types::{ColorSpace, PresentMode, TextureFormat},
     Backend,
 };
 
@@ -56,10 +57,32 @@ pub enum SurfaceUpdateError {
     Other(String),
 }
 
+#[derive(Error, Debug)]
+pub enum SurfaceQueryError {
+    #[error("the surface was lost")]
+    Lost,
+    #[error("a error has occured: `{0}`")]
+    Other(String),
+}
+
 #[derive(Error, Debug)]
 pub enum SurfaceImageAcquireError {
     #[error("no available images")]
     NoImages,
+    #[error("timed out waiting for an image to become available")]
+    Timeout,
+    #[error("a error has occured: `{0}`")]
+    Other(String),
+}
+
+#[derive(Error, Debug)]
+pub enum SurfaceCaptureError {
+    #[error("the surface does not support reading back images")]
+    Unsupported,
+    #[error("the image was not drawn to before capturing")]
+    NoRender,
+    #[error("image did not come from this surface")]
+    BadImage,
     #[error("a error has occured: `{0}`")]
     Other(String),
 }
@@ -101,6 +124,37 @@ impl<B: Backend> Surface<B> {
         self.dims
     }
 
+    /// Gets the presentation mode actually used by the surface. This can differ from the mode
+    /// requested in the configuration if the requested mode is not supported, in which case
+    /// [`PresentMode::Fifo`] is used instead.
+    #[inline(always)]
+    pub fn present_mode(&self) -> PresentMode {
+        unsafe { self.ctx.0.surface_present_mode(&self.id) }
+    }
+
+    /// Gets the color space the presentation engine interprets the images of the surface in. The
+    /// requested format is preferably paired with [`ColorSpace::SrgbNonLinear`], but another
+    /// color space is chosen if the surface doesn't support that combination.
+    #[inline(always)]
+    pub fn color_space(&self) -> ColorSpace {
+        unsafe { self.ctx.0.surface_color_space(&self.id) }
+    }
+
+    /// Asks the OS for the current size in pixels of the surface, which can differ from the
+    /// configured [`dimensions`](Surface::dimensions) if the window was resized. Use this to detect
+    /// size changes and [`update the configuration`](Surface::update_config) before acquiring
+    /// the next image.
+    ///
+    /// If the OS lets the surface decide its own size (e.g. on Wayland), the resolution of the
+    /// current configuration is returned. The size is `(0, 0)` while the window is minimized.
+    ///
+    /// # Errors
+    /// - Returns [`SurfaceQueryError::Lost`] if the surface is no longer usable.
+    #[inline(always)]
+    pub fn query_current_extent(&self) -> Result<(u32, u32), SurfaceQueryError> {
+        unsafe { self.ctx.0.surface_current_extent(&self.id) }
+    }
+
     /// Update the configuration of the surface.
     ///
     /// There must not be any images pending presentation before the configuration is updated.
@@ -116,14 +170,57 @@ impl<B: Backend> Surface<B> {
     }
 
     /// Acquire a new image from the surface to present.
+    ///
+    /// # Arguments
+    /// - `timeout` - The time to wait for an image to become available, or `None` if there should
+    /// be no timeout.
+    ///
+    /// # Errors
+    /// - Returns [`SurfaceImageAcquireError::Timeout`] if no image became available before
+    /// `timeout` elapsed. Applications can use this to skip a frame instead of blocking.
     #[inline(always)]
-    pub fn acquire_image(&mut self) -> Result<SurfaceImage<B>, SurfaceImageAcquireError> {
-        let id = unsafe { self.ctx.0.acquire_image(&mut self.id)? };
+    pub fn acquire_image(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<SurfaceImage<B>, SurfaceImageAcquireError> {
+        let id = unsafe { self.ctx.0.acquire_image(&mut self.id, timeout)? };
         Ok(SurfaceImage {
             ctx: self.ctx.clone(),
             id,
         })
     }
+
+    /// Reads back the contents of a surface image that has been rendered to but not yet
+    /// presented. This is useful for taking screenshots.
+    ///
+    /// The returned pixels are tightly packed, row major, 8-bit RGBA values regardless of the
+    /// format of the surface.
+    ///
+    /// # Arguments
+    /// - `queue` - The queue to perform the copy on.
+    /// - `image` - The surface image to read back.
+    ///
+    /// # Panics
+    /// - If `queue` does not [`support transfer commands`](Queue::supports_transfer).
+    ///
+    /// # Synchronization
+    /// This call blocks until all previous work using `image` and the copy itself are complete.
+    pub fn capture(
+        &self,
+        queue: &Queue<B>,
+        image: &SurfaceImage<B>,
+    ) -> Result<Vec<u8>, SurfaceCaptureError> {
+        assert!(
+            queue.supports_transfer(),
+            "queue `{:?}` does not support transfer commands",
+            queue.ty()
+        );
+        unsafe {
+            self.ctx
+                .0
+                .capture_surface_image(queue.ty(), &self.id, &image.id)
+        }
+    }
 }
 
 impl<B: Backend> SurfaceImage<B> {
@@ -131,6 +228,25 @@ impl<B: Backend> SurfaceImage<B> {
     pub fn internal(&self) -> &B::SurfaceImage {
         &self.id
     }
+
+    /// Hands the image back to the surface without presenting it. This is useful when skipping a
+    /// frame (e.g. when the window is minimized). The next image acquired from `surface` will be
+    /// this image.
+    ///
+    /// # Arguments
+    /// - `surface` - The surface the image was acquired from.
+    ///
+    /// # Panics
+    /// - If the image was not acquired from `surface`.
+    /// - If the image has been rendered to. Rendered images *must* be presented.
+    #[inline(always)]
+    pub fn discard(mut self, surface: &mut Surface<B>) {
+        unsafe {
+            self.ctx
+                .0
+                .discard_surface_image(&mut surface.id, &mut self.id);
+        }
+    }
 }
 
 impl<B: Backend> Drop for Surface<B> {
@@ -1,8 +1,13 @@
+use std::ptr::NonNull;
+
 use crate::{
+    buffer::Buffer,
+    command_buffer::BufferTextureCopy,
     context::Context,
+    queue::Job,
     types::{
-        AnisotropyLevel, CompareOp, Filter, MemoryUsage, SamplerAddressMode, TextureFormat,
-        TextureType, TextureUsage,
+        AnisotropyLevel, CompareOp, Filter, MemoryUsage, SamplerAddressMode, SharingMode,
+        TextureFormat, TextureTiling, TextureType, TextureUsage,
     },
     Backend,
 };
@@ -19,6 +24,24 @@ pub struct TextureCreateInfo {
     pub mip_levels: usize,
     pub texture_usage: TextureUsage,
     pub memory_usage: MemoryUsage,
+    /// Textures that are [`mapped`](Texture::map) *must* use [`TextureTiling::Linear`].
+    pub tiling: TextureTiling,
+    /// Describes how the texture is shared between queues.
+    ///
+    /// Use [`SharingMode::Concurrent`] for textures that are produced on one queue and consumed on
+    /// another (e.g. written by compute and sampled by graphics). Some hardware disables texture
+    /// compression for concurrently shared textures, so prefer [`SharingMode::Exclusive`] when a
+    /// texture is only used on one queue.
+    pub sharing: SharingMode,
+    /// Additional formats the texture can be viewed as, e.g. when rendering into an sRGB view of
+    /// a UNORM texture (see
+    /// [`ColorAttachmentSource::Texture`](crate::render_pass::ColorAttachmentSource::Texture)).
+    /// Each format *must* be [`view compatible`](TextureFormat::is_view_compatible) with
+    /// `format`. Views in another format only support the usages of `texture_usage` that their
+    /// format supports (e.g. sRGB formats usually can't be used for storage), and creation fails
+    /// if a view format supports none of them. Textures with view formats *may* be slower to
+    /// access, so leave this empty unless it is needed.
+    pub view_formats: Vec<TextureFormat>,
     pub debug_name: Option<String>,
 }
 
@@ -39,24 +62,271 @@ pub struct Sampler {
 
 #[derive(Debug, Error)]
 pub enum TextureCreateError {
+    #[error("the texture format does not support mipmap generation")]
+    MipmapGenerationUnsupported,
+    #[error("the texture format does not support the requested usage")]
+    UnsupportedUsage,
+    #[error("sparse residency is not supported for the requested texture")]
+    SparseUnsupported,
+    #[error("the texture format, type, or usage is not supported with the requested tiling")]
+    UnsupportedTiling,
+    #[error("the view format `{0:?}` does not support any of the requested texture usages")]
+    UnsupportedViewFormat(TextureFormat),
+    #[error("an error has occured: {0}")]
+    Other(String),
+}
+
+#[derive(Debug, Error)]
+pub enum SparseBindError {
+    #[error("unable to allocate memory for a sparse texture page: {0}")]
+    AllocationFailed(String),
+    #[error("an error has occured: {0}")]
+    Other(String),
+}
+
+#[derive(Debug, Error)]
+pub enum TextureViewError {
+    #[error("the texture is not mapable")]
+    NotMapable,
     #[error("an error has occured: {0}")]
     Other(String),
 }
 
+/// Describes where the texels of a mapped texture subresource are in memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TextureSubresourceLayout {
+    /// The size in bytes of the subresource.
+    pub size: u64,
+    /// The number of bytes between the start of each row of texels.
+    pub row_pitch: u64,
+    /// The number of bytes between the start of each depth slice of a 3D texture.
+    pub depth_pitch: u64,
+}
+
+/// A view into the memory of a single array element and mip level of a texture. Created with
+/// [`Texture::map`].
+pub struct TextureMapping<'a, B: Backend> {
+    texture: &'a mut Texture<B>,
+    array_element: usize,
+    mip_level: usize,
+    map: NonNull<u8>,
+    layout: TextureSubresourceLayout,
+}
+
+/// Describes how the memory of a sparse texture is divided into pages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SparseProperties {
+    /// The dimensions of a single page in texels.
+    pub page_dims: (u32, u32, u32),
+    /// The first mip level that is part of the mip tail. Mip levels in the tail are always
+    /// resident and can't be bound or unbound.
+    pub mip_tail_first_level: usize,
+}
+
+/// Maps or unmaps a single page of a sparse texture. See [`Context::bind_sparse`].
+pub struct SparseTextureBind<'a, B: Backend> {
+    /// The texture to bind memory to. *Must* have been created with
+    /// [`Texture::new_sparse`].
+    pub texture: &'a Texture<B>,
+    pub array_element: usize,
+    /// *Must* be less than [`SparseProperties::mip_tail_first_level`].
+    pub mip_level: usize,
+    /// Coordinates of the page within the mip level, in pages (not texels).
+    pub page: (u32, u32, u32),
+    /// `true` to make the page resident, or `false` to release its memory.
+    pub resident: bool,
+}
+
 pub struct Texture<B: Backend> {
     ctx: Context<B>,
     dims: (u32, u32, u32),
+    format: TextureFormat,
+    view_formats: Vec<TextureFormat>,
+    array_elements: usize,
+    mip_count: usize,
+    sparse: Option<SparseProperties>,
     pub(crate) id: B::Texture,
 }
 
 impl<B: Backend> Texture<B> {
+    /// Creates a new texture.
+    ///
+    /// # Arguments
+    /// - `ctx` - The [`Context`] to create the texture with.
+    /// - `create_info` - Describes the texture to create.
+    ///
+    /// # Panics
+    /// - If a format in `create_info.view_formats` is not
+    /// [`view compatible`](TextureFormat::is_view_compatible) with `create_info.format`.
     pub fn new(
         ctx: Context<B>,
         create_info: TextureCreateInfo,
     ) -> Result<Self, TextureCreateError> {
         let dims = (create_info.width, create_info.height, create_info.depth);
+        let format = create_info.format;
+        let view_formats = create_info.view_formats.clone();
+        for view_format in &view_formats {
+            assert!(
+                format.is_view_compatible(*view_format),
+                "texture format `{:?}` can't be viewed as `{:?}`",
+                format,
+                view_format
+            );
+        }
+        let array_elements = create_info.array_elements;
+        let mip_count = create_info.mip_levels;
         let id = unsafe { ctx.0.create_texture(create_info)? };
-        Ok(Self { ctx, dims, id })
+        Ok(Self {
+            ctx,
+            dims,
+            format,
+            view_formats,
+            array_elements,
+            mip_count,
+            sparse: None,
+            id,
+        })
+    }
+
+    /// Creates a new texture with sparse residency. No memory is bound to the texture except for
+    /// its mip tail, so it may be much larger than available memory. Pages are made resident on
+    /// demand with [`Context::bind_sparse`].
+    ///
+    /// Reading from a page that isn't resident returns undefined values, and writes to it are
+    /// discarded.
+    ///
+    /// # Arguments
+    /// - `ctx` - The [`Context`] to create the texture with.
+    /// - `create_info` - Describes the texture to create.
+    ///
+    /// # Errors
+    /// - Returns [`TextureCreateError::SparseUnsupported`] if the device doesn't support sparse
+    /// textures (see [`Context::supports_sparse_textures`]) or the format can't be used with
+    /// sparse residency.
+    ///
+    /// # Panics
+    /// - If `create_info.ty` is not [`TextureType::Type2D`].
+    /// - If `create_info.tiling` is not [`TextureTiling::Optimal`].
+    /// - If a format in `create_info.view_formats` is not
+    /// [`view compatible`](TextureFormat::is_view_compatible) with `create_info.format`.
+    pub fn new_sparse(
+        ctx: Context<B>,
+        create_info: TextureCreateInfo,
+    ) -> Result<Self, TextureCreateError> {
+        assert_eq!(
+            create_info.ty,
+            TextureType::Type2D,
+            "only 2D textures support sparse residency"
+        );
+        assert_eq!(
+            create_info.tiling,
+            TextureTiling::Optimal,
+            "sparse textures must use optimal tiling"
+        );
+        if !ctx.supports_sparse_textures() {
+            return Err(TextureCreateError::SparseUnsupported);
+        }
+
+        let dims = (create_info.width, create_info.height, create_info.depth);
+        let format = create_info.format;
+        let view_formats = create_info.view_formats.clone();
+        for view_format in &view_formats {
+            assert!(
+                format.is_view_compatible(*view_format),
+                "texture format `{:?}` can't be viewed as `{:?}`",
+                format,
+                view_format
+            );
+        }
+        let array_elements = create_info.array_elements;
+        let mip_count = create_info.mip_levels;
+        let id = unsafe { ctx.0.create_sparse_texture(create_info)? };
+        let sparse = unsafe { ctx.0.texture_sparse_properties(&id) };
+        Ok(Self {
+            ctx,
+            dims,
+            format,
+            view_formats,
+            array_elements,
+            mip_count,
+            sparse,
+            id,
+        })
+    }
+
+    /// Creates a new texture, uploads the first mip level, and generates the rest of the mip
+    /// chain from it on the [`main`](Context::main) queue.
+    ///
+    /// This is a helper function which does the following:
+    /// 1. Create the texture with [`TRANSFER_SRC`](TextureUsage) and
+    /// [`TRANSFER_DST`](TextureUsage) added to the requested usage.
+    /// 2. Copy `data` into the first mip level through a [`staging`](Buffer::new_staging) buffer.
+    /// 3. [`Generate`](crate::command_buffer::CommandBuffer::generate_mipmaps) the remaining
+    /// `create_info.mip_levels - 1` mip levels.
+    ///
+    /// # Arguments
+    /// - `ctx` - The [`Context`] to create the texture with.
+    /// - `create_info` - Describes the texture to create.
+    /// - `data` - Tightly packed texel data for the first mip level of the texture.
+    ///
+    /// # Synchronization
+    /// The returned [`Job`] completes once the upload and mip generation are complete. Any use of
+    /// the texture after the job is submitted is synchronized by the backend, so waiting on the job
+    /// is only required to know when the work has finished.
+    ///
+    /// # Errors
+    /// - Returns [`TextureCreateError::MipmapGenerationUnsupported`] if the texture format does
+    /// not support mipmap generation (see [`Context::supports_mipmap_generation`]).
+    ///
+    /// # Panics
+    /// - If `create_info.array_elements` is not `1`.
+    /// - If `data.is_empty()`.
+    pub fn new_with_mips(
+        ctx: Context<B>,
+        mut create_info: TextureCreateInfo,
+        data: &[u8],
+    ) -> Result<(Self, Job<B>), TextureCreateError> {
+        assert_eq!(
+            create_info.array_elements, 1,
+            "mipmap generation only supports textures with one array element"
+        );
+        if !ctx.supports_mipmap_generation(create_info.format) {
+            return Err(TextureCreateError::MipmapGenerationUnsupported);
+        }
+
+        create_info.texture_usage |= TextureUsage::TRANSFER_SRC | TextureUsage::TRANSFER_DST;
+        let staging_name = create_info
+            .debug_name
+            .as_ref()
+            .map(|name| format!("{}_staging", name));
+        let texture = Texture::new(ctx.clone(), create_info)?;
+        let staging = match Buffer::new_staging(ctx.clone(), staging_name, data) {
+            Ok(staging) => staging,
+            Err(err) => return Err(TextureCreateError::Other(err.to_string())),
+        };
+
+        let job = ctx
+            .main()
+            .submit(Some("texture_mip_generation"), |commands| {
+                commands.copy_buffer_to_texture(
+                    &texture,
+                    &staging,
+                    BufferTextureCopy {
+                        buffer_offset: 0,
+                        buffer_row_length: 0,
+                        buffer_image_height: 0,
+                        buffer_array_element: 0,
+                        texture_offset: (0, 0, 0),
+                        texture_extent: texture.dims,
+                        texture_mip_level: 0,
+                        texture_array_element: 0,
+                        texture_aspect: None,
+                    },
+                );
+                commands.generate_mipmaps(&texture, 0);
+            });
+
+        Ok((texture, job))
     }
 
     #[inline(always)]
@@ -68,6 +338,77 @@ impl<B: Backend> Texture<B> {
     pub fn dims(&self) -> (u32, u32, u32) {
         self.dims
     }
+
+    #[inline(always)]
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    /// The additional formats the texture can be viewed as.
+    #[inline(always)]
+    pub fn view_formats(&self) -> &[TextureFormat] {
+        &self.view_formats
+    }
+
+    #[inline(always)]
+    pub fn array_elements(&self) -> usize {
+        self.array_elements
+    }
+
+    #[inline(always)]
+    pub fn mip_count(&self) -> usize {
+        self.mip_count
+    }
+
+    /// Maps the memory of an array element and mip level of the texture so it can be accessed by
+    /// the CPU.
+    ///
+    /// # Arguments
+    /// - `array_element` - The array element to map.
+    /// - `mip_level` - The mip level to map.
+    ///
+    /// # Synchronization
+    /// The backend *must* wait for all GPU work using the texture to complete before the texture
+    /// is mapped. Writes made through the mapping are visible to work submitted after the mapping
+    /// is dropped.
+    ///
+    /// # Errors
+    /// - Returns [`TextureViewError::NotMapable`] if the texture was not created with
+    /// [`TextureTiling::Linear`] and a `memory_usage` equal to [`MemoryUsage::CpuToGpu`],
+    /// [`MemoryUsage::GpuToCpu`], or [`MemoryUsage::CpuOnly`].
+    ///
+    /// # Panics
+    /// - If `array_element` or `mip_level` are out of bounds.
+    pub fn map(
+        &mut self,
+        array_element: usize,
+        mip_level: usize,
+    ) -> Result<TextureMapping<'_, B>, TextureViewError> {
+        assert!(
+            array_element < self.array_elements,
+            "`array_element` is out of bounds"
+        );
+        assert!(mip_level < self.mip_count, "`mip_level` is out of bounds");
+        let (map, layout) = unsafe {
+            self.ctx
+                .0
+                .map_texture(&mut self.id, array_element, mip_level)?
+        };
+        Ok(TextureMapping {
+            texture: self,
+            array_element,
+            mip_level,
+            map,
+            layout,
+        })
+    }
+
+    /// Returns how the texture is divided into pages, or `None` if it wasn't created with
+    /// [`Texture::new_sparse`].
+    #[inline(always)]
+    pub fn sparse_properties(&self) -> Option<SparseProperties> {
+        self.sparse
+    }
 }
 
 impl<B: Backend> Drop for Texture<B> {
@@ -79,6 +420,75 @@ impl<B: Backend> Drop for Texture<B> {
     }
 }
 
+impl<'a, B: Backend> TextureMapping<'a, B> {
+    /// Describes how the texels of the subresource are laid out in the mapping.
+    #[inline(always)]
+    pub fn layout(&self) -> TextureSubresourceLayout {
+        self.layout
+    }
+
+    #[inline(always)]
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.map.as_ptr(), self.layout.size as usize) }
+    }
+
+    #[inline(always)]
+    pub fn as_slice_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.map.as_ptr(), self.layout.size as usize) }
+    }
+
+    /// Provides a view into the texels of a single row of the subresource. Rows are
+    /// [`row_pitch`](TextureSubresourceLayout::row_pitch) bytes apart, which may be larger than
+    /// the size of the row's texels.
+    ///
+    /// # Arguments
+    /// - `y` - The row within the depth slice.
+    /// - `z` - The depth slice. Must be `0` for 1D and 2D textures.
+    ///
+    /// # Panics
+    /// - If `y` or `z` are out of bounds for the mip level.
+    #[inline(always)]
+    pub fn row(&self, y: u32, z: u32) -> &[u8] {
+        let range = self.row_range(y, z);
+        &self.as_slice()[range]
+    }
+
+    /// Like [`row`](TextureMapping::row), but provides a mutable view.
+    ///
+    /// # Panics
+    /// - If `y` or `z` are out of bounds for the mip level.
+    #[inline(always)]
+    pub fn row_mut(&mut self, y: u32, z: u32) -> &mut [u8] {
+        let range = self.row_range(y, z);
+        &mut self.as_slice_mut()[range]
+    }
+
+    fn row_range(&self, y: u32, z: u32) -> std::ops::Range<usize> {
+        let dims = self.texture.dims();
+        let height = (dims.1 >> self.mip_level).max(1);
+        let depth = (dims.2 >> self.mip_level).max(1);
+        assert!(y < height, "`y` is out of bounds");
+        assert!(z < depth, "`z` is out of bounds");
+        let width = (dims.0 >> self.mip_level).max(1) as u64;
+        let start = z as u64 * self.layout.depth_pitch + y as u64 * self.layout.row_pitch;
+        let len = width * self.texture.format().bytes_per_texel() as u64;
+        start as usize..(start + len) as usize
+    }
+}
+
+impl<'a, B: Backend> Drop for TextureMapping<'a, B> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        unsafe {
+            self.texture.ctx.0.flush_texture(
+                &mut self.texture.id,
+                self.array_element,
+                self.mip_level,
+            );
+        }
+    }
+}
+
 impl Default for TextureCreateInfo {
     #[inline(always)]
     fn default() -> Self {
@@ -92,6 +502,9 @@ impl Default for TextureCreateInfo {
             mip_levels: 1,
             texture_usage: TextureUsage::empty(),
             memory_usage: MemoryUsage::GpuOnly,
+            tiling: TextureTiling::Optimal,
+            sharing: SharingMode::Exclusive,
+            view_formats: Vec::default(),
             debug_name: None,
         }
     }
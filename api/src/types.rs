@@ -4,7 +4,16 @@ use bitflags::bitflags;
 pub enum TextureFormat {
     R8Unorm,
     Rgba8Unorm,
+    /// Like [`Rgba8Unorm`](TextureFormat::Rgba8Unorm), but texels are stored in the sRGB color
+    /// space and converted to and from linear values when read and written.
+    Rgba8Srgb,
     Bgra8Unorm,
+    /// Like [`Bgra8Unorm`](TextureFormat::Bgra8Unorm), but texels are stored in the sRGB color
+    /// space and converted to and from linear values when read and written.
+    Bgra8Srgb,
+    /// Packed unsigned floating point format with 11 bits for red and green and 10 bits for blue.
+    /// Useful for HDR render targets which don't need an alpha channel.
+    Rg11B10Float,
     D16Unorm,
     D24UnormS8Uint,
     D32Sfloat,
@@ -120,7 +129,8 @@ pub enum QueueType {
     /// The transfer queue is guaranteed to support compute operations and usually operates
     /// asynchronously to other queues.
     Compute,
-    /// The transfer queue is guaranteed to support surface presentation.
+    /// The transfer queue is guaranteed to support surface presentation. It *may* also support
+    /// transfer operations (see [`Queue::supports_transfer`](crate::queue::Queue::supports_transfer)).
     Present,
 }
 
@@ -146,6 +156,13 @@ pub enum LoadOp {
 pub enum ClearColor {
     RgbaF32(f32, f32, f32, f32),
     RU32(u32),
+    /// Clears the depth aspect of a depth stencil attachment. The stencil aspect, if any, is
+    /// cleared to `0`.
+    Depth(f32),
+    /// Clears the depth and stencil aspects of a depth stencil attachment. Only the low 8 bits of
+    /// the stencil value are used. The stencil value is ignored for depth only formats.
+    DepthStencil(f32, u32),
+    #[deprecated(note = "use `ClearColor::Depth` or `ClearColor::DepthStencil` instead")]
     D32S32(f32, u32),
 }
 
@@ -170,6 +187,31 @@ pub enum PresentMode {
     FifoRelaxed,
 }
 
+/// The color space the presentation engine interprets the images of a surface in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ColorSpace {
+    SrgbNonLinear,
+    ExtendedSrgbLinear,
+    ExtendedSrgbNonLinear,
+    DisplayP3Linear,
+    DisplayP3NonLinear,
+    DciP3NonLinear,
+    Bt709Linear,
+    Bt709NonLinear,
+    Bt2020Linear,
+    Hdr10St2084,
+    Hdr10Hlg,
+    DolbyVision,
+    AdobeRgbLinear,
+    AdobeRgbNonLinear,
+    /// Color components are passed through to the display as is.
+    PassThrough,
+    /// The native color space of the display.
+    DisplayNative,
+    /// A color space that doesn't have an equivalent in this crate.
+    Other,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ShaderStage {
     AllGraphics,
@@ -184,6 +226,14 @@ pub enum Filter {
     Linear,
 }
 
+/// A single aspect of a texture.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TextureAspect {
+    Color,
+    Depth,
+    Stencil,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ReductionMode {
     Min,
@@ -236,12 +286,49 @@ pub enum TextureType {
     Type3D,
 }
 
+/// How the texels of a texture are arranged in memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TextureTiling {
+    /// An implementation defined arrangement that is the most efficient for the GPU to access.
+    Optimal,
+    /// Texels are laid out row by row, which allows the texture to be
+    /// [`mapped`](crate::texture::Texture::map) and accessed by the CPU. Devices support very
+    /// few formats and usages with linear tiling, and GPU access is usually slower.
+    Linear,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum MemoryUsage {
     Unknown,
     GpuOnly,
     CpuToGpu,
     GpuToCpu,
+    /// Host visible memory that always lives in system memory. Unlike
+    /// [`CpuToGpu`](MemoryUsage::CpuToGpu), it's never placed in the small device local and host
+    /// visible heap, so it's always available at the cost of slower device access.
+    CpuOnly,
+}
+
+/// Describes what the memory of a [`staging`](crate::buffer::Buffer::new_staging_with_hint)
+/// buffer should be optimized for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum StagingMemoryHint {
+    /// Prefer memory that is both device local and host visible, which is fast to upload from on
+    /// systems with resizable BAR. Falls back to system memory when unavailable.
+    Bandwidth,
+    /// Always use system memory. Device local and host visible memory is often limited to 256MiB,
+    /// so this is the better choice for large or long lived staging buffers.
+    Availability,
+}
+
+/// Describes how a resource is shared between queues.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SharingMode {
+    /// The resource is owned by one queue at a time. This is usually the most performant option.
+    Exclusive,
+    /// The resource can be used by every queue at the same time without transfering ownership
+    /// between them, at a small performance cost.
+    Concurrent,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -250,6 +337,25 @@ pub enum AccessType {
     ReadWrite,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ValidationSeverity {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+/// A message reported by the debugging layers of the backend.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ValidationMessage {
+    pub severity: ValidationSeverity,
+    /// Backend specific identifier for the kind of message.
+    pub id: i32,
+    /// Human readable name of `id`.
+    pub id_name: String,
+    pub message: String,
+}
+
 impl TextureFormat {
     #[inline(always)]
     pub fn is_color(&self) -> bool {
@@ -274,4 +380,115 @@ impl TextureFormat {
             _ => false,
         }
     }
+
+    /// The size in bytes of a single texel of the format.
+    #[inline(always)]
+    pub fn bytes_per_texel(&self) -> u32 {
+        match *self {
+            TextureFormat::R8Unorm => 1,
+            TextureFormat::D16Unorm => 2,
+            TextureFormat::Rgba8Unorm
+            | TextureFormat::Rgba8Srgb
+            | TextureFormat::Bgra8Unorm
+            | TextureFormat::Bgra8Srgb
+            | TextureFormat::Rg11B10Float
+            | TextureFormat::D24UnormS8Uint
+            | TextureFormat::D32Sfloat => 4,
+            TextureFormat::D32SfloatS8Uint => 8,
+        }
+    }
+
+    /// The size in bytes of a single texel of one aspect of the format, as laid out in buffer
+    /// memory by copies of that aspect. Depth is stored in 4 bytes for 24 and 32 bit depth
+    /// formats and stencil is always stored in 1 byte.
+    #[inline(always)]
+    pub fn bytes_per_aspect_texel(&self, aspect: TextureAspect) -> u32 {
+        match aspect {
+            TextureAspect::Color => self.bytes_per_texel(),
+            TextureAspect::Depth => match *self {
+                TextureFormat::D16Unorm => 2,
+                _ => 4,
+            },
+            TextureAspect::Stencil => 1,
+        }
+    }
+
+    /// Checks if a texture of this format can be viewed as the format `other` (e.g. to render
+    /// into an sRGB view of a UNORM texture). Color formats with the same texel size are view
+    /// compatible.
+    #[inline(always)]
+    pub fn is_view_compatible(&self, other: TextureFormat) -> bool {
+        self.is_color() && other.is_color() && self.bytes_per_texel() == other.bytes_per_texel()
+    }
+
+    /// Checks if the format has the given aspect.
+    #[inline(always)]
+    pub fn has_aspect(&self, aspect: TextureAspect) -> bool {
+        match aspect {
+            TextureAspect::Color => self.is_color(),
+            TextureAspect::Depth => self.is_depth(),
+            TextureAspect::Stencil => self.is_stencil(),
+        }
+    }
+
+    /// The aspect used by copies that don't specify one. This is the depth aspect for formats
+    /// with depth and the color aspect otherwise.
+    #[inline(always)]
+    pub fn default_aspect(&self) -> TextureAspect {
+        if self.is_depth() {
+            TextureAspect::Depth
+        } else if self.is_stencil() {
+            TextureAspect::Stencil
+        } else {
+            TextureAspect::Color
+        }
+    }
+
+    /// Checks if texel data of this format can be converted to the format `other` by reordering
+    /// channels with [`reorder_channels`](TextureFormat::reorder_channels).
+    #[inline(always)]
+    pub fn is_reorderable_to(&self, other: TextureFormat) -> bool {
+        matches!(
+            (*self, other),
+            (TextureFormat::Rgba8Unorm, TextureFormat::Bgra8Unorm)
+                | (TextureFormat::Bgra8Unorm, TextureFormat::Rgba8Unorm)
+                | (TextureFormat::Rgba8Srgb, TextureFormat::Bgra8Srgb)
+                | (TextureFormat::Bgra8Srgb, TextureFormat::Rgba8Srgb)
+        ) || *self == other
+    }
+
+    /// Converts tightly packed texel data of this format to the channel order of the format
+    /// `dst` in place. Texture copies don't convert between formats, so this is used to prepare
+    /// data for (or interpret data from) a texture whose format has a different channel order,
+    /// like uploading RGBA data to a [`Bgra8Unorm`](TextureFormat::Bgra8Unorm) texture.
+    ///
+    /// # Arguments
+    /// - `dst` - The format to convert the texels to.
+    /// - `data` - The texels to convert.
+    ///
+    /// # Panics
+    /// - If the format can't be [`reordered`](TextureFormat::is_reorderable_to) to `dst`.
+    /// - If the length of `data` is not a multiple of the size of a texel.
+    pub fn reorder_channels(&self, dst: TextureFormat, data: &mut [u8]) {
+        assert!(
+            self.is_reorderable_to(dst),
+            "texels of format `{:?}` can't be reordered to format `{:?}`",
+            self,
+            dst
+        );
+        assert_eq!(
+            data.len() % self.bytes_per_texel() as usize,
+            0,
+            "texel data must be a multiple of the texel size"
+        );
+
+        if *self == dst {
+            return;
+        }
+
+        // Only 8-bit RGBA and BGRA are reorderable, which differ by swapping red and blue
+        for texel in data.chunks_exact_mut(4) {
+            texel.swap(0, 2);
+        }
+    }
 }
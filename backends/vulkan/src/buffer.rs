@@ -9,7 +9,11 @@ use ash::vk::{self, Handle};
 use crossbeam_channel::Sender;
 use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, Allocator};
 
-use crate::{job::Job, util::garbage_collector::Garbage, VulkanBackend};
+use crate::{
+    job::Job,
+    util::garbage_collector::{dispose, Garbage},
+    VulkanBackend,
+};
 
 pub struct Buffer {
     pub(crate) buffer: vk::Buffer,
@@ -22,12 +26,32 @@ pub struct Buffer {
     /// This is the per element size after alignment.
     pub(crate) aligned_size: u64,
     pub(crate) ref_counter: BufferRefCounter,
+    /// Set if the buffer is placed in the memory of another buffer.
+    pub(crate) alias: Option<BufferAlias>,
     on_drop: Sender<Garbage>,
 }
 
 #[derive(Clone)]
 pub(crate) struct BufferRefCounter(Arc<()>);
 
+pub(crate) struct BufferAlias {
+    /// The buffer that owns the aliased memory.
+    pub root: vk::Buffer,
+    /// Memory the buffer is bound to.
+    memory: vk::DeviceMemory,
+    /// Offset of the buffer within `memory`.
+    offset: u64,
+    /// Pointer to the start of the buffer if the memory is mapped.
+    mapped: Option<NonNull<u8>>,
+    /// Keeps the source buffer, and thus the memory, alive while it is aliased.
+    _source_ref: BufferRefCounter,
+}
+
+// The mapped pointer points into memory owned by the root buffer, which is kept alive by the
+// reference counter.
+unsafe impl Send for BufferAlias {}
+unsafe impl Sync for BufferAlias {}
+
 impl Buffer {
     pub(crate) unsafe fn new(
         device: &ash::Device,
@@ -35,11 +59,157 @@ impl Buffer {
         on_drop: Sender<Garbage>,
         allocator: &mut Allocator,
         limits: &vk::PhysicalDeviceLimits,
+        queue_families: &[u32],
+        create_info: BufferCreateInfo,
+    ) -> Result<Self, BufferCreateError> {
+        let (buffer, aligned_size) =
+            Self::create_buffer(device, limits, queue_families, &create_info)?;
+
+        // Allocate memory
+        let mem_reqs = device.get_buffer_memory_requirements(buffer);
+        let request = AllocationCreateDesc {
+            name: match &create_info.debug_name {
+                Some(name) => &name,
+                None => "buffer",
+            },
+            requirements: mem_reqs,
+            location: crate::util::to_gpu_allocator_memory_location(create_info.memory_usage),
+            linear: true,
+        };
+        let block = match allocator.allocate(&request) {
+            Ok(block) => block,
+            Err(err) => {
+                device.destroy_buffer(buffer, None);
+                return Err(BufferCreateError::Other(err.to_string()));
+            }
+        };
+
+        // Bind buffer to memory
+        if let Err(err) = device.bind_buffer_memory(buffer, block.memory(), block.offset()) {
+            allocator.free(block).unwrap();
+            device.destroy_buffer(buffer, None);
+            return Err(BufferCreateError::Other(err.to_string()));
+        }
+
+        Self::set_debug_name(device, debug, buffer, create_info.debug_name);
+
+        Ok(Buffer {
+            buffer,
+            block: ManuallyDrop::new(block),
+            size: create_info.size,
+            aligned_size,
+            array_elements: create_info.array_elements,
+            buffer_usage: create_info.buffer_usage,
+            memory_usage: create_info.memory_usage,
+            on_drop,
+            ref_counter: BufferRefCounter::default(),
+            alias: None,
+        })
+    }
+
+    /// Creates a buffer placed in the memory of `source`, starting `offset` bytes into it.
+    ///
+    /// The caller is responsible for registering the alias with the global resource tracker.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) unsafe fn new_aliased(
+        device: &ash::Device,
+        debug: Option<&ash::extensions::ext::DebugUtils>,
+        on_drop: Sender<Garbage>,
+        limits: &vk::PhysicalDeviceLimits,
+        queue_families: &[u32],
         create_info: BufferCreateInfo,
+        source: &Buffer,
+        offset: u64,
     ) -> Result<Self, BufferCreateError> {
+        let (buffer, aligned_size) =
+            Self::create_buffer(device, limits, queue_families, &create_info)?;
+
+        // The memory type of the source must be usable by the new buffer. The source was
+        // allocated with one of its own supported types, so requiring a superset of those is
+        // enough.
+        let mem_reqs = device.get_buffer_memory_requirements(buffer);
+        let source_reqs = device.get_buffer_memory_requirements(source.buffer);
+        let err = if mem_reqs.memory_type_bits & source_reqs.memory_type_bits
+            != source_reqs.memory_type_bits
+        {
+            Some(BufferCreateError::AliasIncompatible)
+        } else if !offset.is_multiple_of(mem_reqs.alignment) {
+            Some(BufferCreateError::AliasMisaligned(mem_reqs.alignment))
+        } else if offset + mem_reqs.size > source.aligned_size * source.array_elements as u64 {
+            Some(BufferCreateError::AliasOutOfBounds)
+        } else {
+            None
+        };
+        if let Some(err) = err {
+            device.destroy_buffer(buffer, None);
+            return Err(err);
+        }
+
+        // Bind to the memory of the source
+        let (memory, source_offset) = source.memory();
+        let alias = BufferAlias {
+            root: source.root(),
+            memory,
+            offset: source_offset + offset,
+            mapped: source
+                .mapped_ptr()
+                .map(|ptr| NonNull::new_unchecked(ptr.as_ptr().add(offset as usize))),
+            _source_ref: source.ref_counter.clone(),
+        };
+        if let Err(err) = device.bind_buffer_memory(buffer, alias.memory, alias.offset) {
+            device.destroy_buffer(buffer, None);
+            return Err(BufferCreateError::Other(err.to_string()));
+        }
+
+        Self::set_debug_name(device, debug, buffer, create_info.debug_name);
+
+        Ok(Buffer {
+            buffer,
+            block: ManuallyDrop::new(Allocation::default()),
+            size: create_info.size,
+            aligned_size,
+            array_elements: create_info.array_elements,
+            buffer_usage: create_info.buffer_usage,
+            memory_usage: create_info.memory_usage,
+            on_drop,
+            ref_counter: BufferRefCounter::default(),
+            alias: Some(alias),
+        })
+    }
+
+    /// Creates the buffer object without any memory bound to it. Returns the buffer and the size
+    /// of each array element after alignment.
+    unsafe fn create_buffer(
+        device: &ash::Device,
+        limits: &vk::PhysicalDeviceLimits,
+        queue_families: &[u32],
+        create_info: &BufferCreateInfo,
+    ) -> Result<(vk::Buffer, u64), BufferCreateError> {
+        // Each array element must be bindable as a whole
+        if create_info
+            .buffer_usage
+            .contains(BufferUsage::UNIFORM_BUFFER)
+            && create_info.size > limits.max_uniform_buffer_range as u64
+        {
+            return Err(BufferCreateError::UniformBufferTooLarge(
+                limits.max_uniform_buffer_range as u64,
+            ));
+        }
+        if create_info
+            .buffer_usage
+            .contains(BufferUsage::STORAGE_BUFFER)
+            && create_info.size > limits.max_storage_buffer_range as u64
+        {
+            return Err(BufferCreateError::StorageBufferTooLarge(
+                limits.max_storage_buffer_range as u64,
+            ));
+        }
+
         // Determine memory alignment requirements
         let mut alignment_req = 0;
-        if create_info.memory_usage == MemoryUsage::CpuToGpu {
+        if create_info.memory_usage == MemoryUsage::CpuToGpu
+            || create_info.memory_usage == MemoryUsage::CpuOnly
+        {
             alignment_req = alignment_req.max(limits.non_coherent_atom_size);
         }
         if create_info
@@ -65,44 +235,30 @@ impl Buffer {
         };
 
         // Create the buffer
+        let (sharing_mode, queue_families) =
+            crate::util::to_vk_sharing_mode(create_info.sharing, queue_families);
         let buffer_create_info = vk::BufferCreateInfo::builder()
             .size(aligned_size * create_info.array_elements as u64)
             .usage(crate::util::to_vk_buffer_usage(create_info.buffer_usage))
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .sharing_mode(sharing_mode)
+            .queue_family_indices(queue_families)
             .build();
         let buffer = match device.create_buffer(&buffer_create_info, None) {
             Ok(buffer) => buffer,
             Err(err) => return Err(BufferCreateError::Other(err.to_string())),
         };
 
-        // Allocate memory
-        let mem_reqs = device.get_buffer_memory_requirements(buffer);
-        let request = AllocationCreateDesc {
-            name: match &create_info.debug_name {
-                Some(name) => &name,
-                None => "buffer",
-            },
-            requirements: mem_reqs,
-            location: crate::util::to_gpu_allocator_memory_location(create_info.memory_usage),
-            linear: true,
-        };
-        let block = match allocator.allocate(&request) {
-            Ok(block) => block,
-            Err(err) => {
-                device.destroy_buffer(buffer, None);
-                return Err(BufferCreateError::Other(err.to_string()));
-            }
-        };
-
-        // Bind buffer to memory
-        if let Err(err) = device.bind_buffer_memory(buffer, block.memory(), block.offset()) {
-            allocator.free(block).unwrap();
-            device.destroy_buffer(buffer, None);
-            return Err(BufferCreateError::Other(err.to_string()));
-        }
+        Ok((buffer, aligned_size))
+    }
 
+    unsafe fn set_debug_name(
+        device: &ash::Device,
+        debug: Option<&ash::extensions::ext::DebugUtils>,
+        buffer: vk::Buffer,
+        debug_name: Option<String>,
+    ) {
         // Setup debug name is requested
-        if let Some(name) = create_info.debug_name {
+        if let Some(name) = debug_name {
             if let Some(debug) = debug {
                 let name = CString::new(name).unwrap();
                 let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
@@ -116,18 +272,36 @@ impl Buffer {
                     .unwrap();
             }
         }
+    }
 
-        Ok(Buffer {
-            buffer,
-            block: ManuallyDrop::new(block),
-            size: create_info.size,
-            aligned_size,
-            array_elements: create_info.array_elements,
-            buffer_usage: create_info.buffer_usage,
-            memory_usage: create_info.memory_usage,
-            on_drop,
-            ref_counter: BufferRefCounter::default(),
-        })
+    /// The buffer whose memory this buffer is in. This is the buffer itself if it isn't aliased.
+    #[inline(always)]
+    pub(crate) fn root(&self) -> vk::Buffer {
+        match &self.alias {
+            Some(alias) => alias.root,
+            None => self.buffer,
+        }
+    }
+
+    /// The memory the buffer is bound to and the offset of the buffer within it.
+    #[inline(always)]
+    pub(crate) fn memory(&self) -> (vk::DeviceMemory, u64) {
+        match &self.alias {
+            Some(alias) => (alias.memory, alias.offset),
+            None => unsafe { (self.block.memory(), self.block.offset()) },
+        }
+    }
+
+    /// Pointer to the start of the buffer if its memory is mapped.
+    #[inline(always)]
+    pub(crate) fn mapped_ptr(&self) -> Option<NonNull<u8>> {
+        match &self.alias {
+            Some(alias) => alias.mapped,
+            None => self
+                .block
+                .mapped_ptr()
+                .map(|ptr| unsafe { NonNull::new_unchecked(ptr.as_ptr() as *mut u8) }),
+        }
     }
 
     #[inline(always)]
@@ -140,13 +314,20 @@ impl Buffer {
         ctx: &VulkanBackend,
         idx: usize,
     ) -> Result<(NonNull<u8>, u64), BufferViewError> {
+        // Only host visible memory can be mapped
+        match self.memory_usage {
+            MemoryUsage::CpuToGpu | MemoryUsage::GpuToCpu | MemoryUsage::CpuOnly => {}
+            _ => return Err(BufferViewError::NotMapable),
+        }
+
         // Wait until the last queue that the buffer was used in has finished it's work
         let mut resc_state = ctx.resource_state.write().unwrap();
 
         // NOTE: The reason we set the usage to `None` is because we have to wait for the previous
         // usage to complete. This implies that no one is using this buffer anymore and thus no
         // waits are further needed.
-        if let Some(old) = resc_state.register_buffer(self.buffer, idx as u32, None) {
+        let (buffer, array_elem) = resc_state.buffer_key(self.buffer, idx as u32);
+        if let Some(old) = resc_state.register_buffer(buffer, array_elem, None) {
             ctx.wait_on(
                 &Job {
                     ty: old.queue,
@@ -156,22 +337,22 @@ impl Buffer {
             );
         }
 
-        let map = self.block.mapped_ptr().unwrap();
-        let map =
-            NonNull::new_unchecked((map.as_ptr() as *mut u8).add(self.aligned_size as usize * idx));
+        let map = self.mapped_ptr().unwrap();
+        let map = NonNull::new_unchecked(map.as_ptr().add(self.aligned_size as usize * idx));
         Ok((map, self.size))
     }
 }
 
 impl Drop for Buffer {
     fn drop(&mut self) {
-        self.on_drop
-            .send(Garbage::Buffer {
+        dispose(
+            &self.on_drop,
+            Garbage::Buffer {
                 buffer: self.buffer,
                 allocation: unsafe { ManuallyDrop::take(&mut self.block) },
                 ref_counter: self.ref_counter.clone(),
-            })
-            .unwrap();
+            },
+        );
     }
 }
 
@@ -2,9 +2,21 @@ use std::ffi::CString;
 
 use api::compute_pipeline::{ComputePipelineCreateError, ComputePipelineCreateInfo};
 use ash::vk::{self, Handle};
+use bytemuck::{Pod, Zeroable};
 use crossbeam_channel::Sender;
 
-use crate::util::garbage_collector::Garbage;
+use crate::util::garbage_collector::{dispose, Garbage};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DispatchIndirect {
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+
+unsafe impl Pod for DispatchIndirect {}
+unsafe impl Zeroable for DispatchIndirect {}
 
 pub struct ComputePipeline {
     pub(crate) layout: vk::PipelineLayout,
@@ -108,9 +120,7 @@ impl ComputePipeline {
 
 impl Drop for ComputePipeline {
     fn drop(&mut self) {
-        self.garbage.send(Garbage::Pipeline(self.pipeline)).unwrap();
-        self.garbage
-            .send(Garbage::PipelineLayout(self.layout))
-            .unwrap();
+        dispose(&self.garbage, Garbage::Pipeline(self.pipeline));
+        dispose(&self.garbage, Garbage::PipelineLayout(self.layout));
     }
 }
@@ -1,6 +1,8 @@
+use std::ops::Range;
+
 use api::{
     descriptor_set::{
-        DescriptorBinding, DescriptorSetCreateError, DescriptorSetCreateInfo,
+        BufferRange, DescriptorSetCreateError, DescriptorSetCreateInfo,
         DescriptorSetLayoutCreateError, DescriptorSetLayoutCreateInfo, DescriptorSetUpdate,
         DescriptorType, DescriptorValue,
     },
@@ -9,12 +11,16 @@ use api::{
 };
 use ash::vk;
 use crossbeam_channel::Sender;
+use fxhash::FxHashMap;
 
 use crate::{
-    buffer::BufferRefCounter,
+    buffer::{Buffer, BufferRefCounter},
     job::Job,
     texture::TextureRefCounter,
-    util::{descriptor_pool::DescriptorPools, garbage_collector::Garbage},
+    util::{
+        descriptor_pool::DescriptorPools,
+        garbage_collector::{dispose, Garbage},
+    },
     VulkanBackend,
 };
 
@@ -22,12 +28,18 @@ pub struct DescriptorSet {
     pub(crate) set: vk::DescriptorSet,
     pub(crate) layout: vk::DescriptorSetLayout,
     pub(crate) bound: Vec<Vec<Option<Binding>>>,
+    /// The number of descriptors the set was allocated with in its variable count binding, or `0`
+    /// if the layout has none.
+    pub(crate) variable_count: u32,
     pub(crate) on_drop: Sender<Garbage>,
 }
 
 pub struct DescriptorSetLayout {
     pub(crate) descriptor: DescriptorSetLayoutCreateInfo,
     pub(crate) layout: vk::DescriptorSetLayout,
+    /// Maps binding numbers to their index in `descriptor.bindings`. Binding numbers need not be
+    /// dense, so they can't be used as indices directly.
+    pub(crate) slots: FxHashMap<u32, usize>,
 }
 
 pub(crate) struct Binding {
@@ -40,18 +52,21 @@ pub(crate) enum BoundValue {
     UniformBuffer {
         _ref_counter: BufferRefCounter,
         buffer: vk::Buffer,
-        array_element: usize,
+        /// Array elements which overlap the bound range.
+        array_elements: Range<usize>,
     },
     StorageBuffer {
         _ref_counter: BufferRefCounter,
         buffer: vk::Buffer,
-        array_element: usize,
+        /// Array elements which overlap the bound range.
+        array_elements: Range<usize>,
     },
     Texture {
         _ref_counter: TextureRefCounter,
         image: vk::Image,
         view: vk::ImageView,
         aspect_mask: vk::ImageAspectFlags,
+        base_mip: u32,
         mip_count: u32,
         array_element: usize,
     },
@@ -65,20 +80,23 @@ impl DescriptorSetLayout {
     ) -> Result<Self, DescriptorSetLayoutCreateError> {
         // Pre-cache the pool
         let pool = pools.get(device, create_info.clone());
+        let slots = create_info
+            .bindings
+            .iter()
+            .enumerate()
+            .map(|(i, binding)| (binding.binding, i))
+            .collect();
         Ok(DescriptorSetLayout {
             descriptor: create_info,
             layout: pool.layout(),
+            slots,
         })
     }
 
+    /// Gets the index of a binding within the layout from its binding number.
     #[inline]
-    pub(crate) fn get_binding(&self, binding_value: u32) -> Option<&DescriptorBinding> {
-        for binding in &self.descriptor.bindings {
-            if binding.binding == binding_value {
-                return Some(binding);
-            }
-        }
-        None
+    pub(crate) fn slot(&self, binding_value: u32) -> Option<usize> {
+        self.slots.get(&binding_value).copied()
     }
 }
 
@@ -90,20 +108,43 @@ impl DescriptorSet {
         debug: Option<&ash::extensions::ext::DebugUtils>,
         create_info: DescriptorSetCreateInfo<crate::VulkanBackend>,
     ) -> Result<Self, DescriptorSetCreateError> {
-        let mut bound = Vec::with_capacity(create_info.layout.internal().descriptor.bindings.len());
-        for binding in &create_info.layout.internal().descriptor.bindings {
-            let mut binds = Vec::with_capacity(binding.count);
-            binds.resize_with(binding.count, || None);
+        let descriptor = &create_info.layout.internal().descriptor;
+        let variable_binding = if descriptor.variable_count {
+            descriptor
+                .bindings
+                .iter()
+                .map(|binding| binding.binding)
+                .max()
+        } else {
+            None
+        };
+
+        let mut bound = Vec::with_capacity(descriptor.bindings.len());
+        for binding in &descriptor.bindings {
+            // The variable count binding only holds as many descriptors as were requested
+            let count = if Some(binding.binding) == variable_binding {
+                create_info.variable_count.unwrap_or(binding.count)
+            } else {
+                binding.count
+            };
+            let mut binds = Vec::with_capacity(count);
+            binds.resize_with(count, || None);
             bound.push(binds);
         }
 
-        let pool = pools.get(device, create_info.layout.internal().descriptor.clone());
-        let set = pool.allocate(device, debug, create_info.debug_name);
+        let pool = pools.get(device, descriptor.clone());
+        let (set, variable_count) = pool.allocate(
+            device,
+            debug,
+            create_info.debug_name,
+            create_info.variable_count.map(|count| count as u32),
+        );
         Ok(DescriptorSet {
             set,
             layout: pool.layout(),
             on_drop: garbage,
             bound,
+            variable_count,
         })
     }
 
@@ -135,8 +176,14 @@ impl DescriptorSet {
         let mut images = Vec::with_capacity(updates.len());
 
         for update in updates {
-            // Deal with the old value
-            if let Some(old) = self.bound[update.binding as usize][update.array_element].take() {
+            // Bound values are stored per binding of the layout, in layout order
+            let slot = layout
+                .slot(update.binding)
+                .expect("binding does not exist in the layout");
+
+            // Deal with the old value. Dropping the old binding releases its reference to the
+            // resource, so the garbage collector can free it once its handle is dropped too.
+            if let Some(old) = self.bound[slot][update.array_element].take() {
                 match old.value {
                     // It's safe to destroy the image view now because we guarantee the set is not
                     // being used by
@@ -148,8 +195,8 @@ impl DescriptorSet {
             }
 
             // Bind new value
-            self.bound[update.binding as usize][update.array_element] = Some({
-                let binding = layout.get_binding(update.binding).unwrap();
+            self.bound[slot][update.array_element] = Some({
+                let binding = &layout.descriptor.bindings[slot];
                 let access = match binding.ty {
                     DescriptorType::Texture => vk::AccessFlags::SHADER_READ,
                     DescriptorType::UniformBuffer => vk::AccessFlags::UNIFORM_READ,
@@ -171,17 +218,26 @@ impl DescriptorSet {
                     DescriptorValue::UniformBuffer {
                         buffer,
                         array_element,
+                        range,
                     } => {
                         let buffer = buffer.internal();
+                        let (offset, len, array_elements) = buffer_binding_range(
+                            buffer,
+                            *array_element,
+                            *range,
+                            ctx.properties.limits.min_uniform_buffer_offset_alignment,
+                            ctx.properties.limits.max_uniform_buffer_range,
+                        );
                         buffers.push(
                             vk::DescriptorBufferInfo::builder()
                                 .buffer(buffer.buffer)
-                                .offset(buffer.aligned_size * (*array_element) as u64)
-                                .range(buffer.aligned_size)
+                                .offset(offset)
+                                .range(len)
                                 .build(),
                         );
 
-                        writes.push(
+                        push_write(
+                            &mut writes,
                             vk::WriteDescriptorSet::builder()
                                 .dst_set(self.set)
                                 .dst_binding(update.binding)
@@ -197,24 +253,33 @@ impl DescriptorSet {
                             value: BoundValue::UniformBuffer {
                                 _ref_counter: buffer.ref_counter.clone(),
                                 buffer: buffer.buffer,
-                                array_element: *array_element,
+                                array_elements,
                             },
                         }
                     }
                     DescriptorValue::StorageBuffer {
                         buffer,
                         array_element,
+                        range,
                     } => {
                         let buffer = buffer.internal();
+                        let (offset, len, array_elements) = buffer_binding_range(
+                            buffer,
+                            *array_element,
+                            *range,
+                            ctx.properties.limits.min_storage_buffer_offset_alignment,
+                            ctx.properties.limits.max_storage_buffer_range,
+                        );
                         buffers.push(
                             vk::DescriptorBufferInfo::builder()
                                 .buffer(buffer.buffer)
-                                .offset(buffer.aligned_size * (*array_element) as u64)
-                                .range(buffer.aligned_size)
+                                .offset(offset)
+                                .range(len)
                                 .build(),
                         );
 
-                        writes.push(
+                        push_write(
+                            &mut writes,
                             vk::WriteDescriptorSet::builder()
                                 .dst_set(self.set)
                                 .dst_binding(update.binding)
@@ -230,7 +295,7 @@ impl DescriptorSet {
                             value: BoundValue::StorageBuffer {
                                 _ref_counter: buffer.ref_counter.clone(),
                                 buffer: buffer.buffer,
-                                array_element: *array_element,
+                                array_elements,
                             },
                         }
                     }
@@ -243,12 +308,19 @@ impl DescriptorSet {
                     } => {
                         let texture = texture.internal();
 
-                        // Create a view for the texture
+                        // Create a view for the texture. Sampled views of depth stencil images
+                        // may only contain one aspect, so only the depth aspect is viewed.
+                        let view_aspect =
+                            if texture.aspect_flags.contains(vk::ImageAspectFlags::DEPTH) {
+                                vk::ImageAspectFlags::DEPTH
+                            } else {
+                                texture.aspect_flags
+                            };
                         let create_info = vk::ImageViewCreateInfo::builder()
                             .format(texture.format)
                             .view_type(vk::ImageViewType::TYPE_2D)
                             .subresource_range(vk::ImageSubresourceRange {
-                                aspect_mask: texture.aspect_flags,
+                                aspect_mask: view_aspect,
                                 base_mip_level: *base_mip as u32,
                                 level_count: *mip_count as u32,
                                 base_array_layer: *array_element as u32,
@@ -269,11 +341,12 @@ impl DescriptorSet {
                             vk::DescriptorImageInfo::builder()
                                 .sampler(sampler_cache.get(&ctx.device, *sampler))
                                 .image_view(view)
-                                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                                .image_layout(crate::util::sampled_layout(texture.aspect_flags))
                                 .build(),
                         );
 
-                        writes.push(
+                        push_write(
+                            &mut writes,
                             vk::WriteDescriptorSet::builder()
                                 .dst_set(self.set)
                                 .dst_binding(update.binding)
@@ -291,7 +364,8 @@ impl DescriptorSet {
                                 image: texture.image,
                                 view,
                                 aspect_mask: texture.aspect_flags,
-                                mip_count: texture.mip_count,
+                                base_mip: *base_mip as u32,
+                                mip_count: *mip_count as u32,
                                 array_element: *array_element,
                             },
                         }
@@ -304,14 +378,112 @@ impl DescriptorSet {
     }
 }
 
+/// Adds a write for a single descriptor. If the descriptor is the next array element of the
+/// previous write and its info directly follows the info of the previous write, the previous write
+/// is extended instead so that contiguous array elements are written at once.
+fn push_write(writes: &mut Vec<vk::WriteDescriptorSet>, write: vk::WriteDescriptorSet) {
+    if let Some(last) = writes.last_mut() {
+        let contiguous = match write.descriptor_type {
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER => {
+                last.p_image_info
+                    .wrapping_add(last.descriptor_count as usize)
+                    == write.p_image_info
+            }
+            _ => {
+                last.p_buffer_info
+                    .wrapping_add(last.descriptor_count as usize)
+                    == write.p_buffer_info
+            }
+        };
+        if contiguous
+            && last.dst_set == write.dst_set
+            && last.dst_binding == write.dst_binding
+            && last.descriptor_type == write.descriptor_type
+            && last.dst_array_element + last.descriptor_count == write.dst_array_element
+        {
+            last.descriptor_count += write.descriptor_count;
+            return;
+        }
+    }
+    writes.push(write);
+}
+
+/// Determines the byte offset and length of a buffer binding, along with the array elements the
+/// binding overlaps. The length is `vk::WHOLE_SIZE` for [`BufferRange::Remaining`].
+///
+/// # Panics
+/// - If `array_element` is out of bounds for the buffer.
+/// - If `range` is empty, out of bounds for the buffer, larger than `max_range`, or has an offset
+/// that is not a multiple of `min_alignment`.
+fn buffer_binding_range(
+    buffer: &Buffer,
+    array_element: usize,
+    range: BufferRange,
+    min_alignment: u64,
+    max_range: u32,
+) -> (u64, u64, Range<usize>) {
+    assert!(
+        array_element < buffer.array_elements,
+        "buffer array element is out of bounds"
+    );
+    let element_offset = buffer.aligned_size * array_element as u64;
+    match range {
+        BufferRange::Element => (
+            element_offset,
+            buffer.aligned_size,
+            array_element..(array_element + 1),
+        ),
+        BufferRange::Bytes { offset, len } => {
+            let start = element_offset + offset;
+            let end = start + len;
+            assert_ne!(len, 0, "buffer binding range cannot be empty");
+            assert!(
+                end <= buffer.aligned_size * buffer.array_elements as u64,
+                "buffer binding range is out of bounds"
+            );
+            assert!(
+                len <= max_range as u64,
+                "buffer binding range of `{}` bytes is larger than the maximum of `{}` bytes",
+                len,
+                max_range
+            );
+            assert_eq!(
+                start % min_alignment,
+                0,
+                "buffer binding offset must be a multiple of `{}` bytes",
+                min_alignment
+            );
+            let first = (start / buffer.aligned_size) as usize;
+            let last = ((end - 1) / buffer.aligned_size) as usize;
+            (start, len, first..(last + 1))
+        }
+        BufferRange::Remaining => {
+            let len = buffer.aligned_size * (buffer.array_elements - array_element) as u64;
+            assert!(
+                len <= max_range as u64,
+                "buffer binding range of `{}` bytes is larger than the maximum of `{}` bytes",
+                len,
+                max_range
+            );
+            (
+                element_offset,
+                vk::WHOLE_SIZE,
+                array_element..buffer.array_elements,
+            )
+        }
+    }
+}
+
 impl Drop for DescriptorSet {
     fn drop(&mut self) {
-        self.on_drop
-            .send(Garbage::DescriptorSet {
+        dispose(
+            &self.on_drop,
+            Garbage::DescriptorSet {
                 set: self.set,
                 layout: self.layout,
                 bindings: std::mem::take(&mut self.bound),
-            })
-            .unwrap();
+                variable_count: self.variable_count,
+            },
+        );
     }
 }
@@ -1,9 +1,14 @@
-use api::graphics_pipeline::GraphicsPipelineCreateInfo;
+use api::graphics_pipeline::{
+    AttachmentFormats, GraphicsPipelineCreateError, GraphicsPipelineCreateInfo,
+};
 use ash::vk::{self, Handle};
 use crossbeam_channel::Sender;
 use std::ffi::CString;
 
-use crate::util::{garbage_collector::Garbage, pipeline_cache::PipelineCache};
+use crate::util::{
+    garbage_collector::{dispose, Garbage},
+    pipeline_cache::PipelineCache,
+};
 
 pub struct GraphicsPipeline {
     descriptor: GraphicsPipelineCreateInfo<crate::VulkanBackend>,
@@ -15,25 +20,47 @@ impl GraphicsPipeline {
     pub(crate) unsafe fn new(
         device: &ash::Device,
         garbage: Sender<Garbage>,
+        limits: &vk::PhysicalDeviceLimits,
         descriptor: GraphicsPipelineCreateInfo<crate::VulkanBackend>,
-    ) -> Self {
+    ) -> Result<Self, GraphicsPipelineCreateError> {
+        if let Some(range) = &descriptor.push_constants {
+            if range.size > limits.max_push_constants_size {
+                return Err(GraphicsPipelineCreateError::PushConstantsTooLarge(
+                    range.size,
+                ));
+            }
+        }
+
         // Create the layout
         let mut layouts = Vec::with_capacity(descriptor.layouts.len());
         for layout in &descriptor.layouts {
             layouts.push(layout.internal().layout);
         }
+        let push_constant_ranges: Vec<_> = descriptor
+            .push_constants
+            .iter()
+            .map(|range| {
+                vk::PushConstantRange::builder()
+                    .stage_flags(crate::util::to_vk_shader_stage(range.stage))
+                    .offset(0)
+                    .size(range.size)
+                    .build()
+            })
+            .collect();
         let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
             .set_layouts(&layouts)
+            .push_constant_ranges(&push_constant_ranges)
             .build();
-        let layout = device
-            .create_pipeline_layout(&layout_create_info, None)
-            .unwrap();
+        let layout = match device.create_pipeline_layout(&layout_create_info, None) {
+            Ok(layout) => layout,
+            Err(err) => return Err(GraphicsPipelineCreateError::Other(err.to_string())),
+        };
 
-        Self {
+        Ok(Self {
             descriptor,
             layout,
             garbage,
-        }
+        })
     }
 
     #[inline(always)]
@@ -41,13 +68,54 @@ impl GraphicsPipeline {
         self.layout
     }
 
+    #[inline(always)]
+    pub(crate) fn attachment_formats(&self) -> Option<&AttachmentFormats> {
+        self.descriptor.attachment_formats.as_ref()
+    }
+
+    /// Sets the extended dynamic state of a command buffer to the values described by this
+    /// pipeline. Depth testing and writing are disabled if `depth_attachment` is not set.
+    pub(crate) unsafe fn set_dynamic_state(
+        &self,
+        eds: &ash::extensions::ext::ExtendedDynamicState,
+        command_buffer: vk::CommandBuffer,
+        depth_attachment: bool,
+    ) {
+        let (depth_test, depth_write) = match &self.descriptor.depth_stencil {
+            Some(depth_stencil) if depth_attachment => {
+                (depth_stencil.depth_test, depth_stencil.depth_write)
+            }
+            _ => (false, false),
+        };
+        eds.cmd_set_depth_test_enable(command_buffer, depth_test);
+        eds.cmd_set_depth_write_enable(command_buffer, depth_write);
+        eds.cmd_set_cull_mode(
+            command_buffer,
+            crate::util::to_vk_cull_mode(self.descriptor.rasterization.cull_mode),
+        );
+        eds.cmd_set_front_face(
+            command_buffer,
+            crate::util::to_vk_front_face(self.descriptor.rasterization.front_face),
+        );
+    }
+
     /// Retrieves a pipeline and layout, or creates a new one if needed.
+    ///
+    /// If `extended_dynamic_state` is set, depth test/write, cull mode, and front face are
+    /// declared as dynamic state.
+    ///
+    /// If `depth_attachment` is not set, the render pass has no depth stencil attachment, so the
+    /// pipeline is created with depth testing and writing disabled. Pipelines are cached per
+    /// render pass compatibility class, which includes the depth stencil format, so this is
+    /// always the same for a given `render_pass`.
     pub(crate) unsafe fn get(
         &self,
         device: &ash::Device,
         pipelines: &mut PipelineCache,
         debug: Option<&ash::extensions::ext::DebugUtils>,
         render_pass: vk::RenderPass,
+        depth_attachment: bool,
+        extended_dynamic_state: bool,
     ) -> vk::Pipeline {
         if let Some(pipeline) = pipelines.get(self.layout, render_pass) {
             return pipeline;
@@ -98,14 +166,15 @@ impl GraphicsPipeline {
                 Some(depth_stencil) => depth_stencil.depth_clamp,
                 None => false,
             })
+            .rasterizer_discard_enable(self.descriptor.rasterization.rasterizer_discard)
             .line_width(1.0)
             .build();
 
         let multisampling = vk::PipelineMultisampleStateCreateInfo::builder()
-            .sample_shading_enable(false)
+            .sample_shading_enable(self.descriptor.multisample.sample_shading.is_some())
             .rasterization_samples(vk::SampleCountFlags::TYPE_1)
-            .min_sample_shading(1.0)
-            .alpha_to_coverage_enable(false)
+            .min_sample_shading(self.descriptor.multisample.sample_shading.unwrap_or(1.0))
+            .alpha_to_coverage_enable(self.descriptor.multisample.alpha_to_coverage)
             .alpha_to_one_enable(false)
             .build();
 
@@ -133,7 +202,15 @@ impl GraphicsPipeline {
             .scissors(&scissors)
             .build();
 
-        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        if extended_dynamic_state {
+            dynamic_states.extend_from_slice(&[
+                vk::DynamicState::DEPTH_TEST_ENABLE_EXT,
+                vk::DynamicState::DEPTH_WRITE_ENABLE_EXT,
+                vk::DynamicState::CULL_MODE_EXT,
+                vk::DynamicState::FRONT_FACE_EXT,
+            ]);
+        }
 
         let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
             .dynamic_states(&dynamic_states)
@@ -159,14 +236,16 @@ impl GraphicsPipeline {
         }
 
         let depth_stencil = match &self.descriptor.depth_stencil {
-            Some(depth_stencil) => vk::PipelineDepthStencilStateCreateInfo::builder()
-                .depth_test_enable(depth_stencil.depth_test)
-                .depth_write_enable(depth_stencil.depth_write)
-                .depth_compare_op(crate::util::to_vk_compare_op(depth_stencil.depth_compare))
-                .min_depth_bounds(depth_stencil.min_depth)
-                .max_depth_bounds(depth_stencil.max_depth)
-                .build(),
-            None => vk::PipelineDepthStencilStateCreateInfo::default(),
+            Some(depth_stencil) if depth_attachment => {
+                vk::PipelineDepthStencilStateCreateInfo::builder()
+                    .depth_test_enable(depth_stencil.depth_test)
+                    .depth_write_enable(depth_stencil.depth_write)
+                    .depth_compare_op(crate::util::to_vk_compare_op(depth_stencil.depth_compare))
+                    .min_depth_bounds(depth_stencil.min_depth)
+                    .max_depth_bounds(depth_stencil.max_depth)
+                    .build()
+            }
+            _ => vk::PipelineDepthStencilStateCreateInfo::default(),
         };
 
         let (color_blend, _attachments) = match &self.descriptor.color_blend {
@@ -258,8 +337,6 @@ impl GraphicsPipeline {
 
 impl Drop for GraphicsPipeline {
     fn drop(&mut self) {
-        self.garbage
-            .send(Garbage::PipelineLayout(self.layout))
-            .unwrap();
+        dispose(&self.garbage, Garbage::PipelineLayout(self.layout));
     }
 }
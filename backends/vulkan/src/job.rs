@@ -1,5 +1,6 @@
 use api::types::QueueType;
 
+#[derive(Clone)]
 pub struct Job {
     pub(crate) ty: QueueType,
     pub(crate) target_value: u64,
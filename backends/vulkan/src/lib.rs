@@ -1,60 +1,75 @@
 use api::{
     buffer::{BufferCreateError, BufferCreateInfo, BufferViewError},
-    command_buffer::Command,
+    command_buffer::{BufferTextureCopy, Command},
     compute_pipeline::{ComputePipelineCreateError, ComputePipelineCreateInfo},
     descriptor_set::{
         DescriptorSetCreateError, DescriptorSetCreateInfo, DescriptorSetLayoutCreateError,
         DescriptorSetLayoutCreateInfo, DescriptorSetUpdate,
     },
     graphics_pipeline::{GraphicsPipelineCreateError, GraphicsPipelineCreateInfo},
+    query::{QueryPoolCreateError, QueryPoolCreateInfo},
     queue::SurfacePresentFailure,
-    render_pass::ColorAttachmentSource,
+    render_pass::{ColorAttachmentSource, RenderPassDescriptor},
     shader::{ShaderCreateError, ShaderCreateInfo},
     surface::{
-        SurfaceConfiguration, SurfaceCreateError, SurfaceCreateInfo, SurfaceImageAcquireError,
-        SurfacePresentSuccess, SurfaceUpdateError,
+        SurfaceCaptureError, SurfaceConfiguration, SurfaceCreateError, SurfaceCreateInfo,
+        SurfaceImageAcquireError, SurfacePresentSuccess, SurfaceQueryError, SurfaceUpdateError,
+    },
+    texture::{
+        SparseBindError, SparseProperties, SparseTextureBind, TextureCreateError,
+        TextureCreateInfo, TextureSubresourceLayout, TextureViewError,
     },
-    texture::{TextureCreateError, TextureCreateInfo},
     types::*,
     Backend,
 };
 use ash::vk::{self, DebugUtilsMessageSeverityFlagsEXT};
 use buffer::Buffer;
-use compute_pipeline::ComputePipeline;
-use crossbeam_utils::sync::ShardedLock;
+use compute_pipeline::{ComputePipeline, DispatchIndirect};
+use crossbeam_utils::sync::{ShardedLock, ShardedLockWriteGuard};
 use descriptor_set::{DescriptorSet, DescriptorSetLayout};
 use gpu_allocator::vulkan::*;
 use graphics_pipeline::GraphicsPipeline;
 use job::Job;
+use prebuilt::PrebuiltCommands;
+use query::QueryPool;
 use queue::VkQueue;
 use raw_window_handle::HasRawWindowHandle;
 use render_pass::{DrawIndexedIndirect, FramebufferCache, RenderPassCache};
 use shader::Shader;
 use std::{
     borrow::Cow,
+    collections::{HashMap, VecDeque},
     ffi::{CStr, CString},
     mem::ManuallyDrop,
     ptr::NonNull,
-    sync::Mutex,
+    sync::{Arc, Mutex, MutexGuard},
 };
 use surface::{Surface, SurfaceImage};
-use texture::Texture;
+use texture::{PageChange, SparsePage, Texture};
 use thiserror::Error;
 use util::{
+    descriptor_binds::DescriptorBinds,
     descriptor_pool::DescriptorPools,
-    garbage_collector::{GarbageCollector, TimelineValues},
+    garbage_collector::{dispose, Garbage, GarbageCollector, JobCallback, TimelineValues},
     pipeline_cache::PipelineCache,
     sampler_cache::SamplerCache,
     semaphores::{SemaphoreTracker, WaitInfo},
     tracking::TrackState,
-    usage::{GlobalResourceUsage, PipelineTracker},
+    usage::{
+        GlobalResourceUsage, PipelineTracker, QueueUsage, SubResource, SubResourceUsage, UsageScope,
+    },
 };
 
+#[cfg(feature = "raw")]
+pub use ash;
+
 pub mod buffer;
 pub mod compute_pipeline;
 pub mod descriptor_set;
 pub mod graphics_pipeline;
 pub mod job;
+pub mod prebuilt;
+pub mod query;
 pub mod queue;
 pub mod render_pass;
 pub mod shader;
@@ -62,6 +77,34 @@ pub mod surface;
 pub mod texture;
 pub mod util;
 
+/// A raw command recorded with `CommandBuffer::raw`. Called with the device and the command
+/// buffer being recorded when the commands it was recorded with are submitted. Prebuilt commands
+/// call it once when they are recorded.
+pub type RawCommand = Box<dyn Fn(&ash::Device, vk::CommandBuffer)>;
+
+/// Raw Vulkan handles of a backend. Created with [`VulkanBackend::raw_handles`].
+#[cfg(feature = "raw")]
+pub struct RawHandles<'a> {
+    pub entry: &'a ash::Entry,
+    pub instance: &'a ash::Instance,
+    pub physical_device: vk::PhysicalDevice,
+    pub device: &'a ash::Device,
+    pub main: RawQueue,
+    pub transfer: RawQueue,
+    pub present: RawQueue,
+    pub compute: RawQueue,
+}
+
+/// A raw Vulkan queue of a backend. See [`RawHandles`].
+#[cfg(feature = "raw")]
+pub struct RawQueue {
+    pub queue: vk::Queue,
+    pub family_index: u32,
+    /// Queue types may share the same `vk::Queue` on hardware with few queues. This lock is shared
+    /// by every queue type using `queue`, and *must* be held while `queue` is used.
+    pub lock: Arc<Mutex<()>>,
+}
+
 pub struct VulkanBackendCreateInfo<'a, W: HasRawWindowHandle> {
     pub app_name: String,
     pub engine_name: String,
@@ -69,6 +112,95 @@ pub struct VulkanBackendCreateInfo<'a, W: HasRawWindowHandle> {
     pub window: &'a W,
     /// Enables debugging layers and extensions.
     pub debug: bool,
+    /// The least severe debug messages that are printed and
+    /// [`collected`](api::context::Context::drain_validation_messages). Ignored if `debug` is
+    /// `false`.
+    pub debug_severity: ValidationSeverity,
+    /// The number of command buffers to allocate for each queue up front. Command buffers are
+    /// otherwise allocated on demand, so pre-allocating as many as are expected to be in flight
+    /// at once avoids allocations during the first frames.
+    pub preallocated_command_buffers: usize,
+    /// If `Some`, submitting to a queue blocks while the queue has this many or more submissions
+    /// that the GPU has not finished. This bounds the memory used to track in-flight work when
+    /// the CPU submits much faster than the GPU executes. Must not be `Some(0)`.
+    pub max_pending_submits: Option<u64>,
+    /// The scheduling priorities of each queue type. [`VulkanBackend::new`] panics if any
+    /// priority is outside the range `[0, 1]`.
+    pub queue_priorities: QueuePriorities,
+    /// If `true` and the main queue family supports presentation, images are presented on the
+    /// main queue instead of a separate present queue. Otherwise, a separate queue is preferred
+    /// and the main queue is only used if no other queue supports presentation.
+    ///
+    /// Presentation always waits for the submission that rendered the image, so either choice is
+    /// correctly ordered. Presenting on the main queue avoids synchronizing two queues when
+    /// rendering and presentation happen on the main queue anyway.
+    pub present_on_main: bool,
+    /// Debugging options of the GPU memory allocator.
+    pub allocator_debug: AllocatorDebugSettings,
+    /// If `true`, submissions don't destroy resources that are no longer in use, which keeps the
+    /// cost of garbage collection out of latency-sensitive submissions. Resources are instead
+    /// destroyed when [`collect_garbage`](api::context::Context::collect_garbage) is called,
+    /// which *must* then be called periodically (e.g. once per frame) or memory is never
+    /// reclaimed.
+    pub deferred_garbage_collection: bool,
+}
+
+/// Scheduling priorities of each queue type, in the range `[0, 1]`. Higher priority queues *may*
+/// be given more processing time than lower priority ones, e.g. to keep async compute from
+/// starving graphics work. If there are too few hardware queues for every queue type to get its
+/// own, queue types sharing a queue use the highest of their priorities.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QueuePriorities {
+    pub main: f32,
+    pub transfer: f32,
+    pub present: f32,
+    pub compute: f32,
+}
+
+impl Default for QueuePriorities {
+    #[inline(always)]
+    fn default() -> Self {
+        Self {
+            main: 1.0,
+            transfer: 1.0,
+            present: 1.0,
+            compute: 1.0,
+        }
+    }
+}
+
+/// Debugging options of the GPU memory allocator, used to diagnose memory issues such as leaks.
+/// Messages are written using the [`log`](https://docs.rs/log) crate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AllocatorDebugSettings {
+    /// Log information about the memory heaps and types of the device on creation.
+    pub log_memory_information: bool,
+    /// Log every allocation that hasn't been freed when the backend is destroyed.
+    pub log_leaks_on_shutdown: bool,
+    /// Capture a stack trace for every allocation so leaks can be traced back to their source.
+    /// This is slow.
+    pub store_stack_traces: bool,
+    /// Log every allocation.
+    pub log_allocations: bool,
+    /// Log every free.
+    pub log_frees: bool,
+    /// Include stack traces when logging allocations, frees, and leaks. Requires
+    /// `store_stack_traces`.
+    pub log_stack_traces: bool,
+}
+
+impl Default for AllocatorDebugSettings {
+    #[inline(always)]
+    fn default() -> Self {
+        Self {
+            log_memory_information: false,
+            log_leaks_on_shutdown: true,
+            store_stack_traces: false,
+            log_allocations: false,
+            log_frees: false,
+            log_stack_traces: false,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -92,6 +224,11 @@ pub struct VulkanBackend {
     pub(crate) device: ash::Device,
     pub(crate) surface_loader: ash::extensions::khr::Surface,
     pub(crate) swapchain_loader: ash::extensions::khr::Swapchain,
+    /// Only available if the device supports `VK_EXT_extended_dynamic_state`.
+    pub(crate) extended_dynamic_state: Option<ash::extensions::ext::ExtendedDynamicState>,
+    /// Set if the device supports variable descriptor counts and partially bound descriptors,
+    /// which are needed by variable count descriptor set layouts.
+    pub(crate) variable_descriptor_count: bool,
     pub(crate) main: ShardedLock<VkQueue>,
     pub(crate) transfer: ShardedLock<VkQueue>,
     pub(crate) present: ShardedLock<VkQueue>,
@@ -104,6 +241,13 @@ pub struct VulkanBackend {
     pub(crate) pools: Mutex<DescriptorPools>,
     pub(crate) pipelines: Mutex<PipelineCache>,
     pub(crate) samplers: Mutex<SamplerCache>,
+    /// Messages reported to the debug messenger. Boxed so that the messenger can hold a pointer
+    /// to it.
+    pub(crate) validation_messages: Box<Mutex<VecDeque<ValidationMessage>>>,
+    /// See [`VulkanBackendCreateInfo::max_pending_submits`].
+    pub(crate) max_pending_submits: Option<u64>,
+    /// See [`VulkanBackendCreateInfo::deferred_garbage_collection`].
+    pub(crate) deferred_garbage_collection: bool,
 }
 
 #[derive(Default)]
@@ -112,6 +256,11 @@ pub(crate) struct QueueFamilyIndices {
     pub main: u32,
     /// Must support presentation.
     pub present: u32,
+    /// Set if the present queue type uses the same `vk::Queue` as the main queue type.
+    pub present_shares_main: bool,
+    /// Capabilities of the presentation queue family, which aren't guaranteed beyond
+    /// presentation.
+    pub present_flags: vk::QueueFlags,
     /// Must support transfer.
     pub transfer: u32,
     /// Must support compute.
@@ -119,6 +268,10 @@ pub(crate) struct QueueFamilyIndices {
     /// Contains all queue families which are unique (some queue families may be equivilent on
     /// certain hardware.
     pub unique: Vec<u32>,
+    /// The number of queues available in each queue family, indexed by family.
+    pub queue_counts: Vec<u32>,
+    /// The number of valid timestamp bits of each queue family, indexed by family.
+    pub timestamp_valid_bits: Vec<u32>,
 }
 
 struct PhysicalDeviceQuery {
@@ -138,8 +291,12 @@ impl Backend for VulkanBackend {
     type ComputePipeline = ComputePipeline;
     type DescriptorSetLayout = DescriptorSetLayout;
     type DescriptorSet = DescriptorSet;
+    type QueryPool = QueryPool;
     type Job = Job;
+    type PrebuiltCommands = PrebuiltCommands;
     type DrawIndexedIndirect = DrawIndexedIndirect;
+    type DispatchIndirect = DispatchIndirect;
+    type RawCommand = RawCommand;
 
     #[inline(always)]
     unsafe fn create_surface<'a, W: HasRawWindowHandle>(
@@ -178,8 +335,9 @@ impl Backend for VulkanBackend {
     unsafe fn acquire_image(
         &self,
         surface: &mut Self::Surface,
+        timeout: Option<std::time::Duration>,
     ) -> Result<Self::SurfaceImage, SurfaceImageAcquireError> {
-        surface.acquire_image(self)
+        surface.acquire_image(self, timeout)
     }
 
     #[inline(always)]
@@ -187,454 +345,351 @@ impl Backend for VulkanBackend {
         &self,
         surface: &Self::Surface,
         image: &mut Self::SurfaceImage,
-    ) -> Result<SurfacePresentSuccess, SurfacePresentFailure> {
-        surface.present(
-            image,
-            &self.swapchain_loader,
-            self.present.try_read().unwrap().queue,
-        )
+        track: bool,
+    ) -> Result<(SurfacePresentSuccess, Option<Self::Job>), SurfacePresentFailure> {
+        // The presentation engine doesn't signal our timeline, and work submitted after the
+        // presentation isn't ordered after the semaphore it waits on. Instead, the job waits on
+        // the submission that rendered the image along with previous work on the present queue.
+        let rendered_by = match image.rendered_by() {
+            // Previous work on the present queue is always waited on
+            Some((QueueType::Present, _)) | None => None,
+            Some((ty, value)) => Some((self.queue(ty).read().unwrap().semaphore(), value)),
+        };
+
+        let mut queue = self.present.write().unwrap();
+        let success = {
+            let _lock = queue.lock();
+            surface.present(image, &self.swapchain_loader, queue.queue)?
+        };
+        self.garbage.next_frame();
+
+        let job = if track {
+            if let Err(err) = queue.signal(&self.device, rendered_by) {
+                return Err(SurfacePresentFailure::Other(err.to_string()));
+            }
+            Some(Job {
+                ty: QueueType::Present,
+                target_value: queue.target_timeline_value(),
+            })
+        } else {
+            None
+        };
+
+        Ok((success, job))
+    }
+
+    #[inline(always)]
+    unsafe fn capture_surface_image(
+        &self,
+        queue: QueueType,
+        surface: &Self::Surface,
+        image: &Self::SurfaceImage,
+    ) -> Result<Vec<u8>, SurfaceCaptureError> {
+        surface.capture(self, queue, image)
+    }
+
+    #[inline(always)]
+    unsafe fn surface_present_mode(&self, surface: &Self::Surface) -> PresentMode {
+        surface.present_mode
+    }
+
+    #[inline(always)]
+    unsafe fn surface_color_space(&self, surface: &Self::Surface) -> ColorSpace {
+        crate::util::from_vk_color_space(surface.color_space())
+    }
+
+    #[inline(always)]
+    unsafe fn surface_current_extent(
+        &self,
+        surface: &Self::Surface,
+    ) -> Result<(u32, u32), SurfaceQueryError> {
+        surface.current_extent(self)
     }
 
     #[inline(always)]
     unsafe fn destroy_surface_image(&self, image: &mut Self::SurfaceImage) {
-        if !image.is_signaled() {
+        if !image.is_signaled() && !image.is_discarded() {
             todo!()
         }
     }
 
+    #[inline(always)]
+    unsafe fn discard_surface_image(
+        &self,
+        surface: &mut Self::Surface,
+        image: &mut Self::SurfaceImage,
+    ) {
+        surface.discard(image);
+    }
+
     unsafe fn submit_commands<'a>(
         &self,
         queue: QueueType,
         debug_name: Option<&str>,
+        independent: bool,
         commands: Vec<Command<'a, Self>>,
     ) -> Job {
-        // Lock down all neccesary objects
+        // Callbacks are called before anything is locked since they may submit work themselves
+        self.call_completed_callbacks();
+
+        // Nothing to submit. The returned job is complete once all previously submitted work on
+        // the queue is complete, so it still respects queue ordering.
+        if commands.is_empty() {
+            return Job {
+                ty: queue,
+                target_value: match queue {
+                    QueueType::Main => &self.main,
+                    QueueType::Transfer => &self.transfer,
+                    QueueType::Compute => &self.compute,
+                    QueueType::Present => &self.present,
+                }
+                .read()
+                .unwrap()
+                .target_timeline_value(),
+            };
+        }
+
+        self.throttle(queue);
+
+        // Lock down all neccesary objects. Of the queues, only the one being submitted to is
+        // locked for the whole submission (see `lock_submit_queue`). Everything else is released
+        // once resource tracking is done.
         let mut resc_state = self.resource_state.write().unwrap();
-        let mut allocator = self.allocator.lock().unwrap();
-        let mut pools = self.pools.lock().unwrap();
+        let mut garbage_locks = self.lock_submit_garbage();
         let mut pipelines = self.pipelines.lock().unwrap();
-        let mut main = self.main.write().unwrap();
-        let mut transfer = self.transfer.write().unwrap();
-        let mut compute = self.compute.write().unwrap();
-        let mut present = self.present.write().unwrap();
-
-        // Perform garbage collection
-        let current_values = TimelineValues {
-            main: main.current_timeline_value(&self.device),
-            transfer: transfer.current_timeline_value(&self.device),
-            compute: compute.current_timeline_value(&self.device),
-        };
-        let target_values = TimelineValues {
-            main: main.target_timeline_value(),
-            transfer: transfer.target_timeline_value(),
-            compute: compute.target_timeline_value(),
-        };
-        let next_target_value = match queue {
-            QueueType::Main => &main,
-            QueueType::Transfer => &transfer,
-            QueueType::Compute => &compute,
-            QueueType::Present => &present,
-        }
-        .target_timeline_value()
-            + 1;
-        self.garbage.cleanup(
-            &self.device,
-            &mut allocator,
-            &mut pools,
-            &mut pipelines,
-            current_values,
-            target_values,
-        );
+        pipelines.set_use_frame(self.garbage.frame());
+        let (current_values, target_values) = self.timeline_values();
+        let mut submit_queue = self.lock_submit_queue(queue);
+        let next_target_value = submit_queue.target_timeline_value() + 1;
+        if let Some((allocator, pools)) = &mut garbage_locks {
+            self.garbage.cleanup(
+                &self.device,
+                allocator,
+                pools,
+                &mut pipelines,
+                current_values,
+                target_values,
+            );
+        }
+
+        // Garbage is only collected up front, so creating resources isn't blocked while the
+        // commands are recorded
+        drop(garbage_locks);
 
         // State
         let mut semaphore_tracker = SemaphoreTracker::default();
-        let mut active_render_pass = vk::RenderPass::null();
-        let mut active_layout = vk::PipelineLayout::null();
-        let mut pipeline_tracker = PipelineTracker::new(&mut resc_state, queue, next_target_value);
+        let mut pipeline_tracker = if independent {
+            PipelineTracker::new_independent(&mut resc_state, queue, next_target_value)
+        } else {
+            PipelineTracker::new(&mut resc_state, queue, next_target_value)
+        };
 
         // Acquire a command buffer from the queue
-        let cb = match queue {
-            QueueType::Main => &mut main,
-            QueueType::Transfer => &mut transfer,
-            QueueType::Compute => &mut compute,
-            QueueType::Present => &mut present,
-        }
-        .allocate_command_buffer(&self.device, self.debug.as_ref().map(|(utils, _)| utils));
+        let cb = submit_queue
+            .allocate_command_buffer(&self.device, self.debug.as_ref().map(|(utils, _)| utils));
         let begin_info = vk::CommandBufferBeginInfo::builder()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
             .build();
         self.device.begin_command_buffer(cb, &begin_info).unwrap();
 
-        // Insert debug name
-        if let Some(name) = debug_name {
-            if let Some((debug, _)) = &self.debug {
-                let name = CString::new(name).unwrap();
-                let label = vk::DebugUtilsLabelEXT::builder().label_name(&name).build();
-                debug.cmd_begin_debug_utils_label(cb, &label);
+        // Reset query pools that are about to be written to. This must happen outside of render
+        // passes, so it's done before any other commands are recorded.
+        for command in &commands {
+            if let Command::WriteTimestamp { pool, .. } = command {
+                let pool = pool.internal();
+                if pool.take_reset() {
+                    self.device
+                        .cmd_reset_query_pool(cb, pool.pool, 0, pool.count);
+                }
             }
         }
 
         // Interpret commands
-        for (i, command) in commands.iter().enumerate() {
-            // Track resource state for the command
-            crate::util::tracking::track_resources(TrackState {
-                device: &self.device,
-                command_buffer: cb,
-                index: i,
-                commands: &commands,
-                pipeline_tracker: &mut pipeline_tracker,
-                semaphores: &mut semaphore_tracker,
-            });
+        self.record_into(
+            cb,
+            debug_name,
+            &commands,
+            &mut pipelines,
+            &mut pipeline_tracker,
+            &mut semaphore_tracker,
+        );
 
-            // Perform command operations
-            match command {
-                Command::BeginRenderPass(descriptor) => {
-                    // Get the render pass described
-                    active_render_pass = self.render_passes.get(&self.device, &descriptor);
+        // Grab detected semaphores
+        for (wait_queue, stage) in pipeline_tracker.wait_queues() {
+            let semaphore = if *wait_queue == queue {
+                submit_queue.semaphore()
+            } else {
+                self.queue(*wait_queue).read().unwrap().semaphore()
+            };
+            semaphore_tracker.register_wait(
+                semaphore,
+                WaitInfo {
+                    value: Some(target_values.get(*wait_queue)),
+                    stage: *stage,
+                },
+            );
+        }
 
-                    // Find the render pass
-                    let mut dims = (0, 0);
-                    let mut views = Vec::with_capacity(descriptor.color_attachments.len());
-                    for attachment in &descriptor.color_attachments {
-                        views.push(match &attachment.source {
-                            ColorAttachmentSource::SurfaceImage(image) => {
-                                // Indicate that the surface image has been drawn to
-                                image.internal().signal_draw();
-                                dims = image.internal().dims();
-                                image.internal().view()
-                            }
-                            ColorAttachmentSource::Texture {
-                                texture,
-                                array_element,
-                                mip_level,
-                            } => {
-                                let internal = texture.internal();
-                                dims = (texture.dims().0, texture.dims().1);
-                                texture.internal().views
-                                    [(*array_element * internal.array_elements) + *mip_level]
-                            }
-                        });
-                    }
+        // Tracking is done, so only the queue stays locked while submitting. It keeps the target
+        // value of the queue from being read by other submissions until the submission is made.
+        drop(pipeline_tracker);
+        drop(pipelines);
+        drop(resc_state);
 
-                    if let Some(attachment) = &descriptor.depth_stencil_attachment {
-                        let texture = attachment.texture.internal();
-                        views.push(texture.views[attachment.array_element]);
-                    }
+        // Submit to the queue
+        self.device.end_command_buffer(cb).unwrap();
+        submit_queue
+            .submit(&self.device, cb, independent, semaphore_tracker)
+            .unwrap();
 
-                    // Find the framebuffer
-                    let framebuffer = self.framebuffers.get(
-                        &self.device,
-                        active_render_pass,
-                        views,
-                        vk::Extent2D {
-                            width: dims.0,
-                            height: dims.1,
-                        },
-                    );
+        Job {
+            ty: queue,
+            target_value: next_target_value,
+        }
+    }
 
-                    // Find clear values
-                    let mut clear_values = Vec::with_capacity(descriptor.color_attachments.len());
-                    for attachment in &descriptor.color_attachments {
-                        if let LoadOp::Clear(clear_color) = &attachment.load_op {
-                            let color = match clear_color {
-                                ClearColor::RgbaF32(r, g, b, a) => vk::ClearColorValue {
-                                    float32: [*r, *g, *b, *a],
-                                },
-                                ClearColor::RU32(r) => vk::ClearColorValue {
-                                    uint32: [*r, 0, 0, 0],
-                                },
-                                ClearColor::D32S32(_, _) => {
-                                    panic!("invalid color clear color type")
-                                }
-                            };
-                            clear_values.push(vk::ClearValue { color });
-                        }
-                    }
+    unsafe fn record_commands<'a>(
+        &self,
+        queue: QueueType,
+        debug_name: Option<&str>,
+        commands: Vec<Command<'a, Self>>,
+    ) -> Self::PrebuiltCommands {
+        let mut resc_state = self.resource_state.write().unwrap();
+        let mut pipelines = self.pipelines.lock().unwrap();
+        // Prebuilt commands can be resubmitted at any time, so the pipelines they use are kept
+        pipelines.set_use_frame(u64::MAX);
+
+        // Prebuilt command buffers are never recycled by the queue, so they get their own pool
+        let command_pool = match queue {
+            QueueType::Main => &self.main,
+            QueueType::Transfer => &self.transfer,
+            QueueType::Compute => &self.compute,
+            QueueType::Present => &self.present,
+        }
+        .read()
+        .unwrap()
+        .create_prebuilt_pool(&self.device)
+        .unwrap();
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(1)
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .build();
+        let cb = self.device.allocate_command_buffers(&alloc_info).unwrap()[0];
 
-                    if let Some(attachment) = &descriptor.depth_stencil_attachment {
-                        if let LoadOp::Clear(clear_color) = &attachment.load_op {
-                            let depth_stencil = match clear_color {
-                                ClearColor::D32S32(d, s) => vk::ClearDepthStencilValue {
-                                    depth: *d,
-                                    stencil: *s,
-                                },
-                                _ => panic!("invalid depth clear color"),
-                            };
-                            clear_values.push(vk::ClearValue { depth_stencil })
-                        }
-                    }
+        // The command buffer may still be executing when it is submitted again
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE)
+            .build();
+        self.device.begin_command_buffer(cb, &begin_info).unwrap();
 
-                    // Initial viewport configuration
-                    // NOTE: Viewport is flipped to account for Vulkan coordinate system
-                    let viewport = [vk::Viewport {
-                        width: dims.0 as f32,
-                        height: -(dims.1 as f32),
-                        x: 0.0,
-                        y: dims.1 as f32,
-                        min_depth: 0.0,
-                        max_depth: 1.0,
-                    }];
+        // Query pools written to are reset every time the commands are submitted
+        let mut reset_pools = Vec::default();
+        for command in &commands {
+            if let Command::WriteTimestamp { pool, .. } = command {
+                let pool = pool.internal();
+                if !reset_pools.contains(&pool.pool) {
+                    pool.take_reset();
+                    self.device
+                        .cmd_reset_query_pool(cb, pool.pool, 0, pool.count);
+                    reset_pools.push(pool.pool);
+                }
+            }
+        }
 
-                    let scissor = [vk::Rect2D {
-                        extent: vk::Extent2D {
-                            width: dims.0,
-                            height: dims.1,
-                        },
-                        offset: vk::Offset2D { x: 0, y: 0 },
-                    }];
+        // Interpret commands. Since the state of resources at submission is unknown, tracking
+        // is deferred until the commands are submitted.
+        let mut semaphore_tracker = SemaphoreTracker::default();
+        let mut pipeline_tracker = PipelineTracker::new_deferred(&mut resc_state, queue);
+        self.record_into(
+            cb,
+            debug_name,
+            &commands,
+            &mut pipelines,
+            &mut pipeline_tracker,
+            &mut semaphore_tracker,
+        );
+        self.device.end_command_buffer(cb).unwrap();
 
-                    self.device.cmd_set_viewport(cb, 0, &viewport);
-                    self.device.cmd_set_scissor(cb, 0, &scissor);
+        let (entry, exit) = pipeline_tracker.finish_deferred();
+        PrebuiltCommands::new(queue, command_pool, cb, entry, exit, self.garbage.sender())
+    }
 
-                    // Begin the render pass
-                    let begin_info = vk::RenderPassBeginInfo::builder()
-                        .render_pass(active_render_pass)
-                        .clear_values(&clear_values)
-                        .framebuffer(framebuffer)
-                        .render_area(vk::Rect2D {
-                            offset: vk::Offset2D { x: 0, y: 0 },
-                            extent: vk::Extent2D {
-                                width: dims.0,
-                                height: dims.1,
-                            },
-                        })
-                        .build();
+    unsafe fn resubmit_commands(&self, queue: QueueType, prebuilt: &Self::PrebuiltCommands) -> Job {
+        debug_assert_eq!(queue, prebuilt.queue);
 
-                    self.device
-                        .cmd_begin_render_pass(cb, &begin_info, vk::SubpassContents::INLINE);
-                }
-                Command::EndRenderPass => self.device.cmd_end_render_pass(cb),
-                Command::BeginComputePass => {}
-                Command::EndComputePass => {}
-                Command::BindComputePipeline(pipeline) => {
-                    active_layout = pipeline.internal().layout;
-                    self.device.cmd_bind_pipeline(
-                        cb,
-                        vk::PipelineBindPoint::COMPUTE,
-                        pipeline.internal().pipeline,
-                    );
-                }
-                Command::Dispatch(x, y, z) => {
-                    self.device.cmd_dispatch(cb, *x, *y, *z);
-                }
-                Command::BindGraphicsPipeline(pipeline) => {
-                    active_layout = pipeline.internal().layout();
-                    let pipeline = pipeline.internal().get(
-                        &self.device,
-                        &mut pipelines,
-                        self.debug.as_ref().map(|(utils, _)| utils),
-                        active_render_pass,
-                    );
-                    self.device
-                        .cmd_bind_pipeline(cb, vk::PipelineBindPoint::GRAPHICS, pipeline);
-                }
-                Command::BindDescriptorSets { sets, first, stage } => {
-                    let mut vk_sets = Vec::with_capacity(sets.len());
-                    for set in sets {
-                        vk_sets.push(set.internal().set);
-                    }
+        // Callbacks are called before anything is locked since they may submit work themselves
+        self.call_completed_callbacks();
+        self.throttle(queue);
 
-                    self.device.cmd_bind_descriptor_sets(
-                        cb,
-                        match *stage {
-                            ShaderStage::Compute => vk::PipelineBindPoint::COMPUTE,
-                            _ => vk::PipelineBindPoint::GRAPHICS,
-                        },
-                        active_layout,
-                        *first as u32,
-                        &vk_sets,
-                        &[],
-                    );
-                }
-                Command::BindVertexBuffers { first, binds } => {
-                    let mut buffers = Vec::with_capacity(binds.len());
-                    let mut offsets = Vec::with_capacity(binds.len());
-                    for bind in binds {
-                        let buffer = bind.buffer.internal();
-                        buffers.push(buffer.buffer);
-                        offsets.push(buffer.offset(bind.array_element) + bind.offset);
-                    }
-                    self.device
-                        .cmd_bind_vertex_buffers(cb, *first as u32, &buffers, &offsets);
-                }
-                Command::BindIndexBuffer {
-                    buffer,
-                    array_element,
-                    offset,
-                    ty,
-                } => {
-                    let buffer = buffer.internal();
-                    self.device.cmd_bind_index_buffer(
-                        cb,
-                        buffer.buffer,
-                        buffer.offset(*array_element) + offset,
-                        crate::util::to_vk_index_type(*ty),
-                    );
-                }
-                Command::Draw {
-                    vertex_count,
-                    instance_count,
-                    first_vertex,
-                    first_instance,
-                } => {
-                    self.device.cmd_draw(
-                        cb,
-                        *vertex_count as u32,
-                        *instance_count as u32,
-                        *first_vertex as u32,
-                        *first_instance as u32,
-                    );
-                }
-                Command::DrawIndexed {
-                    index_count,
-                    instance_count,
-                    first_index,
-                    vertex_offset,
-                    first_instance,
-                } => {
-                    self.device.cmd_draw_indexed(
-                        cb,
-                        *index_count as u32,
-                        *instance_count as u32,
-                        *first_index as u32,
-                        *vertex_offset as i32,
-                        *first_instance as u32,
-                    );
-                }
-                Command::DrawIndexedIndirect {
-                    buffer,
-                    array_element,
-                    offset,
-                    draw_count,
-                    stride,
-                } => {
-                    self.device.cmd_draw_indexed_indirect(
-                        cb,
-                        buffer.internal().buffer,
-                        buffer.internal().offset(*array_element) + *offset,
-                        *draw_count as u32,
-                        *stride as u32,
-                    );
-                }
-                Command::CopyBufferToBuffer(copy) => {
-                    let src = copy.src.internal();
-                    let dst = copy.dst.internal();
-                    let region = [vk::BufferCopy::builder()
-                        .dst_offset(dst.offset(copy.dst_array_element) + copy.dst_offset)
-                        .src_offset(src.offset(copy.src_array_element) + copy.src_offset)
-                        .size(copy.len)
-                        .build()];
-                    self.device
-                        .cmd_copy_buffer(cb, src.buffer, dst.buffer, &region);
-                }
-                Command::CopyBufferToTexture {
-                    buffer,
-                    texture,
-                    copy,
-                } => {
-                    let src = buffer.internal();
-                    let dst = texture.internal();
-                    let copy = [vk::BufferImageCopy::builder()
-                        .buffer_offset(src.offset(copy.buffer_array_element) + copy.buffer_offset)
-                        .buffer_row_length(copy.buffer_row_length)
-                        .buffer_image_height(copy.buffer_image_height)
-                        .image_subresource(vk::ImageSubresourceLayers {
-                            aspect_mask: dst.aspect_flags,
-                            mip_level: copy.texture_mip_level as u32,
-                            base_array_layer: copy.texture_array_element as u32,
-                            layer_count: 1,
-                        })
-                        .image_offset(vk::Offset3D {
-                            x: copy.texture_offset.0 as i32,
-                            y: copy.texture_offset.1 as i32,
-                            z: copy.texture_offset.2 as i32,
-                        })
-                        .image_extent(vk::Extent3D {
-                            width: copy.texture_extent.0,
-                            height: copy.texture_extent.1,
-                            depth: copy.texture_extent.2,
-                        })
-                        .build()];
-                    self.device.cmd_copy_buffer_to_image(
-                        cb,
-                        src.buffer,
-                        dst.image,
-                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                        &copy,
-                    );
-                }
-                Command::CopyTextureToBuffer {
-                    buffer,
-                    texture,
-                    copy,
-                } => {
-                    let src = texture.internal();
-                    let dst = buffer.internal();
-                    let copy = [vk::BufferImageCopy::builder()
-                        .buffer_offset(dst.offset(copy.buffer_array_element) + copy.buffer_offset)
-                        .buffer_row_length(copy.buffer_row_length)
-                        .buffer_image_height(copy.buffer_image_height)
-                        .image_subresource(vk::ImageSubresourceLayers {
-                            aspect_mask: src.aspect_flags,
-                            mip_level: copy.texture_mip_level as u32,
-                            base_array_layer: copy.texture_array_element as u32,
-                            layer_count: 1,
-                        })
-                        .image_offset(vk::Offset3D {
-                            x: copy.texture_offset.0 as i32,
-                            y: copy.texture_offset.1 as i32,
-                            z: copy.texture_offset.2 as i32,
-                        })
-                        .image_extent(vk::Extent3D {
-                            width: copy.texture_extent.0,
-                            height: copy.texture_extent.1,
-                            depth: copy.texture_extent.2,
-                        })
-                        .build()];
-                    self.device.cmd_copy_image_to_buffer(
-                        cb,
-                        src.image,
-                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                        dst.buffer,
-                        &copy,
-                    );
-                }
-            }
+        // Lock down all neccesary objects. Of the queues, only the one being submitted to is
+        // locked for the whole submission (see `lock_submit_queue`). Everything else is released
+        // once resource tracking is done.
+        let mut resc_state = self.resource_state.write().unwrap();
+        let mut garbage_locks = self.lock_submit_garbage();
+        let mut pipelines = self.pipelines.lock().unwrap();
+        let (current_values, target_values) = self.timeline_values();
+        let mut submit_queue = self.lock_submit_queue(queue);
+        let next_target_value = submit_queue.target_timeline_value() + 1;
+        if let Some((allocator, pools)) = &mut garbage_locks {
+            self.garbage.cleanup(
+                &self.device,
+                allocator,
+                pools,
+                &mut pipelines,
+                current_values,
+                target_values,
+            );
+        }
+
+        // Garbage is only collected up front, so creating resources isn't blocked while the
+        // commands are recorded
+        drop(garbage_locks);
+
+        // The prebuilt commands are preceded by a command buffer that moves every resource they
+        // use into the state the commands expect
+        let mut semaphore_tracker = SemaphoreTracker::default();
+        let mut pipeline_tracker = PipelineTracker::new(&mut resc_state, queue, next_target_value);
+        let cb = submit_queue
+            .allocate_command_buffer(&self.device, self.debug.as_ref().map(|(utils, _)| utils));
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build();
+        self.device.begin_command_buffer(cb, &begin_info).unwrap();
+        if let Some(barrier) = pipeline_tracker.submit(prebuilt.entry.clone()) {
+            barrier.execute(&self.device, cb);
         }
+        self.device.end_command_buffer(cb).unwrap();
 
         // Grab detected semaphores
-        for (queue, stage) in pipeline_tracker.wait_queues() {
-            let (semaphore, value) = match *queue {
-                QueueType::Main => (main.semaphore(), target_values.main),
-                QueueType::Transfer => (transfer.semaphore(), target_values.transfer),
-                QueueType::Compute => (compute.semaphore(), target_values.compute),
-                QueueType::Present => unreachable!(),
+        for (wait_queue, stage) in pipeline_tracker.wait_queues() {
+            let semaphore = if *wait_queue == queue {
+                submit_queue.semaphore()
+            } else {
+                self.queue(*wait_queue).read().unwrap().semaphore()
             };
             semaphore_tracker.register_wait(
                 semaphore,
                 WaitInfo {
-                    value: Some(value),
+                    value: Some(target_values.get(*wait_queue)),
                     stage: *stage,
                 },
             );
         }
 
-        // Submit to the queue
-        if debug_name.is_some() {
-            if let Some((debug, _)) = &self.debug {
-                debug.cmd_end_debug_utils_label(cb);
-            }
-        }
+        // Resources are left in the state of their last usage within the commands
+        drop(pipeline_tracker);
+        resc_state.register_layouts(&prebuilt.exit);
 
-        self.device.end_command_buffer(cb).unwrap();
-        match queue {
-            QueueType::Main => main,
-            QueueType::Transfer => transfer,
-            QueueType::Compute => compute,
-            QueueType::Present => present,
-        }
-        .submit(&self.device, cb, semaphore_tracker)
-        .unwrap();
+        // Tracking is done, so only the queue stays locked while submitting. It keeps the target
+        // value of the queue from being read by other submissions until the submission is made.
+        drop(pipelines);
+        drop(resc_state);
+
+        // Submit to the queue
+        submit_queue
+            .submit_prebuilt(&self.device, cb, prebuilt.command_buffer, semaphore_tracker)
+            .unwrap();
 
         Job {
             ty: queue,
@@ -685,6 +740,16 @@ impl Backend for VulkanBackend {
         }
     }
 
+    unsafe fn reclaim_commands(&self, job: &Self::Job) {
+        let mut queue = match job.ty {
+            QueueType::Main => self.main.write().unwrap(),
+            QueueType::Transfer => self.transfer.write().unwrap(),
+            QueueType::Compute => self.compute.write().unwrap(),
+            QueueType::Present => self.present.write().unwrap(),
+        };
+        queue.reclaim(&self.device, job.target_value);
+    }
+
     unsafe fn poll_status(&self, job: &Self::Job) -> JobStatus {
         let queue = match job.ty {
             QueueType::Main => self.main.read().unwrap(),
@@ -700,80 +765,742 @@ impl Backend for VulkanBackend {
         }
     }
 
+    unsafe fn on_job_complete(&self, job: &Self::Job, callback: Box<dyn FnOnce() + Send>) {
+        if self.poll_status(job) == JobStatus::Complete {
+            callback();
+        } else {
+            self.garbage.add_callback(JobCallback {
+                ty: job.ty,
+                target_value: job.target_value,
+                callback,
+            });
+        }
+    }
+
+    unsafe fn all_work_complete(&self) -> bool {
+        for queue in [&self.main, &self.transfer, &self.compute, &self.present] {
+            let queue = queue.read().unwrap();
+            if queue.current_timeline_value(&self.device) < queue.target_timeline_value() {
+                return false;
+            }
+        }
+        true
+    }
+
+    unsafe fn wait_all_work(&self, timeout: Option<std::time::Duration>) -> bool {
+        let mut semaphores = Vec::with_capacity(4);
+        let mut values = Vec::with_capacity(4);
+        for queue in [&self.main, &self.transfer, &self.compute, &self.present] {
+            let queue = queue.read().unwrap();
+            semaphores.push(queue.semaphore());
+            values.push(queue.target_timeline_value());
+        }
+
+        let wait = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values)
+            .build();
+        self.device
+            .wait_semaphores(
+                &wait,
+                match timeout {
+                    Some(timeout) => timeout.as_nanos() as u64,
+                    None => u64::MAX,
+                },
+            )
+            .is_ok()
+    }
+
+    #[inline(always)]
+    unsafe fn present_queue_supports_transfer(&self) -> bool {
+        // Graphics and compute capable queue families implicitly support transfer operations
+        self.queue_family_indices.present_flags.intersects(
+            vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER,
+        )
+    }
+
     #[inline(always)]
     unsafe fn create_buffer(
         &self,
         create_info: BufferCreateInfo,
     ) -> Result<Self::Buffer, BufferCreateError> {
-        Buffer::new(
+        let debug_name = create_info.debug_name.clone();
+        let buffer = Buffer::new(
             &self.device,
             self.debug.as_ref().map(|(utils, _)| utils),
             self.garbage.sender(),
             &mut self.allocator.lock().unwrap(),
             &self.properties.limits,
+            &self.queue_family_indices.unique,
             create_info,
-        )
+        )?;
+        if let Some(name) = debug_name {
+            self.resource_state
+                .write()
+                .unwrap()
+                .register_name(buffer.buffer, name);
+        }
+
+        Ok(buffer)
     }
 
-    #[inline(always)]
-    unsafe fn create_texture(
+    unsafe fn create_aliased_buffer(
         &self,
-        create_info: TextureCreateInfo,
-    ) -> Result<Self::Texture, TextureCreateError> {
-        Texture::new(
+        create_info: BufferCreateInfo,
+        source: &Self::Buffer,
+        offset: u64,
+    ) -> Result<Self::Buffer, BufferCreateError> {
+        let mut resc_state = self.resource_state.write().unwrap();
+
+        // Once aliased, the source is tracked as a whole instead of per array element, so any
+        // outstanding per element usage must be complete
+        if source.alias.is_none() {
+            for array_elem in 0..source.array_elements {
+                if let Some(old) =
+                    resc_state.register_buffer(source.buffer, array_elem as u32, None)
+                {
+                    self.wait_on(
+                        &Job {
+                            ty: old.queue,
+                            target_value: old.timeline_value,
+                        },
+                        None,
+                    );
+                }
+            }
+        }
+
+        let debug_name = create_info.debug_name.clone();
+        let buffer = Buffer::new_aliased(
             &self.device,
             self.debug.as_ref().map(|(utils, _)| utils),
             self.garbage.sender(),
-            &mut self.allocator.lock().unwrap(),
+            &self.properties.limits,
+            &self.queue_family_indices.unique,
             create_info,
-        )
+            source,
+            offset,
+        )?;
+        resc_state.register_alias(buffer.buffer, buffer.root());
+        if let Some(name) = debug_name {
+            resc_state.register_name(buffer.buffer, name);
+        }
+
+        Ok(buffer)
     }
 
     #[inline(always)]
-    unsafe fn create_shader(
+    unsafe fn create_texture(
         &self,
-        create_info: ShaderCreateInfo,
-    ) -> Result<Self::Shader, ShaderCreateError> {
-        Shader::new(
-            &self.device,
-            self.debug.as_ref().map(|(utils, _)| utils),
+        create_info: TextureCreateInfo,
+    ) -> Result<Self::Texture, TextureCreateError> {
+        match create_info.tiling {
+            TextureTiling::Optimal => {
+                if !self.supports_texture_usage(create_info.format, create_info.texture_usage) {
+                    return Err(TextureCreateError::UnsupportedUsage);
+                }
+            }
+            // Linear tiling is much more restricted, so the whole description is checked
+            TextureTiling::Linear => {
+                match self.instance.get_physical_device_image_format_properties(
+                    self.physical_device,
+                    util::to_vk_format(create_info.format),
+                    util::to_vk_image_type(create_info.ty),
+                    vk::ImageTiling::LINEAR,
+                    util::to_vk_image_usage(create_info.texture_usage),
+                    vk::ImageCreateFlags::empty(),
+                ) {
+                    Ok(properties)
+                        if properties.max_mip_levels as usize >= create_info.mip_levels
+                            && properties.max_array_layers as usize
+                                >= create_info.array_elements => {}
+                    _ => return Err(TextureCreateError::UnsupportedTiling),
+                }
+            }
+        }
+
+        let debug_name = create_info.debug_name.clone();
+        let texture = Texture::new(
+            self,
+            &mut self.allocator.lock().unwrap(),
             create_info,
-        )
+            false,
+        )?;
+
+        let linear = texture.tiling == TextureTiling::Linear;
+        if debug_name.is_none() && !linear {
+            return Ok(texture);
+        }
+
+        let mut resc_state = self.resource_state.write().unwrap();
+        if let Some(name) = debug_name {
+            resc_state.register_name(texture.image, name);
+        }
+
+        // Linear textures start out in the preinitialized layout
+        if linear {
+            for array_elem in 0..texture.array_elements {
+                for mip_level in 0..texture.mip_count {
+                    resc_state.register_layout(
+                        texture.image,
+                        array_elem as u32,
+                        mip_level,
+                        vk::ImageLayout::PREINITIALIZED,
+                    );
+                }
+            }
+        }
+
+        Ok(texture)
     }
 
-    #[inline(always)]
-    unsafe fn create_graphics_pipeline(
+    unsafe fn create_sparse_texture(
         &self,
-        create_info: GraphicsPipelineCreateInfo<Self>,
-    ) -> Result<Self::GraphicsPipeline, GraphicsPipelineCreateError> {
-        Ok(GraphicsPipeline::new(
+        create_info: TextureCreateInfo,
+    ) -> Result<Self::Texture, TextureCreateError> {
+        if !self.supports_sparse_textures() || !create_info.format.is_color() {
+            return Err(TextureCreateError::SparseUnsupported);
+        }
+
+        if !self.supports_texture_usage(create_info.format, create_info.texture_usage) {
+            return Err(TextureCreateError::UnsupportedUsage);
+        }
+
+        // The format must support sparse residency with the requested usage
+        let format_properties = self
+            .instance
+            .get_physical_device_sparse_image_format_properties(
+                self.physical_device,
+                util::to_vk_format(create_info.format),
+                vk::ImageType::TYPE_2D,
+                vk::SampleCountFlags::TYPE_1,
+                util::to_vk_image_usage(create_info.texture_usage),
+                vk::ImageTiling::OPTIMAL,
+            );
+        if format_properties.is_empty() {
+            return Err(TextureCreateError::SparseUnsupported);
+        }
+
+        let mut resc_state = self.resource_state.write().unwrap();
+        let mut allocator = self.allocator.lock().unwrap();
+        let location = util::to_gpu_allocator_memory_location(create_info.memory_usage);
+        let array_elements = create_info.array_elements;
+        let debug_name = create_info.debug_name.clone();
+        let mut texture = Texture::new(self, &mut allocator, create_info, true)?;
+        if let Some(name) = debug_name {
+            resc_state.register_name(texture.image, name);
+        }
+
+        // Mip levels in the tail are always resident, so they are bound up front
+        let image = texture.image;
+        let mip_count = texture.mip_count;
+        let sparse = texture.sparse.as_mut().unwrap();
+        if sparse.mip_tail_first_lod >= mip_count {
+            return Ok(texture);
+        }
+
+        let tail_count = if sparse.single_mip_tail {
+            1
+        } else {
+            array_elements
+        };
+        let mut binds = Vec::with_capacity(tail_count);
+        for i in 0..tail_count {
+            let request = AllocationCreateDesc {
+                name: "sparse_mip_tail",
+                requirements: vk::MemoryRequirements {
+                    size: sparse.mip_tail_size,
+                    ..sparse.page_requirements
+                },
+                location,
+                linear: false,
+            };
+            let block = match allocator.allocate(&request) {
+                Ok(block) => block,
+                Err(err) => return Err(TextureCreateError::Other(err.to_string())),
+            };
+            binds.push(vk::SparseMemoryBind {
+                resource_offset: sparse.mip_tail_offset + (i as u64 * sparse.mip_tail_stride),
+                size: sparse.mip_tail_size,
+                memory: block.memory(),
+                memory_offset: block.offset(),
+                flags: vk::SparseMemoryBindFlags::empty(),
+            });
+            sparse.mip_tail.push(block);
+        }
+
+        let opaque_binds = [vk::SparseImageOpaqueMemoryBindInfo::builder()
+            .image(image)
+            .binds(&binds)
+            .build()];
+        let mut main = self.main.write().unwrap();
+        if let Err(err) = main.bind_sparse(
             &self.device,
-            self.garbage.sender(),
-            create_info,
-        ))
+            &[],
+            &opaque_binds,
+            SemaphoreTracker::default(),
+        ) {
+            return Err(TextureCreateError::Other(err.to_string()));
+        }
+
+        // Work on other queues must wait for the mip tail to be bound
+        let usage = QueueUsage {
+            queue: QueueType::Main,
+            timeline_value: main.target_timeline_value(),
+        };
+        for array_elem in 0..array_elements {
+            resc_state.register_image(image, array_elem as u32, Some(usage));
+        }
+
+        Ok(texture)
     }
 
     #[inline(always)]
-    unsafe fn create_compute_pipeline(
+    unsafe fn texture_sparse_properties(
         &self,
-        create_info: ComputePipelineCreateInfo<Self>,
-    ) -> Result<Self::ComputePipeline, ComputePipelineCreateError> {
-        ComputePipeline::new(
-            &self.device,
-            self.debug.as_ref().map(|(utils, _)| utils),
-            self.garbage.sender(),
-            create_info,
-        )
+        texture: &Self::Texture,
+    ) -> Option<SparseProperties> {
+        texture.sparse.as_ref().map(|sparse| SparseProperties {
+            page_dims: (
+                sparse.page_dims.width,
+                sparse.page_dims.height,
+                sparse.page_dims.depth,
+            ),
+            mip_tail_first_level: sparse.mip_tail_first_lod.min(texture.mip_count) as usize,
+        })
     }
 
     #[inline(always)]
-    unsafe fn create_descriptor_set(
+    unsafe fn supports_sparse_textures(&self) -> bool {
+        let main_flags = self
+            .instance
+            .get_physical_device_queue_family_properties(self.physical_device)
+            [self.queue_family_indices.main as usize]
+            .queue_flags;
+        self.features.sparse_binding == vk::TRUE
+            && self.features.sparse_residency_image2_d == vk::TRUE
+            && main_flags.contains(vk::QueueFlags::SPARSE_BINDING)
+    }
+
+    unsafe fn bind_sparse<'a>(
         &self,
-        create_info: DescriptorSetCreateInfo<Self>,
-    ) -> Result<Self::DescriptorSet, DescriptorSetCreateError> {
-        DescriptorSet::new(
-            &self.device,
-            &mut self.pools.lock().unwrap(),
+        binds: &[SparseTextureBind<'a, Self>],
+    ) -> Result<Self::Job, SparseBindError> {
+        let mut resc_state = self.resource_state.write().unwrap();
+        let mut allocator = self.allocator.lock().unwrap();
+        let mut main = self.main.write().unwrap();
+        let next_value = main.target_timeline_value() + 1;
+
+        // Every change made to the pages of a texture or to the tracked usage of an image is
+        // recorded, so that it can be undone if binding fails
+        let mut changes = Vec::default();
+        let mut usages = Vec::default();
+        let undo = |changes: Vec<PageChange>,
+                    usages: Vec<(vk::Image, u32, Option<QueueUsage>)>,
+                    resc_state: &mut GlobalResourceUsage,
+                    allocator: &mut Allocator| {
+            for (image, array_element, old) in usages.into_iter().rev() {
+                resc_state.register_image(image, array_element, old);
+            }
+            for change in changes.into_iter().rev() {
+                let mut pages = change
+                    .texture
+                    .sparse
+                    .as_ref()
+                    .unwrap()
+                    .pages
+                    .lock()
+                    .unwrap();
+                match change.released {
+                    Some(block) => {
+                        pages.insert(change.key, block);
+                    }
+                    None => {
+                        let block = pages.remove(&change.key).unwrap();
+                        let _ = allocator.free(block);
+                    }
+                }
+            }
+        };
+
+        let mut semaphore_tracker = SemaphoreTracker::default();
+        let mut image_binds = HashMap::<vk::Image, Vec<vk::SparseImageMemoryBind>>::default();
+        for bind in binds {
+            let texture = bind.texture.internal();
+            let sparse = texture.sparse.as_ref().unwrap();
+            let key = SparsePage {
+                array_element: bind.array_element as u32,
+                mip_level: bind.mip_level as u32,
+                page: bind.page,
+            };
+
+            // Allocate or release the memory of the page
+            let (memory, memory_offset) = {
+                let mut pages = sparse.pages.lock().unwrap();
+                if bind.resident {
+                    if pages.contains_key(&key) {
+                        continue;
+                    }
+                    let request = AllocationCreateDesc {
+                        name: "sparse_page",
+                        requirements: sparse.page_requirements,
+                        location: util::to_gpu_allocator_memory_location(texture.memory_usage),
+                        linear: false,
+                    };
+                    let block = match allocator.allocate(&request) {
+                        Ok(block) => block,
+                        Err(err) => {
+                            drop(pages);
+                            undo(changes, usages, &mut resc_state, &mut allocator);
+                            return Err(SparseBindError::AllocationFailed(err.to_string()));
+                        }
+                    };
+                    let memory = (block.memory(), block.offset());
+                    pages.insert(key, block);
+                    changes.push(PageChange {
+                        texture,
+                        key,
+                        released: None,
+                    });
+                    memory
+                } else {
+                    match pages.remove(&key) {
+                        Some(block) => {
+                            changes.push(PageChange {
+                                texture,
+                                key,
+                                released: Some(block),
+                            });
+                            (vk::DeviceMemory::null(), 0)
+                        }
+                        None => continue,
+                    }
+                }
+            };
+
+            // Pages on the edge of a mip level are clipped to it
+            let (width, height, depth) = bind.texture.dims();
+            let mip_dim = |dim: u32| (dim >> bind.mip_level).max(1);
+            let offset = (
+                bind.page.0 * sparse.page_dims.width,
+                bind.page.1 * sparse.page_dims.height,
+                bind.page.2 * sparse.page_dims.depth,
+            );
+            image_binds
+                .entry(texture.image)
+                .or_default()
+                .push(vk::SparseImageMemoryBind {
+                    subresource: vk::ImageSubresource {
+                        aspect_mask: texture.aspect_flags,
+                        mip_level: key.mip_level,
+                        array_layer: key.array_element,
+                    },
+                    offset: vk::Offset3D {
+                        x: offset.0 as i32,
+                        y: offset.1 as i32,
+                        z: offset.2 as i32,
+                    },
+                    extent: vk::Extent3D {
+                        width: (mip_dim(width) - offset.0).min(sparse.page_dims.width),
+                        height: (mip_dim(height) - offset.1).min(sparse.page_dims.height),
+                        depth: (mip_dim(depth) - offset.2).min(sparse.page_dims.depth),
+                    },
+                    memory,
+                    memory_offset,
+                    flags: vk::SparseMemoryBindFlags::empty(),
+                });
+
+            // Binding must wait for other queues using the texture, and their future work must
+            // wait for the binding
+            let old = resc_state.register_image(
+                texture.image,
+                key.array_element,
+                Some(QueueUsage {
+                    queue: QueueType::Main,
+                    timeline_value: next_value,
+                }),
+            );
+            usages.push((texture.image, key.array_element, old));
+            if let Some(old) = old {
+                let semaphore = match old.queue {
+                    QueueType::Main => continue,
+                    QueueType::Transfer => self.transfer.read().unwrap().semaphore(),
+                    QueueType::Compute => self.compute.read().unwrap().semaphore(),
+                    QueueType::Present => self.present.read().unwrap().semaphore(),
+                };
+                semaphore_tracker.register_wait(
+                    semaphore,
+                    WaitInfo {
+                        value: Some(old.timeline_value),
+                        stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                    },
+                );
+            }
+        }
+
+        let infos: Vec<_> = image_binds
+            .iter()
+            .map(|(image, binds)| {
+                vk::SparseImageMemoryBindInfo::builder()
+                    .image(*image)
+                    .binds(binds)
+                    .build()
+            })
+            .collect();
+        if let Err(err) = main.bind_sparse(&self.device, &infos, &[], semaphore_tracker) {
+            undo(changes, usages, &mut resc_state, &mut allocator);
+            return Err(SparseBindError::Other(err.to_string()));
+        }
+
+        // Released memory is freed once the GPU is done with it
+        let garbage = self.garbage.sender();
+        for change in changes {
+            if let Some(block) = change.released {
+                dispose(&garbage, Garbage::Memory(block));
+            }
+        }
+
+        Ok(Job {
+            ty: QueueType::Main,
+            target_value: next_value,
+        })
+    }
+
+    #[inline(always)]
+    unsafe fn supports_extended_dynamic_state(&self) -> bool {
+        self.extended_dynamic_state.is_some()
+    }
+
+    #[inline(always)]
+    unsafe fn supports_texture_usage(&self, format: TextureFormat, usage: TextureUsage) -> bool {
+        let properties = self.instance.get_physical_device_format_properties(
+            self.physical_device,
+            util::to_vk_format(format),
+        );
+        properties
+            .optimal_tiling_features
+            .contains(util::to_vk_format_features(usage))
+    }
+
+    #[inline(always)]
+    unsafe fn supports_mipmap_generation(&self, format: TextureFormat) -> bool {
+        let properties = self.instance.get_physical_device_format_properties(
+            self.physical_device,
+            util::to_vk_format(format),
+        );
+        properties.optimal_tiling_features.contains(
+            vk::FormatFeatureFlags::BLIT_SRC
+                | vk::FormatFeatureFlags::BLIT_DST
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        )
+    }
+
+    #[inline(always)]
+    unsafe fn prewarm_framebuffer(&self, descriptor: &RenderPassDescriptor<Self>) {
+        let render_pass = self.render_passes.get(&self.device, descriptor);
+        let (views, dims) = render_pass::framebuffer_views(descriptor);
+        self.framebuffers.get(
+            &self.device,
+            render_pass,
+            views,
+            vk::Extent2D {
+                width: dims.0,
+                height: dims.1,
+            },
+        );
+    }
+
+    #[inline(always)]
+    unsafe fn create_query_pool(
+        &self,
+        create_info: QueryPoolCreateInfo,
+    ) -> Result<Self::QueryPool, QueryPoolCreateError> {
+        QueryPool::new(
+            &self.device,
+            self.debug.as_ref().map(|(utils, _)| utils),
+            self.garbage.sender(),
+            &self.properties.limits,
+            create_info,
+        )
+    }
+
+    #[inline(always)]
+    unsafe fn reset_query_pool(&self, pool: &mut Self::QueryPool) {
+        pool.reset();
+    }
+
+    #[inline(always)]
+    unsafe fn query_results(&self, pool: &Self::QueryPool, results: &mut [u64]) -> bool {
+        pool.results(&self.device, results)
+    }
+
+    #[inline(always)]
+    unsafe fn available_query_results(&self, pool: &Self::QueryPool, results: &mut [Option<u64>]) {
+        pool.available_results(&self.device, results)
+    }
+
+    #[inline(always)]
+    unsafe fn timestamp_period(&self) -> f32 {
+        self.properties.limits.timestamp_period
+    }
+
+    #[inline(always)]
+    unsafe fn timestamp_valid_bits(&self, queue: QueueType) -> u32 {
+        let family = match queue {
+            QueueType::Main => self.queue_family_indices.main,
+            QueueType::Transfer => self.queue_family_indices.transfer,
+            QueueType::Compute => self.queue_family_indices.compute,
+            QueueType::Present => self.queue_family_indices.present,
+        };
+        self.queue_family_indices.timestamp_valid_bits[family as usize]
+    }
+
+    #[inline(always)]
+    unsafe fn set_frames_in_flight(&self, frames_in_flight: usize) {
+        self.garbage.set_frames_in_flight(frames_in_flight as u64);
+    }
+
+    unsafe fn collect_garbage(&self) {
+        // Same lock order as submissions
+        let _resc_state = self.resource_state.write().unwrap();
+        let mut allocator = self.allocator.lock().unwrap();
+        let mut pools = self.pools.lock().unwrap();
+        let mut pipelines = self.pipelines.lock().unwrap();
+        let (current_values, target_values) = self.timeline_values();
+        self.garbage.cleanup(
+            &self.device,
+            &mut allocator,
+            &mut pools,
+            &mut pipelines,
+            current_values,
+            target_values,
+        );
+    }
+
+    #[inline(always)]
+    unsafe fn trim_pipeline_variants(&self, frames: usize) {
+        let oldest_frame = self.garbage.frame().saturating_sub(frames as u64);
+        let trimmed = self.pipelines.lock().unwrap().trim(oldest_frame);
+        let sender = self.garbage.sender();
+        for pipeline in trimmed {
+            dispose(&sender, Garbage::Pipeline(pipeline));
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn drain_validation_messages(&self) -> Vec<ValidationMessage> {
+        self.validation_messages.lock().unwrap().drain(..).collect()
+    }
+
+    unsafe fn report_validation_message(&self, severity: ValidationSeverity, message: &str) {
+        // Messages are routed through the debug messenger so they are filtered, printed, and
+        // collected like the ones reported by the validation layers
+        let (debug_utils, _) = match &self.debug {
+            Some(debug) => debug,
+            None => return,
+        };
+        let severity = match severity {
+            ValidationSeverity::Verbose => vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            ValidationSeverity::Info => vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            ValidationSeverity::Warning => vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            ValidationSeverity::Error => vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        };
+        let message = CString::new(message.replace('\0', "")).unwrap();
+        let callback_data = vk::DebugUtilsMessengerCallbackDataEXT::builder()
+            .message_id_name(c"pal")
+            .message(&message)
+            .build();
+        debug_utils.submit_debug_utils_message(
+            severity,
+            vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            &callback_data,
+        );
+    }
+
+    #[inline(always)]
+    unsafe fn create_shader(
+        &self,
+        create_info: ShaderCreateInfo,
+    ) -> Result<Self::Shader, ShaderCreateError> {
+        Shader::new(
+            &self.device,
+            self.debug.as_ref().map(|(utils, _)| utils),
+            create_info,
+        )
+    }
+
+    #[inline(always)]
+    unsafe fn fullscreen_vertex_shader(&self) -> &'static [u8] {
+        bytemuck::cast_slice(&shader::FULLSCREEN_VERTEX_SHADER)
+    }
+
+    #[inline(always)]
+    unsafe fn create_graphics_pipeline(
+        &self,
+        create_info: GraphicsPipelineCreateInfo<Self>,
+    ) -> Result<Self::GraphicsPipeline, GraphicsPipelineCreateError> {
+        if create_info.multisample.sample_shading.is_some()
+            && self.features.sample_rate_shading == vk::FALSE
+        {
+            return Err(GraphicsPipelineCreateError::SampleShadingUnsupported);
+        }
+
+        let pipeline = GraphicsPipeline::new(
+            &self.device,
+            self.garbage.sender(),
+            &self.properties.limits,
+            create_info,
+        )?;
+
+        // Create the pipeline up front if the attachments it will be used with are known
+        if let Some(formats) = pipeline.attachment_formats() {
+            let color_formats: Vec<_> = formats
+                .color
+                .iter()
+                .map(|format| crate::util::to_vk_format(*format))
+                .collect();
+            let depth_stencil_format = formats.depth_stencil.map(crate::util::to_vk_format);
+            let render_pass = self.render_passes.get_compatible(
+                &self.device,
+                &color_formats,
+                depth_stencil_format,
+            );
+            let mut pipelines = self.pipelines.lock().unwrap();
+            pipelines.set_use_frame(self.garbage.frame());
+            pipeline.get(
+                &self.device,
+                &mut pipelines,
+                self.debug.as_ref().map(|(utils, _)| utils),
+                render_pass,
+                depth_stencil_format.is_some(),
+                self.extended_dynamic_state.is_some(),
+            );
+        }
+
+        Ok(pipeline)
+    }
+
+    #[inline(always)]
+    unsafe fn create_compute_pipeline(
+        &self,
+        create_info: ComputePipelineCreateInfo<Self>,
+    ) -> Result<Self::ComputePipeline, ComputePipelineCreateError> {
+        ComputePipeline::new(
+            &self.device,
+            self.debug.as_ref().map(|(utils, _)| utils),
+            self.garbage.sender(),
+            create_info,
+        )
+    }
+
+    #[inline(always)]
+    unsafe fn create_descriptor_set(
+        &self,
+        create_info: DescriptorSetCreateInfo<Self>,
+    ) -> Result<Self::DescriptorSet, DescriptorSetCreateError> {
+        DescriptorSet::new(
+            &self.device,
+            &mut self.pools.lock().unwrap(),
             self.garbage.sender(),
             self.debug.as_ref().map(|(utils, _)| utils),
             create_info,
@@ -785,15 +1512,22 @@ impl Backend for VulkanBackend {
         &self,
         create_info: DescriptorSetLayoutCreateInfo,
     ) -> Result<Self::DescriptorSetLayout, DescriptorSetLayoutCreateError> {
+        if create_info.variable_count && !self.variable_descriptor_count {
+            return Err(DescriptorSetLayoutCreateError::VariableCountUnsupported);
+        }
         DescriptorSetLayout::new(&self.device, &mut self.pools.lock().unwrap(), create_info)
     }
 
-    unsafe fn destroy_buffer(&self, _buffer: &mut Self::Buffer) {
-        // Handled in drop
+    unsafe fn destroy_buffer(&self, buffer: &mut Self::Buffer) {
+        // The buffer itself is destroyed in drop, so only its tracking state is removed here
+        let mut resc_state = self.resource_state.write().unwrap();
+        resc_state.remove_name(buffer.buffer);
+        resc_state.remove_alias(buffer.buffer);
     }
 
-    unsafe fn destroy_texture(&self, _id: &mut Self::Texture) {
-        // Handled in drop
+    unsafe fn destroy_texture(&self, id: &mut Self::Texture) {
+        // The texture itself is destroyed in drop, so only its tracking state is removed here
+        self.resource_state.write().unwrap().remove_name(id.image);
     }
 
     #[inline(always)]
@@ -817,6 +1551,14 @@ impl Backend for VulkanBackend {
         // Not needed
     }
 
+    unsafe fn destroy_query_pool(&self, _pool: &mut Self::QueryPool) {
+        // Handled in drop
+    }
+
+    unsafe fn destroy_prebuilt_commands(&self, _prebuilt: &mut Self::PrebuiltCommands) {
+        // Handled in drop
+    }
+
     #[inline(always)]
     unsafe fn map_memory(
         &self,
@@ -830,6 +1572,20 @@ impl Backend for VulkanBackend {
         // Handled by the allocator
     }
 
+    unsafe fn map_texture(
+        &self,
+        id: &mut Self::Texture,
+        array_element: usize,
+        mip_level: usize,
+    ) -> Result<(NonNull<u8>, TextureSubresourceLayout), TextureViewError> {
+        id.map(self, array_element, mip_level)
+    }
+
+    #[inline(always)]
+    unsafe fn flush_texture(&self, id: &mut Self::Texture, array_element: usize, mip_level: usize) {
+        id.flush(self, array_element, mip_level);
+    }
+
     unsafe fn flush_range(&self, _id: &mut Self::Buffer, _idx: usize) {
         // Not needed because `HOST_COHERENT`
     }
@@ -850,10 +1606,103 @@ impl Backend for VulkanBackend {
 }
 
 impl VulkanBackend {
+    /// Blocks until `queue` has fewer than
+    /// [`max_pending_submits`](VulkanBackendCreateInfo::max_pending_submits) unfinished
+    /// submissions. Does nothing if there is no limit.
+    unsafe fn throttle(&self, queue: QueueType) {
+        let max_pending = match self.max_pending_submits {
+            Some(max_pending) => max_pending,
+            None => return,
+        };
+
+        // The lock is released before waiting so other threads aren't blocked
+        let (semaphore, target_value) = {
+            let queue = match queue {
+                QueueType::Main => &self.main,
+                QueueType::Transfer => &self.transfer,
+                QueueType::Compute => &self.compute,
+                QueueType::Present => &self.present,
+            }
+            .read()
+            .unwrap();
+            (queue.semaphore(), queue.target_timeline_value())
+        };
+
+        // Every submission increments the target value by one, so waiting for this value leaves
+        // `max_pending - 1` submissions in flight, making room for the new one. Timeline values
+        // only increase, so a blocking wait can't miss the value.
+        if target_value >= max_pending {
+            let semaphore = [semaphore];
+            let value = [target_value - max_pending + 1];
+            let wait = vk::SemaphoreWaitInfo::builder()
+                .semaphores(&semaphore)
+                .values(&value)
+                .build();
+            self.device.wait_semaphores(&wait, u64::MAX).unwrap();
+        }
+    }
+
+    /// Gets the raw Vulkan handles of the backend for use with other Vulkan code (e.g. an
+    /// external UI renderer or VR runtime).
+    ///
+    /// # Safety
+    /// - The handles *must not* be used after the backend is dropped, which happens once every
+    /// [`Context`](api::context::Context) referring to it is dropped. Objects created with the
+    /// device *must* be destroyed before then.
+    /// - Queues *must* only be used while their [`lock`](RawQueue::lock) is held. Work submitted
+    /// to a queue *must not* signal or wait on the timeline semaphores of the backend.
+    /// - Resources of the backend used by external code are not tracked. The user is responsible
+    /// for synchronizing them with work submitted through the backend.
+    /// - The handles *must not* be used to destroy objects owned by the backend.
+    #[cfg(feature = "raw")]
+    pub unsafe fn raw_handles(&self) -> RawHandles<'_> {
+        let raw_queue = |queue: &ShardedLock<VkQueue>| {
+            let queue = queue.read().unwrap();
+            RawQueue {
+                queue: queue.queue,
+                family_index: queue.queue_family(),
+                lock: queue.queue_lock(),
+            }
+        };
+
+        RawHandles {
+            entry: &self.entry,
+            instance: &self.instance,
+            physical_device: self.physical_device,
+            device: &self.device,
+            main: raw_queue(&self.main),
+            transfer: raw_queue(&self.transfer),
+            present: raw_queue(&self.present),
+            compute: raw_queue(&self.compute),
+        }
+    }
+
     pub fn new<'a, W: HasRawWindowHandle>(
         create_info: VulkanBackendCreateInfo<'a, W>,
     ) -> Result<Self, VulkanBackendCreateError> {
         let app_name = CString::new(create_info.app_name).unwrap();
+        let preallocated_command_buffers = create_info.preallocated_command_buffers;
+        let queue_priorities = create_info.queue_priorities;
+        let max_pending_submits = create_info.max_pending_submits;
+        let present_on_main = create_info.present_on_main;
+        let allocator_debug = create_info.allocator_debug;
+        let deferred_garbage_collection = create_info.deferred_garbage_collection;
+        assert_ne!(
+            max_pending_submits,
+            Some(0),
+            "the maximum number of pending submits cannot be zero"
+        );
+        for priority in [
+            queue_priorities.main,
+            queue_priorities.transfer,
+            queue_priorities.present,
+            queue_priorities.compute,
+        ] {
+            assert!(
+                (0.0..=1.0).contains(&priority),
+                "queue priorities must be in the range [0, 1]"
+            );
+        }
         let vk_version = vk::API_VERSION_1_2;
 
         // Get required instance layers
@@ -887,7 +1736,7 @@ impl VulkanBackend {
         };
 
         // Get required device extensions
-        let device_extensions = {
+        let mut device_extensions = {
             let mut extensions = vec![
                 ash::extensions::khr::Swapchain::name(),
                 ash::extensions::khr::TimelineSemaphore::name(),
@@ -921,19 +1770,20 @@ impl VulkanBackend {
         let instance = unsafe { entry.create_instance(&instance_create_info, None)? };
 
         // Create debugging utilities if requested
+        let validation_messages = Box::new(Mutex::new(VecDeque::default()));
         let debug = if create_info.debug {
             let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-                .message_severity(
-                    vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                        | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                        | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
-                )
+                .message_severity(util::to_vk_message_severity(create_info.debug_severity))
                 .message_type(
                     vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
                         | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
                         | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
                 )
-                .pfn_user_callback(Some(vulkan_debug_callback));
+                .pfn_user_callback(Some(vulkan_debug_callback))
+                .user_data(
+                    validation_messages.as_ref() as *const Mutex<VecDeque<ValidationMessage>>
+                        as *mut std::os::raw::c_void,
+                );
             let debug_utils_loader = ash::extensions::ext::DebugUtils::new(&entry, &instance);
             let debug_messenger =
                 unsafe { debug_utils_loader.create_debug_utils_messenger(&debug_info, None)? };
@@ -949,7 +1799,13 @@ impl VulkanBackend {
 
         // Query for a physical device
         let pd_query = unsafe {
-            match pick_physical_device(&instance, surface, &surface_loader, &device_extensions) {
+            match pick_physical_device(
+                &instance,
+                surface,
+                &surface_loader,
+                &device_extensions,
+                present_on_main,
+            ) {
                 Some(pd) => pd,
                 None => return Err(VulkanBackendCreateError::NoDevice),
             }
@@ -960,217 +1816,1071 @@ impl VulkanBackend {
             surface_loader.destroy_surface(surface, None);
         }
 
-        // Queue requests
-        let mut priorities = Vec::with_capacity(pd_query.queue_family_indices.unique.len());
-        let mut queue_infos = Vec::with_capacity(pd_query.queue_family_indices.unique.len());
-        let mut queue_indices = (0, 0, 0, 0);
-        for q in &pd_query.queue_family_indices.unique {
-            let mut cur_priorities = Vec::with_capacity(4);
+        // Enable optional extensions and features if they are supported
+        let mut extended_dynamic_state_features =
+            vk::PhysicalDeviceExtendedDynamicStateFeaturesEXT::default();
+        let mut descriptor_indexing_features =
+            vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        unsafe {
+            let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+                .push_next(&mut extended_dynamic_state_features)
+                .push_next(&mut descriptor_indexing_features)
+                .build();
+            instance.get_physical_device_features2(pd_query.device, &mut features2);
+        }
+        let variable_descriptor_count =
+            descriptor_indexing_features.descriptor_binding_variable_descriptor_count == vk::TRUE
+                && descriptor_indexing_features.descriptor_binding_partially_bound == vk::TRUE;
+        let extended_dynamic_state = unsafe {
+            check_device_extensions(
+                &instance,
+                pd_query.device,
+                &[ash::extensions::ext::ExtendedDynamicState::name().as_ptr()],
+            )
+            .is_none()
+        } && extended_dynamic_state_features.extended_dynamic_state
+            == vk::TRUE;
+        if extended_dynamic_state {
+            device_extensions.push(ash::extensions::ext::ExtendedDynamicState::name().as_ptr());
+        }
+
+        // Queue requests
+        let mut priorities = Vec::with_capacity(pd_query.queue_family_indices.unique.len());
+        let mut queue_infos = Vec::with_capacity(pd_query.queue_family_indices.unique.len());
+        let mut queue_indices = (0, 0, 0, 0);
+        for q in &pd_query.queue_family_indices.unique {
+            let mut cur_priorities = Vec::with_capacity(4);
+
+            // Never request more queues than the family has. Once every queue of the family has
+            // been requested, the remaining queue types share the last one, which takes the
+            // highest priority of the types sharing it.
+            let available = pd_query.queue_family_indices.queue_counts[*q as usize] as usize;
+            let next_index = |priorities: &mut Vec<f32>, priority: f32| {
+                if priorities.len() < available {
+                    priorities.push(priority);
+                } else {
+                    let last = priorities.last_mut().unwrap();
+                    *last = last.max(priority);
+                }
+                priorities.len() - 1
+            };
+
+            if pd_query.queue_family_indices.main == *q {
+                queue_indices.0 = next_index(&mut cur_priorities, queue_priorities.main);
+            }
+
+            if pd_query.queue_family_indices.transfer == *q {
+                queue_indices.1 = next_index(&mut cur_priorities, queue_priorities.transfer);
+            }
+
+            if pd_query.queue_family_indices.present == *q {
+                if pd_query.queue_family_indices.present_shares_main {
+                    queue_indices.2 = queue_indices.0;
+                    let shared = &mut cur_priorities[queue_indices.0];
+                    *shared = shared.max(queue_priorities.present);
+                } else {
+                    queue_indices.2 = next_index(&mut cur_priorities, queue_priorities.present);
+                }
+            }
+
+            if pd_query.queue_family_indices.compute == *q {
+                queue_indices.3 = next_index(&mut cur_priorities, queue_priorities.compute);
+            }
+
+            queue_infos.push(
+                vk::DeviceQueueCreateInfo::builder()
+                    .queue_family_index(*q)
+                    .queue_priorities(&cur_priorities)
+                    .build(),
+            );
+
+            priorities.push(cur_priorities);
+        }
+
+        // Request features. Keep in sync with `supports_requested_features`.
+        let features = vk::PhysicalDeviceFeatures::builder()
+            .fill_mode_non_solid(true)
+            .draw_indirect_first_instance(true)
+            .multi_draw_indirect(true)
+            .depth_clamp(true)
+            .sample_rate_shading(pd_query.features.sample_rate_shading == vk::TRUE)
+            .sparse_binding(pd_query.features.sparse_binding == vk::TRUE)
+            .sparse_residency_image2_d(pd_query.features.sparse_residency_image2_d == vk::TRUE)
+            .build();
+
+        let mut features12 = vk::PhysicalDeviceVulkan12Features::builder()
+            .timeline_semaphore(true)
+            .buffer_device_address(true)
+            .runtime_descriptor_array(true)
+            .descriptor_binding_variable_descriptor_count(variable_descriptor_count)
+            .descriptor_binding_partially_bound(variable_descriptor_count)
+            .build();
+
+        let mut extended_dynamic_state_features =
+            vk::PhysicalDeviceExtendedDynamicStateFeaturesEXT::builder()
+                .extended_dynamic_state(true)
+                .build();
+
+        let mut create_info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(&queue_infos)
+            .enabled_extension_names(&device_extensions)
+            .push_next(&mut features12)
+            .enabled_features(&features);
+        if extended_dynamic_state {
+            create_info = create_info.push_next(&mut extended_dynamic_state_features);
+        }
+        let create_info = create_info.build();
+
+        // Create the device
+        let device = unsafe { instance.create_device(pd_query.device, &create_info, None)? };
+
+        // Create swapchain loader
+        let swapchain_loader = ash::extensions::khr::Swapchain::new(&instance, &device);
+
+        // Create optional extension loaders
+        let extended_dynamic_state = if extended_dynamic_state {
+            Some(ash::extensions::ext::ExtendedDynamicState::new(
+                &instance, &device,
+            ))
+        } else {
+            None
+        };
+
+        // Create the memory allocator
+        let allocator = ManuallyDrop::new(Mutex::new(
+            Allocator::new(&AllocatorCreateDesc {
+                instance: instance.clone(),
+                device: device.clone(),
+                physical_device: pd_query.device,
+                debug_settings: gpu_allocator::AllocatorDebugSettings {
+                    log_memory_information: allocator_debug.log_memory_information,
+                    log_leaks_on_shutdown: allocator_debug.log_leaks_on_shutdown,
+                    store_stack_traces: allocator_debug.store_stack_traces,
+                    log_allocations: allocator_debug.log_allocations,
+                    log_frees: allocator_debug.log_frees,
+                    log_stack_traces: allocator_debug.log_stack_traces,
+                },
+                // TODO: Look into this
+                buffer_device_address: false,
+            })
+            .expect("unable to create GPU memory allocator"),
+        ));
+
+        // Create queues. Queue types which share a `vk::Queue` also share its lock.
+        let mut queue_locks = HashMap::<vk::Queue, Arc<Mutex<()>>>::default();
+        let mut queue_lock = |queue: vk::Queue| queue_locks.entry(queue).or_default().clone();
+
+        let main = unsafe {
+            let queue =
+                device.get_device_queue(pd_query.queue_family_indices.main, queue_indices.0 as u32);
+            VkQueue::new(
+                &device,
+                debug.as_ref().map(|(utils, _)| utils),
+                queue,
+                queue_lock(queue),
+                QueueType::Main,
+                pd_query.queue_family_indices.main,
+                preallocated_command_buffers,
+            )?
+        };
+
+        let transfer = unsafe {
+            let queue = device.get_device_queue(
+                pd_query.queue_family_indices.transfer,
+                queue_indices.1 as u32,
+            );
+            VkQueue::new(
+                &device,
+                debug.as_ref().map(|(utils, _)| utils),
+                queue,
+                queue_lock(queue),
+                QueueType::Transfer,
+                pd_query.queue_family_indices.transfer,
+                preallocated_command_buffers,
+            )?
+        };
+
+        let present = unsafe {
+            let queue = device.get_device_queue(
+                pd_query.queue_family_indices.present,
+                queue_indices.2 as u32,
+            );
+            VkQueue::new(
+                &device,
+                debug.as_ref().map(|(utils, _)| utils),
+                queue,
+                queue_lock(queue),
+                QueueType::Present,
+                pd_query.queue_family_indices.present,
+                preallocated_command_buffers,
+            )?
+        };
+
+        let compute = unsafe {
+            let queue = device.get_device_queue(
+                pd_query.queue_family_indices.compute,
+                queue_indices.3 as u32,
+            );
+            VkQueue::new(
+                &device,
+                debug.as_ref().map(|(utils, _)| utils),
+                queue,
+                queue_lock(queue),
+                QueueType::Compute,
+                pd_query.queue_family_indices.compute,
+                preallocated_command_buffers,
+            )?
+        };
+
+        let ctx = Self {
+            entry,
+            instance,
+            debug,
+            physical_device: pd_query.device,
+            queue_family_indices: pd_query.queue_family_indices,
+            properties: pd_query.properties,
+            features: pd_query.features,
+            device,
+            surface_loader,
+            swapchain_loader,
+            extended_dynamic_state,
+            variable_descriptor_count,
+            main: ShardedLock::new(main),
+            transfer: ShardedLock::new(transfer),
+            present: ShardedLock::new(present),
+            compute: ShardedLock::new(compute),
+            allocator,
+            render_passes: RenderPassCache::default(),
+            framebuffers: FramebufferCache::default(),
+            garbage: GarbageCollector::new(),
+            resource_state: ShardedLock::new(GlobalResourceUsage::default()),
+            pools: Mutex::new(DescriptorPools::default()),
+            pipelines: Mutex::new(PipelineCache::default()),
+            samplers: Mutex::new(SamplerCache::default()),
+            validation_messages,
+            max_pending_submits,
+            deferred_garbage_collection,
+        };
+
+        Ok(ctx)
+    }
+}
+
+impl Drop for VulkanBackend {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device_wait_idle().unwrap();
+            let main = self.main.get_mut().unwrap();
+            let transfer = self.transfer.get_mut().unwrap();
+            let compute = self.compute.get_mut().unwrap();
+            let present = self.present.get_mut().unwrap();
+
+            let current = TimelineValues {
+                main: main.current_timeline_value(&self.device),
+                transfer: transfer.current_timeline_value(&self.device),
+                compute: compute.current_timeline_value(&self.device),
+                present: present.current_timeline_value(&self.device),
+            };
+
+            let target = TimelineValues {
+                main: main.target_timeline_value(),
+                transfer: transfer.target_timeline_value(),
+                compute: compute.target_timeline_value(),
+                present: present.target_timeline_value(),
+            };
+
+            // All work is complete, so every remaining callback is called
+            for callback in self.garbage.completed_callbacks(current) {
+                callback();
+            }
+
+            let mut allocator = self.allocator.lock().unwrap();
+            let mut pools = self.pools.lock().unwrap();
+            let mut pipelines = self.pipelines.lock().unwrap();
+            let mut samplers = self.samplers.lock().unwrap();
+            self.garbage.cleanup_all(
+                &self.device,
+                &mut allocator,
+                &mut pools,
+                &mut pipelines,
+                current,
+                target,
+            );
+            pools.release(&self.device);
+            pipelines.release_all(&self.device);
+            samplers.release(&self.device);
+            std::mem::drop(allocator);
+            std::mem::drop(ManuallyDrop::take(&mut self.allocator));
+            self.framebuffers.release(&self.device);
+            self.render_passes.release(&self.device);
+            self.main.get_mut().unwrap().release(&self.device);
+            self.transfer.get_mut().unwrap().release(&self.device);
+            self.compute.get_mut().unwrap().release(&self.device);
+            self.present.get_mut().unwrap().release(&self.device);
+            self.device.destroy_device(None);
+            if let Some((loader, messenger)) = &self.debug {
+                loader.destroy_debug_utils_messenger(*messenger, None);
+            }
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+impl VulkanBackend {
+    /// Transitions an image subresource to the general layout on the main queue and waits for
+    /// the transition to complete, so that the host can access it.
+    pub(crate) unsafe fn transition_for_host(
+        &self,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+    ) {
+        let mut main = self.main.write().unwrap();
+        let cb = main.allocate_command_buffer(&self.device, self.debug.as_ref().map(|(u, _)| u));
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build();
+        self.device.begin_command_buffer(cb, &begin_info).unwrap();
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(old_layout)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ | vk::AccessFlags::HOST_WRITE)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(range)
+            .build();
+        self.device.cmd_pipeline_barrier(
+            cb,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+        self.device.end_command_buffer(cb).unwrap();
+        main.submit(&self.device, cb, false, SemaphoreTracker::default())
+            .unwrap();
+        let job = Job {
+            ty: QueueType::Main,
+            target_value: main.target_timeline_value(),
+        };
+        std::mem::drop(main);
+        self.wait_on(&job, None);
+    }
+
+    /// Records commands into a command buffer, tracking the resources they use.
+    unsafe fn record_into<'a>(
+        &self,
+        cb: vk::CommandBuffer,
+        debug_name: Option<&str>,
+        commands: &[Command<'a, Self>],
+        pipelines: &mut PipelineCache,
+        pipeline_tracker: &mut PipelineTracker,
+        semaphore_tracker: &mut SemaphoreTracker,
+    ) {
+        let mut active_render_pass = vk::RenderPass::null();
+        let mut active_depth_attachment = false;
+        let mut active_layout = vk::PipelineLayout::null();
+        let mut pass_final_usages = Vec::default();
+        let mut graphics_sets = DescriptorBinds::default();
+        let mut compute_sets = DescriptorBinds::default();
+
+        // Insert debug name
+        if let Some(name) = debug_name {
+            if let Some((debug, _)) = &self.debug {
+                let name = CString::new(name).unwrap();
+                let label = vk::DebugUtilsLabelEXT::builder().label_name(&name).build();
+                debug.cmd_begin_debug_utils_label(cb, &label);
+            }
+        }
+
+        // Interpret commands
+        let mut tracked = 0;
+        for (i, command) in commands.iter().enumerate() {
+            // Track resource state for the command. Commands are tracked in batches, so this is
+            // skipped if the command was covered by a previous batch.
+            if i >= tracked {
+                tracked = crate::util::tracking::track_resources(TrackState {
+                    device: &self.device,
+                    command_buffer: cb,
+                    index: i,
+                    commands,
+                    pipeline_tracker,
+                    semaphores: semaphore_tracker,
+                });
+            }
+
+            // Descriptor set binds are deferred until a command that might depend on them
+            if !matches!(command, Command::BindDescriptorSets { .. }) {
+                graphics_sets.flush(
+                    &self.device,
+                    cb,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    active_layout,
+                );
+                compute_sets.flush(
+                    &self.device,
+                    cb,
+                    vk::PipelineBindPoint::COMPUTE,
+                    active_layout,
+                );
+            }
+
+            // Perform command operations
+            match command {
+                Command::BeginRenderPass(descriptor) => {
+                    // Get the render pass described. Attachments sampled by the next pass are
+                    // transitioned by this one
+                    let (final_layouts, final_usages) =
+                        crate::util::tracking::color_attachment_final_layouts(commands, i);
+                    active_render_pass = self.render_passes.get_with_final_layouts(
+                        &self.device,
+                        descriptor,
+                        &final_layouts,
+                    );
+                    pass_final_usages = final_usages;
+                    active_depth_attachment = descriptor.depth_stencil_attachment.is_some();
+
+                    // Indicate that surface images have been drawn to
+                    for attachment in &descriptor.color_attachments {
+                        if let ColorAttachmentSource::SurfaceImage(image) = &attachment.source {
+                            let (queue, value) = pipeline_tracker.submission();
+                            image.internal().signal_draw(queue, value);
+                        }
+                    }
+
+                    // Find the framebuffer
+                    let (views, dims) = render_pass::framebuffer_views(descriptor);
+                    let framebuffer = self.framebuffers.get(
+                        &self.device,
+                        active_render_pass,
+                        views,
+                        vk::Extent2D {
+                            width: dims.0,
+                            height: dims.1,
+                        },
+                    );
+
+                    // Find clear values
+                    let mut clear_values = Vec::with_capacity(descriptor.color_attachments.len());
+                    for attachment in &descriptor.color_attachments {
+                        if let LoadOp::Clear(clear_color) = &attachment.load_op {
+                            let color = util::to_vk_clear_color(*clear_color);
+                            clear_values.push(vk::ClearValue { color });
+                        }
+                    }
+
+                    if let Some(attachment) = &descriptor.depth_stencil_attachment {
+                        if let LoadOp::Clear(clear_color) = &attachment.load_op {
+                            let depth_stencil = util::to_vk_clear_depth_stencil(*clear_color);
+                            clear_values.push(vk::ClearValue { depth_stencil })
+                        }
+                    }
+
+                    // Initial viewport configuration
+                    // NOTE: Viewport is flipped to account for Vulkan coordinate system
+                    let viewport = [vk::Viewport {
+                        width: dims.0 as f32,
+                        height: -(dims.1 as f32),
+                        x: 0.0,
+                        y: dims.1 as f32,
+                        min_depth: 0.0,
+                        max_depth: 1.0,
+                    }];
+
+                    let scissor = [vk::Rect2D {
+                        extent: vk::Extent2D {
+                            width: dims.0,
+                            height: dims.1,
+                        },
+                        offset: vk::Offset2D { x: 0, y: 0 },
+                    }];
+
+                    self.device.cmd_set_viewport(cb, 0, &viewport);
+                    self.device.cmd_set_scissor(cb, 0, &scissor);
+
+                    // Begin the render pass
+                    let begin_info = vk::RenderPassBeginInfo::builder()
+                        .render_pass(active_render_pass)
+                        .clear_values(&clear_values)
+                        .framebuffer(framebuffer)
+                        .render_area(vk::Rect2D {
+                            offset: vk::Offset2D { x: 0, y: 0 },
+                            extent: vk::Extent2D {
+                                width: dims.0,
+                                height: dims.1,
+                            },
+                        })
+                        .build();
+
+                    self.device
+                        .cmd_begin_render_pass(cb, &begin_info, vk::SubpassContents::INLINE);
+                }
+                Command::EndRenderPass => {
+                    self.device.cmd_end_render_pass(cb);
+                    for (resource, usage) in pass_final_usages.drain(..) {
+                        pipeline_tracker.transition_in_pass(resource, usage);
+                    }
+                }
+                Command::BeginComputePass => {}
+                Command::EndComputePass => {}
+                Command::BindComputePipeline(pipeline) => {
+                    active_layout = pipeline.internal().layout;
+                    compute_sets.set_layout(active_layout);
+                    self.device.cmd_bind_pipeline(
+                        cb,
+                        vk::PipelineBindPoint::COMPUTE,
+                        pipeline.internal().pipeline,
+                    );
+                }
+                Command::Dispatch(x, y, z) => {
+                    self.device.cmd_dispatch(cb, *x, *y, *z);
+                }
+                Command::DispatchIndirect {
+                    buffer,
+                    array_element,
+                    offset,
+                } => {
+                    self.device.cmd_dispatch_indirect(
+                        cb,
+                        buffer.internal().buffer,
+                        buffer.internal().offset(*array_element) + *offset,
+                    );
+                }
+                Command::BindGraphicsPipeline(pipeline) => {
+                    active_layout = pipeline.internal().layout();
+                    graphics_sets.set_layout(active_layout);
+                    let vk_pipeline = pipeline.internal().get(
+                        &self.device,
+                        pipelines,
+                        self.debug.as_ref().map(|(utils, _)| utils),
+                        self.render_passes.compatible(active_render_pass),
+                        active_depth_attachment,
+                        self.extended_dynamic_state.is_some(),
+                    );
+                    self.device
+                        .cmd_bind_pipeline(cb, vk::PipelineBindPoint::GRAPHICS, vk_pipeline);
+
+                    // Dynamic state must be reset to match the newly bound pipeline
+                    if let Some(eds) = &self.extended_dynamic_state {
+                        pipeline
+                            .internal()
+                            .set_dynamic_state(eds, cb, active_depth_attachment);
+                    }
+                }
+                Command::SetDepthTestEnable(enable) => {
+                    if let Some(eds) = &self.extended_dynamic_state {
+                        eds.cmd_set_depth_test_enable(cb, *enable && active_depth_attachment);
+                    }
+                }
+                Command::SetDepthWriteEnable(enable) => {
+                    if let Some(eds) = &self.extended_dynamic_state {
+                        eds.cmd_set_depth_write_enable(cb, *enable && active_depth_attachment);
+                    }
+                }
+                Command::SetCullMode(cull_mode) => {
+                    if let Some(eds) = &self.extended_dynamic_state {
+                        eds.cmd_set_cull_mode(cb, crate::util::to_vk_cull_mode(*cull_mode));
+                    }
+                }
+                Command::SetFrontFace(front_face) => {
+                    if let Some(eds) = &self.extended_dynamic_state {
+                        eds.cmd_set_front_face(cb, crate::util::to_vk_front_face(*front_face));
+                    }
+                }
+                Command::PushConstants { stage, data } => {
+                    self.device.cmd_push_constants(
+                        cb,
+                        active_layout,
+                        crate::util::to_vk_shader_stage(*stage),
+                        0,
+                        data,
+                    );
+                }
+                Command::BindDescriptorSets { sets, first, stage } => {
+                    let binds = match *stage {
+                        ShaderStage::Compute => &mut compute_sets,
+                        _ => &mut graphics_sets,
+                    };
+                    binds.bind(*first, sets.iter().map(|set| set.internal().set));
+                }
+                Command::BindVertexBuffers { first, binds } => {
+                    let mut buffers = Vec::with_capacity(binds.len());
+                    let mut offsets = Vec::with_capacity(binds.len());
+                    for bind in binds {
+                        let buffer = bind.buffer.internal();
+                        buffers.push(buffer.buffer);
+                        offsets.push(buffer.offset(bind.array_element) + bind.offset);
+                    }
+                    self.device
+                        .cmd_bind_vertex_buffers(cb, *first as u32, &buffers, &offsets);
+                }
+                Command::BindIndexBuffer {
+                    buffer,
+                    array_element,
+                    offset,
+                    ty,
+                } => {
+                    let buffer = buffer.internal();
+                    self.device.cmd_bind_index_buffer(
+                        cb,
+                        buffer.buffer,
+                        buffer.offset(*array_element) + offset,
+                        crate::util::to_vk_index_type(*ty),
+                    );
+                }
+                Command::Draw {
+                    vertex_count,
+                    instance_count,
+                    first_vertex,
+                    first_instance,
+                } => {
+                    self.device.cmd_draw(
+                        cb,
+                        *vertex_count as u32,
+                        *instance_count as u32,
+                        *first_vertex as u32,
+                        *first_instance as u32,
+                    );
+                }
+                Command::DrawIndexed {
+                    index_count,
+                    instance_count,
+                    first_index,
+                    vertex_offset,
+                    first_instance,
+                } => {
+                    self.device.cmd_draw_indexed(
+                        cb,
+                        *index_count as u32,
+                        *instance_count as u32,
+                        *first_index as u32,
+                        *vertex_offset as i32,
+                        *first_instance as u32,
+                    );
+                }
+                Command::DrawIndexedIndirect {
+                    buffer,
+                    array_element,
+                    offset,
+                    draw_count,
+                    stride,
+                } => {
+                    self.device.cmd_draw_indexed_indirect(
+                        cb,
+                        buffer.internal().buffer,
+                        buffer.internal().offset(*array_element) + *offset,
+                        *draw_count as u32,
+                        *stride as u32,
+                    );
+                }
+                Command::CopyBufferToBuffer(copy) => {
+                    let src = copy.src.internal();
+                    let dst = copy.dst.internal();
+                    let region = [vk::BufferCopy::builder()
+                        .dst_offset(dst.offset(copy.dst_array_element) + copy.dst_offset)
+                        .src_offset(src.offset(copy.src_array_element) + copy.src_offset)
+                        .size(copy.len)
+                        .build()];
+                    self.device
+                        .cmd_copy_buffer(cb, src.buffer, dst.buffer, &region);
+                }
+                Command::CopyBufferRegions { src, dst, regions } => {
+                    let src = src.internal();
+                    let dst = dst.internal();
+                    let regions: Vec<_> = regions
+                        .iter()
+                        .map(|region| {
+                            vk::BufferCopy::builder()
+                                .dst_offset(
+                                    dst.offset(region.dst_array_element) + region.dst_offset,
+                                )
+                                .src_offset(
+                                    src.offset(region.src_array_element) + region.src_offset,
+                                )
+                                .size(region.len)
+                                .build()
+                        })
+                        .collect();
+                    self.device
+                        .cmd_copy_buffer(cb, src.buffer, dst.buffer, &regions);
+                }
+                Command::CopyBufferToTexture {
+                    buffer,
+                    texture,
+                    copy,
+                } => {
+                    let src = buffer.internal();
+                    let dst = texture.internal();
+                    let copy = [to_vk_buffer_image_copy(src, dst, copy)];
+                    self.device.cmd_copy_buffer_to_image(
+                        cb,
+                        src.buffer,
+                        dst.image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &copy,
+                    );
+                }
+                Command::CopyBufferToTextureBatch(copies) => {
+                    // Consecutive copies between the same buffer and texture are recorded together
+                    let mut start = 0;
+                    while start < copies.len() {
+                        let (texture, buffer, _) = copies[start];
+                        let src = buffer.internal();
+                        let dst = texture.internal();
+                        let mut regions = Vec::default();
+                        let mut end = start;
+                        while end < copies.len()
+                            && copies[end].0.internal().image == dst.image
+                            && copies[end].1.internal().buffer == src.buffer
+                        {
+                            regions.push(to_vk_buffer_image_copy(src, dst, &copies[end].2));
+                            end += 1;
+                        }
+
+                        self.device.cmd_copy_buffer_to_image(
+                            cb,
+                            src.buffer,
+                            dst.image,
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            &regions,
+                        );
+                        start = end;
+                    }
+                }
+                Command::CopyTextureToBuffer {
+                    buffer,
+                    texture,
+                    copy,
+                } => {
+                    let src = texture.internal();
+                    let dst = buffer.internal();
+                    let copy = [to_vk_buffer_image_copy(dst, src, copy)];
+                    self.device.cmd_copy_image_to_buffer(
+                        cb,
+                        src.image,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        dst.buffer,
+                        &copy,
+                    );
+                }
+                Command::Raw(command) => {
+                    // Raw commands may bind sets themselves
+                    command(&self.device, cb);
+                    graphics_sets.invalidate();
+                    compute_sets.invalidate();
+                }
+                Command::WriteTimestamp { pool, index } => {
+                    self.device.cmd_write_timestamp(
+                        cb,
+                        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                        pool.internal().pool,
+                        *index,
+                    );
+                }
+                Command::BlitToSurfaceImage {
+                    texture,
+                    array_element,
+                    mip_level,
+                    image,
+                } => {
+                    let src = texture.internal();
+                    let dst = image.internal();
+                    let (queue, value) = pipeline_tracker.submission();
+                    dst.signal_draw(queue, value);
+
+                    // The image must be acquired before it is written to, and it can only be
+                    // presented once the blit is complete
+                    let semaphores = dst.semaphores();
+                    semaphore_tracker.register_signal(semaphores.presentable, None);
+                    semaphore_tracker.register_wait(
+                        semaphores.available,
+                        WaitInfo {
+                            value: None,
+                            stage: vk::PipelineStageFlags::TRANSFER,
+                        },
+                    );
+
+                    let dst_resource = SubResource::Texture {
+                        texture: dst.image(),
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        array_elem: 0,
+                        mip_level: 0,
+                    };
+                    let mut scope = UsageScope::default();
+                    scope.use_resource(
+                        SubResource::Texture {
+                            texture: src.image,
+                            aspect_mask: src.aspect_flags,
+                            array_elem: *array_element as u32,
+                            mip_level: *mip_level as u32,
+                        },
+                        SubResourceUsage {
+                            access: vk::AccessFlags::TRANSFER_READ,
+                            stage: vk::PipelineStageFlags::TRANSFER,
+                            layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        },
+                    );
+                    scope.use_resource(
+                        dst_resource,
+                        SubResourceUsage {
+                            access: vk::AccessFlags::TRANSFER_WRITE,
+                            stage: vk::PipelineStageFlags::TRANSFER,
+                            layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        },
+                    );
+                    if let Some(mut barrier) = pipeline_tracker.submit(scope) {
+                        // The layout transition must happen after the wait on the acquire
+                        barrier.src_stage |= vk::PipelineStageFlags::TRANSFER;
+                        barrier.execute(&self.device, cb);
+                    }
 
-            if pd_query.queue_family_indices.main == *q {
-                queue_indices.0 = cur_priorities.len();
-                cur_priorities.push(1.0);
-            }
+                    let (width, height, _) = texture.dims();
+                    let (dst_width, dst_height) = dst.dims();
+                    let blit = [vk::ImageBlit::builder()
+                        .src_subresource(vk::ImageSubresourceLayers {
+                            aspect_mask: src.aspect_flags,
+                            mip_level: *mip_level as u32,
+                            base_array_layer: *array_element as u32,
+                            layer_count: 1,
+                        })
+                        .src_offsets([
+                            vk::Offset3D::default(),
+                            vk::Offset3D {
+                                x: (width >> *mip_level).max(1) as i32,
+                                y: (height >> *mip_level).max(1) as i32,
+                                z: 1,
+                            },
+                        ])
+                        .dst_subresource(vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: 0,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        })
+                        .dst_offsets([
+                            vk::Offset3D::default(),
+                            vk::Offset3D {
+                                x: dst_width as i32,
+                                y: dst_height as i32,
+                                z: 1,
+                            },
+                        ])
+                        .build()];
+                    self.device.cmd_blit_image(
+                        cb,
+                        src.image,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        dst.image(),
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &blit,
+                        vk::Filter::LINEAR,
+                    );
 
-            if pd_query.queue_family_indices.transfer == *q {
-                queue_indices.1 = cur_priorities.len();
-                cur_priorities.push(1.0);
-            }
+                    // Surface images must be in the present layout when they are presented
+                    let mut scope = UsageScope::default();
+                    scope.use_resource(
+                        dst_resource,
+                        SubResourceUsage {
+                            access: vk::AccessFlags::NONE,
+                            stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                            layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                        },
+                    );
+                    if let Some(barrier) = pipeline_tracker.submit(scope) {
+                        barrier.execute(&self.device, cb);
+                    }
+                }
+                Command::GenerateMipmaps {
+                    texture,
+                    array_elements,
+                } => {
+                    let (width, height, depth) = texture.dims();
+                    let internal = texture.internal();
+                    let mip_extent = |mip: u32| vk::Offset3D {
+                        x: (width >> mip).max(1) as i32,
+                        y: (height >> mip).max(1) as i32,
+                        z: (depth >> mip).max(1) as i32,
+                    };
+
+                    let base_array_layer = array_elements.start as u32;
+                    let layer_count = array_elements.len() as u32;
+
+                    for mip in 1..internal.mip_count {
+                        // Each blit reads the previous level and writes the current one, so the
+                        // levels must be transitioned one at a time. Every array element is
+                        // transitioned and blitted at once since they are independent.
+                        let mut scope = UsageScope::default();
+                        for array_elem in array_elements.clone() {
+                            scope.use_resource(
+                                SubResource::Texture {
+                                    texture: internal.image,
+                                    aspect_mask: internal.aspect_flags,
+                                    array_elem: array_elem as u32,
+                                    mip_level: mip - 1,
+                                },
+                                SubResourceUsage {
+                                    access: vk::AccessFlags::TRANSFER_READ,
+                                    stage: vk::PipelineStageFlags::TRANSFER,
+                                    layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                                },
+                            );
+                            scope.use_resource(
+                                SubResource::Texture {
+                                    texture: internal.image,
+                                    aspect_mask: internal.aspect_flags,
+                                    array_elem: array_elem as u32,
+                                    mip_level: mip,
+                                },
+                                SubResourceUsage {
+                                    access: vk::AccessFlags::TRANSFER_WRITE,
+                                    stage: vk::PipelineStageFlags::TRANSFER,
+                                    layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                                },
+                            );
+                        }
+                        if let Some(barrier) = pipeline_tracker.submit(scope) {
+                            barrier.execute(&self.device, cb);
+                        }
 
-            if pd_query.queue_family_indices.present == *q {
-                queue_indices.2 = cur_priorities.len();
-                cur_priorities.push(1.0);
+                        let blit = [vk::ImageBlit::builder()
+                            .src_subresource(vk::ImageSubresourceLayers {
+                                aspect_mask: internal.aspect_flags,
+                                mip_level: mip - 1,
+                                base_array_layer,
+                                layer_count,
+                            })
+                            .src_offsets([vk::Offset3D::default(), mip_extent(mip - 1)])
+                            .dst_subresource(vk::ImageSubresourceLayers {
+                                aspect_mask: internal.aspect_flags,
+                                mip_level: mip,
+                                base_array_layer,
+                                layer_count,
+                            })
+                            .dst_offsets([vk::Offset3D::default(), mip_extent(mip)])
+                            .build()];
+                        self.device.cmd_blit_image(
+                            cb,
+                            internal.image,
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            internal.image,
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            &blit,
+                            vk::Filter::LINEAR,
+                        );
+                    }
+                }
             }
+        }
 
-            if pd_query.queue_family_indices.compute == *q {
-                queue_indices.3 = cur_priorities.len();
-                cur_priorities.push(1.0);
+        // End debug name
+        if debug_name.is_some() {
+            if let Some((debug, _)) = &self.debug {
+                debug.cmd_end_debug_utils_label(cb);
             }
-
-            queue_infos.push(
-                vk::DeviceQueueCreateInfo::builder()
-                    .queue_family_index(*q)
-                    .queue_priorities(&cur_priorities)
-                    .build(),
-            );
-
-            priorities.push(cur_priorities);
         }
+    }
 
-        // Request features
-        let features = vk::PhysicalDeviceFeatures::builder()
-            .fill_mode_non_solid(true)
-            .draw_indirect_first_instance(true)
-            .multi_draw_indirect(true)
-            .depth_clamp(true)
-            .build();
-
-        let mut features12 = vk::PhysicalDeviceVulkan12Features::builder()
-            .timeline_semaphore(true)
-            .buffer_device_address(true)
-            .runtime_descriptor_array(true)
-            .build();
-
-        let create_info = vk::DeviceCreateInfo::builder()
-            .queue_create_infos(&queue_infos)
-            .enabled_extension_names(&device_extensions)
-            .push_next(&mut features12)
-            .enabled_features(&features)
-            .build();
-
-        // Create the device
-        let device = unsafe { instance.create_device(pd_query.device, &create_info, None)? };
-
-        // Create swapchain loader
-        let swapchain_loader = ash::extensions::khr::Swapchain::new(&instance, &device);
-
-        // Create the memory allocator
-        let allocator = ManuallyDrop::new(Mutex::new(
-            Allocator::new(&AllocatorCreateDesc {
-                instance: instance.clone(),
-                device: device.clone(),
-                physical_device: pd_query.device,
-                debug_settings: gpu_allocator::AllocatorDebugSettings {
-                    log_memory_information: false,
-                    log_leaks_on_shutdown: true,
-                    store_stack_traces: false,
-                    log_allocations: false,
-                    log_frees: false,
-                    log_stack_traces: false,
-                },
-                // TODO: Look into this
-                buffer_device_address: false,
-            })
-            .expect("unable to create GPU memory allocator"),
-        ));
-
-        // Create queues
-        let main = unsafe {
-            VkQueue::new(
-                &device,
-                debug.as_ref().map(|(utils, _)| utils),
-                device.get_device_queue(pd_query.queue_family_indices.main, queue_indices.0 as u32),
-                QueueType::Main,
-                pd_query.queue_family_indices.main,
-            )?
-        };
-
-        let transfer = unsafe {
-            VkQueue::new(
-                &device,
-                debug.as_ref().map(|(utils, _)| utils),
-                device.get_device_queue(
-                    pd_query.queue_family_indices.transfer,
-                    queue_indices.1 as u32,
-                ),
-                QueueType::Transfer,
-                pd_query.queue_family_indices.transfer,
-            )?
-        };
-
-        let present = unsafe {
-            VkQueue::new(
-                &device,
-                debug.as_ref().map(|(utils, _)| utils),
-                device.get_device_queue(
-                    pd_query.queue_family_indices.present,
-                    queue_indices.2 as u32,
-                ),
-                QueueType::Present,
-                pd_query.queue_family_indices.present,
-            )?
-        };
-
-        let compute = unsafe {
-            VkQueue::new(
-                &device,
-                debug.as_ref().map(|(utils, _)| utils),
-                device.get_device_queue(
-                    pd_query.queue_family_indices.compute,
-                    queue_indices.3 as u32,
-                ),
-                QueueType::Compute,
-                pd_query.queue_family_indices.compute,
-            )?
-        };
+    #[inline(always)]
+    fn queue(&self, ty: QueueType) -> &ShardedLock<VkQueue> {
+        match ty {
+            QueueType::Main => &self.main,
+            QueueType::Transfer => &self.transfer,
+            QueueType::Compute => &self.compute,
+            QueueType::Present => &self.present,
+        }
+    }
 
-        let ctx = Self {
-            entry,
-            instance,
-            debug,
-            physical_device: pd_query.device,
-            queue_family_indices: pd_query.queue_family_indices,
-            properties: pd_query.properties,
-            features: pd_query.features,
-            device,
-            surface_loader,
-            swapchain_loader,
-            main: ShardedLock::new(main),
-            transfer: ShardedLock::new(transfer),
-            present: ShardedLock::new(present),
-            compute: ShardedLock::new(compute),
-            allocator,
-            render_passes: RenderPassCache::default(),
-            framebuffers: FramebufferCache::default(),
-            garbage: GarbageCollector::new(),
-            resource_state: ShardedLock::new(GlobalResourceUsage::default()),
-            pools: Mutex::new(DescriptorPools::default()),
-            pipelines: Mutex::new(PipelineCache::default()),
-            samplers: Mutex::new(SamplerCache::default()),
+    /// Reads the current and target timeline values of every queue. Each queue is only locked
+    /// while its values are read, so this never blocks on a submission to another queue for
+    /// longer than the submission itself.
+    ///
+    /// Submissions with commands hold the resource state lock until their resources are tracked,
+    /// and the lock of their queue until they are submitted. So when called with the resource
+    /// state locked, the target values of every queue cover all work that uses tracked resources.
+    unsafe fn timeline_values(&self) -> (TimelineValues, TimelineValues) {
+        let mut current = [0; 4];
+        let mut target = [0; 4];
+        for (i, ty) in [
+            QueueType::Main,
+            QueueType::Transfer,
+            QueueType::Compute,
+            QueueType::Present,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let queue = self.queue(ty).read().unwrap();
+            current[i] = queue.current_timeline_value(&self.device);
+            target[i] = queue.target_timeline_value();
+        }
+        let values = |values: [u64; 4]| TimelineValues {
+            main: values[0],
+            transfer: values[1],
+            compute: values[2],
+            present: values[3],
         };
-
-        Ok(ctx)
+        (values(current), values(target))
     }
-}
-
-impl Drop for VulkanBackend {
-    fn drop(&mut self) {
-        unsafe {
-            self.device.device_wait_idle().unwrap();
-            let main = self.main.get_mut().unwrap();
-            let transfer = self.transfer.get_mut().unwrap();
-            let compute = self.compute.get_mut().unwrap();
 
-            let current = TimelineValues {
-                main: main.current_timeline_value(&self.device),
-                transfer: transfer.current_timeline_value(&self.device),
-                compute: compute.current_timeline_value(&self.device),
-            };
+    /// Locks the allocator and descriptor pools so garbage can be collected during a submission.
+    /// Returns `None` if garbage collection is
+    /// [`deferred`](VulkanBackendCreateInfo::deferred_garbage_collection).
+    #[inline(always)]
+    fn lock_submit_garbage(&self) -> Option<(MutexGuard<Allocator>, MutexGuard<DescriptorPools>)> {
+        if self.deferred_garbage_collection {
+            None
+        } else {
+            Some((self.allocator.lock().unwrap(), self.pools.lock().unwrap()))
+        }
+    }
 
-            let target = TimelineValues {
-                main: main.target_timeline_value(),
-                transfer: transfer.target_timeline_value(),
-                compute: compute.target_timeline_value(),
-            };
+    /// Locks the queue being submitted to for the rest of a submission.
+    ///
+    /// Submissions only hold the lock of the queue they submit to, so presenting, polling, and
+    /// waiting on other queues isn't blocked by a submission. Other queues are only locked briefly
+    /// for reading. Resource tracking is serialized by the resource state lock, since it needs a
+    /// consistent view of every queue (see `timeline_values`), but the lock is released before
+    /// the commands are submitted.
+    ///
+    /// Paths that read another queue while a queue is locked for writing also hold the resource
+    /// state lock, so they can't deadlock with each other. A submission never takes the resource
+    /// state lock again once it is released. The lock order of a submission is: resource state,
+    /// allocator, descriptor pools, pipelines, then the queue.
+    #[inline(always)]
+    fn lock_submit_queue(&self, ty: QueueType) -> ShardedLockWriteGuard<VkQueue> {
+        self.queue(ty).write().unwrap()
+    }
 
-            let mut allocator = self.allocator.lock().unwrap();
-            let mut pools = self.pools.lock().unwrap();
-            let mut pipelines = self.pipelines.lock().unwrap();
-            let mut samplers = self.samplers.lock().unwrap();
-            self.garbage.cleanup_all(
-                &self.device,
-                &mut allocator,
-                &mut pools,
-                &mut pipelines,
-                current,
-                target,
-            );
-            pools.release(&self.device);
-            pipelines.release_all(&self.device);
-            samplers.release(&self.device);
-            std::mem::drop(allocator);
-            std::mem::drop(ManuallyDrop::take(&mut self.allocator));
-            self.framebuffers.release(&self.device);
-            self.render_passes.release(&self.device);
-            self.main.get_mut().unwrap().release(&self.device);
-            self.transfer.get_mut().unwrap().release(&self.device);
-            self.compute.get_mut().unwrap().release(&self.device);
-            self.present.get_mut().unwrap().release(&self.device);
-            self.device.destroy_device(None);
-            if let Some((loader, messenger)) = &self.debug {
-                loader.destroy_debug_utils_messenger(*messenger, None);
-            }
-            self.instance.destroy_instance(None);
+    /// Calls every callback registered with `on_job_complete` whose job has completed.
+    unsafe fn call_completed_callbacks(&self) {
+        let current = TimelineValues {
+            main: self
+                .main
+                .read()
+                .unwrap()
+                .current_timeline_value(&self.device),
+            transfer: self
+                .transfer
+                .read()
+                .unwrap()
+                .current_timeline_value(&self.device),
+            compute: self
+                .compute
+                .read()
+                .unwrap()
+                .current_timeline_value(&self.device),
+            present: self
+                .present
+                .read()
+                .unwrap()
+                .current_timeline_value(&self.device),
+        };
+        for callback in self.garbage.completed_callbacks(current) {
+            callback();
         }
     }
 }
@@ -1182,9 +2892,15 @@ impl QueueFamilyIndices {
         device: vk::PhysicalDevice,
         surface: vk::SurfaceKHR,
         surface_loader: &ash::extensions::khr::Surface,
+        present_on_main: bool,
     ) -> Option<QueueFamilyIndices> {
         let mut properties =
             unsafe { instance.get_physical_device_queue_family_properties(device) };
+        let queue_counts: Vec<_> = properties.iter().map(|family| family.queue_count).collect();
+        let timestamp_valid_bits: Vec<_> = properties
+            .iter()
+            .map(|family| family.timestamp_valid_bits)
+            .collect();
         let mut main = usize::MAX;
         let mut present = usize::MAX;
         let mut transfer = usize::MAX;
@@ -1207,32 +2923,44 @@ impl QueueFamilyIndices {
 
         properties[main].queue_count -= 1;
 
-        // Find presentation queue. Would be nice to be different from main.
-        for (family_idx, _) in properties.iter().enumerate() {
-            let surface_support = unsafe {
-                match surface_loader.get_physical_device_surface_support(
-                    device,
-                    family_idx as u32,
-                    surface,
-                ) {
-                    Ok(support) => support,
-                    Err(_) => return None,
+        let surface_support = |family_idx: usize| unsafe {
+            surface_loader
+                .get_physical_device_surface_support(device, family_idx as u32, surface)
+                .ok()
+        };
+
+        // Present on the main queue itself if requested. Otherwise, find a presentation queue.
+        // Would be nice to be different from main. If every queue of the families supporting
+        // presentation is taken, a queue is shared.
+        let present_shares_main = present_on_main && surface_support(main)?;
+        if present_shares_main {
+            present = main;
+        } else {
+            let mut present_fallback = usize::MAX;
+            for (family_idx, _) in properties.iter().enumerate() {
+                let surface_support = surface_support(family_idx)?;
+
+                if surface_support && present_fallback == usize::MAX {
+                    present_fallback = family_idx;
                 }
-            };
 
-            if surface_support && properties[family_idx].queue_count > 0 {
-                present = family_idx;
-                if family_idx != main {
-                    break;
+                if surface_support && properties[family_idx].queue_count > 0 {
+                    present = family_idx;
+                    if family_idx != main {
+                        break;
+                    }
                 }
             }
-        }
 
-        if present == usize::MAX {
-            return None;
-        }
+            if present == usize::MAX {
+                if present_fallback == usize::MAX {
+                    return None;
+                }
+                present = present_fallback;
+            }
 
-        properties[present].queue_count -= 1;
+            properties[present].queue_count = properties[present].queue_count.saturating_sub(1);
+        }
 
         // Look for a dedicated transfer queue. Supported on some devices. Fallback is main.
         for (family_idx, family) in properties.iter().enumerate() {
@@ -1246,11 +2974,12 @@ impl QueueFamilyIndices {
             }
         }
 
+        // Share the main queue if no other queue is available
         if transfer == usize::MAX {
-            return None;
+            transfer = main;
         }
 
-        properties[transfer].queue_count -= 1;
+        properties[transfer].queue_count = properties[transfer].queue_count.saturating_sub(1);
 
         // Look for a dedicated async compute queue. Supported on some devices. Fallback is main.
         for (family_idx, family) in properties.iter().enumerate() {
@@ -1264,8 +2993,9 @@ impl QueueFamilyIndices {
             }
         }
 
+        // Share the main queue if no other queue is available
         if compute == usize::MAX {
-            return None;
+            compute = main;
         }
 
         let unique = {
@@ -1286,9 +3016,13 @@ impl QueueFamilyIndices {
         Some(QueueFamilyIndices {
             main: main as u32,
             present: present as u32,
+            present_shares_main,
+            present_flags: properties[present].queue_flags,
             transfer: transfer as u32,
             compute: compute as u32,
             unique,
+            queue_counts,
+            timestamp_valid_bits,
         })
     }
 }
@@ -1298,13 +3032,14 @@ unsafe fn pick_physical_device(
     surface: vk::SurfaceKHR,
     loader: &ash::extensions::khr::Surface,
     extensions: &[*const i8],
+    present_on_main: bool,
 ) -> Option<PhysicalDeviceQuery> {
     let devices = match instance.enumerate_physical_devices() {
         Ok(devices) => devices,
         Err(_) => return None,
     };
 
-    let mut device_type = vk::PhysicalDeviceType::OTHER;
+    let mut rank = None;
     let mut query = None;
     for device in devices {
         let properties = instance.get_physical_device_properties(device);
@@ -1332,14 +3067,19 @@ unsafe fn pick_physical_device(
         }
 
         // Must support all queue family indices
-        let qfi = QueueFamilyIndices::find(instance, device, surface, loader);
+        let qfi = QueueFamilyIndices::find(instance, device, surface, loader, present_on_main);
         if qfi.is_none() {
             continue;
         }
 
-        // Pick this device if it's better than the old one
-        if device_type_rank(properties.device_type) >= device_type_rank(device_type) {
-            device_type = properties.device_type;
+        // Pick this device if it's better than the old one. Devices that support every requested
+        // feature are preferred over devices of a better type that don't.
+        let new_rank = (
+            supports_requested_features(instance, device, &features),
+            device_type_rank(properties.device_type),
+        );
+        if rank.is_none_or(|rank| new_rank >= rank) {
+            rank = Some(new_rank);
             query = Some(PhysicalDeviceQuery {
                 device,
                 features,
@@ -1386,6 +3126,28 @@ unsafe fn check_device_extensions(
     None
 }
 
+/// Checks that a physical device supports every feature requested when the logical device is
+/// created.
+unsafe fn supports_requested_features(
+    instance: &ash::Instance,
+    device: vk::PhysicalDevice,
+    features: &vk::PhysicalDeviceFeatures,
+) -> bool {
+    let mut features12 = vk::PhysicalDeviceVulkan12Features::default();
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut features12)
+        .build();
+    instance.get_physical_device_features2(device, &mut features2);
+
+    features.fill_mode_non_solid == vk::TRUE
+        && features.draw_indirect_first_instance == vk::TRUE
+        && features.multi_draw_indirect == vk::TRUE
+        && features.depth_clamp == vk::TRUE
+        && features12.timeline_semaphore == vk::TRUE
+        && features12.buffer_device_address == vk::TRUE
+        && features12.runtime_descriptor_array == vk::TRUE
+}
+
 fn device_type_rank(ty: vk::PhysicalDeviceType) -> u32 {
     match ty {
         vk::PhysicalDeviceType::DISCRETE_GPU => 4,
@@ -1396,11 +3158,14 @@ fn device_type_rank(ty: vk::PhysicalDeviceType) -> u32 {
     }
 }
 
+/// The maximum number of validation messages kept before the oldest are discarded.
+const MAX_VALIDATION_MESSAGES: usize = 1024;
+
 unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut std::os::raw::c_void,
+    user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
     let callback_data = *p_callback_data;
     let message_id_number: i32 = callback_data.message_id_number as i32;
@@ -1453,9 +3218,68 @@ unsafe extern "system" fn vulkan_debug_callback(
         _ => {}
     }
 
+    // Record the message
+    let severity = match message_severity {
+        DebugUtilsMessageSeverityFlagsEXT::VERBOSE => Some(ValidationSeverity::Verbose),
+        DebugUtilsMessageSeverityFlagsEXT::INFO => Some(ValidationSeverity::Info),
+        DebugUtilsMessageSeverityFlagsEXT::WARNING => Some(ValidationSeverity::Warning),
+        DebugUtilsMessageSeverityFlagsEXT::ERROR => Some(ValidationSeverity::Error),
+        _ => None,
+    };
+    if let (Some(severity), false) = (severity, user_data.is_null()) {
+        let messages = &*(user_data as *const Mutex<VecDeque<ValidationMessage>>);
+        let mut messages = messages.lock().unwrap();
+        if messages.len() == MAX_VALIDATION_MESSAGES {
+            messages.pop_front();
+        }
+        messages.push_back(ValidationMessage {
+            severity,
+            id: message_id_number,
+            id_name: message_id_name.into_owned(),
+            message: message.into_owned(),
+        });
+    }
+
     vk::FALSE
 }
 
+/// Converts a copy between a buffer and a texture into a Vulkan copy region.
+#[inline(always)]
+fn to_vk_buffer_image_copy(
+    buffer: &Buffer,
+    texture: &Texture,
+    copy: &BufferTextureCopy,
+) -> vk::BufferImageCopy {
+    vk::BufferImageCopy::builder()
+        .buffer_offset(buffer.offset(copy.buffer_array_element) + copy.buffer_offset)
+        .buffer_row_length(copy.buffer_row_length)
+        .buffer_image_height(copy.buffer_image_height)
+        .image_subresource(vk::ImageSubresourceLayers {
+            aspect_mask: match copy.texture_aspect {
+                Some(aspect) => util::to_vk_aspect_flags(aspect),
+                // Copies may only use one aspect, so prefer depth for depth stencil textures
+                None if texture.aspect_flags.contains(vk::ImageAspectFlags::DEPTH) => {
+                    vk::ImageAspectFlags::DEPTH
+                }
+                None => texture.aspect_flags,
+            },
+            mip_level: copy.texture_mip_level as u32,
+            base_array_layer: copy.texture_array_element as u32,
+            layer_count: 1,
+        })
+        .image_offset(vk::Offset3D {
+            x: copy.texture_offset.0 as i32,
+            y: copy.texture_offset.1 as i32,
+            z: copy.texture_offset.2 as i32,
+        })
+        .image_extent(vk::Extent3D {
+            width: copy.texture_extent.0,
+            height: copy.texture_extent.1,
+            depth: copy.texture_extent.2,
+        })
+        .build()
+}
+
 impl From<vk::Result> for VulkanBackendCreateError {
     fn from(res: vk::Result) -> Self {
         VulkanBackendCreateError::Vulkan(res)
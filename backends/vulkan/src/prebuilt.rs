@@ -0,0 +1,48 @@
+use api::types::QueueType;
+use ash::vk;
+use crossbeam_channel::Sender;
+
+use crate::util::{
+    garbage_collector::{dispose, Garbage},
+    usage::UsageScope,
+};
+
+/// Commands recorded into a reusable command buffer.
+pub struct PrebuiltCommands {
+    pub(crate) queue: QueueType,
+    /// Every prebuilt command buffer gets its own pool so that it can be destroyed independently
+    /// of the command buffers recycled by the queue.
+    pub(crate) command_pool: vk::CommandPool,
+    pub(crate) command_buffer: vk::CommandBuffer,
+    /// The state every subresource must be in before the commands execute.
+    pub(crate) entry: UsageScope,
+    /// The state every subresource is left in after the commands execute.
+    pub(crate) exit: UsageScope,
+    on_drop: Sender<Garbage>,
+}
+
+impl PrebuiltCommands {
+    pub(crate) fn new(
+        queue: QueueType,
+        command_pool: vk::CommandPool,
+        command_buffer: vk::CommandBuffer,
+        entry: UsageScope,
+        exit: UsageScope,
+        on_drop: Sender<Garbage>,
+    ) -> Self {
+        Self {
+            queue,
+            command_pool,
+            command_buffer,
+            entry,
+            exit,
+            on_drop,
+        }
+    }
+}
+
+impl Drop for PrebuiltCommands {
+    fn drop(&mut self) {
+        dispose(&self.on_drop, Garbage::CommandPool(self.command_pool));
+    }
+}
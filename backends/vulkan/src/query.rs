@@ -0,0 +1,129 @@
+use std::{
+    ffi::CString,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use api::query::{QueryPoolCreateError, QueryPoolCreateInfo};
+use ash::vk::{self, Handle};
+use crossbeam_channel::Sender;
+
+use crate::util::garbage_collector::{dispose, Garbage};
+
+pub struct QueryPool {
+    pub(crate) pool: vk::QueryPool,
+    pub(crate) count: u32,
+    /// Queries must be reset before they are written to. The reset is recorded into the next
+    /// command buffer that writes to the pool.
+    needs_reset: AtomicBool,
+    on_drop: Sender<Garbage>,
+}
+
+impl QueryPool {
+    pub(crate) unsafe fn new(
+        device: &ash::Device,
+        debug: Option<&ash::extensions::ext::DebugUtils>,
+        on_drop: Sender<Garbage>,
+        limits: &vk::PhysicalDeviceLimits,
+        create_info: QueryPoolCreateInfo,
+    ) -> Result<Self, QueryPoolCreateError> {
+        if limits.timestamp_compute_and_graphics == vk::FALSE {
+            return Err(QueryPoolCreateError::Unsupported);
+        }
+
+        let pool_create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(create_info.count)
+            .build();
+        let pool = match device.create_query_pool(&pool_create_info, None) {
+            Ok(pool) => pool,
+            Err(err) => return Err(QueryPoolCreateError::Other(err.to_string())),
+        };
+
+        // Setup debug name is requested. Debug names are best effort, so names that can't be
+        // represented and failures to set them are ignored.
+        let name = create_info
+            .debug_name
+            .and_then(|name| CString::new(name).ok());
+        if let (Some(name), Some(debug)) = (name, debug) {
+            let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+                .object_type(vk::ObjectType::QUERY_POOL)
+                .object_handle(pool.as_raw())
+                .object_name(&name)
+                .build();
+
+            // `vkSetDebugUtilsObjectNameEXT`
+            let _ = (debug.fp().set_debug_utils_object_name_ext)(device.handle(), &name_info);
+        }
+
+        Ok(QueryPool {
+            pool,
+            count: create_info.count,
+            needs_reset: AtomicBool::new(true),
+            on_drop,
+        })
+    }
+
+    #[inline(always)]
+    pub(crate) fn reset(&self) {
+        self.needs_reset.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the pool must be reset before it is written to, and clears the flag.
+    #[inline(always)]
+    pub(crate) fn take_reset(&self) -> bool {
+        self.needs_reset.swap(false, Ordering::Relaxed)
+    }
+
+    pub(crate) unsafe fn results(&self, device: &ash::Device, results: &mut [u64]) -> bool {
+        // A pool that hasn't been written to since it was reset has no available results
+        if self.needs_reset.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        device
+            .get_query_pool_results(
+                self.pool,
+                0,
+                results.len() as u32,
+                results,
+                vk::QueryResultFlags::TYPE_64,
+            )
+            .is_ok()
+    }
+
+    /// Reads the result of each query along with its availability. Unavailable queries are left
+    /// as `None`.
+    pub(crate) unsafe fn available_results(
+        &self,
+        device: &ash::Device,
+        results: &mut [Option<u64>],
+    ) {
+        if self.needs_reset.load(Ordering::Relaxed) {
+            return;
+        }
+
+        // Each result is followed by its availability
+        let mut data = vec![[0u64; 2]; results.len()];
+        match device.get_query_pool_results(
+            self.pool,
+            0,
+            data.len() as u32,
+            &mut data,
+            vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+        ) {
+            Ok(()) | Err(vk::Result::NOT_READY) => {}
+            Err(_) => return,
+        }
+        for (result, [value, available]) in results.iter_mut().zip(data) {
+            if available != 0 {
+                *result = Some(value);
+            }
+        }
+    }
+}
+
+impl Drop for QueryPool {
+    fn drop(&mut self) {
+        dispose(&self.on_drop, Garbage::QueryPool(self.pool));
+    }
+}
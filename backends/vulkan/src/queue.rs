@@ -1,4 +1,8 @@
-use std::{collections::VecDeque, ffi::CString};
+use std::{
+    collections::VecDeque,
+    ffi::CString,
+    sync::{Arc, Mutex, MutexGuard},
+};
 
 use api::types::QueueType;
 use ash::vk::{self, Handle};
@@ -8,8 +12,13 @@ use crate::util::semaphores::{SemaphoreTracker, WaitInfo};
 pub(crate) struct VkQueue {
     pub queue: vk::Queue,
     ty: QueueType,
-    /// All commands submitted to this queue must be allocated from this pool.
+    /// On hardware with few queues, multiple queue types may share the same `vk::Queue`. Every
+    /// `VkQueue` using the same `vk::Queue` shares this lock, which must be held when accessing
+    /// `queue` directly.
+    queue_lock: Arc<Mutex<()>>,
+    /// Command buffers recycled by this queue are allocated from this pool.
     command_pool: vk::CommandPool,
+    queue_family: u32,
     /// Queue of free command buffers.
     free: VecDeque<ActiveCommandBuffer>,
     /// Total number of command buffers.
@@ -24,6 +33,8 @@ struct ActiveCommandBuffer {
     pub command_buffer: vk::CommandBuffer,
     /// What value the timeline semaphore must have for this command buffers work to be complete.
     pub target: u64,
+    /// Set once the command buffer has been reset and its memory returned to the pool.
+    pub reclaimed: bool,
 }
 
 impl VkQueue {
@@ -31,8 +42,10 @@ impl VkQueue {
         device: &ash::Device,
         debug: Option<&ash::extensions::ext::DebugUtils>,
         queue: vk::Queue,
+        queue_lock: Arc<Mutex<()>>,
         ty: QueueType,
         queue_family: u32,
+        preallocated: usize,
     ) -> Result<Self, vk::Result> {
         // Create timeline semaphore
         let mut type_create_info = vk::SemaphoreTypeCreateInfo::builder()
@@ -106,15 +119,50 @@ impl VkQueue {
                 .unwrap();
         }
 
-        Ok(Self {
+        let mut out = Self {
             queue,
+            queue_lock,
             semaphore,
             ty,
             command_pool,
-            free: VecDeque::default(),
+            queue_family,
+            free: VecDeque::with_capacity(preallocated),
             command_buffer_count: 0,
             target_value: 0,
-        })
+        };
+
+        // Fill the free list up front so the first submits don't have to allocate. A target of
+        // `0` is always reached, so these are immediately available
+        for _ in 0..preallocated {
+            let command_buffer = out.new_command_buffer(device, debug);
+            out.free.push_back(ActiveCommandBuffer {
+                command_buffer,
+                target: 0,
+                reclaimed: true,
+            });
+        }
+
+        Ok(out)
+    }
+
+    /// Locks the underlying `vk::Queue`. Must be held while using `queue` directly, since it may
+    /// be shared with other queue types.
+    #[inline(always)]
+    pub fn lock(&self) -> MutexGuard<()> {
+        self.queue_lock.lock().unwrap()
+    }
+
+    /// The lock shared by every `VkQueue` using the same `vk::Queue`.
+    #[cfg(feature = "raw")]
+    #[inline(always)]
+    pub fn queue_lock(&self) -> Arc<Mutex<()>> {
+        self.queue_lock.clone()
+    }
+
+    #[cfg(feature = "raw")]
+    #[inline(always)]
+    pub fn queue_family(&self) -> u32 {
+        self.queue_family
     }
 
     #[inline(always)]
@@ -154,76 +202,113 @@ impl VkQueue {
         match command_buffer {
             Some(cb) => cb.command_buffer,
             // If there was no free command buffer, we will allocate one
-            None => {
-                let alloc_info = vk::CommandBufferAllocateInfo::builder()
-                    .command_buffer_count(1)
-                    .command_pool(self.command_pool)
-                    .level(vk::CommandBufferLevel::PRIMARY)
-                    .build();
-                let cb = device.allocate_command_buffers(&alloc_info).unwrap()[0];
-
-                // Name the command buffer
-                if let Some(debug) = debug {
-                    let name = match self.ty {
-                        QueueType::Main => CString::new(format!(
-                            "main_command_buffer_{}",
-                            self.command_buffer_count
-                        )),
-                        QueueType::Transfer => CString::new(format!(
-                            "transfer_command_buffer_{}",
-                            self.command_buffer_count
-                        )),
-                        QueueType::Compute => CString::new(format!(
-                            "compute_command_buffer_{}",
-                            self.command_buffer_count
-                        )),
-                        QueueType::Present => CString::new(format!(
-                            "present_command_buffer_{}",
-                            self.command_buffer_count
-                        )),
-                    }
-                    .unwrap();
+            None => self.new_command_buffer(device, debug),
+        }
+    }
 
-                    let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
-                        .object_type(vk::ObjectType::COMMAND_BUFFER)
-                        .object_handle(cb.as_raw())
-                        .object_name(&name)
-                        .build();
-                    debug
-                        .debug_utils_set_object_name(device.handle(), &name_info)
-                        .unwrap();
-                }
+    /// Allocates and names a new command buffer from the pool of this queue.
+    unsafe fn new_command_buffer(
+        &mut self,
+        device: &ash::Device,
+        debug: Option<&ash::extensions::ext::DebugUtils>,
+    ) -> vk::CommandBuffer {
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(1)
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .build();
+        let cb = device.allocate_command_buffers(&alloc_info).unwrap()[0];
 
-                self.command_buffer_count += 1;
-                cb
+        // Name the command buffer
+        if let Some(debug) = debug {
+            let name = match self.ty {
+                QueueType::Main => {
+                    CString::new(format!("main_command_buffer_{}", self.command_buffer_count))
+                }
+                QueueType::Transfer => CString::new(format!(
+                    "transfer_command_buffer_{}",
+                    self.command_buffer_count
+                )),
+                QueueType::Compute => CString::new(format!(
+                    "compute_command_buffer_{}",
+                    self.command_buffer_count
+                )),
+                QueueType::Present => CString::new(format!(
+                    "present_command_buffer_{}",
+                    self.command_buffer_count
+                )),
             }
+            .unwrap();
+
+            let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+                .object_type(vk::ObjectType::COMMAND_BUFFER)
+                .object_handle(cb.as_raw())
+                .object_name(&name)
+                .build();
+            debug
+                .debug_utils_set_object_name(device.handle(), &name_info)
+                .unwrap();
         }
+
+        self.command_buffer_count += 1;
+        cb
     }
 
+    /// Submits a command buffer allocated from this queue.
+    ///
+    /// Unless `independent` is set, the command buffer waits on all previously submitted work.
+    /// Independent command buffers may overlap with previous work, but they still increment the
+    /// timeline semaphore in submission order, since a signal operation waits on every command
+    /// submitted before it.
     pub unsafe fn submit(
         &mut self,
         device: &ash::Device,
         command_buffer: vk::CommandBuffer,
+        independent: bool,
+        semaphore_tracker: SemaphoreTracker,
+    ) -> ash::prelude::VkResult<()> {
+        self.submit_command_buffers(device, command_buffer, &[], independent, semaphore_tracker)
+    }
+
+    /// Submits a command buffer allocated from this queue followed by a prebuilt command buffer.
+    /// Only the former is recycled.
+    pub unsafe fn submit_prebuilt(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        prebuilt: vk::CommandBuffer,
+        semaphore_tracker: SemaphoreTracker,
+    ) -> ash::prelude::VkResult<()> {
+        self.submit_command_buffers(
+            device,
+            command_buffer,
+            &[prebuilt],
+            false,
+            semaphore_tracker,
+        )
+    }
+
+    unsafe fn submit_command_buffers(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        prebuilt: &[vk::CommandBuffer],
+        independent: bool,
         mut semaphore_tracker: SemaphoreTracker,
     ) -> ash::prelude::VkResult<()> {
-        // Always signal and wait on ourselves
-        semaphore_tracker.register_wait(
-            self.semaphore,
-            WaitInfo {
-                value: Some(self.target_value),
-                stage: vk::PipelineStageFlags::TOP_OF_PIPE,
-            },
-        );
+        // Always signal ourselves. Wait on ourselves unless the work is independent
+        if !independent {
+            semaphore_tracker.register_wait(
+                self.semaphore,
+                WaitInfo {
+                    value: Some(self.target_value),
+                    stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                },
+            );
+        }
         semaphore_tracker.register_signal(self.semaphore, Some(self.target_value + 1));
-        self.target_value += 1;
         let semaphores = semaphore_tracker.finish();
 
-        // Put the command buffer into our free stack
-        self.free.push_back(ActiveCommandBuffer {
-            command_buffer,
-            target: self.target_value,
-        });
-
         // Semaphores
         let mut signals = Vec::with_capacity(semaphores.signals.len());
         let mut signal_values = Vec::with_capacity(semaphores.signals.len());
@@ -244,19 +329,156 @@ impl VkQueue {
         }
 
         // Submit to queue
-        let command_buffer = [command_buffer];
+        let mut command_buffers = Vec::with_capacity(1 + prebuilt.len());
+        command_buffers.push(command_buffer);
+        command_buffers.extend_from_slice(prebuilt);
         let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
             .signal_semaphore_values(&signal_values)
             .wait_semaphore_values(&wait_values)
             .build();
         let submit_info = [vk::SubmitInfo::builder()
-            .command_buffers(&command_buffer)
+            .command_buffers(&command_buffers)
             .signal_semaphores(&signals)
             .wait_semaphores(&waits)
             .wait_dst_stage_mask(&wait_stages)
             .push_next(&mut timeline_info)
             .build()];
-        device.queue_submit(self.queue, &submit_info, vk::Fence::null())
+        let result = {
+            let _lock = self.lock();
+            device.queue_submit(self.queue, &submit_info, vk::Fence::null())
+        };
+
+        // Put the command buffer into our free stack. It can be reused once the submission
+        // completes, or right away if nothing was submitted
+        if result.is_ok() {
+            self.target_value += 1;
+        }
+        self.free.push_back(ActiveCommandBuffer {
+            command_buffer,
+            target: self.target_value,
+            reclaimed: false,
+        });
+
+        result
+    }
+
+    /// Submits sparse memory binding operations. Like every other submission, the operations
+    /// wait on previously submitted work and increment the timeline semaphore.
+    pub unsafe fn bind_sparse(
+        &mut self,
+        device: &ash::Device,
+        image_binds: &[vk::SparseImageMemoryBindInfo],
+        opaque_binds: &[vk::SparseImageOpaqueMemoryBindInfo],
+        mut semaphore_tracker: SemaphoreTracker,
+    ) -> ash::prelude::VkResult<()> {
+        semaphore_tracker.register_wait(
+            self.semaphore,
+            WaitInfo {
+                value: Some(self.target_value),
+                stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+            },
+        );
+        semaphore_tracker.register_signal(self.semaphore, Some(self.target_value + 1));
+        let semaphores = semaphore_tracker.finish();
+
+        let waits: Vec<_> = semaphores.waits.iter().map(|(s, _)| *s).collect();
+        let wait_values: Vec<_> = semaphores
+            .waits
+            .iter()
+            .map(|(_, info)| info.value.unwrap_or_default())
+            .collect();
+        let signals: Vec<_> = semaphores.signals.iter().map(|(s, _)| *s).collect();
+        let signal_values: Vec<_> = semaphores
+            .signals
+            .iter()
+            .map(|(_, value)| value.unwrap_or_default())
+            .collect();
+
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .signal_semaphore_values(&signal_values)
+            .wait_semaphore_values(&wait_values)
+            .build();
+        let bind_info = [vk::BindSparseInfo::builder()
+            .wait_semaphores(&waits)
+            .signal_semaphores(&signals)
+            .image_binds(image_binds)
+            .image_opaque_binds(opaque_binds)
+            .push_next(&mut timeline_info)
+            .build()];
+        {
+            let _lock = self.lock();
+            device.queue_bind_sparse(self.queue, &bind_info, vk::Fence::null())?;
+        }
+        self.target_value += 1;
+        Ok(())
+    }
+
+    /// Submits an empty batch which waits on previously submitted work and increments the
+    /// timeline semaphore. The batch additionally waits until the timeline semaphore in `wait`
+    /// reaches the given value, if provided.
+    pub unsafe fn signal(
+        &mut self,
+        device: &ash::Device,
+        wait: Option<(vk::Semaphore, u64)>,
+    ) -> ash::prelude::VkResult<()> {
+        let semaphore = [self.semaphore];
+        let mut wait_semaphores = vec![self.semaphore];
+        let mut wait_values = vec![self.target_value];
+        if let Some((semaphore, value)) = wait {
+            wait_semaphores.push(semaphore);
+            wait_values.push(value);
+        }
+        let wait_stages = vec![vk::PipelineStageFlags::TOP_OF_PIPE; wait_semaphores.len()];
+        let signal_values = [self.target_value + 1];
+
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .signal_semaphore_values(&signal_values)
+            .wait_semaphore_values(&wait_values)
+            .build();
+        let submit_info = [vk::SubmitInfo::builder()
+            .signal_semaphores(&semaphore)
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .push_next(&mut timeline_info)
+            .build()];
+        {
+            let _lock = self.lock();
+            device.queue_submit(self.queue, &submit_info, vk::Fence::null())?;
+        }
+        self.target_value += 1;
+        Ok(())
+    }
+
+    /// Resets the free command buffers whose work completes by the timeline value `value`,
+    /// returning their memory to the pool. `value` *must* have been reached.
+    pub unsafe fn reclaim(&mut self, device: &ash::Device, value: u64) {
+        for free in self.free.iter_mut() {
+            // Command buffers are freed in submission order
+            if free.target > value {
+                break;
+            }
+            if !free.reclaimed {
+                device
+                    .reset_command_buffer(
+                        free.command_buffer,
+                        vk::CommandBufferResetFlags::RELEASE_RESOURCES,
+                    )
+                    .unwrap();
+                free.reclaimed = true;
+            }
+        }
+    }
+
+    /// Creates a command pool for command buffers that are recorded once and submitted to this
+    /// queue many times.
+    pub unsafe fn create_prebuilt_pool(
+        &self,
+        device: &ash::Device,
+    ) -> ash::prelude::VkResult<vk::CommandPool> {
+        let create_info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(self.queue_family)
+            .build();
+        device.create_command_pool(&create_info, None)
     }
 
     pub unsafe fn release(&self, device: &ash::Device) {
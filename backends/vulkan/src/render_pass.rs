@@ -1,12 +1,9 @@
 use std::collections::{HashMap, HashSet};
 
-use api::{
-    render_pass::{ColorAttachmentSource, RenderPassDescriptor},
-    types::LoadOp,
-};
+use api::render_pass::{ColorAttachmentSource, DepthStencilAttachment, RenderPassDescriptor};
 use ash::vk;
 use bytemuck::{Pod, Zeroable};
-use dashmap::DashMap;
+use dashmap::{mapref::entry::Entry, DashMap};
 use fxhash::FxHashMap;
 
 #[repr(C)]
@@ -25,6 +22,10 @@ unsafe impl Zeroable for DrawIndexedIndirect {}
 #[derive(Default)]
 pub(crate) struct RenderPassCache {
     passes: DashMap<VkRenderPassDescriptor, vk::RenderPass>,
+    /// Maps a compatibility class to the first render pass created in that class.
+    classes: DashMap<VkRenderPassCompatibility, vk::RenderPass>,
+    /// Maps every render pass to the first render pass created that it is compatible with.
+    compatible: DashMap<vk::RenderPass, vk::RenderPass>,
 }
 
 #[derive(Default)]
@@ -42,15 +43,27 @@ pub(crate) struct Framebuffers {
     framebuffers: FxHashMap<Vec<vk::ImageView>, vk::Framebuffer>,
 }
 
+/// Key used to look up render passes in the cache. The depth stencil attachment is part of the
+/// key so that passes without color attachments are still distinguished by their depth format,
+/// layout, and ops.
 #[derive(Default, Hash, PartialEq, Eq)]
 pub(crate) struct VkRenderPassDescriptor {
     pub color_attachments: Vec<VkAttachment>,
     pub depth_stencil_attachment: Option<VkAttachment>,
 }
 
+/// Render passes are compatible if their attachments have matching formats and sample counts.
+/// Every attachment has a single sample, so only formats are considered.
+#[derive(Hash, PartialEq, Eq)]
+pub(crate) struct VkRenderPassCompatibility {
+    pub color_formats: Vec<vk::Format>,
+    pub depth_stencil_format: Option<vk::Format>,
+}
+
 #[derive(Hash, PartialEq, Eq)]
 pub(crate) struct VkAttachment {
     pub image_format: vk::Format,
+    pub layout: vk::ImageLayout,
     pub load_op: vk::AttachmentLoadOp,
     pub store_op: vk::AttachmentStoreOp,
 }
@@ -58,98 +71,210 @@ pub(crate) struct VkAttachment {
 impl RenderPassCache {
     /// Checks if a compatible render pass is in the cache. If it is, it is returned. Otherwise,
     /// a new render pass is created and returned.
+    #[inline(always)]
     pub fn get(
         &self,
         device: &ash::Device,
         pass: &RenderPassDescriptor<crate::VulkanBackend>,
     ) -> vk::RenderPass {
-        let descriptor = VkRenderPassDescriptor::from_descriptor(pass);
-        *self.passes.entry(descriptor).or_insert_with(|| {
-            // Create attachment descriptors
-            let mut attachments = Vec::with_capacity(pass.color_attachments.len());
-            for attachment in &pass.color_attachments {
-                attachments.push(
-                    vk::AttachmentDescription::builder()
-                        .samples(vk::SampleCountFlags::TYPE_1)
-                        .initial_layout(match attachment.load_op {
-                            LoadOp::Load => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                            LoadOp::DontCare => vk::ImageLayout::UNDEFINED,
-                            LoadOp::Clear(_) => vk::ImageLayout::UNDEFINED,
-                        })
-                        .final_layout(match &attachment.source {
-                            ColorAttachmentSource::SurfaceImage(_) => {
-                                vk::ImageLayout::PRESENT_SRC_KHR
-                            }
-                            ColorAttachmentSource::Texture { .. } => {
-                                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
-                            }
-                        })
-                        .load_op(crate::util::to_vk_load_op(attachment.load_op))
-                        .store_op(crate::util::to_vk_store_op(attachment.store_op))
-                        .format(match &attachment.source {
-                            ColorAttachmentSource::SurfaceImage(image) => image.internal().format(),
-                            ColorAttachmentSource::Texture { texture, .. } => {
-                                texture.internal().format
-                            }
-                        })
-                        .build(),
-                );
-            }
+        self.get_by_descriptor(device, VkRenderPassDescriptor::from_descriptor(pass))
+    }
 
-            if let Some(attachment) = &pass.depth_stencil_attachment {
-                attachments.push(
-                    vk::AttachmentDescription::builder()
-                        .samples(vk::SampleCountFlags::TYPE_1)
-                        .initial_layout(match attachment.load_op {
-                            LoadOp::Load => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-                            LoadOp::DontCare => vk::ImageLayout::UNDEFINED,
-                            LoadOp::Clear(_) => vk::ImageLayout::UNDEFINED,
-                        })
-                        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                        .load_op(crate::util::to_vk_load_op(attachment.load_op))
-                        .store_op(crate::util::to_vk_store_op(attachment.store_op))
-                        .format(attachment.texture.internal().format)
-                        .build(),
-                );
-            }
+    /// Like [`get`](RenderPassCache::get), but the color attachments are left in `final_layouts`
+    /// after the pass instead of the layout they are rendered in.
+    #[inline(always)]
+    pub fn get_with_final_layouts(
+        &self,
+        device: &ash::Device,
+        pass: &RenderPassDescriptor<crate::VulkanBackend>,
+        final_layouts: &[vk::ImageLayout],
+    ) -> vk::RenderPass {
+        let mut descriptor = VkRenderPassDescriptor::from_descriptor(pass);
+        for (attachment, layout) in descriptor.color_attachments.iter_mut().zip(final_layouts) {
+            attachment.layout = *layout;
+        }
+        self.get_by_descriptor(device, descriptor)
+    }
 
-            // Link with attachment references
-            let mut attachment_refs = Vec::with_capacity(pass.color_attachments.len());
-            for (i, _) in pass.color_attachments.iter().enumerate() {
-                attachment_refs.push(
-                    vk::AttachmentReference::builder()
-                        .attachment(i as u32)
-                        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                        .build(),
-                );
-            }
+    /// Gets the first render pass created with the given attachment formats, creating one if
+    /// there are none. Pipelines created for the returned pass can be used with any render pass
+    /// with the same attachment formats.
+    pub fn get_compatible(
+        &self,
+        device: &ash::Device,
+        color_formats: &[vk::Format],
+        depth_stencil_format: Option<vk::Format>,
+    ) -> vk::RenderPass {
+        let compatibility = VkRenderPassCompatibility {
+            color_formats: color_formats.to_vec(),
+            depth_stencil_format,
+        };
+        if let Some(class) = self.classes.get(&compatibility) {
+            return *class;
+        }
 
-            // Single subpass
-            let depth_attachment;
-            let subpass = vk::SubpassDescription::builder()
-                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-                .color_attachments(&attachment_refs);
-            let subpass = if pass.depth_stencil_attachment.is_some() {
-                depth_attachment = vk::AttachmentReference::builder()
-                    .attachment(attachment_refs.len() as u32)
-                    .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                    .build();
-                subpass.depth_stencil_attachment(&depth_attachment)
-            } else {
-                subpass
-            };
-            let subpass = [subpass.build()];
+        // Load and store ops don't affect compatibility, so any will do
+        let descriptor = VkRenderPassDescriptor {
+            color_attachments: color_formats
+                .iter()
+                .map(|format| VkAttachment {
+                    image_format: *format,
+                    layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    load_op: vk::AttachmentLoadOp::DONT_CARE,
+                    store_op: vk::AttachmentStoreOp::STORE,
+                })
+                .collect(),
+            depth_stencil_attachment: depth_stencil_format.map(|format| VkAttachment {
+                image_format: format,
+                layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                load_op: vk::AttachmentLoadOp::DONT_CARE,
+                store_op: vk::AttachmentStoreOp::STORE,
+            }),
+        };
+        let pass = self.get_by_descriptor(device, descriptor);
+        self.compatible(pass)
+    }
+
+    fn get_by_descriptor(
+        &self,
+        device: &ash::Device,
+        descriptor: VkRenderPassDescriptor,
+    ) -> vk::RenderPass {
+        let compatibility = VkRenderPassCompatibility {
+            color_formats: descriptor
+                .color_attachments
+                .iter()
+                .map(|attachment| attachment.image_format)
+                .collect(),
+            depth_stencil_format: descriptor
+                .depth_stencil_attachment
+                .as_ref()
+                .map(|attachment| attachment.image_format),
+        };
+        let entry = match self.passes.entry(descriptor) {
+            Entry::Occupied(entry) => return *entry.get(),
+            Entry::Vacant(entry) => entry,
+        };
+        let descriptor = entry.key();
+
+        // Create attachment descriptors. The layout of an attachment before the pass only
+        // matters if it is loaded, in which case it is the layout the attachment was transitioned
+        // into before the pass. This differs from the final layout for surface images, which are
+        // rendered to as color attachments and then transitioned for presentation.
+        let attachment_description = |attachment: &VkAttachment, loaded_layout: vk::ImageLayout| {
+            vk::AttachmentDescription::builder()
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .initial_layout(match attachment.load_op {
+                    vk::AttachmentLoadOp::LOAD => loaded_layout,
+                    _ => vk::ImageLayout::UNDEFINED,
+                })
+                .final_layout(attachment.layout)
+                .load_op(attachment.load_op)
+                .store_op(attachment.store_op)
+                .format(attachment.image_format)
+                .build()
+        };
+        let mut attachments: Vec<_> = descriptor
+            .color_attachments
+            .iter()
+            .map(|attachment| {
+                attachment_description(attachment, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            })
+            .collect();
+        if let Some(attachment) = &descriptor.depth_stencil_attachment {
+            attachments.push(attachment_description(attachment, attachment.layout));
+        }
 
-            // Create the render pass
-            unsafe {
-                let create_info = vk::RenderPassCreateInfo::builder()
-                    .attachments(&attachments)
-                    .subpasses(&subpass)
-                    .build();
+        // Link with attachment references
+        let mut attachment_refs = Vec::with_capacity(descriptor.color_attachments.len());
+        for (i, _) in descriptor.color_attachments.iter().enumerate() {
+            attachment_refs.push(
+                vk::AttachmentReference::builder()
+                    .attachment(i as u32)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .build(),
+            );
+        }
 
-                device.create_render_pass(&create_info, None).unwrap()
-            }
-        })
+        // Single subpass
+        let depth_attachment;
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&attachment_refs);
+        let subpass = if let Some(attachment) = &descriptor.depth_stencil_attachment {
+            depth_attachment = vk::AttachmentReference::builder()
+                .attachment(attachment_refs.len() as u32)
+                .layout(attachment.layout)
+                .build();
+            subpass.depth_stencil_attachment(&depth_attachment)
+        } else {
+            subpass
+        };
+        let subpass = [subpass.build()];
+
+        // External dependencies so the layout transitions at the start and end of the pass are
+        // ordered with attachment accesses made outside of the pass. Every pass uses the same
+        // dependencies, so they don't affect compatibility.
+        let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let attachment_writes = vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+        let attachment_accesses = attachment_writes
+            | vk::AccessFlags::COLOR_ATTACHMENT_READ
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ;
+        let dependencies = [
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(attachment_stages)
+                .src_access_mask(attachment_writes)
+                .dst_stage_mask(attachment_stages)
+                .dst_access_mask(attachment_accesses)
+                .build(),
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(attachment_stages)
+                .src_access_mask(attachment_writes)
+                .dst_stage_mask(
+                    attachment_stages
+                        | vk::PipelineStageFlags::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags::TRANSFER,
+                )
+                .dst_access_mask(
+                    attachment_accesses
+                        | vk::AccessFlags::SHADER_READ
+                        | vk::AccessFlags::TRANSFER_READ,
+                )
+                .build(),
+        ];
+
+        // Create the render pass
+        let render_pass = unsafe {
+            let create_info = vk::RenderPassCreateInfo::builder()
+                .attachments(&attachments)
+                .subpasses(&subpass)
+                .dependencies(&dependencies)
+                .build();
+
+            device.create_render_pass(&create_info, None).unwrap()
+        };
+
+        // Register the render pass with its compatibility class
+        let class = *self.classes.entry(compatibility).or_insert(render_pass);
+        self.compatible.insert(render_pass, class);
+
+        entry.insert(render_pass);
+        render_pass
+    }
+
+    /// Given a render pass produced by this cache, returns the first render pass created that is
+    /// compatible with it. Pipelines created for the returned pass can be used with either pass,
+    /// so this is used to share pipelines between compatible passes.
+    #[inline(always)]
+    pub fn compatible(&self, pass: vk::RenderPass) -> vk::RenderPass {
+        *self.compatible.get(&pass).unwrap()
     }
 
     pub unsafe fn release(&self, device: &ash::Device) {
@@ -267,6 +392,57 @@ impl Framebuffers {
     }
 }
 
+/// Gets the ordered image views used as the framebuffer of a render pass, along with the
+/// dimensions of the framebuffer.
+pub(crate) fn framebuffer_views(
+    descriptor: &RenderPassDescriptor<crate::VulkanBackend>,
+) -> (Vec<vk::ImageView>, (u32, u32)) {
+    let mut dims = (0, 0);
+    let mut views = Vec::with_capacity(descriptor.color_attachments.len() + 1);
+    for attachment in &descriptor.color_attachments {
+        views.push(match &attachment.source {
+            ColorAttachmentSource::SurfaceImage(image) => {
+                dims = image.internal().dims();
+                image.internal().view()
+            }
+            ColorAttachmentSource::Texture {
+                texture,
+                array_element,
+                mip_level,
+                view_format,
+            } => {
+                dims = (
+                    (texture.dims().0 >> *mip_level).max(1),
+                    (texture.dims().1 >> *mip_level).max(1),
+                );
+                match view_format {
+                    Some(format) => texture.internal().view_as(
+                        *array_element,
+                        *mip_level,
+                        crate::util::to_vk_format(*format),
+                    ),
+                    None => texture.internal().view(*array_element, *mip_level),
+                }
+            }
+        });
+    }
+
+    if let Some(attachment) = &descriptor.depth_stencil_attachment {
+        let texture = attachment.texture;
+        dims = (
+            (texture.dims().0 >> attachment.mip_level).max(1),
+            (texture.dims().1 >> attachment.mip_level).max(1),
+        );
+        views.push(
+            texture
+                .internal()
+                .view(attachment.array_element, attachment.mip_level),
+        );
+    }
+
+    (views, dims)
+}
+
 impl VkRenderPassDescriptor {
     pub fn from_descriptor<'a>(
         descriptor: &RenderPassDescriptor<'a, crate::VulkanBackend>,
@@ -276,7 +452,22 @@ impl VkRenderPassDescriptor {
             out.color_attachments.push(VkAttachment {
                 image_format: match &attachment.source {
                     ColorAttachmentSource::SurfaceImage(image) => image.internal().format(),
-                    ColorAttachmentSource::Texture { texture, .. } => texture.internal().format,
+                    // The render pass uses the format of the view, which may differ from the
+                    // format of the texture
+                    ColorAttachmentSource::Texture {
+                        texture,
+                        view_format,
+                        ..
+                    } => match view_format {
+                        Some(format) => crate::util::to_vk_format(*format),
+                        None => texture.internal().format,
+                    },
+                },
+                layout: match &attachment.source {
+                    ColorAttachmentSource::SurfaceImage(_) => vk::ImageLayout::PRESENT_SRC_KHR,
+                    ColorAttachmentSource::Texture { .. } => {
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+                    }
                 },
                 load_op: crate::util::to_vk_load_op(attachment.load_op),
                 store_op: crate::util::to_vk_store_op(attachment.store_op),
@@ -285,6 +476,7 @@ impl VkRenderPassDescriptor {
         if let Some(attachment) = &descriptor.depth_stencil_attachment {
             out.depth_stencil_attachment = Some(VkAttachment {
                 image_format: attachment.texture.internal().format,
+                layout: depth_stencil_attachment_layout(attachment),
                 load_op: crate::util::to_vk_load_op(attachment.load_op),
                 store_op: crate::util::to_vk_store_op(attachment.store_op),
             })
@@ -292,3 +484,15 @@ impl VkRenderPassDescriptor {
         out
     }
 }
+
+/// Determines the layout a depth stencil attachment is in for the duration of a render pass.
+#[inline(always)]
+pub(crate) fn depth_stencil_attachment_layout(
+    attachment: &DepthStencilAttachment<crate::VulkanBackend>,
+) -> vk::ImageLayout {
+    if attachment.read_only_depth {
+        crate::util::depth_read_only_layout(attachment.texture.internal().aspect_flags)
+    } else {
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+    }
+}
@@ -7,6 +7,67 @@ pub struct Shader {
     pub(crate) module: vk::ShaderModule,
 }
 
+/// Magic number at the start of every SPIR-V module.
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// Number of words in the header of a SPIR-V module.
+const SPIRV_HEADER_WORDS: usize = 5;
+
+/// The newest SPIR-V version (major, minor) supported by Vulkan 1.2.
+const MAX_SPIRV_VERSION: (u32, u32) = (1, 5);
+
+/// SPIR-V 1.0 vertex shader that covers the screen with a single triangle using no vertex inputs.
+/// Texture coordinates are written to location `0`, with `(0, 0)` at the top left. Generated from
+/// the following WGSL:
+///
+/// ```wgsl
+/// struct VertexOutput {
+///     @builtin(position) position: vec4<f32>,
+///     @location(0) uv: vec2<f32>,
+/// };
+///
+/// @vertex
+/// fn main(@builtin(vertex_index) index: u32) -> VertexOutput {
+///     let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+///     var out: VertexOutput;
+///     out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+///     out.uv = uv;
+///     return out;
+/// }
+/// ```
+pub(crate) static FULLSCREEN_VERTEX_SHADER: [u32; 240] = [
+    0x07230203, 0x00010000, 0x0000001c, 0x0000002d, 0x00000000, 0x00020011, 0x00000001, 0x0006000b,
+    0x00000001, 0x4c534c47, 0x6474732e, 0x3035342e, 0x00000000, 0x0003000e, 0x00000000, 0x00000001,
+    0x0008000f, 0x00000000, 0x00000018, 0x6e69616d, 0x00000000, 0x00000011, 0x00000014, 0x00000016,
+    0x00050048, 0x0000000c, 0x00000000, 0x00000023, 0x00000000, 0x00050048, 0x0000000c, 0x00000001,
+    0x00000023, 0x00000010, 0x00040047, 0x00000011, 0x0000000b, 0x0000002a, 0x00040047, 0x00000014,
+    0x0000000b, 0x00000000, 0x00040047, 0x00000016, 0x0000001e, 0x00000000, 0x00020013, 0x00000002,
+    0x00040015, 0x00000004, 0x00000020, 0x00000000, 0x0004002b, 0x00000004, 0x00000003, 0x00000001,
+    0x0004002b, 0x00000004, 0x00000005, 0x00000002, 0x00030016, 0x00000007, 0x00000020, 0x0004002b,
+    0x00000007, 0x00000006, 0x40000000, 0x0004002b, 0x00000007, 0x00000008, 0x3f800000, 0x0004002b,
+    0x00000007, 0x00000009, 0x00000000, 0x00040017, 0x0000000a, 0x00000007, 0x00000004, 0x00040017,
+    0x0000000b, 0x00000007, 0x00000002, 0x0004001e, 0x0000000c, 0x0000000a, 0x0000000b, 0x00040020,
+    0x0000000e, 0x00000007, 0x0000000c, 0x0003002e, 0x0000000c, 0x0000000f, 0x00040020, 0x00000012,
+    0x00000001, 0x00000004, 0x0004003b, 0x00000012, 0x00000011, 0x00000001, 0x00040020, 0x00000015,
+    0x00000003, 0x0000000a, 0x0004003b, 0x00000015, 0x00000014, 0x00000003, 0x00040020, 0x00000017,
+    0x00000003, 0x0000000b, 0x0004003b, 0x00000017, 0x00000016, 0x00000003, 0x00030021, 0x00000019,
+    0x00000002, 0x00040020, 0x00000021, 0x00000007, 0x0000000a, 0x0004002b, 0x00000004, 0x00000026,
+    0x00000000, 0x00040020, 0x00000028, 0x00000007, 0x0000000b, 0x00050036, 0x00000002, 0x00000018,
+    0x00000000, 0x00000019, 0x000200f8, 0x00000010, 0x0005003b, 0x0000000e, 0x0000000d, 0x00000007,
+    0x0000000f, 0x0004003d, 0x00000004, 0x00000013, 0x00000011, 0x000200f9, 0x0000001a, 0x000200f8,
+    0x0000001a, 0x000500c4, 0x00000004, 0x0000001b, 0x00000013, 0x00000003, 0x000500c7, 0x00000004,
+    0x0000001c, 0x0000001b, 0x00000005, 0x00040070, 0x00000007, 0x0000001d, 0x0000001c, 0x000500c7,
+    0x00000004, 0x0000001e, 0x00000013, 0x00000005, 0x00040070, 0x00000007, 0x0000001f, 0x0000001e,
+    0x00050050, 0x0000000b, 0x00000020, 0x0000001d, 0x0000001f, 0x0005008e, 0x0000000b, 0x00000022,
+    0x00000020, 0x00000006, 0x00050050, 0x0000000b, 0x00000023, 0x00000008, 0x00000008, 0x00050083,
+    0x0000000b, 0x00000024, 0x00000022, 0x00000023, 0x00060050, 0x0000000a, 0x00000025, 0x00000024,
+    0x00000009, 0x00000008, 0x00050041, 0x00000021, 0x00000027, 0x0000000d, 0x00000026, 0x0003003e,
+    0x00000027, 0x00000025, 0x00050041, 0x00000028, 0x00000029, 0x0000000d, 0x00000003, 0x0003003e,
+    0x00000029, 0x00000020, 0x0004003d, 0x0000000c, 0x0000002a, 0x0000000d, 0x00050051, 0x0000000a,
+    0x0000002b, 0x0000002a, 0x00000000, 0x0003003e, 0x00000014, 0x0000002b, 0x00050051, 0x0000000b,
+    0x0000002c, 0x0000002a, 0x00000001, 0x0003003e, 0x00000016, 0x0000002c, 0x000100fd, 0x00010038,
+];
+
 impl Shader {
     pub(crate) unsafe fn new(
         device: &ash::Device,
@@ -19,6 +80,15 @@ impl Shader {
             )));
         }
 
+        // Check that the module targets a SPIR-V version the device can consume
+        let (major, minor) = spirv_version(&create_info.code)?;
+        if (major, minor) > MAX_SPIRV_VERSION {
+            return Err(ShaderCreateError::Other(format!(
+                "shader uses SPIR-V {}.{}, but at most SPIR-V {}.{} is supported",
+                major, minor, MAX_SPIRV_VERSION.0, MAX_SPIRV_VERSION.1
+            )));
+        }
+
         let module_create_info = vk::ShaderModuleCreateInfo {
             p_code: create_info.code.as_ptr() as *const u32,
             code_size: create_info.code.len(),
@@ -48,3 +118,33 @@ impl Shader {
         Ok(Shader { module })
     }
 }
+
+/// Parses the (major, minor) version from the header of a SPIR-V module.
+fn spirv_version(code: &[u8]) -> Result<(u32, u32), ShaderCreateError> {
+    if code.len() < SPIRV_HEADER_WORDS * std::mem::size_of::<u32>() {
+        return Err(ShaderCreateError::Other(String::from(
+            "shader code is too small to contain a SPIR-V header",
+        )));
+    }
+
+    // The magic number determines the endianness of the module
+    let word = |i: usize| {
+        u32::from_le_bytes([
+            code[i * 4],
+            code[i * 4 + 1],
+            code[i * 4 + 2],
+            code[i * 4 + 3],
+        ])
+    };
+    let version = if word(0) == SPIRV_MAGIC {
+        word(1)
+    } else if word(0).swap_bytes() == SPIRV_MAGIC {
+        word(1).swap_bytes()
+    } else {
+        return Err(ShaderCreateError::Other(String::from(
+            "shader code is not SPIR-V (invalid magic number)",
+        )));
+    };
+
+    Ok(((version >> 16) & 0xFF, (version >> 8) & 0xFF))
+}
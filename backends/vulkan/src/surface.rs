@@ -1,19 +1,26 @@
 use std::{
     ffi::CString,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
 };
 
 use api::{
+    buffer::BufferCreateInfo,
     queue::SurfacePresentFailure,
     surface::{
-        SurfaceConfiguration, SurfaceCreateError, SurfaceCreateInfo, SurfaceImageAcquireError,
-        SurfacePresentSuccess, SurfaceUpdateError,
+        SurfaceCaptureError, SurfaceConfiguration, SurfaceCreateError, SurfaceCreateInfo,
+        SurfaceImageAcquireError, SurfacePresentSuccess, SurfaceQueryError, SurfaceUpdateError,
     },
+    types::{BufferUsage, MemoryUsage, PresentMode, QueueType, SharingMode},
+    Backend,
 };
 use ash::vk::{self, Handle};
 use raw_window_handle::HasRawWindowHandle;
 
-use crate::VulkanBackend;
+use crate::{buffer::Buffer, job::Job, util::semaphores::SemaphoreTracker, VulkanBackend};
 
 pub struct SurfaceId(pub(crate) usize);
 
@@ -22,6 +29,10 @@ pub struct Surface {
     pub(crate) swapchain: vk::SwapchainKHR,
     pub(crate) format: vk::SurfaceFormatKHR,
     pub(crate) resolution: vk::Extent2D,
+    /// Presentation mode the swapchain was created with after any fallback.
+    pub(crate) present_mode: PresentMode,
+    /// Usage flags the swapchain images were created with.
+    pub(crate) image_usage: vk::ImageUsageFlags,
     pub(crate) images: Vec<(vk::Image, vk::ImageView)>,
     /// Semaphores for image availability.
     pub(crate) semaphores: Vec<SurfaceImageSemaphores>,
@@ -29,6 +40,9 @@ pub struct Surface {
     pub(crate) next_semaphore: usize,
     /// Counter for the number of images acquired.
     pub(crate) images_acquired: usize,
+    /// Images that were acquired and then discarded without being presented. These are still
+    /// acquired from the swapchain, so they are handed out again before acquiring new images.
+    pub(crate) discarded: Vec<(usize, SurfaceImageSemaphores)>,
     debug_name: Option<String>,
 }
 
@@ -49,6 +63,10 @@ pub struct SurfaceImage {
     semaphores: SurfaceImageSemaphores,
     /// Indicates that the surface image has been used and is available for present.
     used: AtomicBool,
+    /// The queue and timeline value of the most recent submission that rendered to the image.
+    rendered_by: Mutex<Option<(QueueType, u64)>>,
+    /// Indicates that the surface image was handed back to the surface without being presented.
+    discarded: bool,
 }
 
 #[derive(Copy, Clone)]
@@ -91,10 +109,13 @@ impl Surface {
             swapchain: vk::SwapchainKHR::null(),
             format: vk::SurfaceFormatKHR::default(),
             resolution: vk::Extent2D::default(),
+            present_mode: PresentMode::Fifo,
+            image_usage: vk::ImageUsageFlags::empty(),
             images: Vec::default(),
             semaphores: Vec::default(),
             next_semaphore: 0,
             images_acquired: 0,
+            discarded: Vec::default(),
             debug_name: create_info.debug_name,
         };
 
@@ -142,6 +163,30 @@ impl Surface {
         }
     }
 
+    /// Gets the current extent of the surface as reported by the OS. Falls back to the configured
+    /// resolution if the OS lets the swapchain determine the extent.
+    pub(crate) unsafe fn current_extent(
+        &self,
+        ctx: &VulkanBackend,
+    ) -> Result<(u32, u32), SurfaceQueryError> {
+        let surface_capabilities = match ctx
+            .surface_loader
+            .get_physical_device_surface_capabilities(ctx.physical_device, self.surface)
+        {
+            Ok(capabilities) => capabilities,
+            Err(vk::Result::ERROR_SURFACE_LOST_KHR) => return Err(SurfaceQueryError::Lost),
+            Err(err) => return Err(SurfaceQueryError::Other(err.to_string())),
+        };
+
+        // A special value indicates that the extent is determined by the swapchain
+        let extent = surface_capabilities.current_extent;
+        if extent.width == u32::MAX && extent.height == u32::MAX {
+            Ok((self.resolution.width, self.resolution.height))
+        } else {
+            Ok((extent.width, extent.height))
+        }
+    }
+
     pub(crate) unsafe fn update_config(
         &mut self,
         ctx: &VulkanBackend,
@@ -205,30 +250,30 @@ impl Surface {
 
         // Determine a compatible presentation mode and fallback if the requested one is not
         // available.
-        let present_mode = {
-            let mut present_mode = crate::util::to_vk_present_mode(config.present_mode);
+        self.present_mode = {
+            let mut present_mode = config.present_mode;
 
-            // Fallback if it's not available
-            if !present_modes.contains(&present_mode) {
-                present_mode = vk::PresentModeKHR::IMMEDIATE;
+            // Fallback to FIFO if it's not available since it's the only mode that is required
+            // to be supported
+            if !present_modes.contains(&crate::util::to_vk_present_mode(present_mode)) {
+                present_mode = PresentMode::Fifo;
             }
 
             present_mode
         };
 
         // Determine an approprite format and color space
-        self.format = {
-            let vk_format = crate::util::to_vk_format(config.format);
-            let mut out_format = formats[0];
-            for format in formats {
-                if format.format == vk_format
-                    && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-                {
-                    out_format = format;
-                }
-            }
-            out_format
-        };
+        self.format = select_surface_format(&formats, crate::util::to_vk_format(config.format));
+
+        // Allow surface images to be read back if the surface supports it
+        self.image_usage =
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST;
+        if surface_capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            self.image_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
 
         // Determine if we need exclusive or concurrent access to the images
         let (indices, sharing_mode) = {
@@ -257,12 +302,12 @@ impl Surface {
             .image_color_space(self.format.color_space)
             .image_format(self.format.format)
             .image_extent(surface_resolution)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST)
+            .image_usage(self.image_usage)
             .image_sharing_mode(sharing_mode)
             .queue_family_indices(&indices)
             .pre_transform(pre_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(present_mode)
+            .present_mode(crate::util::to_vk_present_mode(self.present_mode))
             .clipped(true)
             .image_array_layers(1);
 
@@ -306,6 +351,14 @@ impl Surface {
             Err(err) => return Err(SurfaceUpdateError::Other(err.to_string())),
         };
 
+        // Register image names for tracking errors
+        if let Some(name) = &self.debug_name {
+            let mut resc_state = ctx.resource_state.write().unwrap();
+            for (i, (image, _)) in self.images.iter().enumerate() {
+                resc_state.register_name(*image, format!("{}_image_{}", name, i));
+            }
+        }
+
         // Create image availability semaphores
         for _ in 0..self.images.len() {
             let create_info = vk::SemaphoreCreateInfo::default();
@@ -388,25 +441,65 @@ impl Surface {
     pub(crate) unsafe fn acquire_image(
         &mut self,
         ctx: &VulkanBackend,
+        timeout: Option<Duration>,
     ) -> Result<SurfaceImage, SurfaceImageAcquireError> {
+        // Reuse discarded images first. Their availability semaphores have not been waited on yet,
+        // so they can be used as if the image was just acquired.
+        if let Some((image_idx, semaphores)) = self.discarded.pop() {
+            return Ok(self.make_image(image_idx, semaphores));
+        }
+
         if self.images_acquired + 1 > self.images.len() {
             return Err(SurfaceImageAcquireError::NoImages);
         }
 
         // Acquire the image
-        self.next_semaphore = (self.next_semaphore + 1) % self.semaphores.len();
-        let semaphores = self.semaphores[self.next_semaphore];
+        let next_semaphore = (self.next_semaphore + 1) % self.semaphores.len();
+        let semaphores = self.semaphores[next_semaphore];
         let image_idx = match ctx.swapchain_loader.acquire_next_image(
             self.swapchain,
-            u64::MAX,
+            match timeout {
+                Some(timeout) => timeout.as_nanos() as u64,
+                None => u64::MAX,
+            },
             semaphores.available,
             vk::Fence::null(),
         ) {
             Ok((idx, _)) => idx as usize,
+            // `NOT_READY` is reported instead of `TIMEOUT` when the timeout is zero
+            Err(vk::Result::TIMEOUT | vk::Result::NOT_READY) => {
+                return Err(SurfaceImageAcquireError::Timeout)
+            }
             Err(err) => return Err(SurfaceImageAcquireError::Other(err.to_string())),
         };
 
-        Ok(SurfaceImage {
+        // The semaphore is only in use once an image has actually been acquired
+        self.next_semaphore = next_semaphore;
+
+        Ok(self.make_image(image_idx, semaphores))
+    }
+
+    /// Hands an acquired image back to the surface without presenting it.
+    ///
+    /// # Panics
+    /// - If `image` was not acquired from this surface.
+    /// - If `image` has been rendered to.
+    pub(crate) fn discard(&mut self, image: &mut SurfaceImage) {
+        assert!(
+            image.surface() == self.surface,
+            "surface image was not acquired from this surface"
+        );
+        assert!(
+            !image.is_signaled(),
+            "surface images that have been rendered to must be presented"
+        );
+        image.discarded = true;
+        self.discarded.push((image.image_idx, image.semaphores));
+    }
+
+    #[inline(always)]
+    fn make_image(&self, image_idx: usize, semaphores: SurfaceImageSemaphores) -> SurfaceImage {
+        SurfaceImage {
             surface: self.surface,
             dims: (self.resolution.width, self.resolution.height),
             image: self.images[image_idx].0,
@@ -415,15 +508,249 @@ impl Surface {
             image_idx,
             semaphores,
             used: AtomicBool::new(false),
-        })
+            rendered_by: Mutex::new(None),
+            discarded: false,
+        }
+    }
+
+    pub(crate) unsafe fn capture(
+        &self,
+        ctx: &VulkanBackend,
+        queue: QueueType,
+        image: &SurfaceImage,
+    ) -> Result<Vec<u8>, SurfaceCaptureError> {
+        if image.surface() != self.surface {
+            return Err(SurfaceCaptureError::BadImage);
+        }
+
+        if !image.is_signaled() {
+            return Err(SurfaceCaptureError::NoRender);
+        }
+
+        if !self.image_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+            return Err(SurfaceCaptureError::Unsupported);
+        }
+
+        // Only 8-bit four channel formats can be read back. BGRA formats are swizzled into RGBA.
+        let swizzle = match self.format.format {
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => false,
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => true,
+            _ => return Err(SurfaceCaptureError::Unsupported),
+        };
+
+        // Create a buffer to copy the image into
+        let (width, height) = image.dims();
+        let size = width as u64 * height as u64 * 4;
+        let buffer = match Buffer::new(
+            &ctx.device,
+            ctx.debug.as_ref().map(|(utils, _)| utils),
+            ctx.garbage.sender(),
+            &mut ctx.allocator.lock().unwrap(),
+            &ctx.properties.limits,
+            &ctx.queue_family_indices.unique,
+            BufferCreateInfo {
+                size,
+                array_elements: 1,
+                buffer_usage: BufferUsage::TRANSFER_DST,
+                memory_usage: MemoryUsage::GpuToCpu,
+                sharing: SharingMode::Exclusive,
+                debug_name: Some(String::from("surface_capture")),
+            },
+        ) {
+            Ok(buffer) => buffer,
+            Err(err) => return Err(SurfaceCaptureError::Other(err.to_string())),
+        };
+
+        // NOTE: We wait on the CPU for the rendering to complete and then for the copy itself, so
+        // nobody needs to wait on the image afterwards.
+        if let Some(old) =
+            ctx.resource_state
+                .write()
+                .unwrap()
+                .register_image(image.image(), 0, None)
+        {
+            ctx.wait_on(
+                &Job {
+                    ty: old.queue,
+                    target_value: old.timeline_value,
+                },
+                None,
+            );
+        }
+
+        // Record and submit the copy
+        let job = {
+            let mut vk_queue = match queue {
+                QueueType::Main => ctx.main.write().unwrap(),
+                QueueType::Transfer => ctx.transfer.write().unwrap(),
+                QueueType::Compute => ctx.compute.write().unwrap(),
+                QueueType::Present => ctx.present.write().unwrap(),
+            };
+
+            let cb = vk_queue
+                .allocate_command_buffer(&ctx.device, ctx.debug.as_ref().map(|(utils, _)| utils));
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build();
+            ctx.device.begin_command_buffer(cb, &begin_info).unwrap();
+
+            let subresource_range = vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            };
+
+            // Rendered surface images are left in the present layout by the render pass
+            let barrier = [vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image.image())
+                .subresource_range(subresource_range)
+                .build()];
+            ctx.device.cmd_pipeline_barrier(
+                cb,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barrier,
+            );
+
+            let region = [vk::BufferImageCopy::builder()
+                .buffer_offset(0)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                })
+                .build()];
+            ctx.device.cmd_copy_image_to_buffer(
+                cb,
+                image.image(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &region,
+            );
+
+            // Return the image to the present layout and make the copy visible to the host
+            let image_barrier = [vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::empty())
+                .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image.image())
+                .subresource_range(subresource_range)
+                .build()];
+            let buffer_barrier = [vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(buffer.buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build()];
+            ctx.device.cmd_pipeline_barrier(
+                cb,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST | vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &buffer_barrier,
+                &image_barrier,
+            );
+
+            ctx.device.end_command_buffer(cb).unwrap();
+            if let Err(err) = vk_queue.submit(&ctx.device, cb, false, SemaphoreTracker::default()) {
+                return Err(SurfaceCaptureError::Other(err.to_string()));
+            }
+
+            Job {
+                ty: queue,
+                target_value: vk_queue.target_timeline_value(),
+            }
+        };
+        ctx.wait_on(&job, None);
+
+        // Device writes must be made visible to the host, since the memory might not be coherent
+        let atom = ctx.properties.limits.non_coherent_atom_size;
+        let start = buffer.block.offset();
+        let end = (start + size).div_ceil(atom) * atom;
+        let aligned_start = start - (start % atom);
+        let aligned_end = end.min(buffer.block.offset() + buffer.block.size());
+        let range = vk::MappedMemoryRange::builder()
+            .memory(buffer.block.memory())
+            .offset(aligned_start)
+            .size(aligned_end - aligned_start)
+            .build();
+        if let Err(err) = ctx.device.invalidate_mapped_memory_ranges(&[range]) {
+            return Err(SurfaceCaptureError::Other(err.to_string()));
+        }
+
+        // Read back the pixels
+        let map = buffer.block.mapped_ptr().unwrap().as_ptr() as *const u8;
+        let mut pixels = std::slice::from_raw_parts(map, size as usize).to_vec();
+        if swizzle {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        Ok(pixels)
     }
 
     pub(crate) unsafe fn release(&mut self, ctx: &VulkanBackend) {
+        // Discarded images have pending signals on their availability semaphores which must
+        // complete before the semaphores are destroyed
+        if !self.discarded.is_empty() {
+            let semaphores: Vec<_> = self
+                .discarded
+                .drain(..)
+                .map(|(_, semaphores)| semaphores.available)
+                .collect();
+            let stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; semaphores.len()];
+            let submit_info = [vk::SubmitInfo::builder()
+                .wait_semaphores(&semaphores)
+                .wait_dst_stage_mask(&stages)
+                .build()];
+            let queue = ctx.present.write().unwrap();
+            let _lock = queue.lock();
+            ctx.device
+                .queue_submit(queue.queue, &submit_info, vk::Fence::null())
+                .unwrap();
+            ctx.device.queue_wait_idle(queue.queue).unwrap();
+        }
+
         for semaphores in self.semaphores.drain(..) {
             ctx.device.destroy_semaphore(semaphores.available, None);
             ctx.device.destroy_semaphore(semaphores.presentable, None);
         }
 
+        if self.debug_name.is_some() {
+            let mut resc_state = ctx.resource_state.write().unwrap();
+            for (image, _) in &self.images {
+                resc_state.remove_name(*image);
+            }
+        }
+
         for (_, view) in self.images.drain(..) {
             ctx.device.destroy_image_view(view, None);
         }
@@ -432,6 +759,38 @@ impl Surface {
             ctx.swapchain_loader.destroy_swapchain(self.swapchain, None);
         }
     }
+
+    /// The color space of the swapchain images chosen by the last configuration update.
+    #[inline(always)]
+    pub fn color_space(&self) -> vk::ColorSpaceKHR {
+        self.format.color_space
+    }
+}
+
+/// Chooses a surface format from the list of supported formats.
+///
+/// The requested format with the `SRGB_NONLINEAR` color space is preferred. If that isn't
+/// available, the requested format with any other supported color space is used instead. If the
+/// requested format isn't supported at all, the first supported format is used.
+pub(crate) fn select_surface_format(
+    formats: &[vk::SurfaceFormatKHR],
+    requested: vk::Format,
+) -> vk::SurfaceFormatKHR {
+    let mut fallback = None;
+    for format in formats {
+        if format.format != requested {
+            continue;
+        }
+
+        if format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR {
+            return *format;
+        }
+
+        if fallback.is_none() {
+            fallback = Some(*format);
+        }
+    }
+    fallback.unwrap_or(formats[0])
 }
 
 impl SurfaceImage {
@@ -455,14 +814,27 @@ impl SurfaceImage {
         self.surface
     }
 
+    #[inline(always)]
+    pub(crate) fn is_discarded(&self) -> bool {
+        self.discarded
+    }
+
     #[inline(always)]
     pub(crate) fn is_signaled(&self) -> bool {
         self.used.load(Ordering::Relaxed)
     }
 
+    /// Marks the image as rendered to by the submission with timeline value `value` on `queue`.
     #[inline(always)]
-    pub(crate) fn signal_draw(&self) {
+    pub(crate) fn signal_draw(&self, queue: QueueType, value: u64) {
         self.used.store(true, Ordering::Relaxed);
+        *self.rendered_by.lock().unwrap() = Some((queue, value));
+    }
+
+    /// The queue and timeline value of the most recent submission that rendered to the image.
+    #[inline(always)]
+    pub(crate) fn rendered_by(&self) -> Option<(QueueType, u64)> {
+        *self.rendered_by.lock().unwrap()
     }
 
     #[inline(always)]
@@ -1,45 +1,137 @@
-use std::{ffi::CString, mem::ManuallyDrop, sync::Arc};
+use std::{
+    ffi::CString,
+    mem::ManuallyDrop,
+    ptr::NonNull,
+    sync::{Arc, Mutex},
+};
 
-use crate::util::garbage_collector::Garbage;
+use crate::{
+    job::Job,
+    util::garbage_collector::{dispose, Garbage},
+    VulkanBackend,
+};
 use api::{
-    texture::{TextureCreateError, TextureCreateInfo},
+    texture::{TextureCreateError, TextureCreateInfo, TextureSubresourceLayout, TextureViewError},
     types::*,
+    Backend,
 };
 use ash::vk::{self, Handle};
 use crossbeam_channel::Sender;
+use fxhash::FxHashMap;
 use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, Allocator};
 
 pub struct Texture {
     pub(crate) image: vk::Image,
-    /// Image view for each array element and mip level. This array is flattened like so.
-    /// A0M0 -> A0M1 -> A0M2 ... A1M0 -> A1M1 -> A1M2 -> ...
+    /// Image view for each view format, array element, and mip level. This array is flattened
+    /// like so. F0A0M0 -> F0A0M1 ... F0A1M0 -> F0A1M1 -> ... F1A0M0 -> ...
     pub(crate) views: Vec<vk::ImageView>,
+    /// The formats the texture can be viewed as, in the order of `views`. The first is the
+    /// format of the texture.
+    pub(crate) view_formats: Vec<vk::Format>,
     pub(crate) block: ManuallyDrop<Allocation>,
     pub(crate) image_usage: TextureUsage,
     pub(crate) memory_usage: MemoryUsage,
+    pub(crate) tiling: TextureTiling,
     pub(crate) array_elements: usize,
     pub(crate) size: u64,
     pub(crate) ref_counter: TextureRefCounter,
     pub(crate) format: vk::Format,
     pub(crate) mip_count: u32,
     pub(crate) aspect_flags: vk::ImageAspectFlags,
+    /// Only available if the texture was created with sparse residency.
+    pub(crate) sparse: Option<SparseResidency>,
     on_drop: Sender<Garbage>,
 }
 
+/// Tracks the memory bound to a texture created with sparse residency.
+pub(crate) struct SparseResidency {
+    /// Dimensions of a single page in texels.
+    pub page_dims: vk::Extent3D,
+    /// The first mip level that is part of the mip tail.
+    pub mip_tail_first_lod: u32,
+    pub mip_tail_size: u64,
+    pub mip_tail_offset: u64,
+    pub mip_tail_stride: u64,
+    /// If set, a single mip tail is shared by every array element.
+    pub single_mip_tail: bool,
+    /// Requirements for the memory of a single page.
+    pub page_requirements: vk::MemoryRequirements,
+    /// Memory of resident pages, keyed by array element, mip level, and page coordinates.
+    pub pages: Mutex<FxHashMap<SparsePage, Allocation>>,
+    /// Memory bound to the mip tail(s) when the texture was created.
+    pub mip_tail: Vec<Allocation>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct SparsePage {
+    pub array_element: u32,
+    pub mip_level: u32,
+    pub page: (u32, u32, u32),
+}
+
+/// A change made to the resident pages of a sparse texture while binding, which can be undone if
+/// binding fails.
+pub(crate) struct PageChange<'a> {
+    pub texture: &'a Texture,
+    pub key: SparsePage,
+    /// The memory of the page if it was released, or `None` if memory was allocated for it.
+    pub released: Option<Allocation>,
+}
+
 #[derive(Clone)]
 pub(crate) struct TextureRefCounter(Arc<()>);
 
 impl Texture {
     pub(crate) unsafe fn new(
-        device: &ash::Device,
-        debug: Option<&ash::extensions::ext::DebugUtils>,
-        on_drop: Sender<Garbage>,
+        ctx: &VulkanBackend,
         allocator: &mut Allocator,
         create_info: TextureCreateInfo,
+        sparse: bool,
     ) -> Result<Self, TextureCreateError> {
-        // Create the image
+        let device = &ctx.device;
+        let debug = ctx.debug.as_ref().map(|(utils, _)| utils);
+        let non_coherent_atom_size = ctx.properties.limits.non_coherent_atom_size;
+
+        // Views in other formats are limited to the usages their format supports (e.g. sRGB
+        // formats usually don't support storage), which must include at least one of the
+        // requested view usages
         let format = crate::util::to_vk_format(create_info.format);
-        let image_create_info = vk::ImageCreateInfo::builder()
+        let image_usage = crate::util::to_vk_image_usage(create_info.texture_usage);
+        let mut view_formats = vec![format];
+        let mut view_usages = vec![image_usage];
+        for view_format in &create_info.view_formats {
+            let vk_format = crate::util::to_vk_format(*view_format);
+            if view_formats.contains(&vk_format) {
+                continue;
+            }
+
+            let usage = view_usage(ctx, vk_format, create_info.tiling, image_usage);
+            if image_usage.intersects(VIEW_USAGES) && !usage.intersects(VIEW_USAGES) {
+                return Err(TextureCreateError::UnsupportedViewFormat(*view_format));
+            }
+            view_formats.push(vk_format);
+            view_usages.push(usage);
+        }
+
+        // Create the image
+        let (sharing_mode, queue_families) =
+            crate::util::to_vk_sharing_mode(create_info.sharing, &ctx.queue_family_indices.unique);
+
+        // Textures with view formats must be created as mutable, listing the formats so drivers
+        // can keep optimizations (e.g. compression) that all of them are compatible with
+        let mut format_list = vk::ImageFormatListCreateInfo::builder()
+            .view_formats(&view_formats)
+            .build();
+        let mut flags = if sparse {
+            vk::ImageCreateFlags::SPARSE_BINDING | vk::ImageCreateFlags::SPARSE_RESIDENCY
+        } else {
+            vk::ImageCreateFlags::empty()
+        };
+        if view_formats.len() > 1 {
+            flags |= vk::ImageCreateFlags::MUTABLE_FORMAT;
+        }
+
+        let mut image_create_info = vk::ImageCreateInfo::builder()
             .image_type(crate::util::to_vk_image_type(create_info.ty))
             .extent(vk::Extent3D {
                 width: create_info.width,
@@ -49,50 +141,79 @@ impl Texture {
             .mip_levels(create_info.mip_levels as u32)
             .array_layers(create_info.array_elements as u32)
             .format(format)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .usage(crate::util::to_vk_image_usage(create_info.texture_usage))
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .tiling(crate::util::to_vk_image_tiling(create_info.tiling))
+            .initial_layout(match create_info.tiling {
+                // Preserves texels written by the host before the texture is first used
+                TextureTiling::Linear => vk::ImageLayout::PREINITIALIZED,
+                TextureTiling::Optimal => vk::ImageLayout::UNDEFINED,
+            })
+            .usage(image_usage)
+            .sharing_mode(sharing_mode)
+            .queue_family_indices(queue_families)
             .samples(vk::SampleCountFlags::TYPE_1)
-            .flags(vk::ImageCreateFlags::empty())
-            .build();
+            .flags(flags);
+        if view_formats.len() > 1 {
+            image_create_info = image_create_info.push_next(&mut format_list);
+        }
+        let image_create_info = image_create_info.build();
 
         let image = match device.create_image(&image_create_info, None) {
             Ok(image) => image,
             Err(err) => return Err(TextureCreateError::Other(err.to_string())),
         };
 
-        // Determine memory requirements
-        let mem_reqs = device.get_image_memory_requirements(image);
+        // Determine memory requirements. Linear textures are mapped, so their memory is padded to
+        // the non-coherent atom size so that flushed ranges never leave the allocation
+        let mut mem_reqs = device.get_image_memory_requirements(image);
+        if create_info.tiling == TextureTiling::Linear {
+            mem_reqs.alignment = mem_reqs.alignment.max(non_coherent_atom_size);
+            mem_reqs.size = mem_reqs.size.div_ceil(non_coherent_atom_size) * non_coherent_atom_size;
+        }
 
-        // Allocate memory
-        let request = AllocationCreateDesc {
-            name: match &create_info.debug_name {
-                Some(name) => &name,
-                None => "image",
-            },
-            requirements: mem_reqs,
-            location: crate::util::to_gpu_allocator_memory_location(create_info.memory_usage),
-            linear: false,
-        };
+        // Sparse textures have memory bound on demand instead of all at once
+        let (block, sparse) = if sparse {
+            let sparse_reqs = device.get_image_sparse_memory_requirements(image);
+            let reqs = match sparse_reqs
+                .iter()
+                .find(|reqs| reqs.format_properties.aspect_mask == vk::ImageAspectFlags::COLOR)
+            {
+                Some(reqs) => *reqs,
+                None => {
+                    device.destroy_image(image, None);
+                    return Err(TextureCreateError::SparseUnsupported);
+                }
+            };
 
-        let block = match allocator.allocate(&request) {
-            Ok(block) => block,
-            Err(err) => {
-                device.destroy_image(image, None);
-                return Err(TextureCreateError::Other(err.to_string()));
-            }
+            let residency = SparseResidency {
+                page_dims: reqs.format_properties.image_granularity,
+                mip_tail_first_lod: reqs.image_mip_tail_first_lod,
+                mip_tail_size: reqs.image_mip_tail_size,
+                mip_tail_offset: reqs.image_mip_tail_offset,
+                mip_tail_stride: reqs.image_mip_tail_stride,
+                single_mip_tail: reqs
+                    .format_properties
+                    .flags
+                    .contains(vk::SparseImageFormatFlags::SINGLE_MIPTAIL),
+                page_requirements: vk::MemoryRequirements {
+                    size: mem_reqs.alignment,
+                    alignment: mem_reqs.alignment,
+                    memory_type_bits: mem_reqs.memory_type_bits,
+                },
+                pages: Mutex::new(FxHashMap::default()),
+                mip_tail: Vec::default(),
+            };
+            (Allocation::default(), Some(residency))
+        } else {
+            (
+                Self::allocate(device, allocator, image, mem_reqs, &create_info)?,
+                None,
+            )
         };
 
-        // Bind image to memory
-        if let Err(err) = device.bind_image_memory(image, block.memory(), block.offset()) {
-            allocator.free(block).unwrap();
-            device.destroy_image(image, None);
-            return Err(TextureCreateError::Other(err.to_string()));
-        }
-
         // Create views
-        let mut views = Vec::with_capacity(create_info.array_elements * create_info.mip_levels);
+        let mut views = Vec::with_capacity(
+            view_formats.len() * create_info.array_elements * create_info.mip_levels,
+        );
         let aspect_flags = if create_info.format.is_color() {
             vk::ImageAspectFlags::COLOR
         } else {
@@ -103,27 +224,47 @@ impl Texture {
                     vk::ImageAspectFlags::empty()
                 }
         };
-        for i in 0..create_info.array_elements {
-            for j in 0..create_info.mip_levels {
-                let view_create_info = vk::ImageViewCreateInfo::builder()
-                    .format(format)
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .subresource_range(vk::ImageSubresourceRange {
-                        aspect_mask: aspect_flags,
-                        base_mip_level: j as u32,
-                        level_count: 1,
-                        base_array_layer: i as u32,
-                        layer_count: 1,
-                    })
-                    .components(vk::ComponentMapping {
-                        r: vk::ComponentSwizzle::R,
-                        g: vk::ComponentSwizzle::G,
-                        b: vk::ComponentSwizzle::B,
-                        a: vk::ComponentSwizzle::A,
-                    })
-                    .image(image)
-                    .build();
-                views.push(device.create_image_view(&view_create_info, None).unwrap());
+        for (f, view_format) in view_formats.iter().enumerate() {
+            for i in 0..create_info.array_elements {
+                for j in 0..create_info.mip_levels {
+                    let mut usage_info = vk::ImageViewUsageCreateInfo::builder()
+                        .usage(view_usages[f])
+                        .build();
+                    let mut view_create_info = vk::ImageViewCreateInfo::builder()
+                        .format(*view_format)
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: aspect_flags,
+                            base_mip_level: j as u32,
+                            level_count: 1,
+                            base_array_layer: i as u32,
+                            layer_count: 1,
+                        })
+                        .components(vk::ComponentMapping {
+                            r: vk::ComponentSwizzle::R,
+                            g: vk::ComponentSwizzle::G,
+                            b: vk::ComponentSwizzle::B,
+                            a: vk::ComponentSwizzle::A,
+                        })
+                        .image(image);
+                    if f != 0 {
+                        view_create_info = view_create_info.push_next(&mut usage_info);
+                    }
+
+                    match device.create_image_view(&view_create_info, None) {
+                        Ok(view) => views.push(view),
+                        Err(err) => {
+                            for view in views {
+                                device.destroy_image_view(view, None);
+                            }
+                            if sparse.is_none() {
+                                let _ = allocator.free(block);
+                            }
+                            device.destroy_image(image, None);
+                            return Err(TextureCreateError::Other(err.to_string()));
+                        }
+                    }
+                }
             }
         }
 
@@ -159,30 +300,229 @@ impl Texture {
         Ok(Texture {
             image,
             views,
+            view_formats,
             block: ManuallyDrop::new(block),
             image_usage: create_info.texture_usage,
             memory_usage: create_info.memory_usage,
+            tiling: create_info.tiling,
             array_elements: create_info.array_elements,
             size: mem_reqs.size,
-            on_drop,
+            on_drop: ctx.garbage.sender(),
             ref_counter: TextureRefCounter::default(),
             format,
             aspect_flags,
             mip_count: create_info.mip_levels as u32,
+            sparse,
         })
     }
+
+    /// Allocates memory for and binds it to a non-sparse image. The image is destroyed on
+    /// failure.
+    unsafe fn allocate(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        image: vk::Image,
+        mem_reqs: vk::MemoryRequirements,
+        create_info: &TextureCreateInfo,
+    ) -> Result<Allocation, TextureCreateError> {
+        // Allocate memory
+        let request = AllocationCreateDesc {
+            name: match &create_info.debug_name {
+                Some(name) => &name,
+                None => "image",
+            },
+            requirements: mem_reqs,
+            location: crate::util::to_gpu_allocator_memory_location(create_info.memory_usage),
+            linear: create_info.tiling == TextureTiling::Linear,
+        };
+
+        let block = match allocator.allocate(&request) {
+            Ok(block) => block,
+            Err(err) => {
+                device.destroy_image(image, None);
+                return Err(TextureCreateError::Other(err.to_string()));
+            }
+        };
+
+        // Bind image to memory
+        if let Err(err) = device.bind_image_memory(image, block.memory(), block.offset()) {
+            allocator.free(block).unwrap();
+            device.destroy_image(image, None);
+            return Err(TextureCreateError::Other(err.to_string()));
+        }
+
+        Ok(block)
+    }
+
+    pub(crate) unsafe fn map(
+        &self,
+        ctx: &VulkanBackend,
+        array_element: usize,
+        mip_level: usize,
+    ) -> Result<(NonNull<u8>, TextureSubresourceLayout), TextureViewError> {
+        // Only linear textures in host visible memory can be mapped
+        if self.tiling != TextureTiling::Linear {
+            return Err(TextureViewError::NotMapable);
+        }
+        match self.memory_usage {
+            MemoryUsage::CpuToGpu | MemoryUsage::GpuToCpu | MemoryUsage::CpuOnly => {}
+            _ => return Err(TextureViewError::NotMapable),
+        }
+
+        // Wait until the last queue that the texture was used in has finished it's work
+        let mut resc_state = ctx.resource_state.write().unwrap();
+        if let Some(old) = resc_state.register_image(self.image, array_element as u32, None) {
+            ctx.wait_on(
+                &Job {
+                    ty: old.queue,
+                    target_value: old.timeline_value,
+                },
+                None,
+            );
+        }
+
+        // The host can only access the texture in the preinitialized or general layouts
+        let old_layout = resc_state.register_layout(
+            self.image,
+            array_element as u32,
+            mip_level as u32,
+            vk::ImageLayout::GENERAL,
+        );
+        match old_layout {
+            vk::ImageLayout::PREINITIALIZED => {
+                resc_state.register_layout(
+                    self.image,
+                    array_element as u32,
+                    mip_level as u32,
+                    old_layout,
+                );
+            }
+            vk::ImageLayout::GENERAL => {}
+            _ => ctx.transition_for_host(
+                self.image,
+                vk::ImageSubresourceRange {
+                    aspect_mask: self.aspect_flags,
+                    base_mip_level: mip_level as u32,
+                    level_count: 1,
+                    base_array_layer: array_element as u32,
+                    layer_count: 1,
+                },
+                old_layout,
+            ),
+        }
+
+        let layout = ctx.device.get_image_subresource_layout(
+            self.image,
+            vk::ImageSubresource {
+                aspect_mask: self.aspect_flags,
+                mip_level: mip_level as u32,
+                array_layer: array_element as u32,
+            },
+        );
+        // Device writes must be made visible to the host
+        ctx.device
+            .invalidate_mapped_memory_ranges(&[self.mapped_range(ctx, layout)])
+            .unwrap();
+
+        let map = self.block.mapped_ptr().unwrap().as_ptr() as *mut u8;
+        let map = NonNull::new_unchecked(map.add(layout.offset as usize));
+        Ok((
+            map,
+            TextureSubresourceLayout {
+                size: layout.size,
+                row_pitch: layout.row_pitch,
+                depth_pitch: layout.depth_pitch,
+            },
+        ))
+    }
+
+    /// Makes host writes to a mapped subresource of the texture visible to the device.
+    pub(crate) unsafe fn flush(&self, ctx: &VulkanBackend, array_element: usize, mip_level: usize) {
+        let layout = ctx.device.get_image_subresource_layout(
+            self.image,
+            vk::ImageSubresource {
+                aspect_mask: self.aspect_flags,
+                mip_level: mip_level as u32,
+                array_layer: array_element as u32,
+            },
+        );
+        ctx.device
+            .flush_mapped_memory_ranges(&[self.mapped_range(ctx, layout)])
+            .unwrap();
+    }
+
+    /// The range of memory holding a subresource, expanded to multiples of the non-coherent atom
+    /// size as required for flushes and invalidations. The memory of linear textures is padded
+    /// at creation, so the expanded range never leaves the allocation.
+    unsafe fn mapped_range(
+        &self,
+        ctx: &VulkanBackend,
+        layout: vk::SubresourceLayout,
+    ) -> vk::MappedMemoryRange {
+        let atom = ctx.properties.limits.non_coherent_atom_size;
+        let start = self.block.offset() + layout.offset;
+        let end = start + layout.size;
+        let aligned_start = start - (start % atom);
+        let aligned_end = (end.div_ceil(atom) * atom).min(self.block.offset() + self.block.size());
+        vk::MappedMemoryRange::builder()
+            .memory(self.block.memory())
+            .offset(aligned_start)
+            .size(aligned_end - aligned_start)
+            .build()
+    }
+
+    /// Gets the index within `views` of the view for the given array element and mip level.
+    #[inline(always)]
+    pub(crate) fn view_index(&self, array_element: usize, mip_level: usize) -> usize {
+        debug_assert!(array_element < self.array_elements);
+        debug_assert!(mip_level < self.mip_count as usize);
+        (array_element * self.mip_count as usize) + mip_level
+    }
+
+    /// Gets the view for the given array element and mip level.
+    #[inline(always)]
+    pub(crate) fn view(&self, array_element: usize, mip_level: usize) -> vk::ImageView {
+        self.views[self.view_index(array_element, mip_level)]
+    }
+
+    /// Gets the view for the given array element and mip level that views the texture as
+    /// `format`, which must be one of the texture's view formats.
+    #[inline(always)]
+    pub(crate) fn view_as(
+        &self,
+        array_element: usize,
+        mip_level: usize,
+        format: vk::Format,
+    ) -> vk::ImageView {
+        let format_idx = self
+            .view_formats
+            .iter()
+            .position(|view_format| *view_format == format)
+            .expect("the texture can't be viewed with the requested format");
+        let views_per_format = self.array_elements * self.mip_count as usize;
+        self.views[(format_idx * views_per_format) + self.view_index(array_element, mip_level)]
+    }
 }
 
 impl Drop for Texture {
     fn drop(&mut self) {
-        self.on_drop
-            .send(Garbage::Texture {
+        dispose(
+            &self.on_drop,
+            Garbage::Texture {
                 image: self.image,
                 views: std::mem::take(&mut self.views),
                 allocation: unsafe { ManuallyDrop::take(&mut self.block) },
+                sparse_memory: match self.sparse.take() {
+                    Some(sparse) => {
+                        let mut memory = sparse.mip_tail;
+                        memory.extend(sparse.pages.into_inner().unwrap().into_values());
+                        memory
+                    }
+                    None => Vec::default(),
+                },
                 ref_counter: self.ref_counter.clone(),
-            })
-            .unwrap();
+            },
+        );
     }
 }
 
@@ -199,3 +539,52 @@ impl Default for TextureRefCounter {
         TextureRefCounter(Arc::new(()))
     }
 }
+
+/// Image usages that apply to image views rather than the image itself.
+const VIEW_USAGES: vk::ImageUsageFlags = vk::ImageUsageFlags::from_raw(
+    vk::ImageUsageFlags::SAMPLED.as_raw()
+        | vk::ImageUsageFlags::STORAGE.as_raw()
+        | vk::ImageUsageFlags::COLOR_ATTACHMENT.as_raw()
+        | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT.as_raw(),
+);
+
+/// Restricts `usage` to the view usages supported by `format` with the given tiling.
+unsafe fn view_usage(
+    ctx: &VulkanBackend,
+    format: vk::Format,
+    tiling: TextureTiling,
+    usage: vk::ImageUsageFlags,
+) -> vk::ImageUsageFlags {
+    let properties = ctx
+        .instance
+        .get_physical_device_format_properties(ctx.physical_device, format);
+    let features = match tiling {
+        TextureTiling::Optimal => properties.optimal_tiling_features,
+        TextureTiling::Linear => properties.linear_tiling_features,
+    };
+
+    let mut out = usage & !VIEW_USAGES;
+    for (view_usage, feature) in [
+        (
+            vk::ImageUsageFlags::SAMPLED,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE,
+        ),
+        (
+            vk::ImageUsageFlags::STORAGE,
+            vk::FormatFeatureFlags::STORAGE_IMAGE,
+        ),
+        (
+            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            vk::FormatFeatureFlags::COLOR_ATTACHMENT,
+        ),
+        (
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        ),
+    ] {
+        if usage.contains(view_usage) && features.contains(feature) {
+            out |= view_usage;
+        }
+    }
+    out
+}
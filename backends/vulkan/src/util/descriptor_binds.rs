@@ -0,0 +1,99 @@
+use ash::vk;
+
+/// Descriptor sets bound to one pipeline bind point of a command buffer. Binds are deferred until
+/// another command might depend on them, so consecutive binds are coalesced into as few
+/// `vkCmdBindDescriptorSets` calls as possible, and sets that are already bound are not bound
+/// again.
+#[derive(Default)]
+pub(crate) struct DescriptorBinds {
+    /// Layout of the most recently bound pipeline.
+    layout: vk::PipelineLayout,
+    /// Sets bound to the command buffer, by set index.
+    bound: Vec<Option<vk::DescriptorSet>>,
+    /// Sets to bind on the next flush, by set index.
+    pending: Vec<Option<vk::DescriptorSet>>,
+}
+
+impl DescriptorBinds {
+    /// Sets the layout of the bound pipeline. If the layout changed, bound sets are forgotten
+    /// since they may have been disturbed.
+    #[inline]
+    pub fn set_layout(&mut self, layout: vk::PipelineLayout) {
+        if self.layout != layout {
+            self.layout = layout;
+            self.bound.clear();
+        }
+    }
+
+    /// Forgets which sets are bound. Used when sets might have been bound without our knowledge.
+    #[inline]
+    pub fn invalidate(&mut self) {
+        self.bound.clear();
+    }
+
+    /// Queues sets to be bound starting at set index `first`.
+    pub fn bind(&mut self, first: usize, sets: impl Iterator<Item = vk::DescriptorSet>) {
+        for (i, set) in sets.enumerate() {
+            let idx = first + i;
+            if self.pending.len() <= idx {
+                self.pending.resize(idx + 1, None);
+            }
+            self.pending[idx] = Some(set);
+        }
+    }
+
+    /// Records every pending bind that isn't already bound, using one call per contiguous range
+    /// of sets.
+    pub unsafe fn flush(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        layout: vk::PipelineLayout,
+    ) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let mut sets = Vec::with_capacity(self.pending.len());
+        let mut i = 0;
+        while i < self.pending.len() {
+            if !self.needs_bind(i) {
+                i += 1;
+                continue;
+            }
+
+            let first = i;
+            sets.clear();
+            while i < self.pending.len() && self.needs_bind(i) {
+                sets.push(self.pending[i].unwrap());
+                i += 1;
+            }
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                bind_point,
+                layout,
+                first as u32,
+                &sets,
+                &[],
+            );
+        }
+
+        if self.bound.len() < self.pending.len() {
+            self.bound.resize(self.pending.len(), None);
+        }
+        for (i, set) in self.pending.drain(..).enumerate() {
+            if set.is_some() {
+                self.bound[i] = set;
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn needs_bind(&self, idx: usize) -> bool {
+        match self.pending[idx] {
+            Some(set) => self.bound.get(idx).copied().flatten() != Some(set),
+            None => false,
+        }
+    }
+}
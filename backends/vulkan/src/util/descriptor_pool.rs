@@ -22,8 +22,9 @@ pub(crate) struct DescriptorPool {
     pools: Vec<vk::DescriptorPool>,
     /// Current number of sets allocated from the top pool.
     size: usize,
-    /// Free list of descriptor sets.
-    free: Vec<vk::DescriptorSet>,
+    /// Free list of descriptor sets, along with the number of descriptors in their variable count
+    /// binding (`0` if the layout has none).
+    free: Vec<(vk::DescriptorSet, u32)>,
     /// Pool sizes to use when making a new descriptor pool.
     sizes: Vec<vk::DescriptorPoolSize>,
 }
@@ -78,8 +79,35 @@ impl DescriptorPool {
             );
         }
 
+        // The variable count binding must be the one with the highest binding number
+        let variable_binding = if create_info.variable_count {
+            create_info
+                .bindings
+                .iter()
+                .map(|binding| binding.binding)
+                .max()
+        } else {
+            None
+        };
+        let binding_flags = create_info
+            .bindings
+            .iter()
+            .map(|binding| {
+                if Some(binding.binding) == variable_binding {
+                    vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+                        | vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                } else {
+                    vk::DescriptorBindingFlags::empty()
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+            .binding_flags(&binding_flags)
+            .build();
+
         let create_info = vk::DescriptorSetLayoutCreateInfo::builder()
             .bindings(&bindings)
+            .push_next(&mut binding_flags_info)
             .build();
 
         // Create the layout
@@ -120,39 +148,78 @@ impl DescriptorPool {
         self.layout[0]
     }
 
+    /// Returns a set to the free list.
+    ///
+    /// # Arguments
+    /// - `set` - The set to free.
+    /// - `variable_count` - The number of descriptors the set was allocated with in its variable
+    /// count binding, or `0` if the layout has none.
     #[inline]
-    pub fn free(&mut self, set: vk::DescriptorSet) {
-        self.free.push(set);
+    pub fn free(&mut self, set: vk::DescriptorSet, variable_count: u32) {
+        self.free.push((set, variable_count));
     }
 
+    /// Allocates a set, reusing a free set when possible.
+    ///
+    /// # Arguments
+    /// - `variable_count` - The number of descriptors in the variable count binding of the
+    /// layout, or `None` if the layout has none. Free sets are only reused if they have at least
+    /// this many descriptors.
     pub unsafe fn allocate(
         &mut self,
         device: &ash::Device,
         debug: Option<&ash::extensions::ext::DebugUtils>,
         name: Option<String>,
-    ) -> vk::DescriptorSet {
-        let set = match self.free.pop() {
-            Some(free) => free,
+        variable_count: Option<u32>,
+    ) -> (vk::DescriptorSet, u32) {
+        let reusable = self
+            .free
+            .iter()
+            .rposition(|(_, count)| *count >= variable_count.unwrap_or(0));
+        let set = match reusable {
+            Some(i) => self.free.swap_remove(i),
             None => {
+                let counts = [variable_count.unwrap_or(0)];
+                let mut variable_count_info =
+                    vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+                        .descriptor_counts(&counts)
+                        .build();
+
                 // Allocate a new pool if required
                 if self.size == 0 {
-                    self.size = SETS_PER_POOL;
-                    self.pools.push({
-                        let create_info = vk::DescriptorPoolCreateInfo::builder()
-                            .max_sets(self.size as u32)
-                            .pool_sizes(&self.sizes)
-                            .build();
-                        device.create_descriptor_pool(&create_info, None).unwrap()
-                    });
+                    self.push_pool(device);
                 }
 
                 // Allocate new set
                 self.size -= 1;
-                let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                let mut alloc_info = vk::DescriptorSetAllocateInfo::builder()
                     .descriptor_pool(*self.pools.last().unwrap())
-                    .set_layouts(&self.layout)
-                    .build();
-                device.allocate_descriptor_sets(&alloc_info).unwrap()[0]
+                    .set_layouts(&self.layout);
+                if variable_count.is_some() {
+                    alloc_info = alloc_info.push_next(&mut variable_count_info);
+                }
+                let alloc_info = alloc_info.build();
+                let set = match device.allocate_descriptor_sets(&alloc_info) {
+                    Ok(sets) => sets[0],
+                    // The top pool is exhausted even though we haven't hit the set limit, so
+                    // make a new pool and try again
+                    Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+                    | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                        self.push_pool(device);
+                        self.size -= 1;
+                        let mut alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                            .descriptor_pool(*self.pools.last().unwrap())
+                            .set_layouts(&self.layout);
+                        if variable_count.is_some() {
+                            alloc_info = alloc_info.push_next(&mut variable_count_info);
+                        }
+                        device
+                            .allocate_descriptor_sets(&alloc_info.build())
+                            .unwrap()[0]
+                    }
+                    Err(err) => panic!("unable to allocate descriptor set: {}", err),
+                };
+                (set, counts[0])
             }
         };
 
@@ -162,7 +229,7 @@ impl DescriptorPool {
                 let name = CString::new(name).unwrap();
                 let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
                     .object_type(vk::ObjectType::DESCRIPTOR_SET)
-                    .object_handle(set.as_raw())
+                    .object_handle(set.0.as_raw())
                     .object_name(&name)
                     .build();
 
@@ -175,6 +242,17 @@ impl DescriptorPool {
         set
     }
 
+    /// Creates a new descriptor pool and makes it the top pool to allocate from.
+    unsafe fn push_pool(&mut self, device: &ash::Device) {
+        self.size = SETS_PER_POOL;
+        let create_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(self.size as u32)
+            .pool_sizes(&self.sizes)
+            .build();
+        self.pools
+            .push(device.create_descriptor_pool(&create_info, None).unwrap());
+    }
+
     pub unsafe fn release(&mut self, device: &ash::Device) {
         for pool in self.pools.drain(..) {
             device.destroy_descriptor_pool(pool, None);
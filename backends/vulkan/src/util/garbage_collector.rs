@@ -1,11 +1,12 @@
 use std::{
     collections::HashMap,
     sync::{
-        atomic::{AtomicU32, Ordering},
+        atomic::{AtomicU32, AtomicU64, Ordering},
         Mutex,
     },
 };
 
+use api::types::QueueType;
 use ash::vk;
 use crossbeam_channel::{Receiver, Sender};
 use gpu_allocator::vulkan::{Allocation, Allocator};
@@ -22,15 +23,44 @@ use super::{
 
 pub(crate) struct GarbageCollector {
     sender: Sender<Garbage>,
-    receiver: Receiver<Garbage>,
     garbage_id: AtomicU32,
     to_destroy: Mutex<FIHashMap<u32, ToDestroy>>,
     marked: Mutex<Vec<u32>>,
+    callbacks: Mutex<Vec<JobCallback>>,
+    /// The number of frames that have been presented.
+    frame: AtomicU64,
+    /// The minimum number of frames garbage must be held for before it is destroyed.
+    frames_in_flight: AtomicU64,
+    /// Declared last so that it is dropped after anything above that might still send garbage.
+    receiver: Receiver<Garbage>,
+}
+
+/// A function to call once the job it was registered with has completed.
+pub(crate) struct JobCallback {
+    pub ty: QueueType,
+    pub target_value: u64,
+    pub callback: Box<dyn FnOnce() + Send>,
+}
+
+/// Sends garbage to the collector.
+///
+/// Resources hold a reference to the backend through their context, so the collector normally
+/// outlives them. If it doesn't (e.g. an internal object is released while the backend is being
+/// dropped), the device is already being destroyed and the garbage is leaked instead of panicking
+/// on the closed channel.
+#[inline(always)]
+pub(crate) fn dispose(sender: &Sender<Garbage>, garbage: Garbage) {
+    let _ = sender.send(garbage);
 }
 
 pub(crate) enum Garbage {
     PipelineLayout(vk::PipelineLayout),
     Pipeline(vk::Pipeline),
+    QueryPool(vk::QueryPool),
+    /// Destroying the pool also frees the command buffers allocated from it.
+    CommandPool(vk::CommandPool),
+    /// Memory that was unbound from a sparse texture.
+    Memory(Allocation),
     Buffer {
         buffer: vk::Buffer,
         allocation: Allocation,
@@ -40,12 +70,15 @@ pub(crate) enum Garbage {
         image: vk::Image,
         views: Vec<vk::ImageView>,
         allocation: Allocation,
+        /// Memory bound to the pages of a sparse texture.
+        sparse_memory: Vec<Allocation>,
         ref_counter: TextureRefCounter,
     },
     DescriptorSet {
         set: vk::DescriptorSet,
         layout: vk::DescriptorSetLayout,
         bindings: Vec<Vec<Option<Binding>>>,
+        variable_count: u32,
     },
 }
 
@@ -54,11 +87,26 @@ pub(crate) struct TimelineValues {
     pub main: u64,
     pub transfer: u64,
     pub compute: u64,
+    pub present: u64,
+}
+
+impl TimelineValues {
+    #[inline(always)]
+    pub fn get(&self, ty: QueueType) -> u64 {
+        match ty {
+            QueueType::Main => self.main,
+            QueueType::Transfer => self.transfer,
+            QueueType::Compute => self.compute,
+            QueueType::Present => self.present,
+        }
+    }
 }
 
 struct ToDestroy {
     garbage: Garbage,
     values: TimelineValues,
+    /// The frame the garbage was received in.
+    frame: u64,
 }
 
 impl GarbageCollector {
@@ -70,7 +118,49 @@ impl GarbageCollector {
             to_destroy: Mutex::new(HashMap::default()),
             garbage_id: AtomicU32::new(0),
             marked: Mutex::new(Vec::default()),
+            callbacks: Mutex::new(Vec::default()),
+            frame: AtomicU64::new(0),
+            frames_in_flight: AtomicU64::new(0),
+        }
+    }
+
+    /// Signals that a frame has been presented.
+    #[inline(always)]
+    pub fn next_frame(&self) {
+        self.frame.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of frames that have been presented.
+    #[inline(always)]
+    pub fn frame(&self) -> u64 {
+        self.frame.load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    pub fn set_frames_in_flight(&self, frames_in_flight: u64) {
+        self.frames_in_flight
+            .store(frames_in_flight, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub fn add_callback(&self, callback: JobCallback) {
+        self.callbacks.lock().unwrap().push(callback);
+    }
+
+    /// Removes and returns the callbacks whose jobs have completed. The callbacks are returned
+    /// instead of called so that the caller can call them without holding any locks.
+    pub fn completed_callbacks(&self, current: TimelineValues) -> Vec<Box<dyn FnOnce() + Send>> {
+        let mut callbacks = self.callbacks.lock().unwrap();
+        let mut completed = Vec::default();
+        let mut i = 0;
+        while i < callbacks.len() {
+            if callbacks[i].target_value <= current.get(callbacks[i].ty) {
+                completed.push(callbacks.swap_remove(i).callback);
+            } else {
+                i += 1;
+            }
         }
+        completed
     }
 
     pub fn sender(&self) -> Sender<Garbage> {
@@ -87,13 +177,14 @@ impl GarbageCollector {
         target: TimelineValues,
     ) {
         loop {
-            self.cleanup(device, allocator, pools, pipelines, current, target);
+            self.cleanup_inner(device, allocator, pools, pipelines, current, target, true);
             if self.to_destroy.lock().unwrap().is_empty() {
                 break;
             }
         }
     }
 
+    #[inline(always)]
     pub unsafe fn cleanup(
         &self,
         device: &ash::Device,
@@ -103,6 +194,29 @@ impl GarbageCollector {
         current: TimelineValues,
         target: TimelineValues,
     ) {
+        self.cleanup_inner(device, allocator, pools, pipelines, current, target, false);
+    }
+
+    /// Destroys garbage that is no longer in use. If `ignore_frames` is set, garbage is destroyed
+    /// without waiting for the requested number of frames in flight to pass.
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn cleanup_inner(
+        &self,
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        pools: &mut DescriptorPools,
+        pipelines: &mut PipelineCache,
+        current: TimelineValues,
+        target: TimelineValues,
+        ignore_frames: bool,
+    ) {
+        let frame = self.frame.load(Ordering::Relaxed);
+        let frames_in_flight = if ignore_frames {
+            0
+        } else {
+            self.frames_in_flight.load(Ordering::Relaxed)
+        };
+
         // Receive all incoming garbage
         let mut to_destroy = self.to_destroy.lock().unwrap();
         while let Ok(garbage) = self.receiver.try_recv() {
@@ -112,6 +226,7 @@ impl GarbageCollector {
                 ToDestroy {
                     garbage,
                     values: target,
+                    frame,
                 },
             );
         }
@@ -134,9 +249,14 @@ impl GarbageCollector {
                 _ => {}
             }
 
+            if garbage.frame + frames_in_flight > frame {
+                continue;
+            }
+
             if garbage.values.main <= current.main
                 && garbage.values.transfer <= current.transfer
                 && garbage.values.compute <= current.compute
+                && garbage.values.present <= current.present
             {
                 marked.push(*id);
             }
@@ -153,16 +273,26 @@ impl GarbageCollector {
                 Garbage::Pipeline(pipeline) => {
                     device.destroy_pipeline(pipeline, None);
                 }
+                Garbage::QueryPool(pool) => {
+                    device.destroy_query_pool(pool, None);
+                }
+                Garbage::CommandPool(pool) => {
+                    device.destroy_command_pool(pool, None);
+                }
                 Garbage::Buffer {
                     buffer, allocation, ..
                 } => {
                     device.destroy_buffer(buffer, None);
                     allocator.free(allocation).unwrap();
                 }
+                Garbage::Memory(allocation) => {
+                    allocator.free(allocation).unwrap();
+                }
                 Garbage::Texture {
                     image,
                     views,
                     allocation,
+                    sparse_memory,
                     ..
                 } => {
                     device.destroy_image(image, None);
@@ -170,13 +300,20 @@ impl GarbageCollector {
                         device.destroy_image_view(view, None);
                     }
                     allocator.free(allocation).unwrap();
+                    for allocation in sparse_memory {
+                        allocator.free(allocation).unwrap();
+                    }
                 }
                 Garbage::DescriptorSet {
                     set,
                     layout,
                     bindings,
+                    variable_count,
                 } => {
-                    pools.get_by_layout(layout).unwrap().free(set);
+                    pools
+                        .get_by_layout(layout)
+                        .unwrap()
+                        .free(set, variable_count);
                     for binding in bindings {
                         for element in binding {
                             if let Some(element) = element {
@@ -2,6 +2,7 @@ use api::{descriptor_set::DescriptorType, types::*};
 use ash::vk;
 use gpu_allocator::MemoryLocation;
 
+pub mod descriptor_binds;
 pub mod descriptor_pool;
 pub mod fast_int_hasher;
 pub mod garbage_collector;
@@ -12,6 +13,28 @@ pub mod tracking;
 
 pub mod usage;
 
+/// Layout used when the depth aspect of an image is only read from. If the image has a stencil
+/// aspect, it remains writable as an attachment, so the depth aspect can be sampled while the
+/// stencil aspect is used in a render pass.
+#[inline(always)]
+pub(crate) fn depth_read_only_layout(aspect_flags: vk::ImageAspectFlags) -> vk::ImageLayout {
+    if aspect_flags.contains(vk::ImageAspectFlags::STENCIL) {
+        vk::ImageLayout::DEPTH_READ_ONLY_STENCIL_ATTACHMENT_OPTIMAL
+    } else {
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+    }
+}
+
+/// Layout used when an image with the given aspects is sampled in a shader.
+#[inline(always)]
+pub(crate) fn sampled_layout(aspect_flags: vk::ImageAspectFlags) -> vk::ImageLayout {
+    if aspect_flags.contains(vk::ImageAspectFlags::DEPTH) {
+        depth_read_only_layout(aspect_flags)
+    } else {
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    }
+}
+
 #[inline(always)]
 pub(crate) fn rank_pipeline_stage(stage: vk::PipelineStageFlags) -> u32 {
     match stage {
@@ -43,12 +66,38 @@ pub(crate) fn to_vk_present_mode(present_mode: PresentMode) -> vk::PresentModeKH
     }
 }
 
+#[inline(always)]
+pub(crate) fn from_vk_color_space(color_space: vk::ColorSpaceKHR) -> ColorSpace {
+    match color_space {
+        vk::ColorSpaceKHR::SRGB_NONLINEAR => ColorSpace::SrgbNonLinear,
+        vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => ColorSpace::ExtendedSrgbLinear,
+        vk::ColorSpaceKHR::EXTENDED_SRGB_NONLINEAR_EXT => ColorSpace::ExtendedSrgbNonLinear,
+        vk::ColorSpaceKHR::DISPLAY_P3_LINEAR_EXT => ColorSpace::DisplayP3Linear,
+        vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT => ColorSpace::DisplayP3NonLinear,
+        vk::ColorSpaceKHR::DCI_P3_NONLINEAR_EXT => ColorSpace::DciP3NonLinear,
+        vk::ColorSpaceKHR::BT709_LINEAR_EXT => ColorSpace::Bt709Linear,
+        vk::ColorSpaceKHR::BT709_NONLINEAR_EXT => ColorSpace::Bt709NonLinear,
+        vk::ColorSpaceKHR::BT2020_LINEAR_EXT => ColorSpace::Bt2020Linear,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT => ColorSpace::Hdr10St2084,
+        vk::ColorSpaceKHR::HDR10_HLG_EXT => ColorSpace::Hdr10Hlg,
+        vk::ColorSpaceKHR::DOLBYVISION_EXT => ColorSpace::DolbyVision,
+        vk::ColorSpaceKHR::ADOBERGB_LINEAR_EXT => ColorSpace::AdobeRgbLinear,
+        vk::ColorSpaceKHR::ADOBERGB_NONLINEAR_EXT => ColorSpace::AdobeRgbNonLinear,
+        vk::ColorSpaceKHR::PASS_THROUGH_EXT => ColorSpace::PassThrough,
+        vk::ColorSpaceKHR::DISPLAY_NATIVE_AMD => ColorSpace::DisplayNative,
+        _ => ColorSpace::Other,
+    }
+}
+
 #[inline(always)]
 pub(crate) fn to_vk_format(format: TextureFormat) -> vk::Format {
     match format {
         TextureFormat::R8Unorm => vk::Format::R8_UNORM,
         TextureFormat::Rgba8Unorm => vk::Format::R8G8B8A8_UNORM,
+        TextureFormat::Rgba8Srgb => vk::Format::R8G8B8A8_SRGB,
         TextureFormat::Bgra8Unorm => vk::Format::B8G8R8A8_UNORM,
+        TextureFormat::Bgra8Srgb => vk::Format::B8G8R8A8_SRGB,
+        TextureFormat::Rg11B10Float => vk::Format::B10G11R11_UFLOAT_PACK32,
         TextureFormat::D16Unorm => vk::Format::D16_UNORM,
         TextureFormat::D24UnormS8Uint => vk::Format::D24_UNORM_S8_UINT,
         TextureFormat::D32Sfloat => vk::Format::D32_SFLOAT,
@@ -107,6 +156,15 @@ pub(crate) fn to_vk_vertex_format(format: VertexFormat) -> vk::Format {
     }
 }
 
+#[inline(always)]
+pub(crate) fn to_vk_aspect_flags(aspect: TextureAspect) -> vk::ImageAspectFlags {
+    match aspect {
+        TextureAspect::Color => vk::ImageAspectFlags::COLOR,
+        TextureAspect::Depth => vk::ImageAspectFlags::DEPTH,
+        TextureAspect::Stencil => vk::ImageAspectFlags::STENCIL,
+    }
+}
+
 #[inline(always)]
 pub(crate) fn to_vk_shader_stage(ss: ShaderStage) -> vk::ShaderStageFlags {
     match ss {
@@ -126,6 +184,39 @@ pub(crate) fn to_vk_topology(top: PrimitiveTopology) -> vk::PrimitiveTopology {
     }
 }
 
+/// # Panics
+/// - If `cc` is a depth clear value.
+#[inline(always)]
+pub(crate) fn to_vk_clear_color(cc: ClearColor) -> vk::ClearColorValue {
+    match cc {
+        ClearColor::RgbaF32(r, g, b, a) => vk::ClearColorValue {
+            float32: [r, g, b, a],
+        },
+        ClearColor::RU32(r) => vk::ClearColorValue {
+            uint32: [r, 0, 0, 0],
+        },
+        _ => panic!("invalid color clear color type"),
+    }
+}
+
+/// # Panics
+/// - If `cc` is not a depth clear value.
+#[inline(always)]
+#[allow(deprecated)]
+pub(crate) fn to_vk_clear_depth_stencil(cc: ClearColor) -> vk::ClearDepthStencilValue {
+    let (depth, stencil) = match cc {
+        ClearColor::Depth(d) => (d, 0),
+        ClearColor::DepthStencil(d, s) | ClearColor::D32S32(d, s) => (d, s),
+        _ => panic!("invalid depth clear color"),
+    };
+
+    // Stencil aspects are 8 bits. Depth only formats ignore the stencil value.
+    vk::ClearDepthStencilValue {
+        depth,
+        stencil: stencil & 0xFF,
+    }
+}
+
 #[inline(always)]
 pub(crate) fn to_vk_cull_mode(cm: CullMode) -> vk::CullModeFlags {
     match cm {
@@ -288,6 +379,31 @@ pub(crate) fn to_vk_image_usage(iu: TextureUsage) -> vk::ImageUsageFlags {
     out
 }
 
+/// Gets the format features required to create an image with the given usage.
+#[inline(always)]
+pub(crate) fn to_vk_format_features(iu: TextureUsage) -> vk::FormatFeatureFlags {
+    let mut out = vk::FormatFeatureFlags::default();
+    if iu.contains(TextureUsage::TRANSFER_SRC) {
+        out |= vk::FormatFeatureFlags::TRANSFER_SRC;
+    }
+    if iu.contains(TextureUsage::TRANSFER_DST) {
+        out |= vk::FormatFeatureFlags::TRANSFER_DST;
+    }
+    if iu.contains(TextureUsage::SAMPLED) {
+        out |= vk::FormatFeatureFlags::SAMPLED_IMAGE;
+    }
+    if iu.contains(TextureUsage::STORAGE) {
+        out |= vk::FormatFeatureFlags::STORAGE_IMAGE;
+    }
+    if iu.contains(TextureUsage::COLOR_ATTACHMENT) {
+        out |= vk::FormatFeatureFlags::COLOR_ATTACHMENT;
+    }
+    if iu.contains(TextureUsage::DEPTH_STENCIL_ATTACHMENT) {
+        out |= vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT;
+    }
+    out
+}
+
 #[inline(always)]
 pub(crate) fn to_vk_image_type(it: TextureType) -> vk::ImageType {
     match it {
@@ -297,6 +413,48 @@ pub(crate) fn to_vk_image_type(it: TextureType) -> vk::ImageType {
     }
 }
 
+#[inline(always)]
+pub(crate) fn to_vk_image_tiling(tiling: TextureTiling) -> vk::ImageTiling {
+    match tiling {
+        TextureTiling::Optimal => vk::ImageTiling::OPTIMAL,
+        TextureTiling::Linear => vk::ImageTiling::LINEAR,
+    }
+}
+
+/// Returns the message severities at least as severe as `severity`.
+#[inline(always)]
+pub(crate) fn to_vk_message_severity(
+    severity: ValidationSeverity,
+) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+    let mut flags = vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
+    if severity <= ValidationSeverity::Warning {
+        flags |= vk::DebugUtilsMessageSeverityFlagsEXT::WARNING;
+    }
+    if severity <= ValidationSeverity::Info {
+        flags |= vk::DebugUtilsMessageSeverityFlagsEXT::INFO;
+    }
+    if severity <= ValidationSeverity::Verbose {
+        flags |= vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE;
+    }
+    flags
+}
+
+/// Returns the sharing mode and the queue families that must be provided with it. Concurrent
+/// sharing requires at least two unique queue families, so exclusive sharing is used when every
+/// queue belongs to the same family.
+#[inline(always)]
+pub(crate) fn to_vk_sharing_mode(
+    sm: SharingMode,
+    queue_families: &[u32],
+) -> (vk::SharingMode, &[u32]) {
+    match sm {
+        SharingMode::Concurrent if queue_families.len() > 1 => {
+            (vk::SharingMode::CONCURRENT, queue_families)
+        }
+        _ => (vk::SharingMode::EXCLUSIVE, &[]),
+    }
+}
+
 #[inline(always)]
 pub(crate) fn to_gpu_allocator_memory_location(mu: MemoryUsage) -> MemoryLocation {
     match mu {
@@ -304,5 +462,8 @@ pub(crate) fn to_gpu_allocator_memory_location(mu: MemoryUsage) -> MemoryLocatio
         MemoryUsage::GpuOnly => MemoryLocation::GpuOnly,
         MemoryUsage::CpuToGpu => MemoryLocation::CpuToGpu,
         MemoryUsage::GpuToCpu => MemoryLocation::GpuToCpu,
+        // Host cached memory always lives in system memory. If there is none, the allocator
+        // falls back to any host visible memory.
+        MemoryUsage::CpuOnly => MemoryLocation::GpuToCpu,
     }
 }
@@ -3,11 +3,30 @@ use ash::vk;
 
 #[derive(Default)]
 pub(crate) struct PipelineCache {
-    /// Given a pipeline layout and render pass, produces a unique matching pipeline.
-    pipelines: FIHashMap<vk::PipelineLayout, FIHashMap<vk::RenderPass, vk::Pipeline>>,
+    /// Given a pipeline layout and render pass, produces a unique matching pipeline. Only the first
+    /// render pass of each compatibility class is used as a key (see
+    /// [`RenderPassCache::compatible`](crate::render_pass::RenderPassCache::compatible)).
+    pipelines: FIHashMap<vk::PipelineLayout, FIHashMap<vk::RenderPass, CachedPipeline>>,
+    /// The frame recorded as the last use of pipelines returned by [`get`](PipelineCache::get).
+    use_frame: u64,
+}
+
+struct CachedPipeline {
+    pipeline: vk::Pipeline,
+    /// The last frame the pipeline was used in. `u64::MAX` if the pipeline must never be trimmed.
+    last_used: u64,
 }
 
 impl PipelineCache {
+    /// Sets the frame recorded as the last use of pipelines returned by
+    /// [`get`](PipelineCache::get) and [`insert`](PipelineCache::insert). Pipelines used while
+    /// this is `u64::MAX` are never [`trimmed`](PipelineCache::trim), which is used for commands
+    /// that can be resubmitted at any time.
+    #[inline(always)]
+    pub fn set_use_frame(&mut self, frame: u64) {
+        self.use_frame = frame;
+    }
+
     #[inline(always)]
     pub fn count(&self, layout: vk::PipelineLayout) -> usize {
         match self.pipelines.get(&layout) {
@@ -17,11 +36,14 @@ impl PipelineCache {
     }
 
     #[inline(always)]
-    pub fn get(&self, layout: vk::PipelineLayout, pass: vk::RenderPass) -> Option<vk::Pipeline> {
-        match self.pipelines.get(&layout) {
-            Some(passes) => passes.get(&pass).map(|p| *p),
-            None => None,
-        }
+    pub fn get(
+        &mut self,
+        layout: vk::PipelineLayout,
+        pass: vk::RenderPass,
+    ) -> Option<vk::Pipeline> {
+        let cached = self.pipelines.get_mut(&layout)?.get_mut(&pass)?;
+        cached.last_used = cached.last_used.max(self.use_frame);
+        Some(cached.pipeline)
     }
 
     #[inline(always)]
@@ -31,26 +53,43 @@ impl PipelineCache {
         pass: vk::RenderPass,
         pipeline: vk::Pipeline,
     ) {
-        *self
-            .pipelines
-            .entry(layout)
-            .or_default()
-            .entry(pass)
-            .or_default() = pipeline;
+        self.pipelines.entry(layout).or_default().insert(
+            pass,
+            CachedPipeline {
+                pipeline,
+                last_used: self.use_frame,
+            },
+        );
+    }
+
+    /// Removes and returns every pipeline that hasn't been used in the given frame or after.
+    /// The returned pipelines *may* still be in use by the GPU.
+    pub fn trim(&mut self, oldest_frame: u64) -> Vec<vk::Pipeline> {
+        let mut trimmed = Vec::default();
+        for passes in self.pipelines.values_mut() {
+            passes.retain(|_, cached| {
+                let keep = cached.last_used >= oldest_frame;
+                if !keep {
+                    trimmed.push(cached.pipeline);
+                }
+                keep
+            });
+        }
+        trimmed
     }
 
     pub unsafe fn release(&mut self, device: &ash::Device, layout: vk::PipelineLayout) {
         if let Some(mut passes) = self.pipelines.remove(&layout) {
-            for (_, pipeline) in passes.drain() {
-                device.destroy_pipeline(pipeline, None);
+            for (_, cached) in passes.drain() {
+                device.destroy_pipeline(cached.pipeline, None);
             }
         }
     }
 
     pub unsafe fn release_all(&mut self, device: &ash::Device) {
         for (_, mut passes) in self.pipelines.drain() {
-            for (_, pipeline) in passes.drain() {
-                device.destroy_pipeline(pipeline, None);
+            for (_, cached) in passes.drain() {
+                device.destroy_pipeline(cached.pipeline, None);
             }
         }
     }
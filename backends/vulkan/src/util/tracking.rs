@@ -1,6 +1,6 @@
 use api::{
     buffer::Buffer,
-    command_buffer::{BufferTextureCopy, Command, CopyBufferToBuffer},
+    command_buffer::{BufferCopyRegion, BufferTextureCopy, Command, CopyBufferToBuffer},
     descriptor_set::DescriptorSet,
     render_pass::{ColorAttachmentSource, RenderPassDescriptor},
     texture::Texture,
@@ -16,7 +16,7 @@ use ash::vk;
 pub(crate) struct TrackState<'a, 'b> {
     pub device: &'a ash::Device,
     pub command_buffer: vk::CommandBuffer,
-    /// Index of the command to detect the resources of.
+    /// Index of the first command to detect the resources of.
     pub index: usize,
     /// Command list with all commands of a submit.
     pub commands: &'a [Command<'a, crate::VulkanBackend>],
@@ -28,32 +28,85 @@ pub(crate) struct TrackState<'a, 'b> {
 
 /// Given the index of a command in a command list, tracks resources based off the type of
 /// detected command.
-pub(crate) unsafe fn track_resources(mut state: TrackState) {
-    match &state.commands[state.index] {
-        Command::BeginRenderPass(descriptor) => track_render_pass(&mut state, descriptor),
-        Command::Dispatch(_, _, _) => track_dispatch(&mut state),
-        Command::CopyBufferToBuffer(copy_info) => {
-            track_buffer_to_buffer_copy(&mut state, copy_info)
+///
+/// The usage scopes of the following commands are merged into the scope of the command as long
+/// as they don't depend on each other, so that a single pipeline barrier is emitted for the whole
+/// batch. Returns the index of the first command not covered by the batch.
+pub(crate) unsafe fn track_resources(mut state: TrackState) -> usize {
+    let start = state.index;
+    let mut scope = match command_scope(&mut state, start) {
+        Some(scope) => state.pipeline_tracker.resolve_aliases(scope),
+        None => return start + 1,
+    };
+
+    let mut end = start + 1;
+    while end < state.commands.len() {
+        // Mipmap generation and surface image blits insert their own barriers, so they must not
+        // be batched
+        if let Command::GenerateMipmaps { .. } | Command::BlitToSurfaceImage { .. } =
+            &state.commands[end]
+        {
+            break;
+        }
+
+        if let Some(next) = command_scope(&mut state, end) {
+            let next = state.pipeline_tracker.resolve_aliases(next);
+            if scope.conflicts(&next) {
+                break;
+            }
+            scope.merge(next);
+        }
+        end += 1;
+    }
+
+    if let Some(barrier) = state.pipeline_tracker.submit(scope) {
+        barrier.execute(state.device, state.command_buffer);
+    }
+
+    end
+}
+
+/// Determines the resources used by a single command. Returns `None` if the command does not
+/// need state tracking.
+unsafe fn command_scope(state: &mut TrackState, index: usize) -> Option<UsageScope> {
+    let commands = state.commands;
+    match &commands[index] {
+        Command::BeginRenderPass(descriptor) => Some(track_render_pass(state, index, descriptor)),
+        Command::Dispatch(_, _, _) | Command::DispatchIndirect { .. } => {
+            track_dispatch(commands, index)
+        }
+        Command::CopyBufferToBuffer(copy_info) => Some(track_buffer_to_buffer_copy(copy_info)),
+        Command::CopyBufferRegions { src, dst, regions } => {
+            Some(track_buffer_regions_copy(src, dst, regions))
         }
         Command::CopyBufferToTexture {
             buffer,
             texture,
             copy,
-        } => track_buffer_to_texture_copy(&mut state, buffer, texture, copy),
+        } => Some(track_buffer_to_texture_copy(buffer, texture, copy)),
+        // Every copy of the batch is in the same scope, so a single barrier covers them all
+        Command::CopyBufferToTextureBatch(copies) => {
+            let mut scope = UsageScope::default();
+            for (texture, buffer, copy) in copies {
+                scope.merge(track_buffer_to_texture_copy(buffer, texture, copy));
+            }
+            Some(scope)
+        }
         Command::CopyTextureToBuffer {
             buffer,
             texture,
             copy,
-        } => track_texture_to_buffer_copy(&mut state, buffer, texture, copy),
+        } => Some(track_texture_to_buffer_copy(buffer, texture, copy)),
         // All other commands do not need state tracking
-        _ => {}
+        _ => None,
     }
 }
 
 unsafe fn track_render_pass(
     state: &mut TrackState,
+    index: usize,
     descriptor: &RenderPassDescriptor<'_, crate::VulkanBackend>,
-) {
+) -> UsageScope {
     let mut scope = UsageScope::default();
 
     // Track color attachments used in the pass
@@ -84,6 +137,7 @@ unsafe fn track_render_pass(
                 texture,
                 array_element,
                 mip_level,
+                ..
             } => SubResource::Texture {
                 texture: texture.internal().image,
                 aspect_mask: vk::ImageAspectFlags::COLOR,
@@ -102,9 +156,20 @@ unsafe fn track_render_pass(
         );
     }
 
-    // Track depth stencil attachment
+    // Track depth stencil attachment. Read-only depth attachments are only written to if they
+    // have a writable stencil aspect
     if let Some(attachment) = &descriptor.depth_stencil_attachment {
         let internal = attachment.texture.internal();
+        let access = if attachment.read_only_depth
+            && !internal
+                .aspect_flags
+                .contains(vk::ImageAspectFlags::STENCIL)
+        {
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+        } else {
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+        };
         scope.use_resource(
             SubResource::Texture {
                 texture: internal.image,
@@ -113,17 +178,19 @@ unsafe fn track_render_pass(
                 mip_level: attachment.mip_level as u32,
             },
             SubResourceUsage {
-                access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                access,
                 stage: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
                     | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
-                layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                layout: crate::render_pass::depth_stencil_attachment_layout(attachment),
             },
         );
     }
 
-    // Track everything else
-    for command in &state.commands[state.index..] {
+    // Track everything else. Draws only read the state bound by earlier commands in the pass,
+    // so tracking the binds covers both indexed and non-indexed draws. Because the whole pass is
+    // scanned, a non-indexed `Draw` that reads a vertex buffer written before the pass gets its
+    // barrier before the pass begins.
+    for command in &state.commands[index..] {
         match command {
             Command::BindVertexBuffers { binds, .. } => {
                 for bind in binds {
@@ -160,22 +227,122 @@ unsafe fn track_render_pass(
             Command::BindDescriptorSets { sets, .. } => {
                 track_descriptor_sets(sets, &mut scope);
             }
+            Command::DrawIndexedIndirect {
+                buffer,
+                array_element,
+                ..
+            } => {
+                track_indirect_buffer(buffer, *array_element, &mut scope);
+            }
             Command::EndRenderPass => break,
             _ => {}
         }
     }
 
-    // Submit usage scope
-    if let Some(barrier) = state.pipeline_tracker.submit(scope) {
-        barrier.execute(state.device, state.command_buffer);
+    scope
+}
+
+/// Determines the layouts the color attachments of the render pass beginning at `index` are left
+/// in, along with the usages the pass leaves them in.
+///
+/// Color attachments sampled only by the fragment shaders of the render pass that immediately
+/// follows are transitioned to the sampled layout by the pass itself. This avoids a separate
+/// barrier between the passes when textures alternate between being rendered to and sampled
+/// (e.g. ping-pong blurs). Every other attachment stays in the layout it was rendered in.
+pub(crate) unsafe fn color_attachment_final_layouts(
+    commands: &[Command<crate::VulkanBackend>],
+    index: usize,
+) -> (Vec<vk::ImageLayout>, Vec<(SubResource, SubResourceUsage)>) {
+    let descriptor = match &commands[index] {
+        Command::BeginRenderPass(descriptor) => descriptor,
+        _ => unreachable!(),
+    };
+    let mut layouts =
+        vec![vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL; descriptor.color_attachments.len()];
+    let mut usages = Vec::default();
+
+    // Find the render pass that immediately follows
+    let end = match commands[index..]
+        .iter()
+        .position(|command| matches!(command, Command::EndRenderPass))
+    {
+        Some(end) => index + end,
+        None => return (layouts, usages),
+    };
+    let next = match commands.get(end + 1) {
+        Some(Command::BeginRenderPass(next)) => next,
+        _ => return (layouts, usages),
+    };
+
+    // Gather the usages of the next pass
+    let mut scope = UsageScope::default();
+    for command in &commands[(end + 1)..] {
+        match command {
+            Command::BindDescriptorSets { sets, .. } => track_descriptor_sets(sets, &mut scope),
+            Command::EndRenderPass => break,
+            _ => {}
+        }
     }
+    let attached = |texture: vk::Image, array_elem: u32, mip_level: u32| {
+        next.color_attachments
+            .iter()
+            .any(|attachment| match attachment.source {
+                ColorAttachmentSource::Texture {
+                    texture: other,
+                    array_element,
+                    mip_level: other_mip,
+                    ..
+                } => {
+                    other.internal().image == texture
+                        && array_element as u32 == array_elem
+                        && other_mip as u32 == mip_level
+                }
+                ColorAttachmentSource::SurfaceImage(_) => false,
+            })
+    };
+
+    let sampled = SubResourceUsage {
+        access: vk::AccessFlags::SHADER_READ,
+        stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+        layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    };
+    for (layout, attachment) in layouts.iter_mut().zip(&descriptor.color_attachments) {
+        let (texture, array_elem, mip_level) = match attachment.source {
+            ColorAttachmentSource::Texture {
+                texture,
+                array_element,
+                mip_level,
+                ..
+            } => (
+                texture.internal().image,
+                array_element as u32,
+                mip_level as u32,
+            ),
+            ColorAttachmentSource::SurfaceImage(_) => continue,
+        };
+        let resource = SubResource::Texture {
+            texture,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            array_elem,
+            mip_level,
+        };
+        if scope.usage(&resource) == Some(sampled) && !attached(texture, array_elem, mip_level) {
+            *layout = sampled.layout;
+            usages.push((resource, sampled));
+        }
+    }
+
+    (layouts, usages)
 }
 
-unsafe fn track_dispatch(state: &mut TrackState) {
+unsafe fn track_dispatch(
+    commands: &[Command<crate::VulkanBackend>],
+    index: usize,
+) -> Option<UsageScope> {
     // Find the index of the bound pipeline
     let idx = {
         let mut idx = None;
-        for (i, command) in state.commands[..=state.index].iter().enumerate().rev() {
+        for (i, command) in commands[..=index].iter().enumerate().rev() {
             match command {
                 Command::BindComputePipeline(_) => {
                     idx = Some(i);
@@ -186,17 +353,14 @@ unsafe fn track_dispatch(state: &mut TrackState) {
             }
         }
 
-        match idx {
-            Some(idx) => idx,
-            // No bound pipeline so no state track needed
-            None => return,
-        }
+        // No bound pipeline so no state track needed
+        idx?
     };
 
     // Determine how many sets are used by the active pipeline
     let mut total_bound = 0;
     let mut bound = {
-        let pipeline = match &state.commands[idx] {
+        let pipeline = match &commands[idx] {
             Command::BindComputePipeline(pipeline) => pipeline,
             // Unreachable because of early return in previous pass
             _ => unreachable!(),
@@ -208,8 +372,18 @@ unsafe fn track_dispatch(state: &mut TrackState) {
 
     let mut scope = UsageScope::default();
 
+    // Indirect dispatches read their workgroup counts before the shader runs
+    if let Command::DispatchIndirect {
+        buffer,
+        array_element,
+        ..
+    } = &commands[index]
+    {
+        track_indirect_buffer(buffer, *array_element, &mut scope);
+    }
+
     // Determine which sets are actually used
-    for command in state.commands[idx..=state.index].iter().rev() {
+    for command in commands[idx..=index].iter().rev() {
         // Break early if every set is bound
         if total_bound == bound.len() {
             break;
@@ -235,16 +409,30 @@ unsafe fn track_dispatch(state: &mut TrackState) {
         }
     }
 
-    // Submit pipeline values
-    if let Some(barrier) = state.pipeline_tracker.submit(scope) {
-        barrier.execute(state.device, state.command_buffer);
-    }
+    Some(scope)
+}
+
+fn track_indirect_buffer(
+    buffer: &Buffer<crate::VulkanBackend>,
+    array_element: usize,
+    scope: &mut UsageScope,
+) {
+    scope.use_resource(
+        SubResource::Buffer {
+            buffer: buffer.internal().buffer,
+            array_elem: array_element as u32,
+        },
+        SubResourceUsage {
+            access: vk::AccessFlags::INDIRECT_COMMAND_READ,
+            stage: vk::PipelineStageFlags::DRAW_INDIRECT,
+            layout: vk::ImageLayout::UNDEFINED,
+        },
+    );
 }
 
 unsafe fn track_buffer_to_buffer_copy(
-    state: &mut TrackState,
     copy: &CopyBufferToBuffer<'_, crate::VulkanBackend>,
-) {
+) -> UsageScope {
     // Barrier check
     let src = copy.src.internal();
     let dst = copy.dst.internal();
@@ -272,17 +460,51 @@ unsafe fn track_buffer_to_buffer_copy(
         },
     );
 
-    if let Some(barrier) = state.pipeline_tracker.submit(scope) {
-        barrier.execute(state.device, state.command_buffer);
+    scope
+}
+
+unsafe fn track_buffer_regions_copy(
+    src: &Buffer<crate::VulkanBackend>,
+    dst: &Buffer<crate::VulkanBackend>,
+    regions: &[BufferCopyRegion],
+) -> UsageScope {
+    // Barrier check. Every array element touched by a region is used by the copy.
+    let src = src.internal();
+    let dst = dst.internal();
+    let mut scope = UsageScope::default();
+    for region in regions {
+        scope.use_resource(
+            SubResource::Buffer {
+                buffer: src.buffer,
+                array_elem: region.src_array_element as u32,
+            },
+            SubResourceUsage {
+                access: vk::AccessFlags::TRANSFER_READ,
+                stage: vk::PipelineStageFlags::TRANSFER,
+                layout: vk::ImageLayout::UNDEFINED,
+            },
+        );
+        scope.use_resource(
+            SubResource::Buffer {
+                buffer: dst.buffer,
+                array_elem: region.dst_array_element as u32,
+            },
+            SubResourceUsage {
+                access: vk::AccessFlags::TRANSFER_WRITE,
+                stage: vk::PipelineStageFlags::TRANSFER,
+                layout: vk::ImageLayout::UNDEFINED,
+            },
+        );
     }
+
+    scope
 }
 
 unsafe fn track_buffer_to_texture_copy(
-    state: &mut TrackState,
     buffer: &Buffer<crate::VulkanBackend>,
     texture: &Texture<crate::VulkanBackend>,
     copy: &BufferTextureCopy,
-) {
+) -> UsageScope {
     // Barrier check
     let buffer = buffer.internal();
     let texture = texture.internal();
@@ -312,17 +534,14 @@ unsafe fn track_buffer_to_texture_copy(
         },
     );
 
-    if let Some(barrier) = state.pipeline_tracker.submit(scope) {
-        barrier.execute(state.device, state.command_buffer);
-    }
+    scope
 }
 
 unsafe fn track_texture_to_buffer_copy(
-    state: &mut TrackState,
     buffer: &Buffer<crate::VulkanBackend>,
     texture: &Texture<crate::VulkanBackend>,
     copy: &BufferTextureCopy,
-) {
+) -> UsageScope {
     // Barrier check
     let buffer = buffer.internal();
     let texture = texture.internal();
@@ -352,9 +571,7 @@ unsafe fn track_texture_to_buffer_copy(
         },
     );
 
-    if let Some(barrier) = state.pipeline_tracker.submit(scope) {
-        barrier.execute(state.device, state.command_buffer);
-    }
+    scope
 }
 
 unsafe fn track_descriptor_sets(
@@ -376,44 +593,55 @@ unsafe fn track_descriptor_set(set: &DescriptorSet<crate::VulkanBackend>, scope:
                 match &elem.value {
                     BoundValue::UniformBuffer {
                         buffer,
-                        array_element,
+                        array_elements,
                         ..
-                    } => scope.use_resource(
-                        SubResource::Buffer {
-                            buffer: *buffer,
-                            array_elem: *array_element as u32,
-                        },
-                        SubResourceUsage {
-                            access: elem.access,
-                            stage: elem.stage,
-                            layout: vk::ImageLayout::UNDEFINED,
-                        },
-                    ),
+                    } => {
+                        for array_element in array_elements.clone() {
+                            scope.use_resource(
+                                SubResource::Buffer {
+                                    buffer: *buffer,
+                                    array_elem: array_element as u32,
+                                },
+                                SubResourceUsage {
+                                    access: elem.access,
+                                    stage: elem.stage,
+                                    layout: vk::ImageLayout::UNDEFINED,
+                                },
+                            );
+                        }
+                    }
                     BoundValue::StorageBuffer {
                         buffer,
-                        array_element,
+                        array_elements,
                         ..
-                    } => scope.use_resource(
-                        SubResource::Buffer {
-                            buffer: *buffer,
-                            array_elem: *array_element as u32,
-                        },
-                        SubResourceUsage {
-                            access: elem.access,
-                            stage: elem.stage,
-                            layout: vk::ImageLayout::UNDEFINED,
-                        },
-                    ),
-                    // Textures require that you register each mip individually
+                    } => {
+                        for array_element in array_elements.clone() {
+                            scope.use_resource(
+                                SubResource::Buffer {
+                                    buffer: *buffer,
+                                    array_elem: array_element as u32,
+                                },
+                                SubResourceUsage {
+                                    access: elem.access,
+                                    stage: elem.stage,
+                                    layout: vk::ImageLayout::UNDEFINED,
+                                },
+                            );
+                        }
+                    }
+                    // Textures require that you register each mip individually. Only the mips
+                    // visible through the view are registered so that other mips of the same
+                    // texture can be used as attachments (e.g. when downsampling a mip chain).
                     BoundValue::Texture {
                         _ref_counter,
                         image,
                         array_element,
                         aspect_mask,
+                        base_mip,
                         mip_count,
                         ..
                     } => {
-                        for i in 0..*mip_count {
+                        for i in *base_mip..(*base_mip + *mip_count) {
                             scope.use_resource(
                                 SubResource::Texture {
                                     texture: *image,
@@ -424,7 +652,7 @@ unsafe fn track_descriptor_set(set: &DescriptorSet<crate::VulkanBackend>, scope:
                                 SubResourceUsage {
                                     access: elem.access,
                                     stage: elem.stage,
-                                    layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                                    layout: crate::util::sampled_layout(*aspect_mask),
                                 },
                             )
                         }
@@ -1,7 +1,7 @@
 use std::collections::{hash_map::Iter, HashMap};
 
 use api::types::QueueType;
-use ash::vk;
+use ash::vk::{self, Handle};
 use fxhash::FxHashMap;
 
 use super::fast_int_hasher::FIHashMap;
@@ -17,8 +17,17 @@ pub(crate) struct GlobalResourceUsage {
     images: FxHashMap<(vk::Image, u32), QueueUsage>,
     /// Texture + array element + mip level.
     image_layouts: FxHashMap<(vk::Image, u32, u32), vk::ImageLayout>,
+    /// Maps buffers that share memory to the buffer that owns the memory.
+    buffer_aliases: FIHashMap<vk::Buffer, vk::Buffer>,
+    /// Debug names of buffers and images, used to name the offending resource in tracking errors.
+    names: FIHashMap<u64, String>,
 }
 
+/// Array element used to track buffers that share memory. Every buffer placed in the same memory
+/// is tracked as a single resource, so that reusing the memory is synchronized with every
+/// previous use of it.
+pub(crate) const ALIASED_MEMORY: u32 = u32::MAX;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub(crate) struct QueueUsage {
     pub queue: QueueType,
@@ -31,11 +40,28 @@ pub(crate) struct PipelineTracker<'a> {
     next_value: u64,
     usages: FxHashMap<SubResource, SubResourceUsage>,
     queues: FIHashMap<QueueType, vk::PipelineStageFlags>,
+    /// Set when tracking commands that are submitted later. In this mode, nothing is registered
+    /// with the global tracker and the first usage of every subresource is recorded instead.
+    deferred: bool,
+    first_usages: FxHashMap<SubResource, SubResourceUsage>,
+    /// Set when tracking commands that don't implicitly wait on previous work submitted to the
+    /// queue. In this mode, resources last used by the queue make it wait on itself.
+    independent: bool,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub(crate) struct UsageScope {
     usages: FxHashMap<SubResource, SubResourceUsage>,
+    /// The first image subresource used with two layouts. Reported when the scope is submitted,
+    /// since only the global tracker knows the names of resources.
+    layout_conflict: Option<LayoutConflict>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct LayoutConflict {
+    resource: SubResource,
+    old: vk::ImageLayout,
+    new: vk::ImageLayout,
 }
 
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -64,6 +90,9 @@ pub(crate) enum SubResource {
 pub(crate) struct PipelineBarrier {
     pub src_stage: vk::PipelineStageFlags,
     pub dst_stage: vk::PipelineStageFlags,
+    /// Used for aliased memory, since buffer barriers only apply to accesses through the buffer
+    /// they refer to.
+    pub memory_barriers: Vec<vk::MemoryBarrier>,
     pub buffer_barriers: Vec<vk::BufferMemoryBarrier>,
     pub image_barriers: Vec<vk::ImageMemoryBarrier>,
 }
@@ -94,6 +123,30 @@ impl GlobalResourceUsage {
         }
     }
 
+    /// Registers `alias` as a buffer placed in the memory of `root`. From now on, both buffers
+    /// are tracked as the same resource.
+    #[inline(always)]
+    pub fn register_alias(&mut self, alias: vk::Buffer, root: vk::Buffer) {
+        self.buffer_aliases.insert(root, root);
+        self.buffer_aliases.insert(alias, root);
+    }
+
+    /// Stops tracking a destroyed buffer as sharing memory. Aliases of a destroyed buffer keep
+    /// its memory alive, so they are still tracked with it.
+    #[inline(always)]
+    pub fn remove_alias(&mut self, buffer: vk::Buffer) {
+        self.buffer_aliases.remove(&buffer);
+    }
+
+    /// Determines the key a buffer array element is tracked with.
+    #[inline(always)]
+    pub fn buffer_key(&self, buffer: vk::Buffer, array_elem: u32) -> (vk::Buffer, u32) {
+        match self.buffer_aliases.get(&buffer) {
+            Some(root) => (*root, ALIASED_MEMORY),
+            None => (buffer, array_elem),
+        }
+    }
+
     #[inline(always)]
     pub fn register_image(
         &mut self,
@@ -107,6 +160,8 @@ impl GlobalResourceUsage {
         }
     }
 
+    /// Sets the layout of an image subresource, returning the old layout. Images that have never
+    /// been used are reported as `UNDEFINED`, which matches the layout they are created with.
     #[inline(always)]
     pub fn register_layout(
         &mut self,
@@ -117,7 +172,66 @@ impl GlobalResourceUsage {
     ) -> vk::ImageLayout {
         self.image_layouts
             .insert((image, array_elem, mip_level), layout)
-            .unwrap_or_default()
+            .unwrap_or(vk::ImageLayout::UNDEFINED)
+    }
+
+    /// Sets the debug name of a buffer or image. Names must be removed with
+    /// [`remove_name`](GlobalResourceUsage::remove_name) when the resource is destroyed, since
+    /// handles of destroyed resources may be reused.
+    #[inline(always)]
+    pub fn register_name(&mut self, handle: impl Handle, name: String) {
+        self.names.insert(handle.as_raw(), name);
+    }
+
+    /// Removes the debug name of a destroyed buffer or image.
+    #[inline(always)]
+    pub fn remove_name(&mut self, handle: impl Handle) {
+        self.names.remove(&handle.as_raw());
+    }
+
+    /// Describes a subresource for error messages, using the debug name of the resource if it
+    /// has one.
+    pub fn describe(&self, resource: &SubResource) -> String {
+        let name = |handle: u64| match self.names.get(&handle) {
+            Some(name) => format!("`{name}`"),
+            None => format!("{handle:#x}"),
+        };
+        match resource {
+            SubResource::Buffer {
+                buffer,
+                array_elem: ALIASED_MEMORY,
+            } => format!("memory aliased by buffer {}", name(buffer.as_raw())),
+            SubResource::Buffer { buffer, array_elem } => {
+                format!(
+                    "buffer {} (array element {array_elem})",
+                    name(buffer.as_raw())
+                )
+            }
+            SubResource::Texture {
+                texture,
+                array_elem,
+                mip_level,
+                ..
+            } => format!(
+                "texture {} (array element {array_elem}, mip level {mip_level})",
+                name(texture.as_raw())
+            ),
+        }
+    }
+
+    /// Sets the layouts of every image subresource in the scope.
+    pub fn register_layouts(&mut self, scope: &UsageScope) {
+        for (resource, usage) in &scope.usages {
+            if let SubResource::Texture {
+                texture,
+                array_elem,
+                mip_level,
+                ..
+            } = resource
+            {
+                self.register_layout(*texture, *array_elem, *mip_level, usage.layout);
+            }
+        }
     }
 }
 
@@ -130,19 +244,119 @@ impl<'a> PipelineTracker<'a> {
             next_value,
             usages: HashMap::default(),
             queues: HashMap::default(),
+            deferred: false,
+            first_usages: HashMap::default(),
+            independent: false,
+        }
+    }
+
+    /// The queue and timeline value of the submission being tracked.
+    #[inline(always)]
+    pub fn submission(&self) -> (QueueType, u64) {
+        (self.queue_ty, self.next_value)
+    }
+
+    /// Creates a tracker for commands that are submitted without waiting on previous work on the
+    /// queue. Since the queue doesn't wait on itself, resources last used by the queue are
+    /// synchronized like resources used by other queues.
+    #[inline(always)]
+    pub fn new_independent(
+        global: &'a mut GlobalResourceUsage,
+        queue_ty: QueueType,
+        next_value: u64,
+    ) -> Self {
+        Self {
+            independent: true,
+            ..Self::new(global, queue_ty, next_value)
+        }
+    }
+
+    /// Creates a tracker for commands that are recorded now but submitted later. The global
+    /// tracker is only used to resolve aliases.
+    ///
+    /// Since the state of each subresource at submission is unknown, the first usage of every
+    /// subresource is assumed to be its entry state. The entry states are returned by
+    /// [`finish_deferred`](PipelineTracker::finish_deferred) and must be reached by the submitter.
+    #[inline(always)]
+    pub fn new_deferred(global: &'a mut GlobalResourceUsage, queue_ty: QueueType) -> Self {
+        Self {
+            deferred: true,
+            ..Self::new(global, queue_ty, 0)
         }
     }
 
+    /// Consumes a deferred tracker, returning the state every subresource must be in before the
+    /// tracked commands execute and the state they are left in afterwards.
+    pub fn finish_deferred(self) -> (UsageScope, UsageScope) {
+        debug_assert!(self.deferred);
+        (
+            UsageScope {
+                usages: self.first_usages,
+                layout_conflict: None,
+            },
+            UsageScope {
+                usages: self.usages,
+                layout_conflict: None,
+            },
+        )
+    }
+
+    /// Records that a render pass transitioned an image subresource into `usage.layout` through
+    /// the final layout of one of its attachments. The external dependency of the pass makes its
+    /// attachment writes visible to `usage`, so later usages compatible with it need no barrier.
+    pub fn transition_in_pass(&mut self, resource: SubResource, usage: SubResourceUsage) {
+        if let SubResource::Texture {
+            texture,
+            array_elem,
+            mip_level,
+            ..
+        } = resource
+        {
+            if !self.deferred {
+                self.global
+                    .register_layout(texture, array_elem, mip_level, usage.layout);
+            }
+        }
+        self.usages.insert(resource, usage);
+    }
+
+    /// Replaces the buffers in the scope with the keys they are tracked with, so that buffers
+    /// sharing memory are seen as the same resource.
+    pub fn resolve_aliases(&self, scope: UsageScope) -> UsageScope {
+        if self.global.buffer_aliases.is_empty() {
+            return scope;
+        }
+
+        let mut out = UsageScope {
+            layout_conflict: scope.layout_conflict,
+            ..Default::default()
+        };
+        for (resource, usage) in scope.usages {
+            let resource = match resource {
+                SubResource::Buffer { buffer, array_elem } => {
+                    let (buffer, array_elem) = self.global.buffer_key(buffer, array_elem);
+                    SubResource::Buffer { buffer, array_elem }
+                }
+                resource => resource,
+            };
+            out.use_resource(resource, usage);
+        }
+        out
+    }
+
+    /// Registers the usages of a scope, returning the barrier needed before them.
+    ///
+    /// # Panics
+    /// - If an image subresource is used with more than one layout in `scope`.
     pub fn submit(&mut self, scope: UsageScope) -> Option<PipelineBarrier> {
-        let read_accesses: vk::AccessFlags = vk::AccessFlags::MEMORY_READ
-            | vk::AccessFlags::SHADER_READ
-            | vk::AccessFlags::UNIFORM_READ
-            | vk::AccessFlags::TRANSFER_READ
-            | vk::AccessFlags::COLOR_ATTACHMENT_READ
-            | vk::AccessFlags::INDIRECT_COMMAND_READ
-            | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
-            | vk::AccessFlags::INDEX_READ
-            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ;
+        if let Some(conflict) = scope.layout_conflict {
+            panic!(
+                "an image can only have one layout per scope, but {} is used as both {:?} and {:?}",
+                self.global.describe(&conflict.resource),
+                conflict.old,
+                conflict.new
+            );
+        }
 
         let mut barrier = PipelineBarrier::default();
 
@@ -154,6 +368,9 @@ impl<'a> PipelineTracker<'a> {
         let mut buffer_barriers =
             FxHashMap::<(vk::Buffer, u32), vk::BufferMemoryBarrier>::default();
 
+        // Accesses to aliased memory are synchronized with a global memory barrier
+        let mut memory_barrier: Option<vk::MemoryBarrier> = None;
+
         // Analyze each usage
         for (resource, usage) in scope.usages {
             // Check the global tracker to see if we need to wait on certain queues or if we need
@@ -162,28 +379,43 @@ impl<'a> PipelineTracker<'a> {
                 queue: self.queue_ty,
                 timeline_value: self.next_value,
             };
-            let (old_queue_usage, old_layout) = match &resource {
-                SubResource::Buffer { buffer, array_elem } => (
-                    self.global
-                        .register_buffer(*buffer, *array_elem, Some(resc_usage)),
-                    vk::ImageLayout::UNDEFINED,
-                ),
-                SubResource::Texture {
-                    texture,
-                    array_elem,
-                    mip_level,
-                    ..
-                } => (
-                    self.global
-                        .register_image(*texture, *array_elem, Some(resc_usage)),
-                    self.global
-                        .register_layout(*texture, *array_elem, *mip_level, usage.layout),
-                ),
+            let (old_queue_usage, old_layout) = if self.deferred {
+                let old_layout = match self.usages.get(&resource) {
+                    Some(old) => old.layout,
+                    None => {
+                        self.first_usages.insert(resource, usage);
+                        usage.layout
+                    }
+                };
+                (None, old_layout)
+            } else {
+                match &resource {
+                    SubResource::Buffer { buffer, array_elem } => (
+                        self.global
+                            .register_buffer(*buffer, *array_elem, Some(resc_usage)),
+                        vk::ImageLayout::UNDEFINED,
+                    ),
+                    SubResource::Texture {
+                        texture,
+                        array_elem,
+                        mip_level,
+                        ..
+                    } => (
+                        self.global
+                            .register_image(*texture, *array_elem, Some(resc_usage)),
+                        self.global.register_layout(
+                            *texture,
+                            *array_elem,
+                            *mip_level,
+                            usage.layout,
+                        ),
+                    ),
+                }
             };
 
             // Check if this resource was last used by a queue other than us
             if let Some(old_queue_usage) = old_queue_usage {
-                if old_queue_usage.queue != self.queue_ty {
+                if old_queue_usage.queue != self.queue_ty || self.independent {
                     // The new usage might be a combo of a bunch of other usages, so we have to
                     // select them in order. This is OMEGA ugly. Look into changing this
                     let new_stage = if usage.stage.contains(vk::PipelineStageFlags::BOTTOM_OF_PIPE)
@@ -254,8 +486,7 @@ impl<'a> PipelineTracker<'a> {
             let (src_access, src_stage) = match self.usages.get_mut(&resource) {
                 Some(old) => {
                     // Anything other than read-after-read requires a barrier
-                    if !(read_accesses.contains(old.access) && read_accesses.contains(usage.access))
-                    {
+                    if !is_read_only(old.access) || !is_read_only(usage.access) {
                         needs_barrier = true;
                     }
                     (old.access, old.stage)
@@ -267,6 +498,14 @@ impl<'a> PipelineTracker<'a> {
 
             if needs_barrier {
                 match resource {
+                    SubResource::Buffer {
+                        array_elem: ALIASED_MEMORY,
+                        ..
+                    } => {
+                        let entry = memory_barrier.get_or_insert_with(vk::MemoryBarrier::default);
+                        entry.src_access_mask |= src_access;
+                        entry.dst_access_mask |= usage.access;
+                    }
                     SubResource::Buffer { buffer, array_elem } => {
                         buffer_barriers.insert(
                             (buffer, array_elem),
@@ -315,8 +554,9 @@ impl<'a> PipelineTracker<'a> {
             }
         }
 
-        // We only need a barrier if we have registered buffer/image barriers
-        if !image_barriers.is_empty() || !buffer_barriers.is_empty() {
+        // We only need a barrier if we have registered memory/buffer/image barriers
+        if !image_barriers.is_empty() || !buffer_barriers.is_empty() || memory_barrier.is_some() {
+            barrier.memory_barriers = memory_barrier.into_iter().collect();
             barrier.image_barriers = image_barriers.into_iter().map(|(_, v)| v).collect();
             barrier.buffer_barriers = buffer_barriers.into_iter().map(|(_, v)| v).collect();
             Some(barrier)
@@ -332,17 +572,76 @@ impl<'a> PipelineTracker<'a> {
 }
 
 impl UsageScope {
+    /// Adds a usage of a subresource to the scope. Images can only have one layout per scope,
+    /// which is checked when the scope is submitted.
     #[inline(always)]
     pub fn use_resource(&mut self, subresource: SubResource, usage: SubResourceUsage) {
         let mut entry = self.usages.entry(subresource).or_default();
-        assert!(
-            entry.layout == vk::ImageLayout::UNDEFINED || entry.layout == usage.layout,
-            "an image can only have one layout per scope"
-        );
+        if entry.layout != vk::ImageLayout::UNDEFINED
+            && entry.layout != usage.layout
+            && self.layout_conflict.is_none()
+        {
+            self.layout_conflict = Some(LayoutConflict {
+                resource: subresource,
+                old: entry.layout,
+                new: usage.layout,
+            });
+        }
         entry.layout = usage.layout;
         entry.access |= usage.access;
         entry.stage |= usage.stage;
     }
+
+    /// The combined usage of a subresource within the scope, if it is used.
+    #[inline(always)]
+    pub fn usage(&self, subresource: &SubResource) -> Option<SubResourceUsage> {
+        self.usages.get(subresource).copied()
+    }
+
+    /// Merges the usages of another scope into this one.
+    #[inline(always)]
+    pub fn merge(&mut self, other: UsageScope) {
+        if self.layout_conflict.is_none() {
+            self.layout_conflict = other.layout_conflict;
+        }
+        for (subresource, usage) in other.usages {
+            self.use_resource(subresource, usage);
+        }
+    }
+
+    /// Checks if the usages of another scope depend on the usages of this scope. Scopes conflict
+    /// if they share a subresource that is written to by either scope or that is used with
+    /// different layouts.
+    pub fn conflicts(&self, other: &UsageScope) -> bool {
+        other
+            .usages
+            .iter()
+            .any(|(subresource, usage)| match self.usages.get(subresource) {
+                Some(existing) => {
+                    existing.layout != usage.layout
+                        || !is_read_only(existing.access)
+                        || !is_read_only(usage.access)
+                }
+                None => false,
+            })
+    }
+}
+
+/// Checks if an access only reads. Any access containing a write (e.g. a read-write storage
+/// buffer with `SHADER_READ | SHADER_WRITE`) must be synchronized with every other access.
+#[inline(always)]
+fn is_read_only(access: vk::AccessFlags) -> bool {
+    let read_accesses: vk::AccessFlags = vk::AccessFlags::MEMORY_READ
+        | vk::AccessFlags::SHADER_READ
+        | vk::AccessFlags::UNIFORM_READ
+        | vk::AccessFlags::TRANSFER_READ
+        | vk::AccessFlags::COLOR_ATTACHMENT_READ
+        | vk::AccessFlags::INDIRECT_COMMAND_READ
+        | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+        | vk::AccessFlags::INDEX_READ
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+        | vk::AccessFlags::HOST_READ;
+    read_accesses.contains(access)
 }
 
 impl PipelineBarrier {
@@ -353,7 +652,7 @@ impl PipelineBarrier {
             self.src_stage,
             self.dst_stage,
             vk::DependencyFlags::BY_REGION,
-            &[],
+            &self.memory_barriers,
             &self.buffer_barriers,
             &self.image_barriers,
         );
@@ -1,7 +1,9 @@
 /// This example demonstrates how to create a blank window and explains all of the objects used.
 use api::surface::SurfacePresentSuccess;
 use pal::prelude::*;
-use vulkan::{VulkanBackend, VulkanBackendCreateInfo};
+use vulkan::{
+    AllocatorDebugSettings, QueuePriorities, VulkanBackend, VulkanBackendCreateInfo,
+};
 use winit::{
     dpi::PhysicalSize,
     event::{Event, WindowEvent},
@@ -24,6 +26,13 @@ fn main() {
         engine_name: String::from("pal"),
         window: &window,
         debug: true,
+        debug_severity: ValidationSeverity::Warning,
+        preallocated_command_buffers: 0,
+        max_pending_submits: None,
+        present_on_main: false,
+        allocator_debug: AllocatorDebugSettings::default(),
+        deferred_garbage_collection: false,
+        queue_priorities: QueuePriorities::default(),
     })
     .unwrap();
 
@@ -63,7 +72,7 @@ fn main() {
                 }
 
                 // To perform rendering, we begin by asking for an image from our surface
-                let surface_image = surface.acquire_image().unwrap();
+                let surface_image = surface.acquire_image(None).unwrap();
 
                 // Then we acquire a command buffer from our main queue which supports graphics
                 context.main().submit(Some("main_pass"), |command_buffer| {
@@ -2,7 +2,9 @@
 /// the sense that the operations performed make little sense. This simply demonstrates how many
 /// of the features fit together.
 use pal::prelude::*;
-use vulkan::{VulkanBackend, VulkanBackendCreateInfo};
+use vulkan::{
+    AllocatorDebugSettings, QueuePriorities, VulkanBackend, VulkanBackendCreateInfo,
+};
 use winit::{
     dpi::PhysicalSize,
     event::{Event, WindowEvent},
@@ -23,6 +25,13 @@ fn main() {
         engine_name: String::from("pal"),
         window: &window,
         debug: true,
+        debug_severity: ValidationSeverity::Warning,
+        preallocated_command_buffers: 0,
+        max_pending_submits: None,
+        present_on_main: false,
+        allocator_debug: AllocatorDebugSettings::default(),
+        deferred_garbage_collection: false,
+        queue_priorities: QueuePriorities::default(),
     })
     .unwrap();
 
@@ -52,6 +61,7 @@ fn main() {
             array_elements: 1,
             buffer_usage: BufferUsage::TRANSFER_SRC,
             memory_usage: MemoryUsage::CpuToGpu,
+            sharing: SharingMode::Exclusive,
             debug_name: Some(String::from("color_buffer")),
         },
     )
@@ -67,6 +77,7 @@ fn main() {
                 | BufferUsage::VERTEX_BUFFER
                 | BufferUsage::STORAGE_BUFFER,
             memory_usage: MemoryUsage::GpuOnly,
+            sharing: SharingMode::Exclusive,
             debug_name: Some(String::from("vertex_buffer")),
         },
     )
@@ -79,6 +90,7 @@ fn main() {
             array_elements: 1,
             buffer_usage: BufferUsage::TRANSFER_DST | BufferUsage::INDEX_BUFFER,
             memory_usage: MemoryUsage::GpuOnly,
+            sharing: SharingMode::Exclusive,
             debug_name: Some(String::from("index_buffer")),
         },
     )
@@ -91,6 +103,7 @@ fn main() {
             array_elements: 1,
             buffer_usage: BufferUsage::TRANSFER_SRC | BufferUsage::STORAGE_BUFFER,
             memory_usage: MemoryUsage::GpuOnly,
+            sharing: SharingMode::Exclusive,
             debug_name: Some(String::from("index_buffer_intermediate")),
         },
     )
@@ -115,6 +128,7 @@ fn main() {
                 count: 1,
                 stage: ShaderStage::Compute,
             }],
+            variable_count: false,
         },
     )
     .unwrap();
@@ -123,6 +137,7 @@ fn main() {
         context.clone(),
         DescriptorSetCreateInfo {
             layout: compute_layout.clone(),
+            variable_count: None,
             debug_name: Some(String::from("vertex_compute_set")),
         },
     )
@@ -134,6 +149,7 @@ fn main() {
         value: DescriptorValue::StorageBuffer {
             buffer: &vertex_buffer,
             array_element: 0,
+            range: BufferRange::Element,
         },
     }]);
 
@@ -162,6 +178,7 @@ fn main() {
         context.clone(),
         DescriptorSetCreateInfo {
             layout: compute_layout.clone(),
+            variable_count: None,
             debug_name: Some(String::from("index_compute_set")),
         },
     )
@@ -173,6 +190,7 @@ fn main() {
         value: DescriptorValue::StorageBuffer {
             buffer: &index_buffer_intermediate,
             array_element: 0,
+            range: BufferRange::Element,
         },
     }]);
 
@@ -243,6 +261,7 @@ fn main() {
                 topology: PrimitiveTopology::TriangleList,
             },
             rasterization: RasterizationState::default(),
+            multisample: MultisampleState::default(),
             depth_stencil: None,
             color_blend: Some(ColorBlendState {
                 attachments: vec![ColorBlendAttachment {
@@ -250,6 +269,8 @@ fn main() {
                     ..Default::default()
                 }],
             }),
+            attachment_formats: None,
+            push_constants: None,
             debug_name: Some(String::from("graphics_pipeline")),
         },
     )
@@ -336,7 +357,7 @@ fn main() {
                 //
                 // This demonstrates GPU to GPU sync with the previous two queue operations and
                 // also command synchronization because each operation depends on the last.
-                let surface_image = surface.acquire_image().unwrap();
+                let surface_image = surface.acquire_image(None).unwrap();
                 context.main().submit(Some("main_pass"), |command_buffer| {
                     // 1. Generate indices
                     command_buffer.compute_pass(|pass| {
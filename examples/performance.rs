@@ -4,13 +4,17 @@ use std::{
 };
 
 use pal::prelude::*;
-use vulkan::{VulkanBackend, VulkanBackendCreateInfo};
+use vulkan::{
+    AllocatorDebugSettings, QueuePriorities, VulkanBackend, VulkanBackendCreateInfo,
+};
 use winit::{dpi::PhysicalSize, event_loop::EventLoop, window::WindowBuilder};
 
 const TEST1_RUN_COUNT: usize = 10000;
 const TEST1_BUFFER_COUNT: usize = 1024;
 const TEST1_BUFFER_SIZE: u64 = 128;
 
+const TEST3_RUN_COUNT: usize = 10000;
+
 fn main() {
     println!("Initializing Pal...");
     // We don't really care about initialization performance, so we preinitialize both wgpu and pal
@@ -27,6 +31,13 @@ fn main() {
         engine_name: String::from("pal"),
         window: &window,
         debug: false,
+        debug_severity: ValidationSeverity::Warning,
+        preallocated_command_buffers: 8,
+        max_pending_submits: None,
+        present_on_main: false,
+        allocator_debug: AllocatorDebugSettings::default(),
+        deferred_garbage_collection: false,
+        queue_priorities: QueuePriorities::default(),
     })
     .unwrap();
     let pal = Context::new(pal_backend);
@@ -71,6 +82,7 @@ fn main() {
                     array_elements: 1,
                     buffer_usage: BufferUsage::UNIFORM_BUFFER,
                     memory_usage: MemoryUsage::GpuOnly,
+                    sharing: SharingMode::Exclusive,
                     debug_name: None,
                 },
             )
@@ -106,6 +118,129 @@ fn main() {
     // Test 2:
     // This test checks the speed of verifying texture access for many textures in a render pass.
     // TODO
+
+    // Test 3:
+    // This test compares submitting the same commands every run against recording them once and
+    // replaying them, which skips recording and resource tracking.
+    println!("Beginning Test 3...");
+    let mut submit_time = Duration::ZERO;
+    let mut replay_time = Duration::ZERO;
+
+    let pal_buffers = (0..TEST1_BUFFER_COUNT)
+        .map(|_| {
+            Buffer::new(
+                pal.clone(),
+                BufferCreateInfo {
+                    size: TEST1_BUFFER_SIZE,
+                    array_elements: 1,
+                    buffer_usage: BufferUsage::STORAGE_BUFFER,
+                    memory_usage: MemoryUsage::GpuOnly,
+                    sharing: SharingMode::Exclusive,
+                    debug_name: None,
+                },
+            )
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let (pipeline, set) = pal_test3_resources(&pal, &pal_buffers);
+
+    let queue = pal.compute();
+    let prebuilt = queue.record(None, |command_buffer| {
+        command_buffer.compute_pass(|pass| {
+            pass.bind_pipeline(pipeline.clone());
+            pass.bind_sets(0, vec![&set]);
+            pass.dispatch(1, 1, 1);
+        });
+    });
+
+    for _ in 0..TEST3_RUN_COUNT {
+        let begin = Instant::now();
+        queue.submit(None, |command_buffer| {
+            command_buffer.compute_pass(|pass| {
+                pass.bind_pipeline(pipeline.clone());
+                pass.bind_sets(0, vec![&set]);
+                pass.dispatch(1, 1, 1);
+            });
+        });
+        submit_time += Instant::now().duration_since(begin);
+
+        let begin = Instant::now();
+        queue.resubmit(&prebuilt);
+        replay_time += Instant::now().duration_since(begin);
+    }
+    pal.wait_all_work(None);
+    println!(
+        "pal_test3_submit : {} ms",
+        submit_time.as_millis() as f32 / TEST3_RUN_COUNT as f32
+    );
+    println!(
+        "pal_test3_replay : {} ms",
+        replay_time.as_millis() as f32 / TEST3_RUN_COUNT as f32
+    );
+}
+
+fn pal_test3_resources(pal: &Context, buffers: &[Buffer]) -> (ComputePipeline, DescriptorSet) {
+    let layout = DescriptorSetLayout::new(
+        pal.clone(),
+        DescriptorSetLayoutCreateInfo {
+            bindings: vec![DescriptorBinding {
+                binding: 0,
+                ty: DescriptorType::StorageBuffer(AccessType::ReadWrite),
+                count: TEST1_BUFFER_COUNT,
+                stage: ShaderStage::Compute,
+            }],
+            variable_count: false,
+        },
+    )
+    .unwrap();
+
+    let mut set = DescriptorSet::new(
+        pal.clone(),
+        DescriptorSetCreateInfo {
+            layout: layout.clone(),
+            variable_count: None,
+            debug_name: None,
+        },
+    )
+    .unwrap();
+
+    let updates = buffers
+        .iter()
+        .enumerate()
+        .map(|(i, buffer)| DescriptorSetUpdate {
+            binding: 0,
+            array_element: i,
+            value: DescriptorValue::StorageBuffer {
+                buffer,
+                array_element: 0,
+                range: BufferRange::Element,
+            },
+        })
+        .collect::<Vec<_>>();
+    set.update(&updates);
+
+    const SHADER_BIN: &'static [u8] = include_bytes!("./shaders/test1_pal.comp.spv");
+    let shader = Shader::new(
+        pal.clone(),
+        ShaderCreateInfo {
+            code: SHADER_BIN,
+            debug_name: None,
+        },
+    )
+    .unwrap();
+
+    let pipeline = ComputePipeline::new(
+        pal.clone(),
+        ComputePipelineCreateInfo {
+            layouts: vec![layout],
+            module: shader,
+            work_group_size: (1, 1, 1),
+            debug_name: None,
+        },
+    )
+    .unwrap();
+
+    (pipeline, set)
 }
 
 fn pal_test1(pal: &Context, buffers: &[Buffer]) -> Duration {
@@ -120,6 +255,7 @@ fn pal_test1(pal: &Context, buffers: &[Buffer]) -> Duration {
                 count: TEST1_BUFFER_COUNT,
                 stage: ShaderStage::Compute,
             }],
+            variable_count: false,
         },
     )
     .unwrap();
@@ -128,6 +264,7 @@ fn pal_test1(pal: &Context, buffers: &[Buffer]) -> Duration {
         pal.clone(),
         DescriptorSetCreateInfo {
             layout: layout.clone(),
+            variable_count: None,
             debug_name: None,
         },
     )
@@ -141,6 +278,7 @@ fn pal_test1(pal: &Context, buffers: &[Buffer]) -> Duration {
             value: DescriptorValue::UniformBuffer {
                 buffer,
                 array_element: 1,
+                range: BufferRange::Element,
             },
         });
     }
@@ -10,7 +10,9 @@ mod util;
 use glam::{Mat4, Vec3};
 use ordered_float::NotNan;
 use pal::prelude::*;
-use vulkan::{VulkanBackend, VulkanBackendCreateInfo};
+use vulkan::{
+    AllocatorDebugSettings, QueuePriorities, VulkanBackend, VulkanBackendCreateInfo,
+};
 use winit::{
     dpi::PhysicalSize,
     event::{Event, WindowEvent},
@@ -36,6 +38,13 @@ fn main() {
         engine_name: String::from("pal"),
         window: &window,
         debug: true,
+        debug_severity: ValidationSeverity::Warning,
+        preallocated_command_buffers: 0,
+        max_pending_submits: None,
+        present_on_main: false,
+        allocator_debug: AllocatorDebugSettings::default(),
+        deferred_garbage_collection: false,
+        queue_priorities: QueuePriorities::default(),
     })
     .unwrap();
 
@@ -166,6 +175,7 @@ fn main() {
                     topology: PrimitiveTopology::TriangleList,
                 },
                 rasterization: RasterizationState::default(),
+                multisample: MultisampleState::default(),
                 depth_stencil: None,
                 color_blend: Some(ColorBlendState {
                     attachments: vec![ColorBlendAttachment {
@@ -173,6 +183,8 @@ fn main() {
                         ..Default::default()
                     }],
                 }),
+                attachment_formats: None,
+                push_constants: None,
                 debug_name: Some(String::from("triangle_graphics_pipeline")),
             },
         )
@@ -192,6 +204,9 @@ fn main() {
             mip_levels: 1,
             texture_usage: TextureUsage::COLOR_ATTACHMENT | TextureUsage::SAMPLED,
             memory_usage: MemoryUsage::GpuOnly,
+            tiling: TextureTiling::Optimal,
+            sharing: SharingMode::Exclusive,
+            view_formats: Vec::default(),
             debug_name: Some(String::from("triangle_texture")),
         },
     )
@@ -204,6 +219,7 @@ fn main() {
             array_elements: 1,
             buffer_usage: BufferUsage::UNIFORM_BUFFER,
             memory_usage: MemoryUsage::CpuToGpu,
+            sharing: SharingMode::Exclusive,
             debug_name: Some(String::from("uniform_buffer")),
         },
     )
@@ -228,6 +244,7 @@ fn main() {
                         ty: DescriptorType::UniformBuffer,
                     },
                 ],
+                variable_count: false,
             },
         )
         .unwrap();
@@ -236,6 +253,7 @@ fn main() {
             context.clone(),
             DescriptorSetCreateInfo {
                 layout: layout.clone(),
+                variable_count: None,
                 debug_name: Some(String::from("cube_set")),
             },
         )
@@ -272,6 +290,7 @@ fn main() {
                 value: DescriptorValue::UniformBuffer {
                     buffer: &uniform_buffer,
                     array_element: 0,
+                    range: BufferRange::Element,
                 },
             },
         ]);
@@ -329,6 +348,7 @@ fn main() {
                     cull_mode: CullMode::None,
                     ..Default::default()
                 },
+                multisample: MultisampleState::default(),
                 depth_stencil: Some(DepthStencilState {
                     depth_clamp: false,
                     depth_test: true,
@@ -343,6 +363,8 @@ fn main() {
                         ..Default::default()
                     }],
                 }),
+                attachment_formats: None,
+                push_constants: None,
                 debug_name: Some(String::from("cube_graphics_pipeline")),
             },
         )
@@ -364,6 +386,9 @@ fn main() {
             mip_levels: 1,
             texture_usage: TextureUsage::DEPTH_STENCIL_ATTACHMENT,
             memory_usage: MemoryUsage::GpuOnly,
+            tiling: TextureTiling::Optimal,
+            sharing: SharingMode::Exclusive,
+            view_formats: Vec::default(),
             debug_name: Some(String::from("depth_buffer")),
         },
     )
@@ -418,7 +443,7 @@ fn main() {
                     .copy_from_slice(bytemuck::cast_slice(&[mvp]));
 
                 // Begin rendering
-                let surface_image = surface.acquire_image().unwrap();
+                let surface_image = surface.acquire_image(None).unwrap();
                 context.main().submit(Some("main_pass"), |command_buffer| {
                     // First pass, we render a triangle to the texture
                     command_buffer.render_pass(
@@ -428,6 +453,7 @@ fn main() {
                                     texture: &triangle_texture,
                                     array_element: 0,
                                     mip_level: 0,
+                                    view_format: None,
                                 },
                                 load_op: LoadOp::Clear(ClearColor::RgbaF32(0.0, 0.0, 0.0, 0.0)),
                                 store_op: StoreOp::Store,
@@ -461,8 +487,9 @@ fn main() {
                                 texture: &depth_buffer,
                                 array_element: 0,
                                 mip_level: 0,
-                                load_op: LoadOp::Clear(ClearColor::D32S32(1.0, 0)),
+                                load_op: LoadOp::Clear(ClearColor::Depth(1.0)),
                                 store_op: StoreOp::DontCare,
+                                read_only_depth: false,
                             }),
                         },
                         |pass| {
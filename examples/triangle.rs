@@ -1,7 +1,9 @@
 /// This example demonstrates how to draw a simple triangle, including the use of staging buffers
 /// and the async transfer queue.
 use pal::prelude::*;
-use vulkan::{VulkanBackend, VulkanBackendCreateInfo};
+use vulkan::{
+    AllocatorDebugSettings, QueuePriorities, VulkanBackend, VulkanBackendCreateInfo,
+};
 use winit::{
     dpi::PhysicalSize,
     event::{Event, WindowEvent},
@@ -25,6 +27,13 @@ fn main() {
         engine_name: String::from("pal"),
         window: &window,
         debug: true,
+        debug_severity: ValidationSeverity::Warning,
+        preallocated_command_buffers: 0,
+        max_pending_submits: None,
+        present_on_main: false,
+        allocator_debug: AllocatorDebugSettings::default(),
+        deferred_garbage_collection: false,
+        queue_priorities: QueuePriorities::default(),
     })
     .unwrap();
 
@@ -135,6 +144,7 @@ fn main() {
                 topology: PrimitiveTopology::TriangleList,
             },
             rasterization: RasterizationState::default(),
+            multisample: MultisampleState::default(),
             depth_stencil: None,
             color_blend: Some(ColorBlendState {
                 attachments: vec![ColorBlendAttachment {
@@ -142,6 +152,8 @@ fn main() {
                     ..Default::default()
                 }],
             }),
+            attachment_formats: None,
+            push_constants: None,
             debug_name: Some(String::from("graphics_pipeline")),
         },
     )
@@ -162,7 +174,7 @@ fn main() {
                     return;
                 }
 
-                let surface_image = surface.acquire_image().unwrap();
+                let surface_image = surface.acquire_image(None).unwrap();
 
                 context.main().submit(Some("main_pass"), |command_buffer| {
                     command_buffer.render_pass(
@@ -2,7 +2,9 @@
 /// CPU.
 use bytemuck::{Pod, Zeroable};
 use pal::prelude::*;
-use vulkan::{VulkanBackend, VulkanBackendCreateInfo};
+use vulkan::{
+    AllocatorDebugSettings, QueuePriorities, VulkanBackend, VulkanBackendCreateInfo,
+};
 use winit::{
     dpi::PhysicalSize,
     event::{Event, WindowEvent},
@@ -32,6 +34,13 @@ fn main() {
         engine_name: String::from("pal"),
         window: &window,
         debug: true,
+        debug_severity: ValidationSeverity::Warning,
+        preallocated_command_buffers: 0,
+        max_pending_submits: None,
+        present_on_main: false,
+        allocator_debug: AllocatorDebugSettings::default(),
+        deferred_garbage_collection: false,
+        queue_priorities: QueuePriorities::default(),
     })
     .unwrap();
 
@@ -73,6 +82,7 @@ fn main() {
             array_elements: 1,
             buffer_usage: BufferUsage::VERTEX_BUFFER | BufferUsage::TRANSFER_DST,
             memory_usage: MemoryUsage::GpuOnly,
+            sharing: SharingMode::Exclusive,
             debug_name: Some(String::from("vertex_buffer")),
         },
     )
@@ -94,6 +104,7 @@ fn main() {
             array_elements: 1,
             buffer_usage: BufferUsage::INDEX_BUFFER | BufferUsage::TRANSFER_DST,
             memory_usage: MemoryUsage::GpuOnly,
+            sharing: SharingMode::Exclusive,
             debug_name: Some(String::from("index_buffer")),
         },
     )
@@ -134,6 +145,7 @@ fn main() {
             array_elements: 1,
             buffer_usage: BufferUsage::UNIFORM_BUFFER,
             memory_usage: MemoryUsage::CpuToGpu,
+            sharing: SharingMode::Exclusive,
             debug_name: Some(String::from("uniform_buffer")),
         },
     )
@@ -149,6 +161,7 @@ fn main() {
                 count: 1,
                 stage: ShaderStage::Vertex,
             }],
+            variable_count: false,
         },
     )
     .unwrap();
@@ -158,6 +171,7 @@ fn main() {
         context.clone(),
         DescriptorSetCreateInfo {
             layout: layout.clone(),
+            variable_count: None,
             debug_name: Some(String::from("uniform_compute_set")),
         },
     )
@@ -170,6 +184,7 @@ fn main() {
         value: DescriptorValue::UniformBuffer {
             buffer: &uniform_buffer,
             array_element: 0,
+            range: BufferRange::Element,
         },
     }]);
 
@@ -229,6 +244,7 @@ fn main() {
                 topology: PrimitiveTopology::TriangleList,
             },
             rasterization: RasterizationState::default(),
+            multisample: MultisampleState::default(),
             depth_stencil: None,
             color_blend: Some(ColorBlendState {
                 attachments: vec![ColorBlendAttachment {
@@ -236,6 +252,8 @@ fn main() {
                     ..Default::default()
                 }],
             }),
+            attachment_formats: None,
+            push_constants: None,
             debug_name: Some(String::from("graphics_pipeline")),
         },
     )
@@ -274,7 +292,7 @@ fn main() {
                         offset: [timer.cos() * 0.1, timer.sin() * 0.1],
                     }]));
 
-                let surface_image = surface.acquire_image().unwrap();
+                let surface_image = surface.acquire_image(None).unwrap();
 
                 context.main().submit(Some("main_pass"), |command_buffer| {
                     command_buffer.render_pass(
@@ -23,6 +23,7 @@ pub fn create_triangle(ctx: &Context) -> MeshBuffers {
                 array_elements: 1,
                 buffer_usage: BufferUsage::VERTEX_BUFFER | BufferUsage::TRANSFER_DST,
                 memory_usage: MemoryUsage::GpuOnly,
+                sharing: SharingMode::Exclusive,
                 debug_name: Some(String::from("triangle_vertex_buffer")),
             },
         )
@@ -40,6 +41,7 @@ pub fn create_triangle(ctx: &Context) -> MeshBuffers {
                 array_elements: 1,
                 buffer_usage: BufferUsage::INDEX_BUFFER | BufferUsage::TRANSFER_DST,
                 memory_usage: MemoryUsage::GpuOnly,
+                sharing: SharingMode::Exclusive,
                 debug_name: Some(String::from("triangle_index_buffer")),
             },
         )
@@ -82,6 +84,7 @@ pub fn create_cube(ctx: &Context) -> MeshBuffers {
                 array_elements: 1,
                 buffer_usage: BufferUsage::VERTEX_BUFFER | BufferUsage::TRANSFER_DST,
                 memory_usage: MemoryUsage::GpuOnly,
+                sharing: SharingMode::Exclusive,
                 debug_name: Some(String::from("cube_vertex_buffer")),
             },
         )
@@ -99,6 +102,7 @@ pub fn create_cube(ctx: &Context) -> MeshBuffers {
                 array_elements: 1,
                 buffer_usage: BufferUsage::INDEX_BUFFER | BufferUsage::TRANSFER_DST,
                 memory_usage: MemoryUsage::GpuOnly,
+                sharing: SharingMode::Exclusive,
                 debug_name: Some(String::from("cube_index_buffer")),
             },
         )
@@ -3,7 +3,12 @@ type Backend = vulkan::VulkanBackend;
 
 #[cfg(feature = "vulkan")]
 pub mod backend {
-    pub use vulkan::{VulkanBackend, VulkanBackendCreateInfo, VulkanBackendCreateError};
+    pub use vulkan::{
+        AllocatorDebugSettings, QueuePriorities, VulkanBackend, VulkanBackendCreateError,
+        VulkanBackendCreateInfo,
+    };
+    #[cfg(feature = "raw")]
+    pub use vulkan::{ash, RawCommand, RawHandles, RawQueue};
 }
 
 pub mod prelude {
@@ -16,7 +21,8 @@ pub mod prelude {
     pub type Surface = api::surface::Surface<crate::Backend>;
     pub type SurfaceImage = api::surface::SurfaceImage<crate::Backend>;
     pub use api::surface::{
-        SurfaceConfiguration, SurfaceCreateError, SurfaceCreateInfo, SurfacePresentSuccess,
+        SurfaceCaptureError, SurfaceConfiguration, SurfaceCreateError, SurfaceCreateInfo,
+        SurfacePresentSuccess, SurfaceQueryError,
     };
 
     // Render pass
@@ -27,10 +33,22 @@ pub mod prelude {
     };
 
     // Command buffer
-    pub use api::command_buffer::CopyBufferToBuffer;
+    pub use api::command_buffer::{BufferCopyRegion, CopyBufferToBuffer};
+
+    // Render graph
+    pub type RenderGraph<'a> = api::render_graph::RenderGraph<'a, crate::Backend>;
+    pub type PassResources<'a> = api::render_graph::PassResources<'a, crate::Backend>;
+    pub use api::render_graph::{PassDescriptor, RenderGraphError, ResourceId};
 
     // Queue
     pub type Queue = api::queue::Queue<crate::Backend>;
+    pub type PrebuiltCommands<'a> = api::queue::PrebuiltCommands<'a, crate::Backend>;
+
+    // Query
+    pub type QueryPool = api::query::QueryPool<crate::Backend>;
+    pub type QueryRing = api::query::QueryRing<crate::Backend>;
+    pub type FrameProfiler = api::query::FrameProfiler<crate::Backend>;
+    pub use api::query::{QueryPoolCreateError, QueryPoolCreateInfo, TimestampWriteError};
 
     // Shader
     pub type Shader = api::shader::Shader<crate::Backend>;
@@ -39,30 +57,37 @@ pub mod prelude {
     // Graphics pipeline
     pub type GraphicsPipeline = api::graphics_pipeline::GraphicsPipeline<crate::Backend>;
     pub use api::graphics_pipeline::{
-        ColorBlendAttachment, ColorBlendState, DepthStencilState, GraphicsPipelineCreateError,
-        GraphicsPipelineCreateInfo, RasterizationState, ShaderStages, VertexInputAttribute,
-        VertexInputBinding, VertexInputState,
+        AttachmentFormats, ColorBlendAttachment, ColorBlendState, DepthStencilState,
+        GraphicsPipelineCreateError, GraphicsPipelineCreateInfo, MultisampleState,
+        RasterizationState, ShaderStages, VertexInputAttribute, VertexInputBinding,
+        VertexInputState,
     };
 
     // Compute pipeline
     pub type ComputePipeline = api::compute_pipeline::ComputePipeline<crate::Backend>;
+    pub type DispatchIndirect = <crate::Backend as api::Backend>::DispatchIndirect;
     pub use api::compute_pipeline::{ComputePipelineCreateError, ComputePipelineCreateInfo};
 
     // Buffer
     pub type Buffer = api::buffer::Buffer<crate::Backend>;
+    pub type BufferMemoryRegion<'a> = api::buffer::BufferMemoryRegion<'a, crate::Backend>;
     pub use api::buffer::{
         BufferCreateError, BufferCreateInfo, BufferReadView, BufferViewError, BufferWriteView,
     };
 
     // Texture
     pub type Texture = api::texture::Texture<crate::Backend>;
-    pub use api::texture::{Sampler, TextureCreateError, TextureCreateInfo};
+    pub type SparseTextureBind<'a> = api::texture::SparseTextureBind<'a, crate::Backend>;
+    pub use api::texture::{
+        Sampler, SparseBindError, SparseProperties, TextureCreateError, TextureCreateInfo,
+        TextureMapping, TextureSubresourceLayout, TextureViewError,
+    };
 
     // Descriptor set & layout
     pub type DescriptorSetLayout = api::descriptor_set::DescriptorSetLayout<crate::Backend>;
     pub type DescriptorSet = api::descriptor_set::DescriptorSet<crate::Backend>;
     pub use api::descriptor_set::{
-        DescriptorBinding, DescriptorSetCreateError, DescriptorSetCreateInfo,
+        BufferRange, DescriptorBinding, DescriptorSetCreateError, DescriptorSetCreateInfo,
         DescriptorSetLayoutCreateError, DescriptorSetLayoutCreateInfo, DescriptorSetUpdate,
         DescriptorType, DescriptorValue,
     };